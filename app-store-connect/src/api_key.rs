@@ -45,6 +45,13 @@ pub struct UnifiedApiKey {
 
     /// Base64 encoded DER of ECDSA private key material.
     private_key: String,
+
+    /// The team the key belongs to.
+    ///
+    /// API keys are scoped to a single team. Apple doesn't expose the team of
+    /// a key via its components, so it is recorded here for verification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    team_id: Option<String>,
 }
 
 impl UnifiedApiKey {
@@ -71,9 +78,20 @@ impl UnifiedApiKey {
             issuer_id: issuer_id.to_string(),
             key_id: key_id.to_string(),
             private_key,
+            team_id: None,
         })
     }
 
+    /// The team the key belongs to, if recorded.
+    pub fn team_id(&self) -> Option<&str> {
+        self.team_id.as_deref()
+    }
+
+    /// Record the team the key belongs to.
+    pub fn set_team_id(&mut self, team_id: impl ToString) {
+        self.team_id = Some(team_id.to_string());
+    }
+
     /// Construct an instance from serialized JSON.
     pub fn from_json(data: impl AsRef<[u8]>) -> Result<Self> {
         Ok(serde_json::from_slice(data.as_ref())?)
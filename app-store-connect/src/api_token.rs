@@ -10,10 +10,28 @@ use {
     crate::Result,
     jsonwebtoken::{Algorithm, EncodingKey, Header},
     serde::{Deserialize, Serialize},
-    std::{path::Path, time::SystemTime},
+    std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicI64, Ordering},
+            Arc,
+        },
+        time::SystemTime,
+    },
     thiserror::Error,
 };
 
+/// Maximum lifetime of a token, in seconds.
+///
+/// App Store Connect rejects tokens expiring more than 20 minutes after they
+/// were issued.
+pub const MAX_TOKEN_LIFETIME: u64 = 1200;
+
+/// Clock offsets within this many seconds of the current estimate are ignored.
+///
+/// HTTP `Date` headers have 1 second resolution and are subject to network latency.
+const CLOCK_OFFSET_TOLERANCE: i64 = 2;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct ConnectTokenRequest {
     iss: String,
@@ -25,6 +43,27 @@ struct ConnectTokenRequest {
 /// A JWT Token for use with App Store Connect API.
 pub type AppStoreConnectToken = String;
 
+/// Defines the validity window of minted tokens.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TokenValidity {
+    /// Number of seconds to backdate the issued at time of tokens by.
+    ///
+    /// This tolerates servers whose clocks are behind ours.
+    pub not_before_leeway: u64,
+
+    /// Number of seconds after minting that tokens expire.
+    pub lifetime: u64,
+}
+
+impl Default for TokenValidity {
+    fn default() -> Self {
+        Self {
+            not_before_leeway: 0,
+            lifetime: 300,
+        }
+    }
+}
+
 /// Represents a private key used to create JWT tokens for use with App Store Connect.
 ///
 /// See https://developer.apple.com/documentation/appstoreconnectapi/creating_api_keys_for_app_store_connect_api
@@ -41,11 +80,17 @@ pub type AppStoreConnectToken = String;
 ///
 /// All these are issued by Apple. You can log in to App Store Connect and see/manage your keys
 /// at https://appstoreconnect.apple.com/access/api.
+///
+/// Instances track the offset between the local clock and the clock of the
+/// App Store Connect servers so minted tokens can compensate for local clock
+/// skew. Clones of an instance share this state.
 #[derive(Clone)]
 pub struct ConnectTokenEncoder {
     key_id: String,
     issuer_id: String,
     encoding_key: EncodingKey,
+    validity: TokenValidity,
+    clock_offset: Arc<AtomicI64>,
 }
 
 impl ConnectTokenEncoder {
@@ -61,6 +106,8 @@ impl ConnectTokenEncoder {
             key_id,
             issuer_id,
             encoding_key,
+            validity: TokenValidity::default(),
+            clock_offset: Arc::new(AtomicI64::new(0)),
         }
     }
 
@@ -118,10 +165,74 @@ impl ConnectTokenEncoder {
         Err(MissingApiKey.into())
     }
 
+    /// The validity window of tokens minted by [Self::new_default_token()].
+    pub fn token_validity(&self) -> TokenValidity {
+        self.validity
+    }
+
+    /// Set the validity window of minted tokens.
+    ///
+    /// Errors if the window exceeds [MAX_TOKEN_LIFETIME].
+    pub fn set_token_validity(&mut self, validity: TokenValidity) -> Result<()> {
+        let window = validity.not_before_leeway + validity.lifetime;
+
+        if window > MAX_TOKEN_LIFETIME {
+            return Err(TokenValidityTooLong(window).into());
+        }
+
+        self.validity = validity;
+
+        Ok(())
+    }
+
+    /// The estimated number of seconds the server's clock is ahead of ours.
+    ///
+    /// Negative values mean the server's clock is behind ours.
+    pub fn clock_offset(&self) -> i64 {
+        self.clock_offset.load(Ordering::Relaxed)
+    }
+
+    /// Record the current time as reported by the server, as seconds since the UNIX epoch.
+    ///
+    /// This updates the clock offset applied to minted tokens. Returns whether the
+    /// offset changed.
+    pub fn observe_server_time(&self, server_time: i64) -> bool {
+        self.observe_server_time_at(server_time, unix_time())
+    }
+
+    fn observe_server_time_at(&self, server_time: i64, now: u64) -> bool {
+        let offset = server_time - now as i64;
+        let current = self.clock_offset();
+
+        if (offset - current).abs() <= CLOCK_OFFSET_TOLERANCE {
+            return false;
+        }
+
+        if offset.abs() > CLOCK_OFFSET_TOLERANCE {
+            log::warn!(
+                "local clock appears to be {}s {} App Store Connect; compensating in API tokens",
+                offset.abs(),
+                if offset > 0 { "behind" } else { "ahead of" }
+            );
+        }
+
+        self.clock_offset.store(offset, Ordering::Relaxed);
+
+        true
+    }
+
+    /// Mint a new JWT token using the instance's validity window.
+    pub fn new_default_token(&self) -> Result<AppStoreConnectToken> {
+        self.new_token(self.validity.lifetime)
+    }
+
     /// Mint a new JWT token.
     ///
     /// Using the private key and key metadata bound to this instance, we issue a new JWT
     /// for the requested duration.
+    ///
+    /// Times in the token are adjusted by the estimated clock offset and the issued at
+    /// time is backdated by the configured leeway.
     pub fn new_token(&self, duration: u64) -> Result<AppStoreConnectToken> {
         let header = Header {
             kid: Some(self.key_id.clone()),
@@ -129,24 +240,120 @@ impl ConnectTokenEncoder {
             ..Default::default()
         };
 
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("calculating UNIX time should never fail")
-            .as_secs();
-
-        let claims = ConnectTokenRequest {
-            iss: self.issuer_id.clone(),
-            iat: now,
-            exp: now + duration,
-            aud: "appstoreconnect-v1".to_string(),
-        };
+        let claims = self.claims(unix_time(), duration);
 
         let token = jsonwebtoken::encode(&header, &claims, &self.encoding_key)?;
 
         Ok(token)
     }
+
+    /// Resolve the claims of a token minted at local time `now`.
+    fn claims(&self, now: u64, duration: u64) -> ConnectTokenRequest {
+        let now = now.saturating_add_signed(self.clock_offset());
+
+        ConnectTokenRequest {
+            iss: self.issuer_id.clone(),
+            iat: now.saturating_sub(self.validity.not_before_leeway),
+            exp: now + duration,
+            aud: "appstoreconnect-v1".to_string(),
+        }
+    }
+}
+
+/// Obtain the local time as seconds since the UNIX epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("calculating UNIX time should never fail")
+        .as_secs()
 }
 
 #[derive(Clone, Copy, Debug, Error)]
 #[error("no app store connect api key found")]
 pub struct MissingApiKey;
+
+#[derive(Clone, Copy, Debug, Error)]
+#[error("token validity window of {0} seconds exceeds the maximum of 1200 seconds")]
+pub struct TokenValidityTooLong(pub u64);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoder() -> ConnectTokenEncoder {
+        ConnectTokenEncoder::from_jwt_encoding_key(
+            "DEADBEEF42".into(),
+            "issuer".into(),
+            EncodingKey::from_secret(b"secret"),
+        )
+    }
+
+    #[test]
+    fn token_validity() -> Result<()> {
+        let mut encoder = encoder();
+        assert_eq!(encoder.token_validity(), TokenValidity::default());
+
+        let now = 1_700_000_000;
+        let claims = encoder.claims(now, 300);
+        assert_eq!((claims.iat, claims.exp), (now, now + 300));
+
+        encoder.set_token_validity(TokenValidity {
+            not_before_leeway: 60,
+            lifetime: 1140,
+        })?;
+        let claims = encoder.claims(now, encoder.token_validity().lifetime);
+        assert_eq!((claims.iat, claims.exp), (now - 60, now + 1140));
+
+        // The window including the leeway is limited.
+        assert!(encoder
+            .set_token_validity(TokenValidity {
+                not_before_leeway: 61,
+                lifetime: 1140,
+            })
+            .is_err());
+        assert!(encoder
+            .set_token_validity(TokenValidity {
+                not_before_leeway: 0,
+                lifetime: MAX_TOKEN_LIFETIME + 1,
+            })
+            .is_err());
+        assert_eq!(encoder.token_validity().not_before_leeway, 60);
+
+        Ok(())
+    }
+
+    #[test]
+    fn clock_offset() {
+        let encoder = encoder();
+        let now = 1_700_000_000;
+
+        // Differences within the tolerance are ignored.
+        assert!(!encoder.observe_server_time_at(now as i64 + CLOCK_OFFSET_TOLERANCE, now));
+        assert!(!encoder.observe_server_time_at(now as i64 - CLOCK_OFFSET_TOLERANCE, now));
+        assert_eq!(encoder.clock_offset(), 0);
+        let claims = encoder.claims(now, 300);
+        assert_eq!((claims.iat, claims.exp), (now, now + 300));
+
+        // A server ahead of us moves times forward.
+        assert!(encoder.observe_server_time_at(now as i64 + 100, now));
+        assert_eq!(encoder.clock_offset(), 100);
+        let claims = encoder.claims(now, 300);
+        assert_eq!((claims.iat, claims.exp), (now + 100, now + 400));
+
+        // Small changes to an established offset are ignored too.
+        assert!(!encoder.observe_server_time_at(now as i64 + 102, now));
+        assert_eq!(encoder.clock_offset(), 100);
+
+        // A server behind us moves times back. Clones share the offset.
+        let clone = encoder.clone();
+        assert!(clone.observe_server_time_at(now as i64 - 100, now));
+        assert_eq!(encoder.clock_offset(), -100);
+        let claims = encoder.claims(now, 300);
+        assert_eq!((claims.iat, claims.exp), (now - 100, now + 200));
+
+        // Offsets never underflow times.
+        assert!(encoder.observe_server_time_at(0, now));
+        let claims = encoder.claims(now, 300);
+        assert_eq!((claims.iat, claims.exp), (0, 300));
+    }
+}
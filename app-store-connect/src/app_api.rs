@@ -0,0 +1,722 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! App metadata, versions, and screenshot/app preview assets.
+//!
+//! See also <https://developer.apple.com/documentation/appstoreconnectapi/app_store>.
+
+use {
+    crate::{bundle_api::BundleIdPlatform, AppStoreConnectClient, Result},
+    md5::{Digest, Md5},
+    reqwest::Method,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, path::Path},
+};
+
+const APPLE_API_URL: &str = "https://api.appstoreconnect.apple.com/v1";
+
+impl AppStoreConnectClient {
+    fn get_resource<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let token = self.get_token()?;
+        let req = self
+            .client
+            .get(format!("{APPLE_API_URL}/{path}"))
+            .bearer_auth(token)
+            .header("Accept", "application/json");
+        Ok(self.send_request(req)?.json()?)
+    }
+
+    fn create_resource<A: Serialize, T: serde::de::DeserializeOwned>(
+        &self,
+        r#type: &str,
+        attributes: A,
+        relationships: BTreeMap<String, Relationship>,
+    ) -> Result<T> {
+        let token = self.get_token()?;
+        let body = ResourceCreateRequest {
+            data: ResourceCreateRequestData {
+                attributes,
+                relationships,
+                r#type: r#type.into(),
+            },
+        };
+        let req = self
+            .client
+            .post(format!("{APPLE_API_URL}/{}", r#type))
+            .bearer_auth(token)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .json(&body);
+        Ok(self.send_request(req)?.json()?)
+    }
+
+    fn update_resource<A: Serialize, T: serde::de::DeserializeOwned>(
+        &self,
+        r#type: &str,
+        id: &str,
+        attributes: A,
+    ) -> Result<T> {
+        let token = self.get_token()?;
+        let body = ResourceUpdateRequest {
+            data: ResourceUpdateRequestData {
+                attributes,
+                id: id.into(),
+                r#type: r#type.into(),
+            },
+        };
+        let req = self
+            .client
+            .patch(format!("{APPLE_API_URL}/{}/{id}", r#type))
+            .bearer_auth(token)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .json(&body);
+        Ok(self.send_request(req)?.json()?)
+    }
+
+    pub fn list_app_infos(&self, app_id: &str) -> Result<AppInfosResponse> {
+        self.get_resource(&format!("apps/{app_id}/appInfos"))
+    }
+
+    pub fn list_app_info_localizations(
+        &self,
+        app_info_id: &str,
+    ) -> Result<AppInfoLocalizationsResponse> {
+        self.get_resource(&format!("appInfos/{app_info_id}/appInfoLocalizations"))
+    }
+
+    /// Create a localization of app info. `attributes.locale` must be set.
+    pub fn create_app_info_localization(
+        &self,
+        app_info_id: &str,
+        attributes: AppInfoLocalizationAttributes,
+    ) -> Result<AppInfoLocalizationResponse> {
+        self.create_resource(
+            "appInfoLocalizations",
+            attributes,
+            Relationship::single("appInfo", "appInfos", app_info_id),
+        )
+    }
+
+    /// Update a localization of app info. `attributes.locale` can't be changed.
+    pub fn update_app_info_localization(
+        &self,
+        id: &str,
+        attributes: AppInfoLocalizationAttributes,
+    ) -> Result<AppInfoLocalizationResponse> {
+        self.update_resource("appInfoLocalizations", id, attributes)
+    }
+
+    pub fn list_app_store_versions(&self, app_id: &str) -> Result<AppStoreVersionsResponse> {
+        self.get_resource(&format!("apps/{app_id}/appStoreVersions"))
+    }
+
+    pub fn create_app_store_version(
+        &self,
+        app_id: &str,
+        platform: BundleIdPlatform,
+        version_string: &str,
+    ) -> Result<AppStoreVersionResponse> {
+        self.create_resource(
+            "appStoreVersions",
+            AppStoreVersionCreateRequestAttributes {
+                platform: platform.to_string(),
+                version_string: version_string.into(),
+            },
+            Relationship::single("app", "apps", app_id),
+        )
+    }
+
+    pub fn list_app_store_version_localizations(
+        &self,
+        version_id: &str,
+    ) -> Result<AppStoreVersionLocalizationsResponse> {
+        self.get_resource(&format!(
+            "appStoreVersions/{version_id}/appStoreVersionLocalizations"
+        ))
+    }
+
+    /// Create a localization of an app version. `attributes.locale` must be set.
+    pub fn create_app_store_version_localization(
+        &self,
+        version_id: &str,
+        attributes: AppStoreVersionLocalizationAttributes,
+    ) -> Result<AppStoreVersionLocalizationResponse> {
+        self.create_resource(
+            "appStoreVersionLocalizations",
+            attributes,
+            Relationship::single("appStoreVersion", "appStoreVersions", version_id),
+        )
+    }
+
+    /// Update a localization of an app version. `attributes.locale` can't be changed.
+    pub fn update_app_store_version_localization(
+        &self,
+        id: &str,
+        attributes: AppStoreVersionLocalizationAttributes,
+    ) -> Result<AppStoreVersionLocalizationResponse> {
+        self.update_resource("appStoreVersionLocalizations", id, attributes)
+    }
+
+    pub fn list_app_screenshot_sets(&self, localization_id: &str) -> Result<AssetSetsResponse> {
+        self.get_resource(&format!(
+            "appStoreVersionLocalizations/{localization_id}/appScreenshotSets"
+        ))
+    }
+
+    /// Create a screenshot set for a display type like `APP_IPHONE_67`.
+    pub fn create_app_screenshot_set(
+        &self,
+        localization_id: &str,
+        display_type: &str,
+    ) -> Result<AssetSetResponse> {
+        self.create_resource(
+            "appScreenshotSets",
+            AppScreenshotSetCreateRequestAttributes {
+                screenshot_display_type: display_type.into(),
+            },
+            Relationship::single(
+                "appStoreVersionLocalization",
+                "appStoreVersionLocalizations",
+                localization_id,
+            ),
+        )
+    }
+
+    pub fn list_app_preview_sets(&self, localization_id: &str) -> Result<AssetSetsResponse> {
+        self.get_resource(&format!(
+            "appStoreVersionLocalizations/{localization_id}/appPreviewSets"
+        ))
+    }
+
+    /// Create an app preview set for a preview type like `IPHONE_67`.
+    pub fn create_app_preview_set(
+        &self,
+        localization_id: &str,
+        preview_type: &str,
+    ) -> Result<AssetSetResponse> {
+        self.create_resource(
+            "appPreviewSets",
+            AppPreviewSetCreateRequestAttributes {
+                preview_type: preview_type.into(),
+            },
+            Relationship::single(
+                "appStoreVersionLocalization",
+                "appStoreVersionLocalizations",
+                localization_id,
+            ),
+        )
+    }
+
+    /// Upload a screenshot to a screenshot set.
+    pub fn upload_app_screenshot(&self, set_id: &str, path: &Path) -> Result<AssetResponse> {
+        self.upload_asset(
+            "appScreenshots",
+            "appScreenshotSet",
+            "appScreenshotSets",
+            set_id,
+            path,
+        )
+    }
+
+    /// Upload an app preview video to an app preview set.
+    pub fn upload_app_preview(&self, set_id: &str, path: &Path) -> Result<AssetResponse> {
+        self.upload_asset(
+            "appPreviews",
+            "appPreviewSet",
+            "appPreviewSets",
+            set_id,
+            path,
+        )
+    }
+
+    /// Upload an asset.
+    ///
+    /// Assets are uploaded in 3 steps: the asset is reserved, which yields the
+    /// operations to upload its content, the content is uploaded, and the
+    /// upload is committed along with the checksum of the content.
+    fn upload_asset(
+        &self,
+        r#type: &str,
+        set_relationship: &str,
+        set_type: &str,
+        set_id: &str,
+        path: &Path,
+    ) -> Result<AssetResponse> {
+        let data = std::fs::read(path)?;
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow::anyhow!("invalid asset file name: {}", path.display()))?;
+
+        let reservation: AssetResponse = self.create_resource(
+            r#type,
+            AssetCreateRequestAttributes {
+                file_name: file_name.into(),
+                file_size: data.len() as u64,
+            },
+            Relationship::single(set_relationship, set_type, set_id),
+        )?;
+
+        for operation in reservation
+            .data
+            .attributes
+            .upload_operations
+            .iter()
+            .flatten()
+        {
+            let chunk = operation.chunk(&data)?;
+
+            let mut req = self
+                .client
+                .request(
+                    Method::from_bytes(operation.method.as_bytes())?,
+                    &operation.url,
+                )
+                .body(chunk.to_vec());
+            for header in &operation.request_headers {
+                req = req.header(header.name.as_str(), header.value.as_str());
+            }
+            self.send_request(req)?;
+        }
+
+        self.update_resource(
+            r#type,
+            &reservation.data.id,
+            AssetUpdateRequestAttributes {
+                source_file_checksum: format!("{:x}", Md5::digest(&data)),
+                uploaded: true,
+            },
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceCreateRequest<A> {
+    pub data: ResourceCreateRequestData<A>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceCreateRequestData<A> {
+    pub attributes: A,
+    pub relationships: BTreeMap<String, Relationship>,
+    pub r#type: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUpdateRequest<A> {
+    pub data: ResourceUpdateRequestData<A>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUpdateRequestData<A> {
+    pub attributes: A,
+    pub id: String,
+    pub r#type: String,
+}
+
+/// A relationship to another resource.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Relationship {
+    pub data: RelationshipData,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelationshipData {
+    pub id: String,
+    pub r#type: String,
+}
+
+impl Relationship {
+    fn single(name: &str, r#type: &str, id: &str) -> BTreeMap<String, Relationship> {
+        BTreeMap::from([(
+            name.to_string(),
+            Relationship {
+                data: RelationshipData {
+                    id: id.into(),
+                    r#type: r#type.into(),
+                },
+            },
+        )])
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfosResponse {
+    pub data: Vec<AppInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfo {
+    pub attributes: AppInfoAttributes,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfoAttributes {
+    pub app_store_age_rating: Option<String>,
+    pub app_store_state: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfoLocalizationResponse {
+    pub data: AppInfoLocalization,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfoLocalizationsResponse {
+    pub data: Vec<AppInfoLocalization>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfoLocalization {
+    pub attributes: AppInfoLocalizationAttributes,
+    pub id: String,
+}
+
+/// Localized app info. Attributes that are `None` are left unchanged.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfoLocalizationAttributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy_policy_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy_choices_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy_policy_text: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionCreateRequestAttributes {
+    pub platform: String,
+    pub version_string: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionResponse {
+    pub data: AppStoreVersion,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionsResponse {
+    pub data: Vec<AppStoreVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersion {
+    pub attributes: AppStoreVersionAttributes,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionAttributes {
+    pub platform: String,
+    pub version_string: String,
+    pub app_store_state: Option<String>,
+    pub created_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionLocalizationResponse {
+    pub data: AppStoreVersionLocalization,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionLocalizationsResponse {
+    pub data: Vec<AppStoreVersionLocalization>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionLocalization {
+    pub attributes: AppStoreVersionLocalizationAttributes,
+    pub id: String,
+}
+
+/// Localized app version metadata. Attributes that are `None` are left unchanged.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppStoreVersionLocalizationAttributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keywords: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marketing_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub promotional_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub support_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whats_new: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppScreenshotSetCreateRequestAttributes {
+    pub screenshot_display_type: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppPreviewSetCreateRequestAttributes {
+    pub preview_type: String,
+}
+
+/// A screenshot set or app preview set.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetSetResponse {
+    pub data: AssetSet,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetSetsResponse {
+    pub data: Vec<AssetSet>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetSet {
+    pub attributes: AssetSetAttributes,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetSetAttributes {
+    pub screenshot_display_type: Option<String>,
+    pub preview_type: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetCreateRequestAttributes {
+    pub file_name: String,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetUpdateRequestAttributes {
+    pub source_file_checksum: String,
+    pub uploaded: bool,
+}
+
+/// A screenshot or app preview.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetResponse {
+    pub data: Asset,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Asset {
+    pub attributes: AssetAttributes,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetAttributes {
+    pub file_name: Option<String>,
+    pub file_size: Option<u64>,
+    pub source_file_checksum: Option<String>,
+    pub upload_operations: Option<Vec<UploadOperation>>,
+    pub asset_delivery_state: Option<serde_json::Value>,
+}
+
+/// An HTTP request uploading part of an asset.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadOperation {
+    pub method: String,
+    pub url: String,
+    pub offset: u64,
+    pub length: u64,
+    pub request_headers: Vec<HttpHeader>,
+}
+
+impl UploadOperation {
+    /// Obtain the part of the asset content uploaded by this operation.
+    pub fn chunk<'a>(&self, data: &'a [u8]) -> Result<&'a [u8]> {
+        let start = usize::try_from(self.offset)?;
+        let end = start
+            .checked_add(usize::try_from(self.length)?)
+            .ok_or_else(|| anyhow::anyhow!("upload operation out of range of asset"))?;
+
+        data.get(start..end)
+            .ok_or_else(|| anyhow::anyhow!("upload operation out of range of asset"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, serde_json::json};
+
+    #[test]
+    fn create_request_body() -> Result<()> {
+        let body = ResourceCreateRequest {
+            data: ResourceCreateRequestData {
+                attributes: AppScreenshotSetCreateRequestAttributes {
+                    screenshot_display_type: "APP_IPHONE_67".into(),
+                },
+                relationships: Relationship::single(
+                    "appStoreVersionLocalization",
+                    "appStoreVersionLocalizations",
+                    "loc",
+                ),
+                r#type: "appScreenshotSets".into(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&body)?,
+            json!({
+                "data": {
+                    "attributes": {
+                        "screenshotDisplayType": "APP_IPHONE_67",
+                    },
+                    "relationships": {
+                        "appStoreVersionLocalization": {
+                            "data": {
+                                "id": "loc",
+                                "type": "appStoreVersionLocalizations",
+                            },
+                        },
+                    },
+                    "type": "appScreenshotSets",
+                },
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_request_body() -> Result<()> {
+        // Unset attributes are omitted so they are left unchanged.
+        let body = ResourceUpdateRequest {
+            data: ResourceUpdateRequestData {
+                attributes: AppStoreVersionLocalizationAttributes {
+                    whats_new: Some("Bug fixes".into()),
+                    ..Default::default()
+                },
+                id: "loc".into(),
+                r#type: "appStoreVersionLocalizations".into(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&body)?,
+            json!({
+                "data": {
+                    "attributes": {
+                        "whatsNew": "Bug fixes",
+                    },
+                    "id": "loc",
+                    "type": "appStoreVersionLocalizations",
+                },
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn asset_reservation() -> Result<()> {
+        let reservation: AssetResponse = serde_json::from_value(json!({
+            "data": {
+                "attributes": {
+                    "fileName": "screenshot.png",
+                    "fileSize": 10,
+                    "uploadOperations": [
+                        {
+                            "method": "PUT",
+                            "url": "https://example.com/0",
+                            "offset": 0,
+                            "length": 6,
+                            "requestHeaders": [
+                                {"name": "Content-Type", "value": "image/png"},
+                            ],
+                        },
+                        {
+                            "method": "PUT",
+                            "url": "https://example.com/1",
+                            "offset": 6,
+                            "length": 4,
+                            "requestHeaders": [],
+                        },
+                    ],
+                },
+                "id": "asset",
+                "type": "appScreenshots",
+            },
+        }))?;
+
+        assert_eq!(reservation.data.id, "asset");
+        assert_eq!(
+            reservation.data.attributes.file_name.as_deref(),
+            Some("screenshot.png")
+        );
+
+        let operations = reservation.data.attributes.upload_operations.unwrap();
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].request_headers[0].name, "Content-Type");
+
+        let data = b"0123456789";
+        assert_eq!(operations[0].chunk(data)?, b"012345");
+        assert_eq!(operations[1].chunk(data)?, b"6789");
+        assert!(operations[1].chunk(&data[0..8]).is_err());
+
+        let overflow = UploadOperation {
+            method: "PUT".into(),
+            url: "https://example.com/".into(),
+            offset: 1,
+            length: u64::MAX,
+            request_headers: vec![],
+        };
+        assert!(overflow.chunk(data).is_err());
+
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@
 
 mod api_key;
 mod api_token;
+pub mod app_api;
 pub mod bundle_api;
 pub mod certs_api;
 pub mod cli;
@@ -14,14 +15,25 @@ pub mod notary_api;
 pub mod profile_api;
 
 use {
-    reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response},
+    reqwest::{
+        blocking::{Client, ClientBuilder, RequestBuilder, Response},
+        header::{HeaderValue, AUTHORIZATION, DATE},
+        StatusCode,
+    },
     serde_json::Value,
-    std::{path::Path, sync::Mutex},
+    std::{
+        path::Path,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
     thiserror::Error,
 };
 
 pub use crate::api_key::{InvalidPemPrivateKey, UnifiedApiKey};
-pub use crate::api_token::{AppStoreConnectToken, ConnectTokenEncoder, MissingApiKey};
+pub use crate::api_token::{
+    AppStoreConnectToken, ConnectTokenEncoder, MissingApiKey, TokenValidity, TokenValidityTooLong,
+    MAX_TOKEN_LIFETIME,
+};
 
 pub type Result<T> = anyhow::Result<T>;
 
@@ -31,7 +43,24 @@ pub type Result<T> = anyhow::Result<T>;
 pub struct AppStoreConnectClient {
     client: Client,
     connect_token: ConnectTokenEncoder,
-    token: Mutex<Option<AppStoreConnectToken>>,
+    token: Mutex<Option<CachedToken>>,
+}
+
+/// Cached tokens are replaced when they expire within this duration.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// A minted token and the state it was minted with.
+struct CachedToken {
+    token: AppStoreConnectToken,
+    expires: Instant,
+    clock_offset: i64,
+}
+
+impl CachedToken {
+    /// Whether the token must be replaced at `now` given the current clock offset.
+    fn is_stale(&self, now: Instant, clock_offset: i64) -> bool {
+        self.expires <= now + TOKEN_REFRESH_MARGIN || self.clock_offset != clock_offset
+    }
 }
 
 impl AppStoreConnectClient {
@@ -52,15 +81,43 @@ impl AppStoreConnectClient {
         })
     }
 
+    /// Obtain a token for authenticating requests.
+    ///
+    /// A cached token is reused until it is close to expiring or until the
+    /// estimated clock offset of the server changes.
     pub fn get_token(&self) -> Result<String> {
         let mut token = self.token.lock().unwrap();
 
-        // TODO need to handle token expiration.
-        if token.is_none() {
-            token.replace(self.connect_token.new_token(300)?);
+        let stale = match token.as_ref() {
+            Some(cached) => cached.is_stale(Instant::now(), self.connect_token.clock_offset()),
+            None => true,
+        };
+
+        if stale {
+            let clock_offset = self.connect_token.clock_offset();
+            let lifetime = self.connect_token.token_validity().lifetime;
+
+            token.replace(CachedToken {
+                token: self.connect_token.new_default_token()?,
+                expires: Instant::now() + Duration::from_secs(lifetime),
+                clock_offset,
+            });
         }
 
-        Ok(token.as_ref().unwrap().clone())
+        Ok(token.as_ref().unwrap().token.clone())
+    }
+
+    /// Record the server's clock from a response's `Date` header.
+    ///
+    /// Returns whether the estimated clock offset changed.
+    fn observe_response_date(&self, response: &Response) -> bool {
+        response
+            .headers()
+            .get(DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+            .map(|date| self.connect_token.observe_server_time(date.timestamp()))
+            .unwrap_or(false)
     }
 
     pub fn send_request(&self, request: RequestBuilder) -> Result<Response> {
@@ -68,9 +125,26 @@ impl AppStoreConnectClient {
         let method = request.method().to_string();
         let url = request.url().to_string();
 
+        // Retain a copy so we can retry with a token compensating for clock skew.
+        let retry = request
+            .try_clone()
+            .filter(|r| r.headers().contains_key(AUTHORIZATION));
+
         log::debug!("{} {}", request.method(), url);
 
-        let response = self.client.execute(request)?;
+        let mut response = self.client.execute(request)?;
+
+        let offset_changed = self.observe_response_date(&response);
+
+        if response.status() == StatusCode::UNAUTHORIZED && offset_changed {
+            if let Some(mut retry) = retry {
+                log::warn!("request unauthorized; retrying with clock skew compensated token");
+                let value = HeaderValue::from_str(&format!("Bearer {}", self.get_token()?))?;
+                retry.headers_mut().insert(AUTHORIZATION, value);
+
+                response = self.client.execute(retry)?;
+            }
+        }
 
         if response.status().is_success() {
             Ok(response)
@@ -100,3 +174,31 @@ pub struct AppStoreConnectError {
     url: String,
     message: String,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cached_token_staleness() {
+        let now = Instant::now();
+
+        let cached = |expires_in: u64, clock_offset: i64| CachedToken {
+            token: "token".into(),
+            expires: now + Duration::from_secs(expires_in),
+            clock_offset,
+        };
+
+        assert!(!cached(300, 0).is_stale(now, 0));
+        assert!(!cached(31, 0).is_stale(now, 0));
+        // Tokens are refreshed before they expire.
+        assert!(cached(30, 0).is_stale(now, 0));
+        assert!(cached(0, 0).is_stale(now, 0));
+        assert!(cached(300, 0).is_stale(now + Duration::from_secs(270), 0));
+
+        // Tokens minted with another clock offset are replaced.
+        assert!(cached(300, 0).is_stale(now, 5));
+        assert!(cached(300, 5).is_stale(now, 0));
+        assert!(!cached(300, 5).is_stale(now, 5));
+    }
+}
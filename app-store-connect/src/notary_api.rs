@@ -10,6 +10,7 @@
 
 use {
     crate::{AppStoreConnectClient, Result},
+    chrono::{DateTime, Utc},
     serde::{Deserialize, Serialize},
     serde_json::Value,
     thiserror::Error,
@@ -127,14 +128,103 @@ impl SubmissionResponse {
     }
 }
 
+impl SubmissionResponseData {
+    /// Parse the creation date of the submission.
+    pub fn created_date(&self) -> Result<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(&self.attributes.created_date)?.with_timezone(&Utc))
+    }
+}
+
+/// Links to related pages of a paged response.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PagedDocumentLinks {
+    pub next: Option<String>,
+}
+
 /// The notary service’s response to a request for the list of submissions.
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListSubmissionResponse {
     pub data: Vec<SubmissionResponseData>,
+    #[serde(default)]
+    pub links: PagedDocumentLinks,
     pub meta: Value,
 }
 
+/// Criteria for selecting submissions from the submission history.
+#[derive(Clone, Debug, Default)]
+pub struct SubmissionListFilter {
+    /// Only include submissions with this status.
+    pub status: Option<SubmissionResponseStatus>,
+    /// Only include submissions created at or after this time.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only include submissions created before this time.
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl SubmissionListFilter {
+    /// Whether a submission matches the filter.
+    pub fn matches(&self, submission: &SubmissionResponseData) -> Result<bool> {
+        if let Some(status) = self.status {
+            if submission.attributes.status != status {
+                return Ok(false);
+            }
+        }
+
+        if self.created_after.is_none() && self.created_before.is_none() {
+            return Ok(true);
+        }
+
+        let created = submission.created_date()?;
+
+        if let Some(after) = self.created_after {
+            if created < after {
+                return Ok(false);
+            }
+        }
+        if let Some(before) = self.created_before {
+            if created >= before {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Collect submissions matching a filter from all pages of the submission history.
+///
+/// `fetch_page` fetches the page at a URL, starting with `url`.
+fn collect_submissions(
+    url: &str,
+    filter: &SubmissionListFilter,
+    mut fetch_page: impl FnMut(&str) -> Result<ListSubmissionResponse>,
+) -> Result<Vec<SubmissionResponseData>> {
+    let mut res = vec![];
+    let mut seen = std::collections::HashSet::new();
+    let mut url = Some(url.to_string());
+
+    while let Some(page_url) = url.take() {
+        // Guard against servers linking pages in a cycle.
+        if !seen.insert(page_url.clone()) {
+            break;
+        }
+
+        let page = fetch_page(&page_url)?;
+
+        for submission in page.data {
+            if filter.matches(&submission)? {
+                res.push(submission);
+            }
+        }
+
+        url = page.links.next;
+    }
+
+    Ok(res)
+}
+
 #[derive(Clone, Copy, Debug, Error)]
 #[error("notarization {0}")]
 pub struct NotarizationError(SubmissionResponseStatus);
@@ -203,17 +293,37 @@ impl AppStoreConnectClient {
         Ok(self.send_request(req)?.json()?)
     }
 
+    /// Fetch the first page of previous submissions.
     pub fn list_submissions(&self) -> Result<ListSubmissionResponse> {
+        self.list_submissions_page(APPLE_NOTARY_SUBMIT_SOFTWARE_URL)
+    }
+
+    /// Fetch a page of previous submissions given its URL.
+    ///
+    /// The URL of the next page is available from [ListSubmissionResponse::links].
+    pub fn list_submissions_page(&self, url: &str) -> Result<ListSubmissionResponse> {
         let token = self.get_token()?;
         let req = self
             .client
-            .get(APPLE_NOTARY_SUBMIT_SOFTWARE_URL)
+            .get(url)
             .bearer_auth(token)
             .header("Accept", "application/json");
 
         Ok(self.send_request(req)?.json()?)
     }
 
+    /// Fetch all previous submissions matching a filter.
+    ///
+    /// All pages of the submission history are fetched.
+    pub fn list_all_submissions(
+        &self,
+        filter: &SubmissionListFilter,
+    ) -> Result<Vec<SubmissionResponseData>> {
+        collect_submissions(APPLE_NOTARY_SUBMIT_SOFTWARE_URL, filter, |url| {
+            self.list_submissions_page(url)
+        })
+    }
+
     /// Fetch details about a single completed notarization.
     pub fn get_submission_log(&self, submission_id: &str) -> Result<Value> {
         let token = self.get_token()?;
@@ -234,3 +344,114 @@ impl AppStoreConnectClient {
         Ok(logs)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn submission(id: &str, status: &str, created: &str) -> SubmissionResponseData {
+        serde_json::from_value(serde_json::json!({
+            "attributes": {
+                "createdDate": created,
+                "name": format!("{id}.zip"),
+                "status": status,
+            },
+            "id": id,
+            "type": "submissions",
+        }))
+        .unwrap()
+    }
+
+    fn page(data: Vec<SubmissionResponseData>, next: Option<&str>) -> ListSubmissionResponse {
+        ListSubmissionResponse {
+            data,
+            links: PagedDocumentLinks {
+                next: next.map(|x| x.to_string()),
+            },
+            meta: Value::Null,
+        }
+    }
+
+    fn date(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn filter_matches() -> Result<()> {
+        let accepted = submission("a", "Accepted", "2024-03-01T12:00:00.000Z");
+        let in_progress = submission("b", "In Progress", "2024-03-02T12:00:00.000Z");
+
+        let all = SubmissionListFilter::default();
+        assert!(all.matches(&accepted)?);
+        assert!(all.matches(&in_progress)?);
+
+        let status = SubmissionListFilter {
+            status: Some(SubmissionResponseStatus::InProgress),
+            ..Default::default()
+        };
+        assert!(!status.matches(&accepted)?);
+        assert!(status.matches(&in_progress)?);
+
+        // The lower bound is inclusive and the upper bound exclusive.
+        let window = SubmissionListFilter {
+            created_after: Some(date("2024-03-01T12:00:00Z")),
+            created_before: Some(date("2024-03-02T12:00:00Z")),
+            ..Default::default()
+        };
+        assert!(window.matches(&accepted)?);
+        assert!(!window.matches(&in_progress)?);
+
+        let bad_date = submission("c", "Accepted", "yesterday");
+        assert!(all.matches(&bad_date)?);
+        assert!(window.matches(&bad_date).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pagination() -> Result<()> {
+        let mut fetched = vec![];
+
+        let res = collect_submissions(
+            "page1",
+            &SubmissionListFilter {
+                status: Some(SubmissionResponseStatus::Accepted),
+                ..Default::default()
+            },
+            |url| {
+                fetched.push(url.to_string());
+
+                Ok(match url {
+                    "page1" => page(
+                        vec![
+                            submission("a", "Accepted", "2024-03-01T12:00:00Z"),
+                            submission("b", "Invalid", "2024-03-01T12:00:00Z"),
+                        ],
+                        Some("page2"),
+                    ),
+                    "page2" => page(
+                        vec![submission("c", "Accepted", "2024-03-01T12:00:00Z")],
+                        // A cycle back to the first page is not followed.
+                        Some("page1"),
+                    ),
+                    _ => panic!("unexpected page {url}"),
+                })
+            },
+        )?;
+
+        assert_eq!(fetched, vec!["page1", "page2"]);
+        assert_eq!(
+            res.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+            vec!["a", "c"]
+        );
+
+        assert!(
+            collect_submissions("page1", &SubmissionListFilter::default(), |_| {
+                Err(anyhow::anyhow!("request failed"))
+            })
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -5,7 +5,9 @@
 //! Bundles backed by a directory.
 
 use {
-    crate::BundlePackageType,
+    crate::{
+        declared_localizations, locale_from_lproj_name, BundlePackageType, LocalizationDifferences,
+    },
     anyhow::{anyhow, Context, Result},
     simple_file_manifest::{is_executable, FileEntry, FileManifest},
     std::{
@@ -261,6 +263,54 @@ impl DirectoryBundle {
         }
     }
 
+    /// Obtain the locales having a `.lproj` directory in this bundle.
+    ///
+    /// `.lproj` directories are looked for in the bundle's resources directory,
+    /// which is the root directory of shallow bundles. Results are sorted and
+    /// include `Base` if present.
+    pub fn localizations(&self) -> Result<Vec<String>> {
+        let resources_dir = if self.shallow {
+            self.root.clone()
+        } else {
+            self.resolve_path("Resources")
+        };
+
+        if !resources_dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut res = vec![];
+
+        for entry in std::fs::read_dir(&resources_dir)? {
+            let entry = entry?;
+
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            if let Some(locale) = locale_from_lproj_name(&entry.file_name().to_string_lossy()) {
+                res.push(locale.to_string());
+            }
+        }
+
+        res.sort();
+
+        Ok(res)
+    }
+
+    /// Compare `CFBundleLocalizations` against the `.lproj` directories present.
+    ///
+    /// Bundles not declaring `CFBundleLocalizations` are treated as declaring
+    /// no localizations.
+    pub fn localization_differences(&self) -> Result<LocalizationDifferences> {
+        let declared = declared_localizations(&self.info_plist)?.unwrap_or_default();
+
+        Ok(LocalizationDifferences::new(
+            &declared,
+            &self.localizations()?,
+        ))
+    }
+
     /// Obtain all files within this bundle.
     ///
     /// The iteration order is deterministic.
@@ -710,4 +760,34 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn localizations() -> Result<()> {
+        let (_temp, td) = temp_dir()?;
+
+        let root = td.join("MyApp.app");
+        let resources = root.join("Contents").join("Resources");
+        create_dir_all(resources.join("Base.lproj"))?;
+        create_dir_all(resources.join("en.lproj"))?;
+        create_dir_all(resources.join("de.lproj"))?;
+
+        let mut dict = plist::Dictionary::new();
+        dict.insert(
+            "CFBundleLocalizations".to_string(),
+            plist::Value::Array(vec!["en".into(), "fr".into()]),
+        );
+        plist::Value::from(dict).to_file_xml(root.join("Contents").join("Info.plist"))?;
+
+        let bundle = DirectoryBundle::new_from_path(&root)?;
+        assert_eq!(
+            bundle.localizations()?,
+            vec!["Base".to_string(), "de".to_string(), "en".to_string()]
+        );
+
+        let diff = bundle.localization_differences()?;
+        assert_eq!(diff.declared_missing, vec!["fr".to_string()]);
+        assert_eq!(diff.present_undeclared, vec!["de".to_string()]);
+
+        Ok(())
+    }
 }
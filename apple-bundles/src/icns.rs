@@ -0,0 +1,155 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Apple icon images (`.icns` files).
+
+An `.icns` file holds multiple images of an icon at different resolutions.
+macOS picks the most appropriate image for the size and pixel density an icon
+is displayed at. Application bundles reference their icon through the
+`CFBundleIconFile` key in `Info.plist`.
+
+This module assembles `.icns` files from PNG images, like `iconutil`. Images
+are embedded verbatim: they are not resized. So an image must be provided for
+each resolution the icon should have. Apple recommends sizes 16, 32, 128, 256,
+512, and 1024 pixels square.
+*/
+
+use anyhow::{anyhow, Result};
+
+/// The 8 byte signature of PNG files.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Icon types holding PNG data and the pixel size of their images.
+///
+/// Retina types (e.g. `ic11`, 16x16@2x) share pixel sizes with standard types
+/// (e.g. `icp5`, 32x32). Images are used for both.
+const ICON_TYPES: &[(&[u8; 4], u32)] = &[
+    (b"icp4", 16),
+    (b"ic11", 32),
+    (b"icp5", 32),
+    (b"ic12", 64),
+    (b"icp6", 64),
+    (b"ic07", 128),
+    (b"ic13", 256),
+    (b"ic08", 256),
+    (b"ic14", 512),
+    (b"ic09", 512),
+    (b"ic10", 1024),
+];
+
+/// Resolve the width and height of a PNG image.
+fn png_dimensions(data: &[u8]) -> Result<(u32, u32)> {
+    // The IHDR chunk holding dimensions must be the first chunk.
+    if data.len() < 24 || !data.starts_with(PNG_SIGNATURE) || &data[12..16] != b"IHDR" {
+        return Err(anyhow!("data is not a PNG image"));
+    }
+
+    let word = |offset: usize| {
+        u32::from_be_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+
+    Ok((word(16), word(20)))
+}
+
+/// Create an `.icns` file from PNG images.
+///
+/// Images must be square and have a pixel size of 16, 32, 64, 128, 256, 512,
+/// or 1024. It is an error for multiple images to have the same size.
+pub fn create_icns(images: impl IntoIterator<Item = impl AsRef<[u8]>>) -> Result<Vec<u8>> {
+    let mut by_size: Vec<(u32, Vec<u8>)> = vec![];
+
+    for image in images {
+        let image = image.as_ref();
+        let (width, height) = png_dimensions(image)?;
+
+        if width != height {
+            return Err(anyhow!(
+                "icon images must be square; got {}x{}",
+                width,
+                height
+            ));
+        }
+
+        if !ICON_TYPES.iter().any(|(_, size)| *size == width) {
+            return Err(anyhow!("unsupported icon image size {}x{}", width, height));
+        }
+
+        if by_size.iter().any(|(size, _)| *size == width) {
+            return Err(anyhow!("multiple icon images are {}x{}", width, height));
+        }
+
+        by_size.push((width, image.to_vec()));
+    }
+
+    if by_size.is_empty() {
+        return Err(anyhow!("no images to create an icon from"));
+    }
+
+    let mut elements = vec![];
+
+    for (icon_type, size) in ICON_TYPES {
+        if let Some((_, image)) = by_size.iter().find(|(s, _)| s == size) {
+            let length =
+                u32::try_from(image.len() + 8).map_err(|_| anyhow!("icon image exceeds 4 GB"))?;
+
+            elements.extend_from_slice(*icon_type);
+            elements.extend_from_slice(&length.to_be_bytes());
+            elements.extend_from_slice(image);
+        }
+    }
+
+    let length =
+        u32::try_from(elements.len() + 8).map_err(|_| anyhow!("icon file exceeds 4 GB"))?;
+
+    let mut data = b"icns".to_vec();
+    data.extend_from_slice(&length.to_be_bytes());
+    data.extend_from_slice(&elements);
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn png(size: u32) -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend_from_slice(&13u32.to_be_bytes());
+        data.extend_from_slice(b"IHDR");
+        data.extend_from_slice(&size.to_be_bytes());
+        data.extend_from_slice(&size.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn create() -> Result<()> {
+        let icns = create_icns([png(32), png(16)])?;
+
+        let mut expected = b"icns".to_vec();
+        expected.extend_from_slice(&(8 + 3 * 37u32).to_be_bytes());
+        for (icon_type, size) in [(b"icp4", 16), (b"ic11", 32), (b"icp5", 32)] {
+            expected.extend_from_slice(icon_type);
+            expected.extend_from_slice(&37u32.to_be_bytes());
+            expected.extend_from_slice(&png(size));
+        }
+        assert_eq!(icns, expected);
+
+        assert!(create_icns([png(16), png(16)]).is_err());
+        assert!(create_icns([png(20)]).is_err());
+        assert!(create_icns([b"not an image".to_vec()]).is_err());
+        assert!(create_icns(Vec::<Vec<u8>>::new()).is_err());
+
+        let mut rectangle = png(16);
+        rectangle[19] = 32;
+        assert!(create_icns([rectangle]).is_err());
+
+        Ok(())
+    }
+}
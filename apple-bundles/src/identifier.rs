@@ -0,0 +1,121 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Bundle and code signing identifiers.
+
+Bundle identifiers (`CFBundleIdentifier`) and code signing identifiers are
+reverse DNS-like strings such as `com.example.MyApp`. Apple's documentation
+restricts bundle identifiers to alphanumeric characters, hyphens, and periods.
+Code signing identifiers of non-bundle binaries are conventionally derived from
+file names and frequently also contain underscores, which Apple's tooling
+accepts.
+
+This module validates identifiers against these rules and normalizes arbitrary
+strings into conforming identifiers.
+*/
+
+use anyhow::{anyhow, Result};
+
+/// Maximum length in bytes of an identifier.
+pub const MAX_IDENTIFIER_LENGTH: usize = 255;
+
+/// Whether a character is allowed in an identifier.
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')
+}
+
+/// Validate an identifier string.
+///
+/// Identifiers must be non-empty, at most [MAX_IDENTIFIER_LENGTH] bytes, only
+/// consist of characters allowed by [is_identifier_char], and not have empty
+/// components (e.g. leading, trailing, or consecutive periods).
+pub fn validate_identifier(s: &str) -> Result<()> {
+    if s.is_empty() {
+        return Err(anyhow!("identifier is empty"));
+    }
+
+    if s.len() > MAX_IDENTIFIER_LENGTH {
+        return Err(anyhow!(
+            "identifier {} is longer than {} bytes",
+            s,
+            MAX_IDENTIFIER_LENGTH
+        ));
+    }
+
+    if let Some(c) = s.chars().find(|c| !is_identifier_char(*c)) {
+        return Err(anyhow!(
+            "identifier {} contains invalid character {:?}",
+            s,
+            c
+        ));
+    }
+
+    if s.split('.').any(|component| component.is_empty()) {
+        return Err(anyhow!("identifier {} has an empty component", s));
+    }
+
+    Ok(())
+}
+
+/// Normalize a string into a valid identifier.
+///
+/// Disallowed characters are replaced with `-`, empty components are removed,
+/// and the result is truncated to [MAX_IDENTIFIER_LENGTH] bytes. Valid
+/// identifiers are returned unchanged.
+///
+/// Returns `None` if nothing remains of the input.
+pub fn normalize_identifier(s: &str) -> Option<String> {
+    let replaced = s
+        .chars()
+        .map(|c| if is_identifier_char(c) { c } else { '-' })
+        .collect::<String>();
+
+    let mut normalized = replaced
+        .split('.')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join(".");
+
+    // Only ASCII remains, so truncation can't split a character.
+    normalized.truncate(MAX_IDENTIFIER_LENGTH);
+    let normalized = normalized.trim_end_matches('.');
+
+    if normalized.is_empty() {
+        None
+    } else {
+        Some(normalized.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        assert!(validate_identifier("com.example.my_program").is_ok());
+        assert!(validate_identifier("libfoo-1").is_ok());
+        assert!(validate_identifier("").is_err());
+        assert!(validate_identifier("com.example.My App").is_err());
+        assert!(validate_identifier(".foo").is_err());
+        assert!(validate_identifier("com..example").is_err());
+        assert!(validate_identifier(&"a".repeat(MAX_IDENTIFIER_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_identifier("com.example.my_program"),
+            Some("com.example.my_program".to_string())
+        );
+        assert_eq!(normalize_identifier("My App"), Some("My-App".to_string()));
+        assert_eq!(normalize_identifier(".foo"), Some("foo".to_string()));
+        assert_eq!(normalize_identifier("a..b."), Some("a.b".to_string()));
+        assert_eq!(normalize_identifier("..."), None);
+        assert_eq!(
+            normalize_identifier(&"a".repeat(300)).map(|s| s.len()),
+            Some(MAX_IDENTIFIER_LENGTH)
+        );
+    }
+}
@@ -0,0 +1,558 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Mach-O install names and run paths.
+
+Mach-O binaries reference the dynamic libraries they link against by their
+*install name*. Install names are often absolute paths on the machine that
+built the library, which don't exist on other machines. Libraries bundled with
+an application are instead referenced relative to a run path (`@rpath/...`),
+with the run paths of the binary (`LC_RPATH`) resolving to the directory
+holding the libraries (e.g. `@executable_path/../Frameworks`).
+
+This module rewrites install names and run paths, like `install_name_tool`.
+Load commands are rewritten in place: the binary is never grown, so new load
+commands must fit in the padding between the existing load commands and the
+first section. Linking with `-headerpad_max_install_names` reserves space for
+this.
+
+Rewriting load commands invalidates existing code signatures. Binaries must
+be signed after being modified.
+*/
+
+use {
+    crate::macho_ranges,
+    anyhow::{anyhow, Result},
+    std::collections::BTreeMap,
+};
+
+const LC_REQ_DYLD: u32 = 0x80000000;
+const LC_SEGMENT: u32 = 0x1;
+const LC_LOAD_DYLIB: u32 = 0xc;
+const LC_ID_DYLIB: u32 = 0xd;
+const LC_LOAD_WEAK_DYLIB: u32 = 0x18 | LC_REQ_DYLD;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_RPATH: u32 = 0x1c | LC_REQ_DYLD;
+const LC_REEXPORT_DYLIB: u32 = 0x1f | LC_REQ_DYLD;
+const LC_LAZY_LOAD_DYLIB: u32 = 0x20;
+const LC_LOAD_UPWARD_DYLIB: u32 = 0x23 | LC_REQ_DYLD;
+
+/// Size of the fixed fields of a `dylib_command`.
+const DYLIB_COMMAND_SIZE: usize = 24;
+
+/// Size of the fixed fields of a `rpath_command`.
+const RPATH_COMMAND_SIZE: usize = 12;
+
+/// Whether a load command references a dynamic library to load.
+fn is_dylib_reference(cmd: u32) -> bool {
+    matches!(
+        cmd,
+        LC_LOAD_DYLIB
+            | LC_LOAD_WEAK_DYLIB
+            | LC_REEXPORT_DYLIB
+            | LC_LAZY_LOAD_DYLIB
+            | LC_LOAD_UPWARD_DYLIB
+    )
+}
+
+/// The header of a single architecture Mach-O binary.
+struct MachHeader {
+    big_endian: bool,
+    is_64: bool,
+    load_commands: Vec<Vec<u8>>,
+}
+
+impl MachHeader {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let magic = data
+            .get(0..4)
+            .ok_or_else(|| anyhow!("data too short to be a Mach-O binary"))?;
+
+        let (big_endian, is_64) = match u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]])
+        {
+            0xfeedface => (false, false),
+            0xfeedfacf => (false, true),
+            0xcefaedfe => (true, false),
+            0xcffaedfe => (true, true),
+            _ => return Err(anyhow!("data is not a single architecture Mach-O binary")),
+        };
+
+        let mut header = Self {
+            big_endian,
+            is_64,
+            load_commands: vec![],
+        };
+
+        let ncmds = header.read_u32(data, 16)?;
+        let mut offset = header.header_size();
+
+        for i in 0..ncmds {
+            let size = header.read_u32(data, offset + 4)? as usize;
+            if size < 8 {
+                return Err(anyhow!("load command {} has invalid size {}", i, size));
+            }
+
+            let command = data
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow!("load command {} is truncated", i))?;
+
+            header.load_commands.push(command.to_vec());
+            offset += size;
+        }
+
+        Ok(header)
+    }
+
+    fn header_size(&self) -> usize {
+        if self.is_64 {
+            32
+        } else {
+            28
+        }
+    }
+
+    fn read_u32(&self, data: &[u8], offset: usize) -> Result<u32> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("Mach-O data is truncated"))?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_u64(&self, data: &[u8], offset: usize) -> Result<u64> {
+        let low = self.read_u32(data, offset)? as u64;
+        let high = self.read_u32(data, offset + 4)? as u64;
+
+        Ok(if self.big_endian {
+            (low << 32) | high
+        } else {
+            (high << 32) | low
+        })
+    }
+
+    fn write_u32(&self, data: &mut [u8], offset: usize, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+
+        data[offset..offset + 4].copy_from_slice(&bytes);
+    }
+
+    fn command_type(&self, command: &[u8]) -> u32 {
+        // Commands are at least 8 bytes, as validated by parse().
+        self.read_u32(command, 0).unwrap_or_default()
+    }
+
+    /// Read the string referenced by the `lc_str` at `offset` in a command.
+    fn command_string(&self, command: &[u8], offset: usize) -> Result<String> {
+        let start = self.read_u32(command, offset)? as usize;
+        let value = command
+            .get(start..)
+            .ok_or_else(|| anyhow!("load command string is out of bounds"))?;
+        let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+
+        Ok(String::from_utf8_lossy(&value[..end]).to_string())
+    }
+
+    /// Construct a command from the fixed fields of `command` and a string.
+    fn command_with_string(&self, command: &[u8], fixed_size: usize, value: &str) -> Vec<u8> {
+        let align = if self.is_64 { 8 } else { 4 };
+
+        let mut data = command[0..fixed_size].to_vec();
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+        data.resize(data.len().div_ceil(align) * align, 0);
+
+        let size = data.len() as u32;
+        self.write_u32(&mut data, 4, size);
+        self.write_u32(&mut data, 8, fixed_size as u32);
+
+        data
+    }
+
+    /// The offset of the first file content following the load commands.
+    ///
+    /// This is the limit load commands can grow to.
+    fn load_commands_limit(&self, data_len: usize) -> Result<usize> {
+        let mut limit = data_len;
+
+        for command in &self.load_commands {
+            let (sections_offset, section_size, nsects_offset, offset_field, fileoff, filesize) =
+                match self.command_type(command) {
+                    LC_SEGMENT_64 => (
+                        72,
+                        80,
+                        64,
+                        48,
+                        self.read_u64(command, 40)?,
+                        self.read_u64(command, 48)?,
+                    ),
+                    LC_SEGMENT => (
+                        56,
+                        68,
+                        48,
+                        40,
+                        self.read_u32(command, 32)? as u64,
+                        self.read_u32(command, 36)? as u64,
+                    ),
+                    _ => continue,
+                };
+
+            if fileoff > 0 && filesize > 0 {
+                limit = limit.min(fileoff as usize);
+            }
+
+            for i in 0..self.read_u32(command, nsects_offset)? as usize {
+                let offset =
+                    self.read_u32(command, sections_offset + i * section_size + offset_field)?;
+
+                if offset > 0 {
+                    limit = limit.min(offset as usize);
+                }
+            }
+        }
+
+        Ok(limit)
+    }
+}
+
+/// Resolve the headers of all binaries in single architecture or universal Mach-O data.
+fn headers(data: &[u8]) -> Result<Vec<MachHeader>> {
+    macho_ranges(data)?
+        .into_iter()
+        .map(|(_, range)| MachHeader::parse(&data[range]))
+        .collect()
+}
+
+/// Resolve strings from load commands of all binaries in Mach-O data.
+///
+/// Values are de-duplicated across the binaries of universal binaries.
+fn command_strings(data: &[u8], filter: impl Fn(u32) -> bool) -> Result<Vec<String>> {
+    let mut values: Vec<String> = vec![];
+
+    for header in headers(data)? {
+        for command in &header.load_commands {
+            if filter(header.command_type(command)) {
+                let value = header.command_string(command, 8)?;
+
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+/// Resolve the install name (`LC_ID_DYLIB`) of a Mach-O dynamic library.
+///
+/// Returns `None` if the binary doesn't define an install name, which is the
+/// case for executables.
+pub fn macho_install_name(data: &[u8]) -> Result<Option<String>> {
+    Ok(command_strings(data, |cmd| cmd == LC_ID_DYLIB)?
+        .into_iter()
+        .next())
+}
+
+/// Resolve the install names of dynamic libraries referenced by a Mach-O binary.
+pub fn macho_dylib_references(data: &[u8]) -> Result<Vec<String>> {
+    command_strings(data, is_dylib_reference)
+}
+
+/// Resolve the run paths (`LC_RPATH`) of a Mach-O binary.
+pub fn macho_rpaths(data: &[u8]) -> Result<Vec<String>> {
+    command_strings(data, |cmd| cmd == LC_RPATH)
+}
+
+/// Changes to install names and run paths of a Mach-O binary.
+///
+/// This is the equivalent of the arguments to `install_name_tool`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InstallNameChanges {
+    id: Option<String>,
+    dylibs: BTreeMap<String, String>,
+    rpaths: BTreeMap<String, String>,
+    add_rpaths: Vec<String>,
+}
+
+impl InstallNameChanges {
+    /// Whether no changes are defined.
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none()
+            && self.dylibs.is_empty()
+            && self.rpaths.is_empty()
+            && self.add_rpaths.is_empty()
+    }
+
+    /// Set the install name of a dynamic library (`-id`).
+    ///
+    /// Binaries without an install name are unchanged.
+    pub fn set_id(&mut self, name: impl ToString) {
+        self.id = Some(name.to_string());
+    }
+
+    /// Change a referenced dynamic library install name (`-change`).
+    pub fn change_dylib(&mut self, old: impl ToString, new: impl ToString) {
+        self.dylibs.insert(old.to_string(), new.to_string());
+    }
+
+    /// Change a run path (`-rpath`).
+    pub fn change_rpath(&mut self, old: impl ToString, new: impl ToString) {
+        self.rpaths.insert(old.to_string(), new.to_string());
+    }
+
+    /// Add a run path (`-add_rpath`).
+    ///
+    /// Run paths already present in a binary aren't added again.
+    pub fn add_rpath(&mut self, path: impl ToString) {
+        let path = path.to_string();
+
+        if !self.add_rpaths.contains(&path) {
+            self.add_rpaths.push(path);
+        }
+    }
+
+    /// Apply changes to Mach-O data, returning the new data.
+    ///
+    /// Universal binaries have changes applied to each binary they contain.
+    /// The size of the data never changes. It is an error if the rewritten load
+    /// commands don't fit in the space available in the header.
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut result = data.to_vec();
+
+        for (_, range) in macho_ranges(data)? {
+            self.apply_thin(&mut result[range])?;
+        }
+
+        Ok(result)
+    }
+
+    fn apply_thin(&self, data: &mut [u8]) -> Result<()> {
+        let header = MachHeader::parse(data)?;
+
+        let mut commands = vec![];
+        let mut rpaths = vec![];
+
+        for command in &header.load_commands {
+            let cmd = header.command_type(command);
+
+            let new = match cmd {
+                LC_ID_DYLIB => self.id.clone(),
+                cmd if is_dylib_reference(cmd) => self
+                    .dylibs
+                    .get(&header.command_string(command, 8)?)
+                    .cloned(),
+                LC_RPATH => {
+                    let path = header.command_string(command, 8)?;
+                    let new = self.rpaths.get(&path).cloned();
+                    rpaths.push(new.clone().unwrap_or(path));
+
+                    new
+                }
+                _ => None,
+            };
+
+            commands.push(match new {
+                Some(value) if cmd == LC_RPATH => {
+                    header.command_with_string(command, RPATH_COMMAND_SIZE, &value)
+                }
+                Some(value) => header.command_with_string(command, DYLIB_COMMAND_SIZE, &value),
+                None => command.clone(),
+            });
+        }
+
+        for path in &self.add_rpaths {
+            if !rpaths.contains(path) {
+                let mut command = vec![0; RPATH_COMMAND_SIZE];
+                header.write_u32(&mut command, 0, LC_RPATH);
+
+                commands.push(header.command_with_string(&command, RPATH_COMMAND_SIZE, path));
+            }
+        }
+
+        let old_size = header.load_commands.iter().map(|c| c.len()).sum::<usize>();
+        let new_size = commands.iter().map(|c| c.len()).sum::<usize>();
+
+        let start = header.header_size();
+        let limit = header.load_commands_limit(data.len())?;
+
+        if start + new_size > limit {
+            return Err(anyhow!(
+                "insufficient space in Mach-O header for load commands ({} bytes needed; {} available); relink with -headerpad_max_install_names",
+                new_size,
+                limit.saturating_sub(start)
+            ));
+        }
+
+        let end = start + old_size.max(new_size);
+        data[start..end].fill(0);
+
+        let mut offset = start;
+        for command in &commands {
+            data[offset..offset + command.len()].copy_from_slice(command);
+            offset += command.len();
+        }
+
+        header.write_u32(data, 16, commands.len() as u32);
+        header.write_u32(data, 20, new_size as u32);
+
+        Ok(())
+    }
+}
+
+/// Construction of Mach-O binaries for tests.
+#[cfg(test)]
+pub(crate) mod testutil {
+    use super::*;
+
+    /// Construct a load command holding a string after its fixed fields.
+    pub fn command(cmd: u32, fixed_size: usize, value: &str) -> Vec<u8> {
+        let mut data = cmd.to_le_bytes().to_vec();
+        data.resize(fixed_size, 0);
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+        data.resize(data.len().div_ceil(8) * 8, 0);
+
+        let size = data.len() as u32;
+        data[4..8].copy_from_slice(&size.to_le_bytes());
+        data[8..12].copy_from_slice(&(fixed_size as u32).to_le_bytes());
+
+        data
+    }
+
+    /// Construct a 64-bit Mach-O with content starting at offset 512.
+    pub fn macho(cpu_type: u32, commands: &[Vec<u8>]) -> Vec<u8> {
+        let mut segment = LC_SEGMENT_64.to_le_bytes().to_vec();
+        segment.extend_from_slice(&72u32.to_le_bytes());
+        segment.resize(40, 0);
+        segment.extend_from_slice(&512u64.to_le_bytes());
+        segment.extend_from_slice(&64u64.to_le_bytes());
+        segment.resize(72, 0);
+
+        let mut commands = commands.to_vec();
+        commands.insert(0, segment);
+
+        let mut data = 0xfeedfacfu32.to_le_bytes().to_vec();
+        data.extend_from_slice(&cpu_type.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&(commands.len() as u32).to_le_bytes());
+        data.extend_from_slice(
+            &(commands.iter().map(|c| c.len()).sum::<usize>() as u32).to_le_bytes(),
+        );
+        data.extend_from_slice(&[0; 8]);
+        for command in commands {
+            data.extend_from_slice(&command);
+        }
+        data.resize(512, 0);
+        data.extend_from_slice(&[0xff; 64]);
+
+        data
+    }
+
+    /// Construct an arm64 Mach-O having an install name and dependencies.
+    pub fn dylib(id: Option<&str>, dependencies: &[&str]) -> Vec<u8> {
+        let commands = id
+            .map(|id| command(LC_ID_DYLIB, DYLIB_COMMAND_SIZE, id))
+            .into_iter()
+            .chain(
+                dependencies
+                    .iter()
+                    .map(|name| command(LC_LOAD_DYLIB, DYLIB_COMMAND_SIZE, name)),
+            )
+            .collect::<Vec<_>>();
+
+        macho(crate::CPU_TYPE_ARM64, &commands)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{testutil::*, *},
+        crate::create_universal_binary,
+    };
+
+    #[test]
+    fn rewrite() -> Result<()> {
+        let lib = macho(
+            crate::CPU_TYPE_ARM64,
+            &[
+                command(
+                    LC_ID_DYLIB,
+                    DYLIB_COMMAND_SIZE,
+                    "/usr/local/lib/libfoo.dylib",
+                ),
+                command(
+                    LC_LOAD_DYLIB,
+                    DYLIB_COMMAND_SIZE,
+                    "/usr/local/lib/libbar.dylib",
+                ),
+                command(
+                    LC_LOAD_DYLIB,
+                    DYLIB_COMMAND_SIZE,
+                    "/usr/lib/libSystem.B.dylib",
+                ),
+                command(LC_RPATH, RPATH_COMMAND_SIZE, "/usr/local/lib"),
+            ],
+        );
+
+        assert_eq!(
+            macho_install_name(&lib)?,
+            Some("/usr/local/lib/libfoo.dylib".to_string())
+        );
+        assert_eq!(
+            macho_dylib_references(&lib)?,
+            vec!["/usr/local/lib/libbar.dylib", "/usr/lib/libSystem.B.dylib"]
+        );
+        assert_eq!(macho_rpaths(&lib)?, vec!["/usr/local/lib"]);
+
+        let mut changes = InstallNameChanges::default();
+        assert!(changes.is_empty());
+        changes.set_id("@rpath/libfoo.dylib");
+        changes.change_dylib("/usr/local/lib/libbar.dylib", "@rpath/libbar.dylib");
+        changes.change_rpath("/usr/local/lib", "@loader_path");
+        changes.add_rpath("@loader_path");
+        changes.add_rpath("@executable_path/../Frameworks");
+
+        let fat = create_universal_binary([lib.clone(), macho(crate::CPU_TYPE_X86_64, &[])])?;
+        let fat = changes.apply(&fat)?;
+        assert_eq!(
+            macho_rpaths(&fat)?,
+            vec!["@loader_path", "@executable_path/../Frameworks"]
+        );
+
+        let new = changes.apply(&lib)?;
+        assert_eq!(new.len(), lib.len());
+        assert_eq!(&new[512..], &lib[512..]);
+        assert_eq!(
+            macho_install_name(&new)?,
+            Some("@rpath/libfoo.dylib".to_string())
+        );
+        assert_eq!(
+            macho_dylib_references(&new)?,
+            vec!["@rpath/libbar.dylib", "/usr/lib/libSystem.B.dylib"]
+        );
+        assert_eq!(
+            macho_rpaths(&new)?,
+            vec!["@loader_path", "@executable_path/../Frameworks"]
+        );
+
+        // Changes are idempotent.
+        assert_eq!(changes.apply(&new)?, new);
+
+        let mut changes = InstallNameChanges::default();
+        changes.add_rpath("/".repeat(512));
+        assert!(changes.apply(&lib).is_err());
+
+        Ok(())
+    }
+}
@@ -4,8 +4,20 @@
 
 mod directory_bundle;
 pub use directory_bundle::*;
+mod icns;
+pub use icns::*;
+mod identifier;
+pub use identifier::*;
+mod install_name;
+pub use install_name::*;
+mod localization;
+pub use localization::*;
 mod macos_application_bundle;
 pub use macos_application_bundle::*;
+mod privacy_manifest;
+pub use privacy_manifest::*;
+mod universal_binary;
+pub use universal_binary::*;
 
 /// Denotes the type of a bundle.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Bundle localization primitives.
+
+Localized bundle resources live in `<locale>.lproj` directories in the
+bundle's resources directory. The `CFBundleLocalizations` key in the
+`Info.plist` declares which localizations a bundle supports. The
+`InfoPlist.strings` file within each `.lproj` directory holds localized
+values of `Info.plist` keys.
+*/
+
+use {
+    anyhow::{anyhow, Result},
+    std::collections::BTreeMap,
+};
+
+/// Suffix of directories holding localized resources.
+pub const LPROJ_SUFFIX: &str = ".lproj";
+
+/// Name of the `.lproj` directory holding resources for the development region.
+///
+/// `Base` isn't a real localization, so it is never expected in `CFBundleLocalizations`.
+pub const BASE_LOCALIZATION: &str = "Base";
+
+/// Resolve the locale name from a `.lproj` directory name.
+///
+/// Returns `None` if the name doesn't have the `.lproj` suffix.
+pub fn locale_from_lproj_name(name: &str) -> Option<&str> {
+    name.strip_suffix(LPROJ_SUFFIX).filter(|x| !x.is_empty())
+}
+
+/// Encode key-value pairs into the content of a `.strings` file.
+///
+/// The file is encoded as UTF-8, which Apple's tooling accepts.
+pub fn encode_strings_file(strings: &BTreeMap<String, String>) -> Vec<u8> {
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    strings
+        .iter()
+        .map(|(k, v)| format!("\"{}\" = \"{}\";\n", escape(k), escape(v)))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Describes disagreements between declared and present localizations.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LocalizationDifferences {
+    /// Localizations in `CFBundleLocalizations` without a `.lproj` directory.
+    pub declared_missing: Vec<String>,
+
+    /// `.lproj` directories not declared in `CFBundleLocalizations`.
+    pub present_undeclared: Vec<String>,
+}
+
+impl LocalizationDifferences {
+    /// Compare declared localizations against present `.lproj` directories.
+    pub fn new(declared: &[String], present: &[String]) -> Self {
+        Self {
+            declared_missing: declared
+                .iter()
+                .filter(|x| !present.contains(x))
+                .cloned()
+                .collect::<Vec<_>>(),
+            present_undeclared: present
+                .iter()
+                .filter(|x| x.as_str() != BASE_LOCALIZATION && !declared.contains(x))
+                .cloned()
+                .collect::<Vec<_>>(),
+        }
+    }
+
+    /// Whether declared and present localizations agree.
+    pub fn is_empty(&self) -> bool {
+        self.declared_missing.is_empty() && self.present_undeclared.is_empty()
+    }
+}
+
+/// Resolve the `CFBundleLocalizations` values from an `Info.plist` dictionary.
+///
+/// Returns `None` if the key isn't defined.
+pub(crate) fn declared_localizations(
+    info_plist: &plist::Dictionary,
+) -> Result<Option<Vec<String>>> {
+    if let Some(value) = info_plist.get("CFBundleLocalizations") {
+        let values = value
+            .as_array()
+            .ok_or_else(|| anyhow!("CFBundleLocalizations not an array"))?;
+
+        Ok(Some(
+            values
+                .iter()
+                .map(|x| {
+                    Ok(x.as_string()
+                        .ok_or_else(|| anyhow!("CFBundleLocalizations value not a string"))?
+                        .to_string())
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strings_file() {
+        let mut strings = BTreeMap::new();
+        strings.insert("CFBundleName".to_string(), "Mon \"App\"".to_string());
+        strings.insert("NSHumanReadableCopyright".to_string(), "a\nb".to_string());
+
+        assert_eq!(
+            String::from_utf8(encode_strings_file(&strings)).unwrap(),
+            "\"CFBundleName\" = \"Mon \\\"App\\\"\";\n\"NSHumanReadableCopyright\" = \"a\\nb\";\n"
+        );
+    }
+
+    #[test]
+    fn differences() {
+        let declared = vec!["en".to_string(), "fr".to_string()];
+        let present = vec!["Base".to_string(), "de".to_string(), "en".to_string()];
+
+        let diff = LocalizationDifferences::new(&declared, &present);
+        assert_eq!(diff.declared_missing, vec!["fr".to_string()]);
+        assert_eq!(diff.present_undeclared, vec!["de".to_string()]);
+        assert!(!diff.is_empty());
+
+        assert!(LocalizationDifferences::new(&declared, &declared).is_empty());
+        assert_eq!(locale_from_lproj_name("en.lproj"), Some("en"));
+        assert_eq!(locale_from_lproj_name(".lproj"), None);
+        assert_eq!(locale_from_lproj_name("Resources"), None);
+    }
+}
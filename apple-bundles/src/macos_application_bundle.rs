@@ -9,10 +9,18 @@ for documentation of the macOS Application Bundle format.
 */
 
 use {
-    crate::BundlePackageType,
+    crate::{
+        create_icns, create_universal_binary, declared_localizations, encode_strings_file,
+        is_macho, locale_from_lproj_name, macho_dylib_references, macho_install_name, macho_rpaths,
+        validate_identifier, BundlePackageType, DirectoryBundle, InstallNameChanges,
+        LocalizationDifferences, PrivacyManifest, BASE_LOCALIZATION, PRIVACY_MANIFEST_FILENAME,
+    },
     anyhow::{anyhow, Context, Result},
-    simple_file_manifest::{FileEntry, FileManifest, FileManifestError},
-    std::path::{Path, PathBuf},
+    simple_file_manifest::{is_executable, FileEntry, FileManifest, FileManifestError},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        path::{Path, PathBuf},
+    },
 };
 
 /// Primitive used to iteratively construct a macOS Application Bundle.
@@ -63,6 +71,12 @@ use {
 pub struct MacOsApplicationBundleBuilder {
     /// Files constituting the application bundle.
     files: FileManifest,
+
+    /// Original install names of embedded libraries to their new install names.
+    install_names: BTreeMap<String, String>,
+
+    /// Paths of embedded libraries, relative to `Contents/Frameworks/`.
+    libraries: BTreeSet<PathBuf>,
 }
 
 impl MacOsApplicationBundleBuilder {
@@ -73,6 +87,8 @@ impl MacOsApplicationBundleBuilder {
     pub fn new(bundle_name: impl ToString) -> Result<Self> {
         let mut instance = Self {
             files: FileManifest::default(),
+            install_names: BTreeMap::new(),
+            libraries: BTreeSet::new(),
         };
 
         instance
@@ -147,6 +163,24 @@ impl MacOsApplicationBundleBuilder {
         self.files.add_file_entry(path, entry)
     }
 
+    /// Add a universal binary combining Mach-O binaries for multiple architectures.
+    ///
+    /// This is the equivalent of `lipo -create`. Each input is a single
+    /// architecture Mach-O binary (e.g. for x86_64 or aarch64) or a universal
+    /// binary. The combined binary is added as an executable file at `path`.
+    pub fn add_universal_binary(
+        &mut self,
+        path: impl AsRef<Path>,
+        binaries: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+
+        let data = create_universal_binary(binaries)
+            .with_context(|| format!("creating universal binary {}", path.display()))?;
+
+        Ok(self.add_file(path, FileEntry::new_from_data(data, true))?)
+    }
+
     /// Set the content of `Contents/Info.plist` using a `plist::Dictionary`.
     ///
     /// This allows you to define the `Info.plist` file with some validation
@@ -165,6 +199,31 @@ impl MacOsApplicationBundleBuilder {
         Ok(self.add_file("Contents/Info.plist", data)?)
     }
 
+    /// Set the content of `Contents/Info.plist` from plist data.
+    ///
+    /// The data can be in any plist format (XML, binary, etc). It must define a
+    /// dictionary. It is normalized to XML when stored.
+    pub fn set_info_plist_from_data(&mut self, data: &[u8]) -> Result<()> {
+        let value = plist::Value::from_reader(std::io::Cursor::new(data))
+            .context("parsing Info.plist data")?;
+
+        let dict = value
+            .into_dictionary()
+            .ok_or_else(|| anyhow!("Info.plist data is not a dictionary"))?;
+
+        self.set_info_plist_from_dictionary(dict)
+    }
+
+    /// Set the content of `Contents/Info.plist` from a plist file.
+    ///
+    /// See [Self::set_info_plist_from_data()] for semantics.
+    pub fn set_info_plist_from_path(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+
+        self.set_info_plist_from_data(&data)
+    }
+
     /// Obtain the value of a key in the `Contents/Info.plist` file.
     ///
     /// Returns `Some(Value)` if the key exists, `None` otherwise.
@@ -205,12 +264,31 @@ impl MacOsApplicationBundleBuilder {
         Ok(old)
     }
 
+    /// Remove a key from the `Contents/Info.plist` file.
+    ///
+    /// Returns the removed value, if the key was present.
+    pub fn remove_info_plist_key(&mut self, key: &str) -> Result<Option<plist::Value>> {
+        let Some(mut dict) = self.info_plist().context("retrieving Info.plist")? else {
+            return Ok(None);
+        };
+
+        let old = dict.remove(key);
+
+        if old.is_some() {
+            self.set_info_plist_from_dictionary(dict)
+                .context("replacing Info.plist dictionary")?;
+        }
+
+        Ok(old)
+    }
+
     /// Defines required keys in the `Contents/Info.plist` file.
     ///
     /// The following keys are set:
     ///
     /// `display_name` sets `CFBundleDisplayName`, the bundle display name.
-    /// `identifier` sets `CFBundleIdentifier`, the bundle identifier.
+    /// `identifier` sets `CFBundleIdentifier`, the bundle identifier. It must pass
+    /// [validate_identifier].
     /// `version` sets `CFBundleVersion`, the bundle version string.
     /// `signature` sets `CFBundleSignature`, the bundle creator OS type code.
     /// `executable` sets `CFBundleExecutable`, the name of the main executable file.
@@ -231,9 +309,12 @@ impl MacOsApplicationBundleBuilder {
             ));
         }
 
+        let identifier = identifier.to_string();
+        validate_identifier(&identifier).context("validating CFBundleIdentifier")?;
+
         self.set_info_plist_key("CFBundleDisplayName", display_name.to_string())
             .context("setting CFBundleDisplayName")?;
-        self.set_info_plist_key("CFBundleIdentifier", identifier.to_string())
+        self.set_info_plist_key("CFBundleIdentifier", identifier)
             .context("setting CFBundleIdentifier")?;
         self.set_info_plist_key("CFBundleVersion", version.to_string())
             .context("setting CFBundleVersion")?;
@@ -259,6 +340,24 @@ impl MacOsApplicationBundleBuilder {
         )?)
     }
 
+    /// Add the icon for the bundle from PNG images.
+    ///
+    /// The images are combined into a multi-resolution
+    /// `Contents/Resources/<BundleName>.icns` file, as described by
+    /// `create_icns()`. `CFBundleIconFile` is set to reference it.
+    pub fn add_icon_from_pngs(
+        &mut self,
+        images: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<()> {
+        self.add_icon(create_icns(images)?)?;
+
+        let bundle_name = self.bundle_name().context("resolving bundle name")?;
+        self.set_info_plist_key("CFBundleIconFile", bundle_name)
+            .context("setting CFBundleIconFile")?;
+
+        Ok(())
+    }
+
     /// Add a file to the `Contents/MacOS/` directory.
     ///
     /// The passed path will be prefixed with `Contents/MacOS/`.
@@ -270,6 +369,18 @@ impl MacOsApplicationBundleBuilder {
         self.add_file(PathBuf::from("Contents/MacOS").join(path), entry)
     }
 
+    /// Add a universal binary to the `Contents/MacOS/` directory.
+    ///
+    /// This is typically used for the main executable. See
+    /// `add_universal_binary()` for the handling of `binaries`.
+    pub fn add_universal_binary_macos(
+        &mut self,
+        path: impl AsRef<Path>,
+        binaries: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<()> {
+        self.add_universal_binary(PathBuf::from("Contents/MacOS").join(path), binaries)
+    }
+
     /// Add a file to the `Contents/Resources/` directory.
     ///
     /// The passed path will be prefixed with `Contents/Resources/`
@@ -297,6 +408,103 @@ impl MacOsApplicationBundleBuilder {
         )
     }
 
+    /// Add the content of a directory as localized resources.
+    ///
+    /// All regular files under `source_dir` are added to the `<locale>.lproj`
+    /// directory, preserving their relative paths. Files already present in the
+    /// `.lproj` directory are retained unless replaced by a file having the same
+    /// path. So calling this multiple times for the same locale merges directories.
+    pub fn add_lproj_directory(
+        &mut self,
+        locale: impl ToString,
+        source_dir: impl AsRef<Path>,
+    ) -> Result<()> {
+        let locale = locale.to_string();
+        let source_dir = source_dir.as_ref();
+
+        for entry in walkdir::WalkDir::new(source_dir).sort_by_file_name() {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let rel_path = entry.path().strip_prefix(source_dir)?;
+            let metadata = entry.metadata()?;
+
+            self.add_localized_resources_file(
+                &locale,
+                rel_path,
+                FileEntry::new_from_path(entry.path(), is_executable(&metadata)),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Define the `InfoPlist.strings` file for a locale.
+    ///
+    /// This file holds localized values of `Info.plist` keys. An existing
+    /// file for the locale is replaced.
+    pub fn set_info_plist_strings(
+        &mut self,
+        locale: impl ToString,
+        strings: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        Ok(self.add_localized_resources_file(
+            locale,
+            "InfoPlist.strings",
+            encode_strings_file(strings),
+        )?)
+    }
+
+    /// Obtain the locales having a `.lproj` directory in this bundle.
+    ///
+    /// Results are sorted and include `Base` if present.
+    pub fn localizations(&self) -> Vec<String> {
+        self.files
+            .iter_entries()
+            .filter_map(|(path, _)| {
+                let mut components = path.strip_prefix("Contents/Resources").ok()?.components();
+                let dir = components.next()?.as_os_str().to_string_lossy();
+
+                // The entry must be a file inside the `.lproj` directory.
+                components.next()?;
+
+                locale_from_lproj_name(&dir).map(|x| x.to_string())
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+    }
+
+    /// Set `CFBundleLocalizations` from the `.lproj` directories present.
+    ///
+    /// `Base` is not a real localization and is excluded.
+    pub fn set_bundle_localizations_from_resources(&mut self) -> Result<()> {
+        let locales = self
+            .localizations()
+            .into_iter()
+            .filter(|x| x != BASE_LOCALIZATION)
+            .map(plist::Value::from)
+            .collect::<Vec<_>>();
+
+        self.set_info_plist_key("CFBundleLocalizations", locales)?;
+
+        Ok(())
+    }
+
+    /// Compare `CFBundleLocalizations` against the `.lproj` directories present.
+    pub fn localization_differences(&self) -> Result<LocalizationDifferences> {
+        let declared =
+            declared_localizations(&self.info_plist()?.unwrap_or_default())?.unwrap_or_default();
+
+        Ok(LocalizationDifferences::new(
+            &declared,
+            &self.localizations(),
+        ))
+    }
+
     /// Add a file to the `Contents/Frameworks/` directory.
     ///
     /// The passed path will be prefixed with `Contents/Frameworks/`.
@@ -308,6 +516,247 @@ impl MacOsApplicationBundleBuilder {
         self.add_file(PathBuf::from("Contents/Frameworks").join(path), entry)
     }
 
+    /// Add a universal binary to the `Contents/Frameworks/` directory.
+    ///
+    /// This is typically used for embedded dylibs. See
+    /// `add_universal_binary()` for the handling of `binaries`.
+    pub fn add_universal_binary_frameworks(
+        &mut self,
+        path: impl AsRef<Path>,
+        binaries: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<()> {
+        self.add_universal_binary(PathBuf::from("Contents/Frameworks").join(path), binaries)
+    }
+
+    /// Embed a dynamic library in the `Contents/Frameworks/` directory.
+    ///
+    /// The passed path will be prefixed with `Contents/Frameworks/`. The
+    /// install name of the library is changed to `@rpath/<path>` and references
+    /// to its original install name from Mach-O binaries in `Contents/MacOS/`
+    /// and `Contents/Frameworks/` are changed to match. The main executable
+    /// gains an `@executable_path/../Frameworks` run path so the library can be
+    /// found. It is named by `CFBundleExecutable`, or `CFBundleName` if that
+    /// isn't set. Other binaries are only rewritten if they reference a changed
+    /// install name.
+    ///
+    /// Only binaries already in the bundle are rewritten, so the main
+    /// executable should be added first. Rewriting binaries invalidates their
+    /// code signatures: the bundle must be signed after it is materialized.
+    pub fn add_dylib(&mut self, path: impl AsRef<Path>, entry: impl Into<FileEntry>) -> Result<()> {
+        let path = path.as_ref();
+        let entry = entry.into();
+
+        self.register_library(path, &entry.resolve_content()?)?;
+        self.add_file_frameworks(path, entry)?;
+
+        self.rewrite_install_names()
+    }
+
+    /// Embed a framework in the `Contents/Frameworks/` directory.
+    ///
+    /// `path` is the path to a `.framework` directory on the filesystem. All
+    /// its files, including symlinks, are copied to
+    /// `Contents/Frameworks/<name>.framework`.
+    ///
+    /// Install names of the framework's libraries are rewritten as described
+    /// by `add_dylib()`.
+    pub fn add_framework(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let bundle = DirectoryBundle::new_from_path(path.as_ref())?;
+
+        if bundle.package_type() != BundlePackageType::Framework {
+            return Err(anyhow!("{} is not a framework", path.as_ref().display()));
+        }
+
+        let framework_dir = PathBuf::from(bundle.name());
+
+        for file in bundle.files(true)? {
+            let dest_path = framework_dir.join(file.relative_path());
+            let entry = file.as_file_entry()?;
+
+            if entry.link_target().is_none() {
+                let data = entry.resolve_content()?;
+
+                if is_macho(&data) && macho_install_name(&data)?.is_some() {
+                    self.register_library(&dest_path, &data)?;
+                }
+            }
+
+            self.add_file_frameworks(dest_path, entry)?;
+        }
+
+        self.rewrite_install_names()
+    }
+
+    /// Record a library to embed at a path relative to `Contents/Frameworks/`.
+    fn register_library(&mut self, path: &Path, data: &[u8]) -> Result<()> {
+        let install_name = macho_install_name(data)?
+            .ok_or_else(|| anyhow!("{} is not a dynamic library", path.display()))?;
+
+        let new_name = Self::embedded_install_name(path);
+
+        if install_name != new_name {
+            self.install_names.insert(install_name, new_name);
+        }
+
+        self.libraries.insert(path.to_path_buf());
+
+        Ok(())
+    }
+
+    /// The install name of a library at a path relative to `Contents/Frameworks/`.
+    fn embedded_install_name(path: &Path) -> String {
+        let components = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+
+        format!("@rpath/{}", components.join("/"))
+    }
+
+    /// The path of the main executable, if the bundle names one.
+    fn main_executable_path(&self) -> Result<Option<PathBuf>> {
+        let name = match self.get_info_plist_key("CFBundleExecutable")? {
+            Some(value) => value.into_string(),
+            None => self
+                .get_info_plist_key("CFBundleName")?
+                .and_then(|value| value.into_string()),
+        };
+
+        Ok(name.map(|name| PathBuf::from("Contents/MacOS").join(name)))
+    }
+
+    /// Apply install name changes to Mach-O binaries in the bundle.
+    fn rewrite_install_names(&mut self) -> Result<()> {
+        let frameworks_dir = Path::new("Contents/Frameworks");
+        let executable_rpath = "@executable_path/../Frameworks";
+
+        let main_executable = self.main_executable_path()?;
+
+        let mut replacements = vec![];
+
+        for (path, entry) in self.files.iter_entries() {
+            if entry.link_target().is_some()
+                || !(path.starts_with("Contents/MacOS") || path.starts_with(frameworks_dir))
+            {
+                continue;
+            }
+
+            let data = entry.resolve_content()?;
+            if !is_macho(&data) {
+                continue;
+            }
+
+            let mut changes = InstallNameChanges::default();
+
+            let references = macho_dylib_references(&data)
+                .with_context(|| format!("resolving libraries of {}", path.display()))?;
+            for (old, new) in &self.install_names {
+                if references.contains(old) {
+                    changes.change_dylib(old, new);
+                }
+            }
+
+            if let Ok(library) = path.strip_prefix(frameworks_dir) {
+                if self.libraries.contains(library) {
+                    let name = Self::embedded_install_name(library);
+
+                    if macho_install_name(&data)?.as_ref() != Some(&name) {
+                        changes.set_id(name);
+                    }
+                }
+            }
+
+            // Libraries resolve `@rpath` using the run paths of the executable
+            // loading them as well, so they don't need their own.
+            if main_executable.as_ref() == Some(path)
+                && !macho_rpaths(&data)?.iter().any(|x| x == executable_rpath)
+            {
+                changes.add_rpath(executable_rpath);
+            }
+
+            if changes.is_empty() {
+                continue;
+            }
+
+            let new_data = changes
+                .apply(&data)
+                .with_context(|| format!("rewriting install names of {}", path.display()))?;
+
+            replacements.push((
+                path.to_path_buf(),
+                FileEntry::new_from_data(new_data, entry.is_executable()),
+            ));
+        }
+
+        for (path, entry) in replacements {
+            self.files.add_file_entry(path, entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Define the privacy manifest of the application.
+    ///
+    /// This writes `Contents/Resources/PrivacyInfo.xcprivacy`, replacing an
+    /// existing manifest.
+    pub fn set_privacy_manifest(&mut self, manifest: &PrivacyManifest) -> Result<()> {
+        Ok(self.add_file_resources(PRIVACY_MANIFEST_FILENAME, manifest.to_xml()?)?)
+    }
+
+    /// Define the privacy manifest of a framework in `Contents/Frameworks/`.
+    ///
+    /// `framework` is the name of the framework directory, e.g. `Foo.framework`.
+    /// The manifest is written next to each `Info.plist` of the framework, which
+    /// must already have been added. For versioned frameworks, this is
+    /// `Versions/<version>/Resources/`.
+    pub fn set_framework_privacy_manifest(
+        &mut self,
+        framework: impl AsRef<Path>,
+        manifest: &PrivacyManifest,
+    ) -> Result<()> {
+        let framework_dir = PathBuf::from("Contents/Frameworks").join(framework.as_ref());
+
+        let resources_dirs = self
+            .files
+            .iter_entries()
+            .filter_map(|(path, _)| {
+                let rel_path = path.strip_prefix(&framework_dir).ok()?;
+
+                if rel_path.file_name()? != "Info.plist" {
+                    return None;
+                }
+
+                let parent = rel_path.parent()?;
+                let mut components = parent.components().map(|c| c.as_os_str());
+
+                let is_resources = match (components.next(), components.next(), components.next()) {
+                    (Some(a), None, None) => a == "Resources",
+                    (Some(a), Some(_), Some(c)) => {
+                        a == "Versions" && c == "Resources" && components.next().is_none()
+                    }
+                    _ => false,
+                };
+
+                is_resources.then(|| path.parent().map(|x| x.to_path_buf()))?
+            })
+            .collect::<Vec<_>>();
+
+        if resources_dirs.is_empty() {
+            return Err(anyhow!(
+                "framework {} has no Info.plist in its Resources directory",
+                framework_dir.display()
+            ));
+        }
+
+        let data = manifest.to_xml()?;
+
+        for dir in resources_dirs {
+            self.add_file(dir.join(PRIVACY_MANIFEST_FILENAME), data.clone())?;
+        }
+
+        Ok(())
+    }
+
     /// Add a file to the `Contents/Plugins/` directory.
     ///
     /// The passed path will be prefixed with `Contents/Plugins/`.
@@ -408,6 +857,58 @@ mod tests {
             Some(&plist::Value::from("MyProgram"))
         );
 
+        assert!(builder
+            .set_info_plist_required_keys("My Program", "My Program", "0.1", "mypg", "MyProgram")
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plist_values() -> Result<()> {
+        let mut builder = MacOsApplicationBundleBuilder::new("MyProgram")?;
+
+        let mut dict = plist::Dictionary::new();
+        dict.insert("CFBundleURLName".into(), "com.example.url".into());
+        dict.insert(
+            "CFBundleURLSchemes".into(),
+            vec![plist::Value::from("myprogram")].into(),
+        );
+
+        builder.set_info_plist_key("LSUIElement", true)?;
+        builder.set_info_plist_key("MyProgramBuildNumber", 42)?;
+        builder.set_info_plist_key("CFBundleURLTypes", vec![plist::Value::from(dict.clone())])?;
+
+        let info_plist = builder.info_plist()?.unwrap();
+        assert_eq!(
+            info_plist.get("LSUIElement"),
+            Some(&plist::Value::Boolean(true))
+        );
+        assert_eq!(
+            info_plist.get("CFBundleURLTypes"),
+            Some(&plist::Value::from(vec![plist::Value::from(dict)]))
+        );
+
+        assert_eq!(
+            builder.remove_info_plist_key("LSUIElement")?,
+            Some(plist::Value::Boolean(true))
+        );
+        assert_eq!(builder.remove_info_plist_key("LSUIElement")?, None);
+
+        let mut data = vec![];
+        plist::Value::from(info_plist).to_writer_binary(&mut data)?;
+        let mut builder = MacOsApplicationBundleBuilder::new("Other")?;
+        builder.set_info_plist_from_data(&data)?;
+        assert_eq!(builder.bundle_name()?, "MyProgram");
+        assert_eq!(
+            builder.get_info_plist_key("LSUIElement")?,
+            Some(plist::Value::Boolean(true))
+        );
+
+        assert!(builder
+            .set_info_plist_from_data(b"<plist><array/></plist>")
+            .is_err());
+
         Ok(())
     }
 
@@ -424,6 +925,29 @@ mod tests {
             &PathBuf::from("Contents/Resources/MyProgram.icns")
         );
 
+        let png = |size: u32| {
+            let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+            data.extend_from_slice(&size.to_be_bytes());
+            data.extend_from_slice(&size.to_be_bytes());
+            data
+        };
+
+        builder.add_icon_from_pngs([png(16), png(32)])?;
+        assert_eq!(
+            &builder
+                .files()
+                .get("Contents/Resources/MyProgram.icns")
+                .unwrap()
+                .resolve_content()?[0..4],
+            b"icns"
+        );
+        assert_eq!(
+            builder.get_info_plist_key("CFBundleIconFile")?,
+            Some("MyProgram".into())
+        );
+
+        assert!(builder.add_icon_from_pngs([vec![42]]).is_err());
+
         Ok(())
     }
 
@@ -455,4 +979,204 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn add_universal_binary() -> Result<()> {
+        let thin = |cpu_type: u32| {
+            let mut data = 0xfeedfacfu32.to_le_bytes().to_vec();
+            data.extend_from_slice(&cpu_type.to_le_bytes());
+            data.extend_from_slice(&[0; 24]);
+            data
+        };
+
+        let mut builder = MacOsApplicationBundleBuilder::new("MyProgram")?;
+        builder.add_universal_binary_macos(
+            "MyProgram",
+            [thin(crate::CPU_TYPE_X86_64), thin(crate::CPU_TYPE_ARM64)],
+        )?;
+        builder.add_universal_binary_frameworks("libfoo.dylib", [thin(crate::CPU_TYPE_ARM64)])?;
+
+        let entry = builder.files().get("Contents/MacOS/MyProgram").unwrap();
+        assert!(entry.is_executable());
+        assert_eq!(&entry.resolve_content()?[0..4], &[0xca, 0xfe, 0xba, 0xbe]);
+        assert!(builder
+            .files()
+            .get("Contents/Frameworks/libfoo.dylib")
+            .is_some());
+
+        assert!(builder
+            .add_universal_binary_macos(
+                "Other",
+                [thin(crate::CPU_TYPE_ARM64), thin(crate::CPU_TYPE_ARM64)]
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn privacy_manifest() -> Result<()> {
+        let mut builder = MacOsApplicationBundleBuilder::new("MyProgram")?;
+
+        let mut manifest = PrivacyManifest::default();
+        manifest.add_accessed_api_type("NSPrivacyAccessedAPICategoryUserDefaults", ["CA92.1"]);
+
+        builder.set_privacy_manifest(&manifest)?;
+        assert_eq!(
+            builder
+                .files()
+                .get("Contents/Resources/PrivacyInfo.xcprivacy")
+                .unwrap()
+                .resolve_content()?,
+            manifest.to_xml()?
+        );
+
+        assert!(builder
+            .set_framework_privacy_manifest("Foo.framework", &manifest)
+            .is_err());
+
+        builder.add_file_frameworks("Foo.framework/Versions/A/Resources/Info.plist", vec![42])?;
+        builder.set_framework_privacy_manifest("Foo.framework", &manifest)?;
+        assert!(builder
+            .files()
+            .get("Contents/Frameworks/Foo.framework/Versions/A/Resources/PrivacyInfo.xcprivacy")
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn localizations() -> Result<()> {
+        let mut builder = MacOsApplicationBundleBuilder::new("MyProgram")?;
+
+        let temp = tempfile::Builder::new()
+            .prefix("apple-bundles-")
+            .tempdir()?;
+        std::fs::create_dir_all(temp.path().join("nested"))?;
+        std::fs::write(temp.path().join("Localizable.strings"), b"")?;
+        std::fs::write(temp.path().join("nested").join("image.png"), b"")?;
+
+        builder.add_lproj_directory("de", temp.path())?;
+        builder.add_localized_resources_file("Base", "Main.nib", vec![42])?;
+
+        let mut strings = BTreeMap::new();
+        strings.insert("CFBundleName".to_string(), "Mein Programm".to_string());
+        builder.set_info_plist_strings("de", &strings)?;
+        builder.set_info_plist_strings("en", &strings)?;
+
+        assert!(builder
+            .files()
+            .get("Contents/Resources/de.lproj/nested/image.png")
+            .is_some());
+        assert_eq!(
+            builder.localizations(),
+            vec!["Base".to_string(), "de".to_string(), "en".to_string()]
+        );
+
+        builder.set_info_plist_key("CFBundleLocalizations", vec![plist::Value::from("fr")])?;
+        let diff = builder.localization_differences()?;
+        assert_eq!(diff.declared_missing, vec!["fr".to_string()]);
+        assert_eq!(
+            diff.present_undeclared,
+            vec!["de".to_string(), "en".to_string()]
+        );
+
+        builder.set_bundle_localizations_from_resources()?;
+        assert!(builder.localization_differences()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn embed_libraries() -> Result<()> {
+        let macho = crate::install_name::testutil::dylib;
+
+        let temp = tempfile::Builder::new()
+            .prefix("apple-bundles-")
+            .tempdir()?;
+        let framework = temp.path().join("Bar.framework");
+        std::fs::create_dir_all(framework.join("Resources"))?;
+        plist::Value::from(plist::Dictionary::new())
+            .to_file_xml(framework.join("Resources").join("Info.plist"))?;
+        std::fs::write(
+            framework.join("Bar"),
+            macho(Some("/opt/Bar.framework/Bar"), &[]),
+        )?;
+
+        let mut builder = MacOsApplicationBundleBuilder::new("MyProgram")?;
+        builder.add_file_macos(
+            "MyProgram",
+            FileEntry::new_from_data(
+                macho(
+                    None,
+                    &["/usr/local/lib/libfoo.1.dylib", "/opt/Bar.framework/Bar"],
+                ),
+                true,
+            ),
+        )?;
+        let helper = macho(None, &["/usr/lib/libSystem.B.dylib"]);
+        builder.add_file_macos("helper", FileEntry::new_from_data(helper.clone(), true))?;
+        builder.add_dylib(
+            "libfoo.dylib",
+            macho(
+                Some("/usr/local/lib/libfoo.1.dylib"),
+                &["/opt/Bar.framework/Bar"],
+            ),
+        )?;
+        builder.add_framework(&framework)?;
+
+        let content = |path: &str| {
+            builder
+                .files()
+                .get(path)
+                .unwrap()
+                .resolve_content()
+                .unwrap()
+        };
+
+        let main = content("Contents/MacOS/MyProgram");
+        assert!(builder
+            .files()
+            .get("Contents/MacOS/MyProgram")
+            .unwrap()
+            .is_executable());
+        assert_eq!(
+            crate::macho_dylib_references(&main)?,
+            vec!["@rpath/libfoo.dylib", "@rpath/Bar.framework/Bar"]
+        );
+        assert_eq!(
+            crate::macho_rpaths(&main)?,
+            vec!["@executable_path/../Frameworks"]
+        );
+
+        let foo = content("Contents/Frameworks/libfoo.dylib");
+        assert_eq!(
+            macho_install_name(&foo)?,
+            Some("@rpath/libfoo.dylib".to_string())
+        );
+        assert_eq!(
+            crate::macho_dylib_references(&foo)?,
+            vec!["@rpath/Bar.framework/Bar"]
+        );
+        assert!(crate::macho_rpaths(&foo)?.is_empty());
+
+        let bar = content("Contents/Frameworks/Bar.framework/Bar");
+        assert_eq!(
+            macho_install_name(&bar)?,
+            Some("@rpath/Bar.framework/Bar".to_string())
+        );
+        assert!(crate::macho_rpaths(&bar)?.is_empty());
+
+        // Binaries not referencing embedded libraries are left alone.
+        assert_eq!(content("Contents/MacOS/helper"), helper);
+        assert!(builder
+            .files()
+            .get("Contents/Frameworks/Bar.framework/Resources/Info.plist")
+            .is_some());
+
+        assert!(builder.add_dylib("other", macho(None, &[])).is_err());
+        assert!(builder.add_framework(temp.path()).is_err());
+
+        Ok(())
+    }
 }
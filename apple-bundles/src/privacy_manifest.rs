@@ -0,0 +1,185 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Privacy manifests.
+
+A privacy manifest is a `PrivacyInfo.xcprivacy` property list in a bundle's
+resources directory. It declares whether the bundle tracks users, which
+domains it connects to for tracking, and why it uses APIs Apple classifies
+as *required reason* APIs.
+
+See <https://developer.apple.com/documentation/bundleresources/privacy_manifest_files>
+for Apple's documentation of the format.
+*/
+
+use {
+    anyhow::{Context, Result},
+    std::collections::{BTreeMap, BTreeSet},
+};
+
+/// Filename of a privacy manifest.
+pub const PRIVACY_MANIFEST_FILENAME: &str = "PrivacyInfo.xcprivacy";
+
+/// Describes the content of a `PrivacyInfo.xcprivacy` file.
+///
+/// Required reason API categories (e.g. `NSPrivacyAccessedAPICategoryFileTimestamp`)
+/// and reason codes (e.g. `C617.1`) are stored verbatim, as Apple regularly
+/// defines new values.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PrivacyManifest {
+    tracking: bool,
+    tracking_domains: BTreeSet<String>,
+    accessed_api_types: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl PrivacyManifest {
+    /// Whether the bundle uses data for tracking (`NSPrivacyTracking`).
+    pub fn tracking(&self) -> bool {
+        self.tracking
+    }
+
+    /// Set whether the bundle uses data for tracking (`NSPrivacyTracking`).
+    pub fn set_tracking(&mut self, value: bool) {
+        self.tracking = value;
+    }
+
+    /// Domains the bundle connects to for tracking (`NSPrivacyTrackingDomains`).
+    pub fn tracking_domains(&self) -> impl Iterator<Item = &str> {
+        self.tracking_domains.iter().map(|x| x.as_str())
+    }
+
+    /// Declare a domain the bundle connects to for tracking.
+    pub fn add_tracking_domain(&mut self, domain: impl ToString) {
+        self.tracking_domains.insert(domain.to_string());
+    }
+
+    /// Required reason API categories and the reasons they are used.
+    pub fn accessed_api_types(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.accessed_api_types
+    }
+
+    /// Declare use of a required reason API category.
+    ///
+    /// Reasons are merged with those of previous declarations of the same category.
+    pub fn add_accessed_api_type(
+        &mut self,
+        category: impl ToString,
+        reasons: impl IntoIterator<Item = impl ToString>,
+    ) {
+        self.accessed_api_types
+            .entry(category.to_string())
+            .or_default()
+            .extend(reasons.into_iter().map(|x| x.to_string()));
+    }
+
+    /// Obtain the manifest as a plist dictionary.
+    pub fn to_dictionary(&self) -> plist::Dictionary {
+        let mut dict = plist::Dictionary::new();
+
+        dict.insert("NSPrivacyTracking".into(), self.tracking.into());
+        dict.insert(
+            "NSPrivacyTrackingDomains".into(),
+            self.tracking_domains
+                .iter()
+                .map(|x| plist::Value::from(x.as_str()))
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        // Data collection isn't modeled yet. But the key is expected to be present.
+        dict.insert(
+            "NSPrivacyCollectedDataTypes".into(),
+            plist::Value::Array(vec![]),
+        );
+        dict.insert(
+            "NSPrivacyAccessedAPITypes".into(),
+            self.accessed_api_types
+                .iter()
+                .map(|(category, reasons)| {
+                    let mut entry = plist::Dictionary::new();
+                    entry.insert("NSPrivacyAccessedAPIType".into(), category.as_str().into());
+                    entry.insert(
+                        "NSPrivacyAccessedAPITypeReasons".into(),
+                        reasons
+                            .iter()
+                            .map(|x| plist::Value::from(x.as_str()))
+                            .collect::<Vec<_>>()
+                            .into(),
+                    );
+
+                    plist::Value::from(entry)
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        );
+
+        dict
+    }
+
+    /// Serialize the manifest to XML plist data.
+    pub fn to_xml(&self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+
+        plist::Value::from(self.to_dictionary())
+            .to_writer_xml(&mut data)
+            .context("serializing privacy manifest to XML")?;
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_dictionary() -> Result<()> {
+        let mut manifest = PrivacyManifest::default();
+        manifest.set_tracking(true);
+        manifest.add_tracking_domain("tracker.example.com");
+        manifest.add_accessed_api_type("NSPrivacyAccessedAPICategoryUserDefaults", ["CA92.1"]);
+        manifest.add_accessed_api_type(
+            "NSPrivacyAccessedAPICategoryFileTimestamp",
+            ["C617.1", "3B52.1"],
+        );
+        manifest.add_accessed_api_type("NSPrivacyAccessedAPICategoryFileTimestamp", ["C617.1"]);
+
+        let dict = manifest.to_dictionary();
+        assert_eq!(
+            dict.get("NSPrivacyTracking"),
+            Some(&plist::Value::Boolean(true))
+        );
+        assert_eq!(
+            dict.get("NSPrivacyTrackingDomains"),
+            Some(&plist::Value::from(vec![plist::Value::from(
+                "tracker.example.com"
+            )]))
+        );
+
+        let apis = dict
+            .get("NSPrivacyAccessedAPITypes")
+            .and_then(|x| x.as_array())
+            .unwrap();
+        assert_eq!(apis.len(), 2);
+
+        let api = apis[0].as_dictionary().unwrap();
+        assert_eq!(
+            api.get("NSPrivacyAccessedAPIType"),
+            Some(&plist::Value::from(
+                "NSPrivacyAccessedAPICategoryFileTimestamp"
+            ))
+        );
+        assert_eq!(
+            api.get("NSPrivacyAccessedAPITypeReasons"),
+            Some(&plist::Value::from(vec![
+                plist::Value::from("3B52.1"),
+                plist::Value::from("C617.1"),
+            ]))
+        );
+
+        let parsed = plist::Value::from_reader_xml(std::io::Cursor::new(manifest.to_xml()?))?;
+        assert_eq!(parsed.into_dictionary(), Some(dict));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Universal Mach-O binaries.
+
+A universal (or *fat*) binary holds Mach-O binaries for multiple architectures.
+macOS loads the binary matching the architecture of the running machine.
+
+This module combines Mach-O binaries into a universal binary, like
+`lipo -create`. Only Mach-O headers are inspected: binary content is copied
+verbatim.
+*/
+
+use {
+    anyhow::{anyhow, Result},
+    std::ops::Range,
+};
+
+/// Magic of universal binaries.
+const FAT_MAGIC: u32 = 0xcafebabe;
+
+/// Size of the universal binary header.
+const FAT_HEADER_SIZE: usize = 8;
+
+/// Size of an architecture record in the universal binary header.
+const FAT_ARCH_SIZE: usize = 20;
+
+/// Mask for capability bits in CPU subtypes.
+const CPU_SUBTYPE_MASK: u32 = 0xff000000;
+
+/// CPU type of x86-64 Mach-O binaries.
+pub const CPU_TYPE_X86_64: u32 = 0x01000007;
+
+/// CPU type of aarch64 Mach-O binaries.
+pub const CPU_TYPE_ARM64: u32 = 0x0100000c;
+
+/// The architecture of a single architecture Mach-O binary.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MachOArchitecture {
+    /// The CPU type (`CPU_TYPE_*`).
+    pub cpu_type: u32,
+    /// The CPU subtype.
+    pub cpu_subtype: u32,
+}
+
+impl MachOArchitecture {
+    /// Resolve the architecture of a single architecture Mach-O binary.
+    pub fn from_macho_data(data: &[u8]) -> Result<Self> {
+        if data.len() < 12 {
+            return Err(anyhow!("data too short to be a Mach-O binary"));
+        }
+
+        let word = |offset: usize, big_endian: bool| {
+            let bytes = [
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ];
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+
+        let big_endian = match word(0, false) {
+            0xfeedface | 0xfeedfacf => false,
+            0xcefaedfe | 0xcffaedfe => true,
+            _ => return Err(anyhow!("data is not a single architecture Mach-O binary")),
+        };
+
+        Ok(Self {
+            cpu_type: word(4, big_endian),
+            cpu_subtype: word(8, big_endian),
+        })
+    }
+
+    /// The name of this architecture as used by Apple's tools, if known.
+    pub fn name(&self) -> Option<&'static str> {
+        match (self.cpu_type, self.cpu_subtype & !CPU_SUBTYPE_MASK) {
+            (CPU_TYPE_X86_64, 8) => Some("x86_64h"),
+            (CPU_TYPE_X86_64, _) => Some("x86_64"),
+            (CPU_TYPE_ARM64, 2) => Some("arm64e"),
+            (CPU_TYPE_ARM64, _) => Some("arm64"),
+            _ => None,
+        }
+    }
+
+    /// The log2 alignment of binaries of this architecture in universal binaries.
+    ///
+    /// This matches the page size of the architecture.
+    fn alignment(&self) -> u32 {
+        if self.cpu_type == CPU_TYPE_ARM64 {
+            14
+        } else {
+            12
+        }
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        self.cpu_type == other.cpu_type
+            && self.cpu_subtype & !CPU_SUBTYPE_MASK == other.cpu_subtype & !CPU_SUBTYPE_MASK
+    }
+}
+
+/// Resolve the byte ranges of single architecture binaries within Mach-O data.
+///
+/// Universal binaries yield the ranges of the binaries they contain.
+pub(crate) fn macho_ranges(data: &[u8]) -> Result<Vec<(MachOArchitecture, Range<usize>)>> {
+    if data.len() < FAT_HEADER_SIZE || data[0..4] != FAT_MAGIC.to_be_bytes() {
+        return Ok(vec![(
+            MachOArchitecture::from_macho_data(data)?,
+            0..data.len(),
+        )]);
+    }
+
+    let word = |offset: usize| -> Result<u32> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("universal binary header is truncated"))?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let count = word(4)? as usize;
+
+    (0..count)
+        .map(|i| {
+            let record = FAT_HEADER_SIZE + i * FAT_ARCH_SIZE;
+            let offset = word(record + 8)? as usize;
+            let size = word(record + 12)? as usize;
+
+            let binary = data
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow!("universal binary architecture {} is truncated", i))?;
+
+            Ok((
+                MachOArchitecture::from_macho_data(binary)?,
+                offset..offset + size,
+            ))
+        })
+        .collect()
+}
+
+/// Whether data is a single architecture or universal Mach-O binary.
+pub(crate) fn is_macho(data: &[u8]) -> bool {
+    macho_ranges(data).is_ok()
+}
+
+/// Split Mach-O data into single architecture binaries.
+///
+/// Universal binaries yield the binaries they contain.
+fn thin_binaries(data: &[u8]) -> Result<Vec<(MachOArchitecture, &[u8])>> {
+    Ok(macho_ranges(data)?
+        .into_iter()
+        .map(|(arch, range)| (arch, &data[range]))
+        .collect())
+}
+
+/// Combine Mach-O binaries into a universal binary.
+///
+/// Each input is a single architecture Mach-O binary or a universal binary.
+/// Binaries are written in the order given. It is an error for multiple
+/// binaries to have the same architecture.
+pub fn create_universal_binary(
+    binaries: impl IntoIterator<Item = impl AsRef<[u8]>>,
+) -> Result<Vec<u8>> {
+    let binaries = binaries.into_iter().collect::<Vec<_>>();
+
+    let mut thin: Vec<(MachOArchitecture, &[u8])> = vec![];
+    for data in &binaries {
+        for (arch, binary) in thin_binaries(data.as_ref())? {
+            if thin.iter().any(|(existing, _)| existing.is_same(&arch)) {
+                return Err(anyhow!(
+                    "multiple binaries have architecture {}",
+                    arch.name().unwrap_or("unknown")
+                ));
+            }
+
+            thin.push((arch, binary));
+        }
+    }
+
+    if thin.is_empty() {
+        return Err(anyhow!("no binaries to combine into a universal binary"));
+    }
+
+    let mut header = vec![];
+    header.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+    header.extend_from_slice(&(thin.len() as u32).to_be_bytes());
+
+    let mut offset = FAT_HEADER_SIZE + thin.len() * FAT_ARCH_SIZE;
+    let mut placements = vec![];
+
+    for (arch, binary) in &thin {
+        let align = 1usize << arch.alignment();
+        offset = offset.div_ceil(align) * align;
+
+        let start = u32::try_from(offset).map_err(|_| anyhow!("universal binary exceeds 4 GB"))?;
+        let size =
+            u32::try_from(binary.len()).map_err(|_| anyhow!("Mach-O binary exceeds 4 GB"))?;
+
+        header.extend_from_slice(&arch.cpu_type.to_be_bytes());
+        header.extend_from_slice(&arch.cpu_subtype.to_be_bytes());
+        header.extend_from_slice(&start.to_be_bytes());
+        header.extend_from_slice(&size.to_be_bytes());
+        header.extend_from_slice(&arch.alignment().to_be_bytes());
+
+        placements.push((offset, binary));
+        offset += binary.len();
+    }
+
+    let mut data = header;
+    for (offset, binary) in placements {
+        data.resize(offset, 0);
+        data.extend_from_slice(binary);
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn thin(cpu_type: u32, cpu_subtype: u32) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&0xfeedfacfu32.to_le_bytes());
+        data.extend_from_slice(&cpu_type.to_le_bytes());
+        data.extend_from_slice(&cpu_subtype.to_le_bytes());
+        data.resize(64, 0xff);
+        data
+    }
+
+    #[test]
+    fn create() -> Result<()> {
+        let x86 = thin(CPU_TYPE_X86_64, 3);
+        let arm = thin(CPU_TYPE_ARM64, 0);
+
+        assert_eq!(
+            MachOArchitecture::from_macho_data(&arm)?.name(),
+            Some("arm64")
+        );
+
+        let fat = create_universal_binary([&x86, &arm])?;
+        assert_eq!(&fat[0..8], &[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 2]);
+        assert_eq!(&fat[0x1000..0x1040], x86.as_slice());
+        assert_eq!(&fat[0x4000..0x4040], arm.as_slice());
+
+        let arches = thin_binaries(&fat)?;
+        assert_eq!(arches.len(), 2);
+        assert_eq!(arches[0].0.name(), Some("x86_64"));
+        assert_eq!(arches[0].1, x86.as_slice());
+        assert_eq!(arches[1].0.name(), Some("arm64"));
+        assert_eq!(arches[1].1, arm.as_slice());
+
+        // Universal binaries are flattened.
+        assert_eq!(create_universal_binary([&fat])?, fat);
+
+        assert!(create_universal_binary([&x86, &x86]).is_err());
+        assert!(create_universal_binary([&fat, &arm]).is_err());
+        assert!(create_universal_binary([b"not a binary".to_vec()]).is_err());
+        assert!(create_universal_binary(Vec::<Vec<u8>>::new()).is_err());
+
+        Ok(())
+    }
+}
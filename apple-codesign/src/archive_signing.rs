@@ -0,0 +1,643 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Signing content within archives.
+//!
+//! Command line tools are often distributed as zip files or tarballs. This
+//! module signs the Mach-O binaries and bundles within such archives and
+//! rewrites the archives.
+//!
+//! Existing entries retain their order and metadata. Only the content of
+//! files changed by signing is replaced. Files created by signing, such as
+//! the `_CodeSignature/CodeResources` file of bundles, are inserted after the
+//! last entry of the bundle they belong to.
+
+use {
+    crate::{error::AppleCodesignError, reader::path_is_macho, signing::UnifiedSigner},
+    apple_bundles::DirectoryBundle,
+    flate2::{read::GzDecoder, write::GzEncoder, Compression},
+    log::{info, warn},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fs::File,
+        io::{BufReader, BufWriter, Read, Write},
+        path::{Component, Path, PathBuf},
+    },
+};
+
+/// The format of an archive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    /// A zip file.
+    Zip,
+    /// An uncompressed tarball.
+    Tar,
+    /// A gzip compressed tarball.
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Attempt to resolve the archive format of a file from its content.
+    ///
+    /// Returns `None` if the file isn't a recognized archive.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Option<Self>, AppleCodesignError> {
+        let mut header = vec![];
+        File::open(path.as_ref())?
+            .take(512)
+            .read_to_end(&mut header)?;
+
+        Ok(if header.starts_with(&[0x50, 0x4b, 0x03, 0x04]) {
+            Some(Self::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            Some(Self::Tar)
+        } else {
+            None
+        })
+    }
+}
+
+/// Signs Mach-O binaries and bundles within an archive.
+pub struct ArchiveSigner {
+    path: PathBuf,
+    format: ArchiveFormat,
+}
+
+impl ArchiveSigner {
+    /// Construct an instance from an archive file.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        let path = path.as_ref().to_path_buf();
+
+        let format =
+            ArchiveFormat::from_path(&path)?.ok_or(AppleCodesignError::UnrecognizedPathType)?;
+
+        Ok(Self { path, format })
+    }
+
+    /// The format of the archive being signed.
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Sign the content of the archive and write a new archive to `output_path`.
+    ///
+    /// `output_path` can be the same as the input path.
+    pub fn write_signed_archive(
+        &self,
+        signer: &UnifiedSigner,
+        output_path: impl AsRef<Path>,
+    ) -> Result<(), AppleCodesignError> {
+        let output_path = output_path.as_ref();
+
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+
+        info!("extracting {} to {}", self.path.display(), root.display());
+        let names = match self.format {
+            ArchiveFormat::Zip => extract_zip(&self.path, root)?,
+            ArchiveFormat::Tar | ArchiveFormat::TarGz => extract_tar(self.open_tar()?, root)?,
+        };
+
+        let signed_roots = sign_extracted(signer, root)?;
+
+        // Files created by signing are inserted after the last entry sharing
+        // their signed root.
+        let existing = names.iter().map(|x| x.as_str()).collect::<BTreeSet<_>>();
+        let mut additions = BTreeMap::<usize, Vec<String>>::new();
+
+        for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let name = archive_name(entry.path().strip_prefix(root).expect("path under root"));
+            if existing.contains(name.as_str()) {
+                continue;
+            }
+
+            let Some(signed_root) = signed_roots
+                .iter()
+                .find(|r| name.starts_with(&format!("{r}/")))
+            else {
+                continue;
+            };
+
+            let index = names
+                .iter()
+                .rposition(|x| x == signed_root || x.starts_with(&format!("{signed_root}/")))
+                .unwrap_or(names.len().saturating_sub(1));
+
+            additions.entry(index).or_default().push(name);
+        }
+
+        // Write to a temporary file so the input can be the output.
+        let output_path_temp =
+            output_path.with_file_name(if let Some(file_name) = output_path.file_name() {
+                file_name.to_string_lossy().to_string() + ".tmp"
+            } else {
+                "archive.tmp".to_string()
+            });
+
+        warn!("writing signed archive to {}", output_path_temp.display());
+        {
+            let fh = BufWriter::new(File::create(&output_path_temp)?);
+
+            match self.format {
+                ArchiveFormat::Zip => {
+                    write_zip(&self.path, root, &additions, fh)?.flush()?;
+                }
+                ArchiveFormat::Tar => {
+                    write_tar(self.open_tar()?, root, &additions, fh)?.flush()?;
+                }
+                ArchiveFormat::TarGz => {
+                    let encoder = GzEncoder::new(fh, Compression::default());
+                    write_tar(self.open_tar()?, root, &additions, encoder)?
+                        .finish()?
+                        .flush()?;
+                }
+            }
+        }
+
+        if output_path.exists() {
+            warn!("removing existing {}", output_path.display());
+            std::fs::remove_file(output_path)?;
+        }
+
+        warn!(
+            "renaming {} -> {}",
+            output_path_temp.display(),
+            output_path.display()
+        );
+        std::fs::rename(&output_path_temp, output_path)?;
+
+        Ok(())
+    }
+
+    fn open_tar(&self) -> Result<tar::Archive<Box<dyn Read>>, AppleCodesignError> {
+        let fh = BufReader::new(File::open(&self.path)?);
+
+        let reader: Box<dyn Read> = if self.format == ArchiveFormat::TarGz {
+            Box::new(GzDecoder::new(fh))
+        } else {
+            Box::new(fh)
+        };
+
+        Ok(tar::Archive::new(reader))
+    }
+}
+
+/// Convert a relative filesystem path to an archive entry name.
+fn archive_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Sign bundles and Mach-O binaries extracted to a directory.
+///
+/// Returns the names of the signed entities relative to the directory.
+fn sign_extracted(signer: &UnifiedSigner, root: &Path) -> Result<Vec<String>, AppleCodesignError> {
+    let mut signed = vec![];
+
+    // Directories are visited before their content. So only the outermost
+    // bundles are signed. Signing them takes care of nested content.
+    let mut walker = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter();
+
+    while let Some(entry) = walker.next() {
+        let entry = entry?;
+        let path = entry.path();
+        let name = archive_name(path.strip_prefix(root).expect("path under root"));
+
+        if entry.file_type().is_dir() {
+            if path.extension().is_some() && DirectoryBundle::new_from_path(path).is_ok() {
+                warn!("signing bundle {} in archive", name);
+                signer.sign_bundle(path, path)?;
+                signed.push(name);
+                walker.skip_current_dir();
+            }
+        } else if entry.file_type().is_file() && path_is_macho(path)? {
+            warn!("signing Mach-O {} in archive", name);
+            signer.sign_macho(path, path)?;
+            signed.push(name);
+        }
+    }
+
+    if signed.is_empty() {
+        warn!("no Mach-O binaries or bundles found in archive");
+    }
+
+    Ok(signed)
+}
+
+fn extract_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    root: &Path,
+) -> Result<Vec<String>, AppleCodesignError> {
+    let mut names = vec![];
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        names.push(archive_name(&entry.path()?));
+
+        if !entry.unpack_in(root)? {
+            warn!(
+                "not extracting {} since it escapes the archive root",
+                entry.path()?.display()
+            );
+        }
+    }
+
+    Ok(names)
+}
+
+fn write_tar<W: Write>(
+    mut archive: tar::Archive<Box<dyn Read>>,
+    root: &Path,
+    additions: &BTreeMap<usize, Vec<String>>,
+    writer: W,
+) -> Result<W, AppleCodesignError> {
+    let mut builder = tar::Builder::new(writer);
+
+    for (index, entry) in archive.entries()?.enumerate() {
+        let entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let mut header = entry.header().clone();
+
+        // Entries escaping the archive root weren't extracted.
+        let extracted = path.components().all(|c| matches!(c, Component::Normal(_)));
+
+        match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous if extracted => {
+                let data = std::fs::read(root.join(&path))?;
+                header.set_size(data.len() as _);
+                builder.append_data(&mut header, &path, data.as_slice())?;
+            }
+            tar::EntryType::Symlink | tar::EntryType::Link => {
+                let target = entry.link_name()?.ok_or_else(|| {
+                    AppleCodesignError::CliGeneralError(format!(
+                        "link {} in archive lacks a target",
+                        path.display()
+                    ))
+                })?;
+                builder.append_link(&mut header, &path, target)?;
+            }
+            _ => {
+                builder.append_data(&mut header, &path, entry)?;
+            }
+        }
+
+        for name in additions.get(&index).into_iter().flatten() {
+            info!("adding {} to archive", name);
+            builder.append_path_with_name(root.join(name), name)?;
+        }
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+fn zip_is_symlink(file: &zip::read::ZipFile) -> bool {
+    file.unix_mode()
+        .map(|mode| mode & 0o170000 == 0o120000)
+        .unwrap_or(false)
+}
+
+/// Ensure no existing path from `root` to `root.join(relative)` is a symlink.
+///
+/// Writing through a symlink extracted from an archive could otherwise write
+/// anywhere on the filesystem.
+fn ensure_no_symlinks(root: &Path, relative: &Path) -> Result<(), AppleCodesignError> {
+    let mut path = root.to_path_buf();
+
+    for component in relative.components() {
+        path.push(component);
+
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(AppleCodesignError::CliGeneralError(format!(
+                    "refusing to extract {} through symlink {}",
+                    relative.display(),
+                    path.display()
+                )));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_zip(path: &Path, root: &Path) -> Result<Vec<String>, AppleCodesignError> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut names = vec![];
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+
+        let Some(relative) = file.enclosed_name() else {
+            warn!(
+                "not extracting {} since it escapes the archive root",
+                file.name()
+            );
+            names.push(file.name().trim_end_matches('/').to_string());
+            continue;
+        };
+        names.push(archive_name(&relative));
+
+        ensure_no_symlinks(root, &relative)?;
+        let dest = root.join(&relative);
+
+        if file.is_dir() {
+            std::fs::create_dir_all(&dest)?;
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        if zip_is_symlink(&file) {
+            let target = String::from_utf8_lossy(&data).to_string();
+
+            // Only allow links to content within the same directory tree so
+            // links can't point outside the archive root.
+            if Path::new(&target)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(AppleCodesignError::CliGeneralError(format!(
+                    "refusing to extract symlink {} to {}",
+                    relative.display(),
+                    target
+                )));
+            }
+
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(target, &dest)?;
+            #[cfg(not(unix))]
+            std::fs::write(&dest, target)?;
+        } else {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&dest)?
+                .write_all(&data)?;
+        }
+    }
+
+    Ok(names)
+}
+
+fn write_zip<W: Write + std::io::Seek>(
+    path: &Path,
+    root: &Path,
+    additions: &BTreeMap<usize, Vec<String>>,
+    writer: W,
+) -> Result<W, AppleCodesignError> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut zf = zip::ZipWriter::new(writer);
+    zf.set_raw_comment(archive.comment().into());
+
+    for index in 0..archive.len() {
+        let replacement = {
+            let mut file = archive.by_index(index)?;
+
+            match file.enclosed_name() {
+                Some(relative) if file.is_file() && !zip_is_symlink(&file) => {
+                    let mut original = vec![];
+                    file.read_to_end(&mut original)?;
+                    ensure_no_symlinks(root, &relative)?;
+                    let data = std::fs::read(root.join(relative))?;
+
+                    if data != original {
+                        let mut options = zip::write::SimpleFileOptions::default()
+                            .compression_method(file.compression())
+                            .large_file(data.len() as u64 > u32::MAX as u64);
+                        if let Some(time) = file.last_modified() {
+                            options = options.last_modified_time(time);
+                        }
+                        if let Some(mode) = file.unix_mode() {
+                            options = options.unix_permissions(mode);
+                        }
+
+                        Some((file.name().to_string(), options, data))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        };
+
+        if let Some((name, options, data)) = replacement {
+            zf.start_file(name, options)?;
+            zf.write_all(&data)?;
+        } else {
+            zf.raw_copy_file(archive.by_index_raw(index)?)?;
+        }
+
+        for name in additions.get(&index).into_iter().flatten() {
+            info!("adding {} to archive", name);
+            zf.start_file(
+                name.as_str(),
+                zip::write::SimpleFileOptions::default().unix_permissions(0o644),
+            )?;
+            zf.write_all(&std::fs::read(root.join(name))?)?;
+        }
+    }
+
+    Ok(zf.finish()?)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{macho::MachFile, macho_builder::MachOBuilder, signing_settings::SigningSettings},
+    };
+
+    fn macho_data() -> Result<Vec<u8>, AppleCodesignError> {
+        MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()
+    }
+
+    fn assert_signed(data: &[u8]) -> Result<(), AppleCodesignError> {
+        for binary in MachFile::parse(data)?.into_iter() {
+            assert!(binary.code_signature()?.is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_tar_gz() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("tool.tar.gz");
+
+        let macho = macho_data()?;
+        {
+            let mut builder =
+                tar::Builder::new(GzEncoder::new(File::create(&path)?, Compression::default()));
+
+            for (name, mode, data) in [
+                ("tool/README", 0o644, b"readme".as_slice()),
+                ("tool/bin/tool", 0o755, macho.as_slice()),
+            ] {
+                let mut header = tar::Header::new_gnu();
+                header.set_mode(mode);
+                header.set_mtime(1234567890);
+                header.set_size(data.len() as _);
+                builder.append_data(&mut header, name, data)?;
+            }
+
+            builder.into_inner()?.finish()?;
+        }
+
+        assert_eq!(ArchiveFormat::from_path(&path)?, Some(ArchiveFormat::TarGz));
+
+        let signer = UnifiedSigner::new(SigningSettings::default());
+        signer.sign_path_in_place(&path)?;
+
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(&path)?));
+        let mut entries = vec![];
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            entries.push((
+                entry.path()?.display().to_string(),
+                entry.header().mode()?,
+                entry.header().mtime()?,
+                data,
+            ));
+        }
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "tool/README");
+        assert_eq!(entries[0].3, b"readme");
+        assert_eq!(entries[1].0, "tool/bin/tool");
+        assert_eq!(entries[1].1, 0o755);
+        assert_eq!(entries[1].2, 1234567890);
+        assert_ne!(entries[1].3, macho);
+        assert_signed(&entries[1].3)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_zip() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("tool.zip");
+
+        let macho = macho_data()?;
+        {
+            let mut zf = zip::ZipWriter::new(File::create(&path)?);
+            zf.start_file(
+                "tool",
+                zip::write::SimpleFileOptions::default().unix_permissions(0o755),
+            )?;
+            zf.write_all(&macho)?;
+            zf.start_file(
+                "LICENSE",
+                zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored),
+            )?;
+            zf.write_all(b"license")?;
+            zf.finish()?;
+        }
+
+        let signer = UnifiedSigner::new(SigningSettings::default());
+        let output = temp_dir.path().join("signed.zip");
+        signer.sign_path(&path, &output)?;
+
+        let mut archive = zip::ZipArchive::new(File::open(&output)?)?;
+        assert_eq!(archive.len(), 2);
+
+        let mut file = archive.by_index(0)?;
+        assert_eq!(file.name(), "tool");
+        assert_eq!(file.unix_mode().map(|x| x & 0o777), Some(0o755));
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        assert_signed(&data)?;
+        drop(file);
+
+        let file = archive.by_index(1)?;
+        assert_eq!(file.name(), "LICENSE");
+        assert_eq!(file.compression(), zip::CompressionMethod::Stored);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_symlink_escape() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let outside = temp_dir.path().join("outside");
+        std::fs::create_dir(&outside)?;
+        let outside_str = outside.to_string_lossy().to_string();
+
+        let options = zip::write::SimpleFileOptions::default();
+
+        for (name, links) in [
+            ("absolute.zip", vec![("link", outside_str.as_str())]),
+            ("parent.zip", vec![("link", "../../outside")]),
+            ("chained.zip", vec![("dir", "."), ("link", "dir/../..")]),
+        ] {
+            let path = temp_dir.path().join(name);
+            {
+                let mut zf = zip::ZipWriter::new(File::create(&path)?);
+                for (link, target) in links {
+                    zf.add_symlink(link, target, options)?;
+                }
+                zf.start_file("link/evil", options)?;
+                zf.write_all(b"evil")?;
+                zf.finish()?;
+            }
+
+            let root = tempfile::tempdir_in(temp_dir.path())?;
+            assert!(extract_zip(&path, root.path()).is_err(), "{}", name);
+            assert_eq!(std::fs::read_dir(&outside)?.count(), 0, "{}", name);
+        }
+
+        // A link within the archive is fine. But writing through it isn't.
+        let path = temp_dir.path().join("relative.zip");
+        {
+            let mut zf = zip::ZipWriter::new(File::create(&path)?);
+            zf.add_directory("dir", options)?;
+            zf.add_symlink("link", "dir", options)?;
+            zf.finish()?;
+        }
+        let root = tempfile::tempdir_in(temp_dir.path())?;
+        extract_zip(&path, root.path())?;
+        assert_eq!(
+            std::fs::read_link(root.path().join("link"))?,
+            PathBuf::from("dir")
+        );
+
+        let path = temp_dir.path().join("through-relative.zip");
+        {
+            let mut zf = zip::ZipWriter::new(File::create(&path)?);
+            zf.add_directory("dir", options)?;
+            zf.add_symlink("link", "dir", options)?;
+            zf.start_file("link/evil", options)?;
+            zf.write_all(b"evil")?;
+            zf.finish()?;
+        }
+        let root = tempfile::tempdir_in(temp_dir.path())?;
+        assert!(extract_zip(&path, root.path()).is_err());
+        assert!(!root.path().join("dir").join("evil").exists());
+
+        Ok(())
+    }
+}
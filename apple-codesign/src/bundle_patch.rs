@@ -0,0 +1,787 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Differential updates of signed bundles.
+
+Software updaters often ship only the files that changed between releases
+instead of the full bundle. But a bundle's code signature seals every file
+in it: the `_CodeSignature/CodeResources` file records digests of all
+resources and the main executable's signature records the digest of
+`CodeResources`. So changing any file requires re-signing the bundle and
+shipping the re-signed files along with the changed ones.
+
+This module computes the set of changes between an installed, signed bundle
+and its signed successor. The changed files plus a manifest describing all
+changes form an update payload. Updaters apply the payload to the installed
+bundle, yielding a bundle identical to the signed successor and whose
+signature is therefore valid. Applying a payload is refused unless the
+installed bundle's `CodeResources` matches the one the payload was computed
+against.
+*/
+
+use {
+    crate::{cryptography::DigestType, AppleCodesignError},
+    serde::{Deserialize, Serialize},
+    simple_file_manifest::create_symlink,
+    std::{
+        collections::BTreeMap,
+        ffi::OsStr,
+        path::{Component, Path, PathBuf},
+    },
+};
+
+/// Filename of the manifest in an update payload directory.
+pub const BUNDLE_PATCH_MANIFEST_FILENAME: &str = "patch-manifest.json";
+
+/// State of a file within a bundle.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum FileState {
+    Regular { sha256: String, executable: bool },
+    Symlink(String),
+}
+
+/// Resolve the state of all files in a directory, keyed by relative path.
+///
+/// Paths use `/` as a separator regardless of platform.
+fn directory_state(root: &Path) -> Result<BTreeMap<String, FileState>, AppleCodesignError> {
+    let mut res = BTreeMap::new();
+
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let rel_path = entry
+            .path()
+            .strip_prefix(root)
+            .expect("walked path should be relative to root")
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        let state = if entry.file_type().is_symlink() {
+            FileState::Symlink(
+                std::fs::read_link(entry.path())?
+                    .to_string_lossy()
+                    .to_string(),
+            )
+        } else {
+            let data = std::fs::read(entry.path())?;
+
+            FileState::Regular {
+                sha256: hex::encode(DigestType::Sha256.digest_data(&data)?),
+                executable: simple_file_manifest::is_executable(&entry.metadata()?),
+            }
+        };
+
+        res.insert(rel_path, state);
+    }
+
+    Ok(res)
+}
+
+/// Resolve the SHA-256 of a bundle's `CodeResources` file, if present.
+///
+/// This is the `_CodeSignature/CodeResources` file closest to the bundle root.
+fn code_resources_sha256(root: &Path) -> Result<Option<String>, AppleCodesignError> {
+    let mut found: Option<(usize, PathBuf)> = None;
+
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+
+        if entry.file_type().is_file()
+            && entry.file_name() == "CodeResources"
+            && entry.path().parent().and_then(|p| p.file_name())
+                == Some(OsStr::new("_CodeSignature"))
+            && found
+                .as_ref()
+                .map_or(true, |(depth, _)| entry.depth() < *depth)
+        {
+            found = Some((entry.depth(), entry.into_path()));
+        }
+    }
+
+    found
+        .map(|(_, path)| {
+            Ok(hex::encode(
+                DigestType::Sha256.digest_data(&std::fs::read(path)?)?,
+            ))
+        })
+        .transpose()
+}
+
+/// Resolve a bundle relative path from a patch manifest or the command line.
+///
+/// Such paths may come from untrusted sources. So paths that are absolute or
+/// contain `.` or `..` components are rejected, as they could otherwise
+/// reference files outside the bundle.
+fn bundle_relative_path(value: &str) -> Result<PathBuf, AppleCodesignError> {
+    let path = PathBuf::from(value);
+
+    if path.as_os_str().is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(AppleCodesignError::CliGeneralError(format!(
+            "illegal path in bundle patch: {}",
+            value
+        )));
+    }
+
+    Ok(path)
+}
+
+/// Remove whatever exists at a path, including directories.
+///
+/// Symlinks are removed, not followed.
+fn remove_path(path: &Path) -> Result<(), AppleCodesignError> {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path)?,
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(_) => {}
+    }
+
+    Ok(())
+}
+
+/// Set or clear the executable bits of a file.
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> Result<(), AppleCodesignError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(if executable {
+        mode | 0o111
+    } else {
+        mode & !0o111
+    });
+    std::fs::set_permissions(path, permissions)?;
+
+    Ok(())
+}
+
+/// Set or clear the executable bits of a file.
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> Result<(), AppleCodesignError> {
+    Ok(())
+}
+
+/// An action to perform on a single path when applying a patch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum BundlePatchAction {
+    /// Write a regular file from the payload.
+    Write {
+        /// SHA-256 of the file content.
+        sha256: String,
+        /// Whether the file is executable.
+        executable: bool,
+    },
+
+    /// Create a symlink.
+    Symlink {
+        /// The symlink target.
+        target: String,
+    },
+
+    /// Remove the file.
+    Remove,
+}
+
+/// A change to a single path in a bundle.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BundlePatchEntry {
+    /// Bundle relative path, using `/` as a separator.
+    pub path: String,
+
+    /// What to do with the path.
+    #[serde(flatten)]
+    pub action: BundlePatchAction,
+}
+
+impl BundlePatchEntry {
+    /// Resolve the bundle relative path of this entry.
+    ///
+    /// Manifests may come from untrusted sources. So paths that are absolute or
+    /// contain `.` or `..` components are rejected, as they could otherwise
+    /// reference files outside the bundle.
+    pub fn relative_path(&self) -> Result<PathBuf, AppleCodesignError> {
+        bundle_relative_path(&self.path)
+    }
+}
+
+/// Describes the changes transforming a signed bundle into its signed successor.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BundlePatch {
+    /// SHA-256 of the base bundle's `CodeResources` file, if present.
+    ///
+    /// Updaters can use this to verify the patch is being applied to the
+    /// bundle it was computed against.
+    pub base_code_resources_sha256: Option<String>,
+
+    /// Changes to apply, sorted by path.
+    pub entries: Vec<BundlePatchEntry>,
+}
+
+impl BundlePatch {
+    /// Compute the patch transforming the bundle at `base` into the bundle at `target`.
+    pub fn compute(base: &Path, target: &Path) -> Result<Self, AppleCodesignError> {
+        let base_state = directory_state(base)?;
+        let target_state = directory_state(target)?;
+
+        let mut entries = vec![];
+
+        for (path, state) in &target_state {
+            if base_state.get(path) == Some(state) {
+                continue;
+            }
+
+            // A symlink replacing a file (or vice versa) is handled by the
+            // writer removing whatever exists at the path first.
+            let action = match state {
+                FileState::Regular { sha256, executable } => BundlePatchAction::Write {
+                    sha256: sha256.clone(),
+                    executable: *executable,
+                },
+                FileState::Symlink(target) => BundlePatchAction::Symlink {
+                    target: target.clone(),
+                },
+            };
+
+            entries.push(BundlePatchEntry {
+                path: path.clone(),
+                action,
+            });
+        }
+
+        for path in base_state.keys() {
+            if !target_state.contains_key(path) {
+                entries.push(BundlePatchEntry {
+                    path: path.clone(),
+                    action: BundlePatchAction::Remove,
+                });
+            }
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self {
+            base_code_resources_sha256: code_resources_sha256(base)?,
+            entries,
+        })
+    }
+
+    /// Write an update payload to a directory.
+    ///
+    /// Files written or replaced by the patch are copied from the bundle at
+    /// `target` into `dest_dir` and the patch manifest is written to
+    /// [BUNDLE_PATCH_MANIFEST_FILENAME].
+    pub fn write_payload(&self, target: &Path, dest_dir: &Path) -> Result<(), AppleCodesignError> {
+        std::fs::create_dir_all(dest_dir)?;
+
+        for entry in &self.entries {
+            let path = entry.relative_path()?;
+
+            if let BundlePatchAction::Write { .. } = entry.action {
+                let dest_path = dest_dir.join(&path);
+
+                if let Some(parent) = dest_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                std::fs::copy(target.join(&path), dest_path)?;
+            }
+        }
+
+        std::fs::write(
+            dest_dir.join(BUNDLE_PATCH_MANIFEST_FILENAME),
+            serde_json::to_vec_pretty(self)?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Read a patch manifest from an update payload directory.
+    pub fn from_payload(payload_dir: &Path) -> Result<Self, AppleCodesignError> {
+        let data = std::fs::read(payload_dir.join(BUNDLE_PATCH_MANIFEST_FILENAME))?;
+
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Apply the patch to the bundle at `bundle_root` using files from `payload_dir`.
+    ///
+    /// Entry paths, the digests of payload files, and the digest of the bundle's
+    /// `CodeResources` file are verified before the bundle is modified.
+    ///
+    /// Directories left empty by removed files are removed as well.
+    pub fn apply(&self, payload_dir: &Path, bundle_root: &Path) -> Result<(), AppleCodesignError> {
+        if code_resources_sha256(bundle_root)? != self.base_code_resources_sha256 {
+            return Err(AppleCodesignError::CliGeneralError(format!(
+                "{} is not the bundle the patch was computed against (CodeResources digest mismatch)",
+                bundle_root.display()
+            )));
+        }
+
+        for entry in &self.entries {
+            let path = entry.relative_path()?;
+
+            if let BundlePatchAction::Write { sha256, .. } = &entry.action {
+                let data = std::fs::read(payload_dir.join(path))?;
+
+                if hex::encode(DigestType::Sha256.digest_data(&data)?) != *sha256 {
+                    return Err(AppleCodesignError::CliGeneralError(format!(
+                        "digest mismatch for {} in update payload",
+                        entry.path
+                    )));
+                }
+            }
+        }
+
+        for entry in &self.entries {
+            let path = entry.relative_path()?;
+            let dest_path = bundle_root.join(&path);
+
+            // Patches never write through symlinks. Refuse to do so, as a
+            // symlink created by an earlier entry could point anywhere.
+            for ancestor in path.ancestors().skip(1) {
+                if !ancestor.as_os_str().is_empty()
+                    && bundle_root
+                        .join(ancestor)
+                        .symlink_metadata()
+                        .is_ok_and(|m| m.file_type().is_symlink())
+                {
+                    return Err(AppleCodesignError::CliGeneralError(format!(
+                        "refusing to apply bundle patch through symlink {}",
+                        ancestor.display()
+                    )));
+                }
+            }
+
+            // A file may replace a directory. Its content is removed by the patch
+            // anyway.
+            remove_path(&dest_path)?;
+
+            if let BundlePatchAction::Remove = entry.action {
+                for ancestor in path.ancestors().skip(1) {
+                    let dir = bundle_root.join(ancestor);
+
+                    if ancestor.as_os_str().is_empty()
+                        || !dir.is_dir()
+                        || std::fs::read_dir(&dir)?.next().is_some()
+                    {
+                        break;
+                    }
+
+                    std::fs::remove_dir(&dir)?;
+                }
+
+                continue;
+            }
+
+            if let Some(parent) = dest_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            match &entry.action {
+                BundlePatchAction::Write { executable, .. } => {
+                    std::fs::copy(payload_dir.join(&path), &dest_path)?;
+                    set_executable(&dest_path, *executable)?;
+                }
+                BundlePatchAction::Symlink { target } => {
+                    create_symlink(&dest_path, target)?;
+                }
+                BundlePatchAction::Remove => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Create a copy of `base` at `dest` with the files from `overlay` written over it.
+///
+/// Symlinks are preserved. `removed` holds bundle relative paths of files or
+/// directories to remove from the copy.
+pub fn stage_bundle_update(
+    base: &Path,
+    overlay: &Path,
+    removed: &[String],
+    dest: &Path,
+) -> Result<(), AppleCodesignError> {
+    for source in [base, overlay] {
+        for entry in walkdir::WalkDir::new(source).sort_by_file_name() {
+            let entry = entry?;
+            let dest_path = dest.join(
+                entry
+                    .path()
+                    .strip_prefix(source)
+                    .expect("walked path should be relative to root"),
+            );
+
+            if entry.file_type().is_dir() {
+                if !dest_path.is_dir() {
+                    remove_path(&dest_path)?;
+                }
+                std::fs::create_dir_all(&dest_path)?;
+                continue;
+            }
+
+            remove_path(&dest_path)?;
+
+            if entry.file_type().is_symlink() {
+                create_symlink(&dest_path, std::fs::read_link(entry.path())?)?;
+            } else {
+                std::fs::copy(entry.path(), &dest_path)?;
+            }
+        }
+    }
+
+    for path in removed {
+        let dest_path = dest.join(bundle_relative_path(path)?);
+
+        if dest_path.symlink_metadata().is_err() {
+            return Err(AppleCodesignError::CliGeneralError(format!(
+                "{} does not exist in the bundle being updated",
+                path
+            )));
+        }
+
+        remove_path(&dest_path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let base = td.path().join("base");
+        let target = td.path().join("target");
+        let payload = td.path().join("payload");
+        let installed = td.path().join("installed");
+
+        for root in [&base, &target] {
+            std::fs::create_dir_all(root.join("Contents/_CodeSignature"))?;
+            std::fs::write(root.join("Contents/unchanged"), b"same")?;
+        }
+        std::fs::write(base.join("Contents/_CodeSignature/CodeResources"), b"old")?;
+        std::fs::write(target.join("Contents/_CodeSignature/CodeResources"), b"new")?;
+        std::fs::write(base.join("Contents/removed"), b"gone")?;
+        std::fs::write(target.join("Contents/added"), b"added")?;
+
+        let patch = BundlePatch::compute(&base, &target)?;
+        assert_eq!(
+            patch
+                .entries
+                .iter()
+                .map(|e| e.path.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Contents/_CodeSignature/CodeResources",
+                "Contents/added",
+                "Contents/removed"
+            ]
+        );
+        assert_eq!(patch.entries[2].action, BundlePatchAction::Remove);
+        assert_eq!(
+            patch.base_code_resources_sha256,
+            Some(hex::encode(DigestType::Sha256.digest_data(b"old")?))
+        );
+
+        patch.write_payload(&target, &payload)?;
+        assert!(!payload.join("Contents/unchanged").exists());
+
+        let overlay = td.path().join("overlay");
+        std::fs::create_dir_all(overlay.join("Contents"))?;
+        std::fs::write(overlay.join("Contents/added"), b"added")?;
+        stage_bundle_update(&base, &overlay, &[], &installed)?;
+        assert_eq!(std::fs::read(installed.join("Contents/added"))?, b"added");
+        assert_eq!(std::fs::read(installed.join("Contents/removed"))?, b"gone");
+
+        let patch = BundlePatch::from_payload(&payload)?;
+        patch.apply(&payload, &installed)?;
+
+        assert!(BundlePatch::compute(&installed, &target)?
+            .entries
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_paths() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let payload = td.path().join("payload");
+        let installed = td.path().join("installed");
+        std::fs::create_dir_all(&payload)?;
+        std::fs::create_dir_all(&installed)?;
+        std::fs::write(payload.join("evil"), b"evil")?;
+
+        for path in ["../evil", "Contents/../../evil", "/tmp/evil", "./evil", ""] {
+            let entry = BundlePatchEntry {
+                path: path.to_string(),
+                action: BundlePatchAction::Remove,
+            };
+            assert!(entry.relative_path().is_err(), "{}", path);
+
+            let patch = BundlePatch {
+                base_code_resources_sha256: None,
+                entries: vec![entry],
+            };
+            assert!(patch.apply(&payload, &installed).is_err(), "{}", path);
+            assert!(
+                patch.write_payload(&installed, &payload).is_err(),
+                "{}",
+                path
+            );
+        }
+
+        assert_eq!(
+            BundlePatchEntry {
+                path: "Contents/MacOS/app".into(),
+                action: BundlePatchAction::Remove,
+            }
+            .relative_path()?,
+            PathBuf::from("Contents/MacOS/app")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn base_mismatch() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let base = td.path().join("base");
+        let target = td.path().join("target");
+        let payload = td.path().join("payload");
+        let other = td.path().join("other");
+
+        for (root, code_resources) in [(&base, "old"), (&target, "new"), (&other, "other")] {
+            std::fs::create_dir_all(root.join("Contents/_CodeSignature"))?;
+            std::fs::write(
+                root.join("Contents/_CodeSignature/CodeResources"),
+                code_resources,
+            )?;
+        }
+        // Nested bundles don't count.
+        std::fs::create_dir_all(base.join("Contents/Frameworks/A.framework/_CodeSignature"))?;
+        std::fs::write(
+            base.join("Contents/Frameworks/A.framework/_CodeSignature/CodeResources"),
+            b"nested",
+        )?;
+
+        let patch = BundlePatch::compute(&base, &target)?;
+        assert_eq!(
+            patch.base_code_resources_sha256,
+            Some(hex::encode(DigestType::Sha256.digest_data(b"old")?))
+        );
+        patch.write_payload(&target, &payload)?;
+
+        assert!(patch.apply(&payload, &other).is_err());
+        assert_eq!(
+            std::fs::read(other.join("Contents/_CodeSignature/CodeResources"))?,
+            b"other"
+        );
+
+        std::fs::remove_file(other.join("Contents/_CodeSignature/CodeResources"))?;
+        assert!(patch.apply(&payload, &other).is_err());
+
+        patch.apply(&payload, &base)?;
+        assert_eq!(
+            std::fs::read(base.join("Contents/_CodeSignature/CodeResources"))?,
+            b"new"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deletions() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let base = td.path().join("base");
+        let overlay = td.path().join("overlay");
+        let target = td.path().join("target");
+        let payload = td.path().join("payload");
+        let installed = td.path().join("installed");
+
+        std::fs::create_dir_all(base.join("Contents/Resources/old/nested"))?;
+        std::fs::create_dir_all(&overlay)?;
+        std::fs::write(base.join("Contents/Info.plist"), b"plist")?;
+        std::fs::write(base.join("Contents/Resources/stale"), b"stale")?;
+        std::fs::write(base.join("Contents/Resources/old/a"), b"a")?;
+        std::fs::write(base.join("Contents/Resources/old/nested/b"), b"b")?;
+
+        stage_bundle_update(
+            &base,
+            &overlay,
+            &[
+                "Contents/Resources/old".into(),
+                "Contents/Resources/stale".into(),
+            ],
+            &target,
+        )?;
+        assert!(!target.join("Contents/Resources/old").exists());
+        assert!(!target.join("Contents/Resources/stale").exists());
+        assert!(target.join("Contents/Info.plist").exists());
+
+        for removed in ["Contents/missing", "../base"] {
+            let scratch = td.path().join("scratch");
+            assert!(
+                stage_bundle_update(&base, &overlay, &[removed.into()], &scratch).is_err(),
+                "{}",
+                removed
+            );
+        }
+
+        let patch = BundlePatch::compute(&base, &target)?;
+        assert!(patch
+            .entries
+            .iter()
+            .all(|e| e.action == BundlePatchAction::Remove));
+        assert_eq!(patch.entries.len(), 3);
+        patch.write_payload(&target, &payload)?;
+
+        stage_bundle_update(&base, &overlay, &[], &installed)?;
+        patch.apply(&payload, &installed)?;
+
+        // Directories emptied by the patch are removed, others are kept.
+        assert!(!installed.join("Contents/Resources").exists());
+        assert!(installed.join("Contents/Info.plist").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn directory_replaced_by_file() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let base = td.path().join("base");
+        let overlay = td.path().join("overlay");
+        let target = td.path().join("target");
+        let payload = td.path().join("payload");
+        let installed = td.path().join("installed");
+
+        std::fs::create_dir_all(base.join("Contents/thing/nested"))?;
+        std::fs::write(base.join("Contents/thing/nested/a"), b"a")?;
+        std::fs::write(base.join("Contents/other"), b"other")?;
+        std::fs::create_dir_all(overlay.join("Contents/other"))?;
+        std::fs::write(overlay.join("Contents/thing"), b"thing")?;
+        std::fs::write(overlay.join("Contents/other/b"), b"b")?;
+
+        stage_bundle_update(&base, &overlay, &[], &target)?;
+        assert_eq!(std::fs::read(target.join("Contents/thing"))?, b"thing");
+        assert_eq!(std::fs::read(target.join("Contents/other/b"))?, b"b");
+
+        let patch = BundlePatch::compute(&base, &target)?;
+        patch.write_payload(&target, &payload)?;
+
+        let empty = td.path().join("empty");
+        std::fs::create_dir_all(&empty)?;
+        stage_bundle_update(&base, &empty, &[], &installed)?;
+        patch.apply(&payload, &installed)?;
+
+        assert!(BundlePatch::compute(&installed, &target)?
+            .entries
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executable_flag() -> Result<(), AppleCodesignError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let td = tempfile::tempdir()?;
+        let payload = td.path().join("payload");
+        let installed = td.path().join("installed");
+        std::fs::create_dir_all(payload.join("Contents/MacOS"))?;
+        std::fs::create_dir_all(&installed)?;
+
+        // Payloads may lose permissions in transit.
+        for name in ["tool", "data"] {
+            let path = payload.join("Contents/MacOS").join(name);
+            std::fs::write(&path, name)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+        }
+        std::fs::set_permissions(
+            payload.join("Contents/MacOS/data"),
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+
+        let patch = BundlePatch {
+            base_code_resources_sha256: None,
+            entries: vec![
+                BundlePatchEntry {
+                    path: "Contents/MacOS/data".into(),
+                    action: BundlePatchAction::Write {
+                        sha256: hex::encode(DigestType::Sha256.digest_data(b"data")?),
+                        executable: false,
+                    },
+                },
+                BundlePatchEntry {
+                    path: "Contents/MacOS/tool".into(),
+                    action: BundlePatchAction::Write {
+                        sha256: hex::encode(DigestType::Sha256.digest_data(b"tool")?),
+                        executable: true,
+                    },
+                },
+            ],
+        };
+        patch.apply(&payload, &installed)?;
+
+        let mode = |name: &str| -> Result<u32, AppleCodesignError> {
+            Ok(
+                std::fs::metadata(installed.join("Contents/MacOS").join(name))?
+                    .permissions()
+                    .mode()
+                    & 0o777,
+            )
+        };
+        assert_eq!(mode("tool")?, 0o755);
+        assert_eq!(mode("data")?, 0o644);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escape() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let payload = td.path().join("payload");
+        let installed = td.path().join("installed");
+        let outside = td.path().join("outside");
+        std::fs::create_dir_all(payload.join("link"))?;
+        std::fs::create_dir_all(&installed)?;
+        std::fs::create_dir_all(&outside)?;
+        std::fs::write(payload.join("link/evil"), b"evil")?;
+
+        let patch = BundlePatch {
+            base_code_resources_sha256: None,
+            entries: vec![
+                BundlePatchEntry {
+                    path: "link".into(),
+                    action: BundlePatchAction::Symlink {
+                        target: outside.display().to_string(),
+                    },
+                },
+                BundlePatchEntry {
+                    path: "link/evil".into(),
+                    action: BundlePatchAction::Write {
+                        sha256: hex::encode(DigestType::Sha256.digest_data(b"evil")?),
+                        executable: false,
+                    },
+                },
+            ],
+        };
+
+        assert!(patch.apply(&payload, &installed).is_err());
+        assert!(!outside.join("evil").exists());
+
+        Ok(())
+    }
+}
@@ -8,14 +8,17 @@ use {
     crate::{
         code_directory::CodeDirectoryBlob,
         code_requirement::{CodeRequirementExpression, RequirementType},
-        code_resources::{normalized_resources_path, CodeResourcesBuilder, CodeResourcesRule},
+        code_resources::{
+            ignore_file_rules, normalized_resources_path, CodeResourcesBuilder, CodeResourcesRule,
+            IGNORE_FILE_NAME,
+        },
         cryptography::DigestType,
         embedded_signature::{Blob, BlobData},
         error::AppleCodesignError,
         macho::MachFile,
         macho_signing::{write_macho_file, MachOSigner},
-        signing::path_identifier,
-        signing_settings::{SettingsScope, SigningSettings},
+        signing::{normalize_derived_identifier, path_identifier},
+        signing_settings::{CompatibilityTarget, SettingsScope, SigningSettings},
     },
     apple_bundles::{BundlePackageType, DirectoryBundle},
     log::{debug, info, warn},
@@ -46,6 +49,7 @@ pub fn copy_bundle(
         settings: &settings,
         previously_installed_paths: Default::default(),
         installed_paths: Default::default(),
+        derived_identifiers: Default::default(),
     };
 
     for file in bundle
@@ -214,6 +218,9 @@ impl BundleSigner {
         // twice.
         let mut installed_rel_paths = BTreeSet::<PathBuf>::new();
 
+        // Root relative paths of binaries having identifiers derived from their path.
+        let mut derived_identifiers = vec![];
+
         for (rel, nested) in bundles {
             let rel_path = PathBuf::from(rel);
 
@@ -245,6 +252,12 @@ impl BundleSigner {
                     bundle_installed,
                 )?;
 
+                derived_identifiers.extend(
+                    info.derived_identifiers
+                        .into_iter()
+                        .map(|(identifier, p)| (identifier, rel_path.join(p))),
+                );
+
                 info.installed_rel_paths
             };
 
@@ -260,12 +273,42 @@ impl BundleSigner {
             .get(&None)
             .expect("main bundle should have a key");
 
-        Ok(main
-            .write_signed_bundle(dest_dir, settings, installed_rel_paths)?
-            .bundle)
+        let info = main.write_signed_bundle(dest_dir, settings, installed_rel_paths)?;
+        derived_identifiers.extend(info.derived_identifiers);
+
+        for (identifier, paths) in identifier_collisions(&derived_identifiers) {
+            warn!(
+                "{} binaries have binary identifier {}: {}; consider setting distinct identifiers via --binary-identifier",
+                paths.len(),
+                identifier,
+                paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        Ok(info.bundle)
     }
 }
 
+/// Find identifiers assigned to multiple paths.
+fn identifier_collisions(identifiers: &[(String, PathBuf)]) -> BTreeMap<&str, Vec<&Path>> {
+    let mut paths = BTreeMap::<&str, Vec<&Path>>::new();
+
+    for (identifier, path) in identifiers {
+        paths
+            .entry(identifier.as_str())
+            .or_default()
+            .push(path.as_path());
+    }
+
+    paths.retain(|_, paths| paths.len() > 1);
+
+    paths
+}
+
 /// Metadata about a signed Mach-O file or bundle.
 ///
 /// If referring to a bundle, the metadata refers to the 1st Mach-O in the
@@ -399,6 +442,11 @@ pub struct BundleSigningContext<'a, 'key> {
     pub previously_installed_paths: BTreeSet<PathBuf>,
     /// Bundle relative paths of files that are installed by this signing operation.
     pub installed_paths: BTreeSet<PathBuf>,
+    /// Binary identifiers derived from paths and the bundle relative paths they
+    /// were derived from.
+    ///
+    /// Used to detect multiple binaries being assigned the same identifier.
+    pub derived_identifiers: Vec<(String, PathBuf)>,
 }
 
 impl<'a, 'key> BundleSigningContext<'a, 'key> {
@@ -480,9 +528,15 @@ impl<'a, 'key> BundleSigningContext<'a, 'key> {
         // binary identifier from the filename so everything is consistent.
         // Unless an existing setting overrides it, of course.
         if settings.binary_identifier(SettingsScope::Main).is_none() {
-            let identifier = path_identifier(bundle_rel_path)?;
+            let identifier = normalize_derived_identifier(
+                &path_identifier(bundle_rel_path)?,
+                settings.normalize_identifiers(),
+            )?;
             info!("setting binary identifier based on path: {}", identifier);
 
+            self.derived_identifiers
+                .push((identifier.clone(), bundle_rel_path.to_path_buf()));
+
             settings.set_binary_identifier(SettingsScope::Main, &identifier);
         }
 
@@ -511,6 +565,10 @@ pub struct BundleSigningInfo {
 
     /// Bundle relative paths of files that are installed by this signing operation.
     pub installed_rel_paths: BTreeSet<PathBuf>,
+
+    /// Binary identifiers derived from paths and the bundle relative paths they
+    /// were derived from.
+    pub derived_identifiers: Vec<(String, PathBuf)>,
 }
 
 /// A primitive for signing a single Apple bundle.
@@ -551,6 +609,33 @@ impl SingleBundleSigner {
             dest_dir.display()
         );
 
+        // Mismatched localizations don't prevent signing or notarization. But they
+        // are likely a packaging mistake, so call attention to them. Many bundles
+        // don't declare their localizations at all, so only check bundles that do.
+        let differences = if self
+            .bundle
+            .info_plist()
+            .contains_key("CFBundleLocalizations")
+        {
+            self.bundle.localization_differences().ok()
+        } else {
+            None
+        };
+        if let Some(differences) = differences {
+            for locale in &differences.declared_missing {
+                warn!(
+                    "CFBundleLocalizations declares {} but no {}.lproj directory exists",
+                    locale, locale
+                );
+            }
+            for locale in &differences.present_undeclared {
+                warn!(
+                    "{}.lproj directory exists but {} is not declared in CFBundleLocalizations",
+                    locale, locale
+                );
+            }
+        }
+
         // Frameworks are a bit special.
         //
         // Modern frameworks typically have a `Versions/` directory containing directories
@@ -577,6 +662,7 @@ impl SingleBundleSigner {
                     settings,
                     previously_installed_paths,
                     installed_paths: Default::default(),
+                    derived_identifiers: Default::default(),
                 };
 
                 for file in self
@@ -593,6 +679,7 @@ impl SingleBundleSigner {
                 return Ok(BundleSigningInfo {
                     bundle,
                     installed_rel_paths: context.installed_paths,
+                    derived_identifiers: context.derived_identifiers,
                 });
             } else {
                 info!("found an unversioned framework; signing like normal");
@@ -638,6 +725,7 @@ impl SingleBundleSigner {
                 };
 
                 if need_sha1_sha256
+                    && settings.compatibility_target() == CompatibilityTarget::Automatic
                     && resources_digests != vec![DigestType::Sha1, DigestType::Sha256]
                 {
                     info!(
@@ -666,6 +754,11 @@ impl SingleBundleSigner {
 
         // Ensure emitted digests match what we're configured to emit.
         resources_builder.set_digests(resources_digests.into_iter());
+        resources_builder
+            .set_digest_cache_path(settings.resource_digest_cache().map(|p| p.to_path_buf()));
+        resources_builder.set_digest_backend(settings.digest_backend());
+        resources_builder
+            .set_version1_only(settings.compatibility_target().version1_resources_only());
 
         // Exclude code signature files we'll write.
         resources_builder.add_exclusion_rule(CodeResourcesRule::new("^_CodeSignature/")?.exclude());
@@ -689,11 +782,23 @@ impl SingleBundleSigner {
             );
         }
 
+        // Paths listed in the bundle's ignore file are omitted from sealing.
+        let ignore_path = self.bundle.resolve_path(IGNORE_FILE_NAME);
+        if ignore_path.is_file() {
+            info!("applying resource rules from {}", ignore_path.display());
+
+            for rule in ignore_file_rules(&std::fs::read_to_string(&ignore_path)?)? {
+                resources_builder.add_rule(rule.clone());
+                resources_builder.add_rule2(rule);
+            }
+        }
+
         let mut context = BundleSigningContext {
             dest_dir: dest_dir_root.clone(),
             settings,
             previously_installed_paths,
             installed_paths: Default::default(),
+            derived_identifiers: Default::default(),
         };
 
         resources_builder.walk_and_seal_directory(
@@ -770,6 +875,35 @@ impl SingleBundleSigner {
         Ok(BundleSigningInfo {
             bundle,
             installed_rel_paths: context.installed_paths,
+            derived_identifiers: context.derived_identifiers,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derived_identifier_collisions() {
+        let identifiers = vec![
+            ("helper".to_string(), PathBuf::from("Contents/MacOS/helper")),
+            (
+                "helper".to_string(),
+                PathBuf::from("Contents/Frameworks/A.framework/Versions/A/Helpers/helper"),
+            ),
+            ("tool".to_string(), PathBuf::from("Contents/MacOS/tool")),
+        ];
+
+        let collisions = identifier_collisions(&identifiers);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(
+            collisions.get("helper"),
+            Some(&vec![
+                Path::new("Contents/MacOS/helper"),
+                Path::new("Contents/Frameworks/A.framework/Versions/A/Helpers/helper"),
+            ])
+        );
+        assert!(identifier_collisions(&identifiers[1..]).is_empty());
+    }
+}
@@ -9,7 +9,7 @@ use {
         error::AppleCodesignError,
         remote_signing::{
             session_negotiation::{PublicKeyInitiator, SessionInitiatePeer, SharedSecretInitiator},
-            RemoteSignError, UnjoinedSigningClient,
+            RemoteSignError,
         },
         signing_settings::SigningSettings,
     },
@@ -22,6 +22,9 @@ use {
     x509_certificate::CapturedX509Certificate,
 };
 
+#[cfg(feature = "remote-signing")]
+use crate::remote_signing::UnjoinedSigningClient;
+
 #[cfg(feature = "yubikey")]
 use {
     crate::{cli::prompt_smartcard_pin, yubikey::YubiKey},
@@ -73,6 +76,19 @@ impl SigningCertificates {
         }
     }
 
+    /// Retain only the signing identity at the given index.
+    ///
+    /// Keys and certificates are paired by index. Certificates without a
+    /// corresponding key are retained as extra certificates.
+    pub fn retain_identity(&mut self, index: usize) {
+        let extra = self.certs.split_off(self.keys.len().min(self.certs.len()));
+        let key = self.keys.swap_remove(index);
+        let cert = self.certs.swap_remove(index);
+
+        self.keys = vec![key];
+        self.certs = std::iter::once(cert).chain(extra).collect();
+    }
+
     /// Loads the instance into a [SigningSettings].
     pub fn load_into_signing_settings<'settings, 'slf: 'settings>(
         &'slf self,
@@ -219,34 +235,62 @@ pub struct MacosKeychainSigningKey {
     pub sha256_fingerprint: Option<String>,
 }
 
-impl KeySource for MacosKeychainSigningKey {
+impl MacosKeychainSigningKey {
+    /// Resolve the keychain domains to search.
     #[cfg(target_os = "macos")]
-    fn resolve_certificates(&self) -> Result<SigningCertificates, AppleCodesignError> {
-        // No arguments pertinent to keychains. Don't even speak to the
-        // keychain API since this could only error.
-        if self.domains.is_empty() && self.sha256_fingerprint.is_none() {
-            return Ok(Default::default());
-        }
-
-        // Collect all the keychain domains to search.
+    fn keychain_domains(&self) -> Vec<KeychainDomain> {
         let domains = if self.domains.is_empty() {
             vec!["user".to_string()]
         } else {
             self.domains.clone()
         };
 
-        let domains = domains
+        domains
             .into_iter()
             .map(|domain| {
                 KeychainDomain::try_from(domain.as_str())
                     .expect("clap should have validated domain values")
             })
-            .collect::<Vec<_>>();
+            .collect::<Vec<_>>()
+    }
+
+    /// Resolve all code signing identities in the configured keychain domains.
+    ///
+    /// Unlike [KeySource::resolve_certificates], this ignores the fingerprint
+    /// filter and returns every identity found.
+    #[cfg(target_os = "macos")]
+    pub fn resolve_all_identities(&self) -> Result<SigningCertificates, AppleCodesignError> {
+        let mut res = SigningCertificates::default();
+
+        for domain in self.keychain_domains() {
+            for cert in keychain_find_code_signing_certificates(domain, None)? {
+                res.certs.push(cert.as_captured_x509_certificate());
+                res.keys.push(Box::new(cert));
+            }
+        }
+
+        Ok(res)
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn resolve_all_identities(&self) -> Result<SigningCertificates, AppleCodesignError> {
+        Ok(Default::default())
+    }
+}
+
+impl KeySource for MacosKeychainSigningKey {
+    #[cfg(target_os = "macos")]
+    fn resolve_certificates(&self) -> Result<SigningCertificates, AppleCodesignError> {
+        // No arguments pertinent to keychains. Don't even speak to the
+        // keychain API since this could only error.
+        if self.domains.is_empty() && self.sha256_fingerprint.is_none() {
+            return Ok(Default::default());
+        }
 
         // Now iterate all the keychains and try to find requested certificates.
         let mut res = SigningCertificates::default();
 
-        for domain in domains {
+        for domain in self.keychain_domains() {
             for cert in keychain_find_code_signing_certificates(domain, None)? {
                 let matches = if let Some(wanted_fingerprint) = &self.sha256_fingerprint {
                     let got_fingerprint = hex::encode(cert.sha256_fingerprint()?.as_ref());
@@ -486,6 +530,7 @@ pub struct RemoteSigningKey {
 }
 
 impl KeySource for RemoteSigningKey {
+    #[cfg(feature = "remote-signing")]
     fn resolve_certificates(&self) -> Result<SigningCertificates, AppleCodesignError> {
         if let Some(initiator) = self.remote_signing_initiator()? {
             let client = UnjoinedSigningClient::new_initiator(
@@ -506,6 +551,18 @@ impl KeySource for RemoteSigningKey {
         }
     }
 
+    #[cfg(not(feature = "remote-signing"))]
+    fn resolve_certificates(&self) -> Result<SigningCertificates, AppleCodesignError> {
+        if self.remote_signing_initiator()?.is_some() {
+            Err(AppleCodesignError::FeatureDisabled(
+                "remote signing",
+                "remote-signing",
+            ))
+        } else {
+            Ok(Default::default())
+        }
+    }
+
     fn exclusive(&self) -> bool {
         true
     }
@@ -6,20 +6,79 @@ use {
     crate::{
         cli::{certificate_source::CertificateSource, ScopedSigningSettingsValues},
         error::AppleCodesignError,
+        key_usage_policy::KeyUsagePolicy,
     },
     figment::{
         providers::{Env, Format, Serialized, Toml},
         Figment,
     },
     log::debug,
-    serde::{Deserialize, Serialize},
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
     std::{
         collections::BTreeMap,
         ops::{Deref, DerefMut},
-        path::Path,
+        path::{Path, PathBuf},
     },
 };
 
+/// Merge `overrides` into `base` field by field.
+///
+/// Fields that `overrides` leaves unset keep their value from `base`.
+fn merge_fields<T: Serialize + DeserializeOwned>(
+    base: &T,
+    overrides: &T,
+) -> Result<T, AppleCodesignError> {
+    fn merge(base: &mut serde_json::Value, overrides: serde_json::Value) {
+        match (base, overrides) {
+            (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+                for (key, value) in overrides {
+                    merge(base.entry(key).or_insert(serde_json::Value::Null), value);
+                }
+            }
+            (_, serde_json::Value::Null) => {}
+            (base, value) => *base = value,
+        }
+    }
+
+    let mut value = serde_json::to_value(base)?;
+    merge(&mut value, serde_json::to_value(overrides)?);
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Merge the signer defined by CLI arguments into a configured signer.
+///
+/// Fields set by `cli` win. If `cli` defines any signer source, configured
+/// sources of other types are dropped so they don't leak into the signer.
+fn merge_signer(
+    base: &CertificateSource,
+    cli: &CertificateSource,
+) -> Result<CertificateSource, AppleCodesignError> {
+    if *cli == CertificateSource::default() {
+        return Ok(base.clone());
+    }
+
+    let mut base = serde_json::to_value(base)?;
+    let cli = serde_json::to_value(cli)?;
+
+    if let (serde_json::Value::Object(base), serde_json::Value::Object(cli)) = (&mut base, &cli) {
+        base.retain(|key, _| cli.contains_key(key));
+    }
+
+    merge_fields(
+        &serde_json::from_value::<CertificateSource>(base)?,
+        &serde_json::from_value(cli)?,
+    )
+}
+
+/// Resolve the absolute path of `path`, resolving symlinks if it exists.
+fn absolute_path(path: &Path) -> Result<PathBuf, AppleCodesignError> {
+    Ok(match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => std::env::current_dir()?.canonicalize()?.join(path),
+    })
+}
+
 /// Configuration file profile definition.
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
@@ -43,6 +102,105 @@ pub struct SignConfig {
     /// Keys are scope paths. Values are per-path configs.
     #[serde(default, rename = "path", skip_serializing_if = "BTreeMap::is_empty")]
     pub paths: BTreeMap<String, ScopedSigningSettingsValues>,
+
+    /// Keys are glob patterns matching signed artifacts. Values are overrides.
+    #[serde(
+        default,
+        rename = "artifact",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub artifacts: BTreeMap<String, ArtifactSignConfig>,
+
+    /// Constraints on the use of the signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_usage_policy: Option<KeyUsagePolicy>,
+}
+
+impl SignConfig {
+    /// Resolve the configuration for signing the artifact at `path`.
+    ///
+    /// Artifact sections whose pattern matches `path` are applied in pattern
+    /// order. Patterns are matched against `path` relative to the directory of
+    /// the config file defining them, or the current directory if they weren't
+    /// defined by a file. If they define any signer source, it replaces the
+    /// entire signer, so sources of other types don't leak into the artifact's
+    /// signer. Per-path settings they define are merged field by field.
+    ///
+    /// `cli` holds the settings given as CLI arguments. They take precedence
+    /// over all configured settings and are merged field by field.
+    ///
+    /// The key usage policy can't be overridden.
+    pub fn for_artifact(&self, path: &Path, cli: &Self) -> Result<Self, AppleCodesignError> {
+        let mut config = Self {
+            signer: self.signer.clone(),
+            paths: self.paths.clone(),
+            artifacts: BTreeMap::new(),
+            key_usage_policy: self.key_usage_policy.clone(),
+        };
+
+        let path = absolute_path(path)?;
+
+        for (pattern, artifact) in &self.artifacts {
+            let base_dir = match &artifact.base_dir {
+                Some(dir) => absolute_path(dir)?,
+                None => absolute_path(Path::new("."))?,
+            };
+            let relative = path
+                .strip_prefix(&base_dir)
+                .unwrap_or(path.as_path())
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            if !glob::Pattern::new(pattern)?.matches(&relative) {
+                continue;
+            }
+
+            debug!("applying artifact settings {} to {}", pattern, relative);
+
+            if artifact.signer != CertificateSource::default() {
+                config.signer = artifact.signer.clone();
+            }
+
+            for (scope, values) in &artifact.paths {
+                let merged = match config.paths.get(scope) {
+                    Some(base) => merge_fields(base, values)?,
+                    None => values.clone(),
+                };
+                config.paths.insert(scope.clone(), merged);
+            }
+        }
+
+        config.signer = merge_signer(&config.signer, &cli.signer)?;
+
+        for (scope, values) in &cli.paths {
+            let merged = match config.paths.get(scope) {
+                Some(base) => merge_fields(base, values)?,
+                None => values.clone(),
+            };
+            config.paths.insert(scope.clone(), merged);
+        }
+
+        Ok(config)
+    }
+}
+
+/// Configuration overriding [SignConfig] for matching artifacts.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArtifactSignConfig {
+    /// Defines a source for the cryptographic signing key.
+    #[serde(default)]
+    pub signer: CertificateSource,
+
+    /// Keys are scope paths. Values are per-path configs.
+    #[serde(default, rename = "path", skip_serializing_if = "BTreeMap::is_empty")]
+    pub paths: BTreeMap<String, ScopedSigningSettingsValues>,
+
+    /// Directory of the config file defining this section.
+    ///
+    /// The section's pattern is relative to it.
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
@@ -51,6 +209,12 @@ pub struct RemoteSignConfig {
     /// Defines a source for the cryptographic signing key.
     #[serde(default)]
     pub signer: CertificateSource,
+
+    /// Constraints on the use of the signing key.
+    ///
+    /// Only `signing_hours` can be enforced by the remote signer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_usage_policy: Option<KeyUsagePolicy>,
 }
 
 /// Used to instantiate [Config] instances.
@@ -152,7 +316,22 @@ impl ConfigBuilder {
 
     /// Obtain a config profile.
     pub fn config(self) -> Result<Config, AppleCodesignError> {
-        Ok(self.loader.extract()?)
+        let mut config = self.loader.extract::<Config>()?;
+
+        // Artifact patterns are relative to the config file defining them.
+        if let Ok(figment::value::Value::Dict(_, artifacts)) =
+            self.loader.find_value("sign.artifact")
+        {
+            for (pattern, artifact) in config.sign.artifacts.iter_mut() {
+                artifact.base_dir = artifacts
+                    .get(pattern)
+                    .and_then(|value| self.loader.get_metadata(value.tag()))
+                    .and_then(|metadata| metadata.source.as_ref()?.file_path()?.parent())
+                    .map(|dir| dir.to_path_buf());
+            }
+        }
+
+        Ok(config)
     }
 }
 
@@ -415,6 +594,7 @@ mod test {
             code_resources_file = "code-resources"
             code_signature_flags = ["runtime"]
             digests = ["sha1", "sha256"]
+            alternate_code_directories = ["sha384"]
             entitlements_xml_file = "entitlements.plist"
             launch_constraints_self_file = "lc-self"
             launch_constraints_parent_file = "lc-parent"
@@ -436,6 +616,7 @@ mod test {
                     code_resources_file: Some("code-resources".into()),
                     code_signature_flags: vec!["runtime".into()],
                     digests: vec!["sha1".into(), "sha256".into()],
+                    alternate_code_directories: vec!["sha384".into()],
                     entitlements_xml_file: Some("entitlements.plist".into()),
                     launch_constraints_self_file: Some("lc-self".into()),
                     launch_constraints_parent_file: Some("lc-parent".into()),
@@ -447,4 +628,196 @@ mod test {
             )])
         );
     }
+
+    #[test]
+    fn key_usage_policy() {
+        let c = ConfigBuilder::default()
+            .toml_string(
+                r#"
+                [default.sign.key_usage_policy]
+                allowed_identifiers = ["com.example.*"]
+                signing_hours = "09:00-17:00"
+
+                [default.sign.artifact."*.pkg"]
+                signer.p12 = { path = "installer.p12" }
+                "#,
+            )
+            .config()
+            .unwrap();
+
+        let policy = KeyUsagePolicy {
+            allowed_identifiers: vec!["com.example.*".into()],
+            signing_hours: Some("09:00-17:00".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(c.sign.key_usage_policy, Some(policy.clone()));
+        assert_eq!(
+            c.sign
+                .for_artifact(Path::new("MyApp.pkg"), &SignConfig::default())
+                .unwrap()
+                .key_usage_policy,
+            Some(policy)
+        );
+    }
+
+    #[test]
+    fn artifact_overrides() {
+        let c = ConfigBuilder::default()
+            .toml_string(
+                r#"
+                [default.sign]
+                signer.smartcard = { slot = "9c" }
+
+                [default.sign.path."Contents/MacOS/bin"]
+                binary_identifier = "base"
+
+                [release.sign.artifact."dist/*.pkg"]
+                signer.p12 = { path = "installer.p12" }
+
+                [release.sign.artifact."dist/*.pkg".path."Contents/MacOS/bin"]
+                binary_identifier = "override"
+                "#,
+            )
+            .profile("release".into())
+            .config()
+            .unwrap();
+
+        let cli = SignConfig::default();
+
+        let app = c
+            .sign
+            .for_artifact(Path::new("dist/MyApp.app"), &cli)
+            .unwrap();
+        assert!(app.signer.p12_key.is_none());
+        assert_eq!(
+            app.paths["Contents/MacOS/bin"].binary_identifier,
+            Some("base".into())
+        );
+
+        let pkg = c
+            .sign
+            .for_artifact(Path::new("dist/MyApp.pkg"), &cli)
+            .unwrap();
+        assert!(pkg.signer.smartcard_key.is_none());
+        assert_eq!(
+            pkg.signer.p12_key.as_ref().and_then(|key| key.path.clone()),
+            Some(PathBuf::from("installer.p12"))
+        );
+        assert_eq!(
+            pkg.paths["Contents/MacOS/bin"].binary_identifier,
+            Some("override".into())
+        );
+    }
+
+    #[test]
+    fn artifact_cli_precedence() {
+        let c = ConfigBuilder::default()
+            .toml_string(
+                r#"
+                [default.sign.artifact."*.pkg"]
+                signer.p12 = { path = "installer.p12", password = "secret" }
+
+                [default.sign.artifact."*.pkg".path."Contents/MacOS/bin"]
+                binary_identifier = "config"
+                entitlements_xml_file = "config.plist"
+                "#,
+            )
+            .config()
+            .unwrap();
+
+        // A CLI source of the same type is merged field by field.
+        let cli = SignConfig {
+            signer: CertificateSource {
+                p12_key: Some(P12SigningKey {
+                    path: Some("cli.p12".into()),
+                    password: None,
+                    password_path: None,
+                }),
+                ..Default::default()
+            },
+            paths: BTreeMap::from_iter([(
+                "Contents/MacOS/bin".into(),
+                ScopedSigningSettingsValues {
+                    binary_identifier: Some("cli".into()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let pkg = c.sign.for_artifact(Path::new("MyApp.pkg"), &cli).unwrap();
+        assert_eq!(
+            pkg.signer.p12_key,
+            Some(P12SigningKey {
+                path: Some("cli.p12".into()),
+                password: Some("secret".into()),
+                password_path: None,
+            })
+        );
+        assert_eq!(
+            pkg.paths["Contents/MacOS/bin"].binary_identifier,
+            Some("cli".into())
+        );
+        assert_eq!(
+            pkg.paths["Contents/MacOS/bin"].entitlements_xml_file,
+            Some("config.plist".into())
+        );
+
+        // A CLI source of another type replaces the configured one.
+        let cli = SignConfig {
+            signer: CertificateSource {
+                smartcard_key: Some(SmartcardSigningKey {
+                    slot: Some("9c".into()),
+                    pin: None,
+                    pin_env: None,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let pkg = c.sign.for_artifact(Path::new("MyApp.pkg"), &cli).unwrap();
+        assert!(pkg.signer.p12_key.is_none());
+        assert!(pkg.signer.smartcard_key.is_some());
+    }
+
+    #[test]
+    fn artifact_relative_to_config() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let config_path = td.path().join("rcodesign.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [default.sign.artifact."dist/*.pkg"]
+            signer.p12 = { path = "installer.p12" }
+            "#,
+        )?;
+        std::fs::create_dir(td.path().join("dist"))?;
+        let pkg_path = td.path().join("dist/MyApp.pkg");
+        std::fs::write(&pkg_path, b"pkg")?;
+
+        let c = ConfigBuilder::default().toml_file(&config_path).config()?;
+        assert_eq!(
+            c.sign.artifacts["dist/*.pkg"].base_dir.as_deref(),
+            Some(td.path())
+        );
+
+        // The artifact path is resolved before matching, so it matches
+        // regardless of how it is spelled.
+        let spelled = td.path().join("dist/../dist/./MyApp.pkg");
+        for path in [&pkg_path, &spelled] {
+            let pkg = c.sign.for_artifact(path, &SignConfig::default())?;
+            assert!(pkg.signer.p12_key.is_some());
+        }
+
+        // Paths relative to another directory don't match.
+        let other = td.path().join("other/dist/MyApp.pkg");
+        std::fs::create_dir_all(other.parent().unwrap())?;
+        std::fs::write(&other, b"pkg")?;
+        let pkg = c.sign.for_artifact(&other, &SignConfig::default())?;
+        assert!(pkg.signer.p12_key.is_none());
+
+        Ok(())
+    }
 }
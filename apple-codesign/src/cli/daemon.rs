@@ -0,0 +1,617 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The `daemon` command.
+
+use {
+    crate::{
+        cli::{
+            certificate_source::CertificateSource,
+            config::{Config, SignConfig},
+            CliCommand, Context, ScopedSigningSettings, SigningSettingsArgs,
+        },
+        error::AppleCodesignError,
+        signing::UnifiedSigner,
+        signing_settings::SigningSettings,
+        PathType,
+    },
+    clap::Parser,
+    log::{error, warn},
+    serde::{Deserialize, Serialize},
+    std::{
+        io::{BufRead, BufReader, Write},
+        os::{
+            fd::AsRawFd,
+            unix::{
+                fs::PermissionsExt,
+                net::{UnixListener, UnixStream},
+            },
+        },
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc,
+        },
+        time::Duration,
+    },
+};
+
+/// How long a connection may stay idle before it is closed.
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often to check whether the daemon is stopping while waiting for connections.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A request sent to the daemon.
+///
+/// Requests are JSON objects, one per line.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum DaemonRequest {
+    /// Sign a path, in place unless an output path is given.
+    Sign {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+
+    /// Stop the daemon.
+    Shutdown,
+}
+
+/// The response to a request.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+struct DaemonResponse {
+    ok: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<Result<(), AppleCodesignError>> for DaemonResponse {
+    fn from(res: Result<(), AppleCodesignError>) -> Self {
+        Self {
+            ok: res.is_ok(),
+            error: res.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// A request waiting to be processed by the signing thread.
+struct Job {
+    request: DaemonRequest,
+    reply: mpsc::Sender<DaemonResponse>,
+}
+
+#[derive(Parser)]
+pub struct Daemon {
+    #[command(flatten)]
+    settings: SigningSettingsArgs,
+
+    /// Path of the Unix domain socket to accept requests on
+    ///
+    /// The socket must not exist. It is removed when the daemon stops.
+    #[arg(long, value_name = "PATH")]
+    socket: PathBuf,
+
+    #[command(flatten)]
+    certificate: CertificateSource,
+}
+
+impl Daemon {
+    /// Resolve signing settings and serve requests until a shutdown request.
+    fn sign_requests(
+        &self,
+        context: &Context,
+        listener: UnixListener,
+    ) -> Result<(), AppleCodesignError> {
+        let c = &context.config.sign;
+
+        let mut settings = SigningSettings::default();
+
+        let certs = c.signer.resolve_certificates(true)?;
+        certs.load_into_signing_settings(&mut settings)?;
+
+        self.settings.load_into_settings(&mut settings, c)?;
+
+        // Settings are locked in. Certificates, keys, and settings now stay
+        // warm for the lifetime of the daemon.
+        warn!(
+            "accepting signing requests on {}; send a shutdown request to stop",
+            self.socket.display()
+        );
+        serve(listener, &settings)?;
+
+        if let Some(private) = certs.private_key_optional()? {
+            private.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Create a Unix domain socket at `path` only the current user can connect to.
+///
+/// Anyone able to connect can sign with our key. The socket is created in a
+/// private staging directory and linked into place after its permissions are
+/// restricted, so other users can't connect in between. Linking fails if the
+/// path exists, so existing files are never replaced.
+fn bind_socket(path: &Path) -> Result<UnixListener, AppleCodesignError> {
+    let error = |e: std::io::Error| {
+        AppleCodesignError::CliGeneralError(format!(
+            "unable to listen on {}: {}",
+            path.display(),
+            e
+        ))
+    };
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let staging = tempfile::Builder::new()
+        .prefix(".rcodesign-daemon-")
+        .permissions(std::fs::Permissions::from_mode(0o700))
+        .tempdir_in(parent)
+        .map_err(error)?;
+    let staged = staging.path().join("socket");
+
+    let listener = UnixListener::bind(&staged).map_err(error)?;
+    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600)).map_err(error)?;
+    std::fs::hard_link(&staged, path).map_err(error)?;
+
+    Ok(listener)
+}
+
+/// Resolve the user ID of the process on the other end of a connection.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    // SAFETY: the buffer is a valid ucred and its length is passed.
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+
+    if res == 0 {
+        Ok(cred.uid)
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Resolve the user ID of the process on the other end of a connection.
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+
+    // SAFETY: both out pointers are valid.
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == 0 {
+        Ok(uid)
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Resolve the user ID of the process on the other end of a connection.
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn peer_uid(_stream: &UnixStream) -> std::io::Result<u32> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "peer credentials are not supported on this platform",
+    ))
+}
+
+/// Ensure a connection comes from the user running the daemon.
+///
+/// Socket permissions already restrict connections. But this also rejects
+/// clients of other users that could access the socket anyway, e.g. because
+/// the socket directory was shared.
+fn authorize_peer(stream: &UnixStream) -> Result<(), AppleCodesignError> {
+    let uid = peer_uid(stream)?;
+    // SAFETY: geteuid() has no preconditions and can't fail.
+    let our_uid = unsafe { libc::geteuid() };
+
+    if uid == our_uid {
+        Ok(())
+    } else {
+        Err(AppleCodesignError::CliGeneralError(format!(
+            "rejecting connection from user {}",
+            uid
+        )))
+    }
+}
+
+/// Accept connections and process their requests until a shutdown request.
+///
+/// Each connection is read on its own thread, so an idle client can't block
+/// others. Signing keys can't be shared across threads, so requests are
+/// passed to the calling thread and signed one at a time.
+fn serve(listener: UnixListener, settings: &SigningSettings) -> Result<(), AppleCodesignError> {
+    listener.set_nonblocking(true)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (jobs, queue) = mpsc::channel::<Job>();
+
+    let acceptor = {
+        let stop = stop.clone();
+        std::thread::spawn(move || accept_connections(listener, &stop, jobs))
+    };
+
+    // Consuming the queue drops it once we stop, which fails requests of
+    // other connections still in flight.
+    for job in queue {
+        let (response, shutdown) = match job.request {
+            DaemonRequest::Sign { input, output } => (
+                DaemonResponse::from(sign(settings, &input, output.as_deref())),
+                false,
+            ),
+            DaemonRequest::Shutdown => (
+                DaemonResponse {
+                    ok: true,
+                    error: None,
+                },
+                true,
+            ),
+        };
+
+        // The client may have gone away in the meantime.
+        job.reply.send(response).ok();
+
+        if shutdown {
+            warn!("shutdown requested");
+            break;
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+
+    acceptor.join().map_err(|_| {
+        AppleCodesignError::CliGeneralError("thread accepting connections panicked".into())
+    })
+}
+
+/// Accept connections and spawn a thread for each until the daemon stops.
+fn accept_connections(listener: UnixListener, stop: &AtomicBool, jobs: mpsc::Sender<Job>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let jobs = jobs.clone();
+
+                // A failing client shouldn't take down the daemon.
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &jobs) {
+                        error!("error handling connection: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => {
+                error!("error accepting connection: {}", e);
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Process the requests of a connection.
+///
+/// The connection is closed after a shutdown request.
+fn handle_connection(
+    stream: UnixStream,
+    jobs: &mpsc::Sender<Job>,
+) -> Result<(), AppleCodesignError> {
+    // Accepted sockets may inherit the listener's non-blocking mode.
+    stream.set_nonblocking(false)?;
+    authorize_peer(&stream)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, shutdown) = match serde_json::from_str::<DaemonRequest>(&line) {
+            Ok(request) => {
+                let shutdown = request == DaemonRequest::Shutdown;
+                (submit(jobs, request), shutdown)
+            }
+            Err(e) => (
+                DaemonResponse {
+                    ok: false,
+                    error: Some(format!("invalid request: {}", e)),
+                },
+                false,
+            ),
+        };
+
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+
+        if shutdown {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pass a request to the signing thread and wait for its response.
+fn submit(jobs: &mpsc::Sender<Job>, request: DaemonRequest) -> DaemonResponse {
+    let (reply, response) = mpsc::channel();
+
+    jobs.send(Job { request, reply })
+        .ok()
+        .and_then(|_| response.recv().ok())
+        .unwrap_or_else(|| {
+            DaemonResponse::from(Err(AppleCodesignError::CliGeneralError(
+                "daemon is shutting down".into(),
+            )))
+        })
+}
+
+fn sign(
+    settings: &SigningSettings,
+    input: &Path,
+    output: Option<&Path>,
+) -> Result<(), AppleCodesignError> {
+    settings.ensure_preset_settings(PathType::from_path(input)? == PathType::Xar)?;
+
+    let signer = UnifiedSigner::new(settings.clone());
+
+    if let Some(output) = output {
+        warn!("signing {} to {}", input.display(), output.display());
+        signer.sign_path(input, output)
+    } else {
+        warn!("signing {} in place", input.display());
+        signer.sign_path_in_place(input)
+    }
+}
+
+impl CliCommand for Daemon {
+    fn as_config(&self) -> Result<Option<Config>, AppleCodesignError> {
+        let paths = ScopedSigningSettings::try_from(&self.settings.scoped)?;
+
+        Ok(Some(Config {
+            sign: SignConfig {
+                signer: self.certificate.clone(),
+                paths: paths.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+    }
+
+    fn run(&self, context: &Context) -> Result<(), AppleCodesignError> {
+        // Binding first ensures we fail before prompting for any PINs.
+        let listener = bind_socket(&self.socket)?;
+
+        let res = self.sign_requests(context, listener);
+
+        if let Err(e) = std::fs::remove_file(&self.socket) {
+            // Don't mask why the daemon stopped.
+            error!("unable to remove {}: {}", self.socket.display(), e);
+            res?;
+            return Err(e.into());
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            certificate::{create_self_signed_code_signing_certificate, CertificateProfile},
+            key_usage_policy::KeyUsagePolicy,
+            macho::MachFile,
+            macho_builder::MachOBuilder,
+        },
+        x509_certificate::KeyAlgorithm,
+    };
+
+    #[test]
+    fn request_parsing() -> Result<(), AppleCodesignError> {
+        assert_eq!(
+            serde_json::from_str::<DaemonRequest>(r#"{"command": "sign", "input": "/app"}"#)?,
+            DaemonRequest::Sign {
+                input: "/app".into(),
+                output: None,
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonRequest>(
+                r#"{"command": "sign", "input": "/app", "output": "/signed"}"#
+            )?,
+            DaemonRequest::Sign {
+                input: "/app".into(),
+                output: Some("/signed".into()),
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<DaemonRequest>(r#"{"command": "shutdown"}"#)?,
+            DaemonRequest::Shutdown
+        );
+        assert!(serde_json::from_str::<DaemonRequest>(r#"{"command": "sign"}"#).is_err());
+
+        assert_eq!(
+            serde_json::to_string(&DaemonResponse::from(Ok::<_, AppleCodesignError>(())))?,
+            r#"{"ok":true}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&DaemonResponse::from(Err(
+                AppleCodesignError::CliGeneralError("bad".into())
+            )))?,
+            r#"{"ok":false,"error":"bad"}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn authorize_peer_same_user() -> Result<(), AppleCodesignError> {
+        let (client, server) = UnixStream::pair()?;
+
+        authorize_peer(&server)?;
+        authorize_peer(&client)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn socket_round_trip() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let socket = temp_dir.path().join("rcodesign.sock");
+        let input = temp_dir.path().join("tool");
+        let output = temp_dir.path().join("tool-signed");
+
+        std::fs::write(
+            &input,
+            MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?,
+        )?;
+
+        let listener = bind_socket(&socket)?;
+        assert_eq!(
+            std::fs::metadata(&socket)?.permissions().mode() & 0o777,
+            0o600
+        );
+        // The staging directory is cleaned up.
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 2);
+        // Existing paths are never replaced.
+        assert!(bind_socket(&socket).is_err());
+
+        let server = std::thread::spawn(move || serve(listener, &SigningSettings::default()));
+
+        let stream = UnixStream::connect(&socket)?;
+        let mut writer = stream.try_clone()?;
+        let mut lines = BufReader::new(stream).lines();
+
+        writeln!(
+            writer,
+            "{}",
+            serde_json::json!({
+                "command": "sign",
+                "input": input,
+                "output": output,
+            })
+        )?;
+        assert_eq!(lines.next().transpose()?.as_deref(), Some(r#"{"ok":true}"#));
+
+        for binary in MachFile::parse(&std::fs::read(&output)?)?.into_iter() {
+            assert!(binary.code_signature()?.is_some());
+        }
+
+        writeln!(writer, "not json")?;
+        assert!(lines
+            .next()
+            .transpose()?
+            .is_some_and(|line| line.starts_with(r#"{"ok":false,"error":"invalid request"#)));
+
+        writeln!(writer, r#"{{"command": "shutdown"}}"#)?;
+        assert_eq!(lines.next().transpose()?.as_deref(), Some(r#"{"ok":true}"#));
+
+        server.join().expect("server thread should not panic")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_usage_policy_per_request() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let socket = temp_dir.path().join("rcodesign.sock");
+        let allowed = temp_dir.path().join("example-tool");
+        let denied = temp_dir.path().join("other-tool");
+
+        for path in [&allowed, &denied] {
+            std::fs::write(
+                path,
+                MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?,
+            )?;
+        }
+
+        let listener = bind_socket(&socket)?;
+
+        // Signing keys aren't Send, so the daemon side is set up on its thread.
+        let server = std::thread::spawn(move || -> Result<(), AppleCodesignError> {
+            let (cert, key) = create_self_signed_code_signing_certificate(
+                KeyAlgorithm::Ed25519,
+                CertificateProfile::DeveloperIdApplication,
+                "team",
+                "Joe Developer",
+                "US",
+                chrono::Duration::hours(1),
+            )?;
+
+            // The policy comes from the daemon's settings, not from the client.
+            let mut settings = SigningSettings::default();
+            settings.set_signing_key(&key, cert);
+            settings.set_key_usage_policy(Some(KeyUsagePolicy {
+                allowed_identifiers: vec!["example-*".into()],
+                ..Default::default()
+            }))?;
+
+            serve(listener, &settings)
+        });
+
+        let stream = UnixStream::connect(&socket)?;
+        let mut writer = stream.try_clone()?;
+        let mut lines = BufReader::new(stream).lines();
+
+        for (input, ok) in [(&denied, false), (&allowed, true), (&denied, false)] {
+            writeln!(
+                writer,
+                "{}",
+                serde_json::json!({"command": "sign", "input": input})
+            )?;
+            let line = lines.next().transpose()?.unwrap_or_default();
+            assert_eq!(line.starts_with(r#"{"ok":true"#), ok, "{}", line);
+        }
+
+        writeln!(writer, r#"{{"command": "shutdown"}}"#)?;
+        assert_eq!(lines.next().transpose()?.as_deref(), Some(r#"{"ok":true}"#));
+
+        server.join().expect("server thread should not panic")?;
+
+        assert!(MachFile::parse(&std::fs::read(&allowed)?)?
+            .into_iter()
+            .all(|binary| binary.code_signature().is_ok_and(|sig| sig.is_some())));
+
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use {
     crate::{
         cli::{CliCommand, Context},
         code_requirement::CodeRequirements,
-        cryptography::DigestType,
+        cryptography::{DigestBackendType, DigestType},
         error::{AppleCodesignError, Result},
     },
     clap::{Parser, ValueEnum},
@@ -345,6 +345,71 @@ impl CliCommand for DebugCreateMachO {
     }
 }
 
+#[derive(Parser)]
+pub struct DebugDigestBenchmark {
+    /// Digest algorithm to benchmark.
+    #[arg(long, default_value_t = DigestType::Sha256)]
+    digest: DigestType,
+
+    /// Size in megabytes of the data to digest.
+    #[arg(long, default_value = "256")]
+    size_mb: usize,
+
+    /// Number of times to digest the data with each backend.
+    #[arg(long, default_value = "3")]
+    iterations: usize,
+}
+
+impl CliCommand for DebugDigestBenchmark {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let data = vec![0x42u8; self.size_mb * 1024 * 1024];
+        let mut digests = vec![];
+
+        for backend_type in DigestBackendType::value_variants() {
+            let backend = backend_type.backend();
+            let mut best = std::time::Duration::MAX;
+            let mut digest = vec![];
+
+            if let Err(e) = backend.hasher(self.digest) {
+                println!("{}: skipped ({})", backend.name(), e);
+                continue;
+            }
+
+            for _ in 0..self.iterations.max(1) {
+                let start = std::time::Instant::now();
+
+                // Feed data in chunks like signing operations do.
+                let mut hasher = backend.hasher(self.digest)?;
+                for chunk in data.chunks(16384) {
+                    hasher.update(chunk);
+                }
+                digest = hasher.finish();
+
+                best = best.min(start.elapsed());
+            }
+
+            println!(
+                "{}: {} {} MB in {:.3}s ({:.1} MB/s)",
+                backend.name(),
+                self.digest,
+                self.size_mb,
+                best.as_secs_f64(),
+                self.size_mb as f64 / best.as_secs_f64()
+            );
+
+            digests.push(digest);
+        }
+
+        if digests.windows(2).any(|w| w[0] != w[1]) {
+            return Err(AppleCodesignError::LogicError(
+                "digest backends produced different digests".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Parser)]
 pub struct DebugFileTree {
     /// Directory to walk.
@@ -0,0 +1,566 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The `diagnose` command.
+
+use {
+    crate::{
+        certificate::AppleCertificate,
+        cli::{certificate_source::CertificateSource, CliCommand, Context},
+        error::AppleCodesignError,
+    },
+    clap::Parser,
+};
+
+#[cfg(feature = "network")]
+use {
+    crate::{
+        cli::APPLE_TIMESTAMP_URL,
+        ticket_lookup::{default_client, APPLE_TICKET_LOOKUP_URL},
+    },
+    std::time::Duration,
+};
+
+/// App Store Connect API endpoint probed for reachability.
+#[cfg(feature = "network")]
+const APP_STORE_CONNECT_URL: &str = "https://api.appstoreconnect.apple.com/v1/";
+
+/// Clock skew in seconds above which we warn.
+///
+/// App Store Connect rejects tokens issued in the future. Some time-stamp
+/// servers are similarly strict.
+#[cfg(feature = "network")]
+const CLOCK_SKEW_WARNING_SECONDS: i64 = 30;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Severity {
+    Ok,
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// The result of a single diagnostic check.
+struct Finding {
+    check: &'static str,
+    severity: Severity,
+    message: String,
+    remedy: Option<String>,
+}
+
+impl Finding {
+    fn new(check: &'static str, severity: Severity, message: impl ToString) -> Self {
+        Self {
+            check,
+            severity,
+            message: message.to_string(),
+            remedy: None,
+        }
+    }
+
+    fn with_remedy(mut self, remedy: impl ToString) -> Self {
+        self.remedy = Some(remedy.to_string());
+        self
+    }
+}
+
+/// Describe the clock skew relative to a server, in seconds.
+///
+/// Positive skew means the local clock is ahead of the server.
+#[cfg(feature = "network")]
+fn clock_finding(skew: Option<(&str, i64)>) -> Finding {
+    match skew {
+        Some((name, seconds)) if seconds.abs() > CLOCK_SKEW_WARNING_SECONDS => Finding::new(
+            "clock",
+            Severity::Warning,
+            format!(
+                "local clock is {}s {} the {}",
+                seconds.abs(),
+                if seconds > 0 { "ahead of" } else { "behind" },
+                name
+            ),
+        )
+        .with_remedy(
+            "synchronize the system clock (e.g. via NTP); skew causes App Store Connect to \
+            reject API tokens",
+        ),
+        Some((name, seconds)) => Finding::new(
+            "clock",
+            Severity::Ok,
+            format!("local clock within {}s of the {}", seconds.abs(), name),
+        ),
+        None => Finding::new(
+            "clock",
+            Severity::Info,
+            "unable to determine clock skew: no server reported its time",
+        ),
+    }
+}
+
+/// Resolve the result of the command from its findings.
+///
+/// Only errors cause failure.
+fn findings_result(findings: &[Finding]) -> Result<(), AppleCodesignError> {
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity >= Severity::Error)
+        .count();
+
+    if errors > 0 {
+        Err(AppleCodesignError::CliGeneralError(format!(
+            "{} problems found",
+            errors
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Describe the code signature of the running executable.
+#[cfg(target_os = "macos")]
+fn self_signature_finding() -> Result<Finding, AppleCodesignError> {
+    let exe = std::env::current_exe()?;
+    let data = std::fs::read(&exe)?;
+    let mach = crate::macho::MachFile::parse(&data)?;
+
+    let macho = mach.iter_macho().next().ok_or_else(|| {
+        AppleCodesignError::CliGeneralError("executable has no Mach-O binaries".into())
+    })?;
+
+    let Some(signature) = macho.code_signature()? else {
+        return Ok(Finding::new(
+            "self signature",
+            Severity::Warning,
+            format!("{} is not signed", exe.display()),
+        )
+        .with_remedy("sign rcodesign so macOS allows it to access the keychain"));
+    };
+
+    let kind = if signature.signature_data()?.is_some() {
+        "signed"
+    } else {
+        "ad-hoc signed"
+    };
+
+    let entitlements = if let Some(blob) = signature.entitlements()? {
+        let value = plist::Value::from_reader_xml(blob.as_str().as_bytes())?;
+
+        value
+            .as_dictionary()
+            .map(|dict| dict.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default()
+    } else {
+        vec![]
+    };
+
+    Ok(Finding::new(
+        "self signature",
+        Severity::Ok,
+        if entitlements.is_empty() {
+            format!("{} is {} without entitlements", exe.display(), kind)
+        } else {
+            format!(
+                "{} is {} with entitlements: {}",
+                exe.display(),
+                kind,
+                entitlements.join(", ")
+            )
+        },
+    ))
+}
+
+#[derive(Parser)]
+pub struct Diagnose {
+    /// URL of the time-stamp server to probe
+    #[cfg(feature = "network")]
+    #[arg(long, default_value = APPLE_TIMESTAMP_URL)]
+    timestamp_url: String,
+
+    /// Do not perform checks requiring network access
+    #[arg(long)]
+    offline: bool,
+
+    #[command(flatten)]
+    certificate: CertificateSource,
+}
+
+impl Diagnose {
+    #[cfg(feature = "yubikey")]
+    fn check_smartcard(&self) -> Vec<Finding> {
+        let mut ctx = match ::yubikey::reader::Context::open() {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                return vec![Finding::new(
+                    "smartcard",
+                    Severity::Warning,
+                    format!("unable to access smartcard readers: {}", e),
+                )
+                .with_remedy("ensure the PC/SC service (pcscd on Linux) is running")];
+            }
+        };
+
+        let readers = match ctx.iter() {
+            Ok(readers) => readers.collect::<Vec<_>>(),
+            Err(e) => {
+                return vec![Finding::new(
+                    "smartcard",
+                    Severity::Warning,
+                    format!("unable to enumerate smartcard readers: {}", e),
+                )];
+            }
+        };
+
+        if readers.is_empty() {
+            return vec![
+                Finding::new("smartcard", Severity::Info, "no smartcard devices found")
+                    .with_remedy("insert a smartcard if you intend to sign with one"),
+            ];
+        }
+
+        readers
+            .into_iter()
+            .map(|reader| match reader.open() {
+                Ok(yk) => Finding::new(
+                    "smartcard",
+                    Severity::Ok,
+                    format!(
+                        "found {} (serial {}, version {})",
+                        reader.name(),
+                        yk.serial(),
+                        yk.version()
+                    ),
+                ),
+                Err(e) => Finding::new(
+                    "smartcard",
+                    Severity::Warning,
+                    format!("unable to open {}: {}", reader.name(), e),
+                )
+                .with_remedy("ensure no other process has exclusive access to the device"),
+            })
+            .collect::<Vec<_>>()
+    }
+
+    #[cfg(not(feature = "yubikey"))]
+    fn check_smartcard(&self) -> Vec<Finding> {
+        vec![Finding::new(
+            "smartcard",
+            Severity::Info,
+            "smartcard support not compiled in",
+        )
+        .with_remedy("rebuild with `cargo build --features smartcard` to use smartcards")]
+    }
+
+    fn check_keys(&self) -> Vec<Finding> {
+        if self.certificate.key_sources(true).is_empty() {
+            return vec![Finding::new(
+                "signing key",
+                Severity::Info,
+                "no signing key source specified",
+            )
+            .with_remedy("pass the same key arguments you would pass to `rcodesign sign`")];
+        }
+
+        let certs = match self.certificate.resolve_certificates(true) {
+            Ok(certs) => certs,
+            Err(e) => {
+                return vec![Finding::new(
+                    "signing key",
+                    Severity::Error,
+                    format!("unable to load signing key material: {}", e),
+                )
+                .with_remedy("verify the key paths, passwords, and device PINs are correct")];
+            }
+        };
+
+        let mut res = vec![];
+
+        match (certs.keys.len(), certs.certs.len()) {
+            (0, 0) => res.push(
+                Finding::new(
+                    "signing key",
+                    Severity::Error,
+                    "key sources resolved no keys or certificates",
+                )
+                .with_remedy("verify the specified key sources contain a code signing identity"),
+            ),
+            (0, _) => res.push(
+                Finding::new(
+                    "signing key",
+                    Severity::Warning,
+                    "certificates found but no private key is accessible",
+                )
+                .with_remedy("signing requires a private key; check the key source"),
+            ),
+            (keys, 0) => res.push(
+                Finding::new(
+                    "signing key",
+                    Severity::Error,
+                    format!("{} private keys found but no certificate", keys),
+                )
+                .with_remedy("specify the certificate corresponding to the private key"),
+            ),
+            (keys, count) => res.push(Finding::new(
+                "signing key",
+                Severity::Ok,
+                format!(
+                    "{} private keys and {} certificates accessible",
+                    keys, count
+                ),
+            )),
+        }
+
+        for cert in &certs.certs {
+            let name = cert
+                .subject_common_name()
+                .unwrap_or_else(|| "<unknown>".to_string());
+
+            if !cert.time_constraints_valid(None) {
+                res.push(
+                    Finding::new(
+                        "certificate",
+                        Severity::Error,
+                        format!(
+                            "{} is not valid at current time (expires {})",
+                            name,
+                            cert.validity_not_after().to_rfc3339()
+                        ),
+                    )
+                    .with_remedy("obtain a new certificate from Apple"),
+                );
+            } else if let Some(profile) = cert.apple_guess_profile() {
+                res.push(Finding::new(
+                    "certificate",
+                    Severity::Ok,
+                    format!("{} is a {} certificate", name, profile),
+                ));
+            } else {
+                res.push(Finding::new(
+                    "certificate",
+                    Severity::Info,
+                    format!("{} is not an Apple issued code signing certificate", name),
+                ));
+            }
+        }
+
+        res
+    }
+
+    #[cfg(target_os = "macos")]
+    fn check_self_signature(&self) -> Vec<Finding> {
+        vec![self_signature_finding().unwrap_or_else(|e| {
+            Finding::new(
+                "self signature",
+                Severity::Warning,
+                format!("unable to inspect own signature: {}", e),
+            )
+        })]
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn check_self_signature(&self) -> Vec<Finding> {
+        vec![]
+    }
+
+    /// Probe network endpoints and compute clock skew from response `Date` headers.
+    #[cfg(feature = "network")]
+    fn check_network(&self) -> Vec<Finding> {
+        let client = match default_client() {
+            Ok(client) => client,
+            Err(e) => {
+                return vec![Finding::new(
+                    "network",
+                    Severity::Error,
+                    format!("unable to construct HTTP client: {}", e),
+                )];
+            }
+        };
+
+        let endpoints = [
+            ("time-stamp server", self.timestamp_url.as_str()),
+            ("App Store Connect API", APP_STORE_CONNECT_URL),
+            ("notarization ticket lookup", APPLE_TICKET_LOOKUP_URL),
+        ];
+
+        let mut res = vec![];
+        let mut skew = None;
+
+        for (name, url) in endpoints {
+            let before = chrono::Utc::now();
+
+            // Any HTTP response, even an error status, proves reachability.
+            match client.get(url).timeout(Duration::from_secs(15)).send() {
+                Ok(response) => {
+                    res.push(Finding::new(
+                        "network",
+                        Severity::Ok,
+                        format!("{} ({}) reachable: HTTP {}", name, url, response.status()),
+                    ));
+
+                    if skew.is_none() {
+                        skew = response
+                            .headers()
+                            .get(reqwest::header::DATE)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+                            .map(|server| {
+                                let after = chrono::Utc::now();
+                                let local = before + (after - before) / 2;
+
+                                (
+                                    name,
+                                    (local - server.with_timezone(&chrono::Utc)).num_seconds(),
+                                )
+                            });
+                    }
+                }
+                Err(e) => res.push(
+                    Finding::new(
+                        "network",
+                        Severity::Error,
+                        format!("{} ({}) unreachable: {}", name, url, e),
+                    )
+                    .with_remedy("check proxy, firewall, and DNS settings"),
+                ),
+            }
+        }
+
+        res.push(clock_finding(skew));
+
+        res
+    }
+
+    #[cfg(not(feature = "network"))]
+    fn check_network(&self) -> Vec<Finding> {
+        vec![
+            Finding::new("network", Severity::Info, "network support not compiled in")
+                .with_remedy("rebuild with the `network` feature to run network checks"),
+        ]
+    }
+
+    /// Run all checks.
+    fn findings(&self) -> Vec<Finding> {
+        let mut findings = vec![];
+
+        findings.extend(self.check_smartcard());
+        findings.extend(self.check_keys());
+        findings.extend(self.check_self_signature());
+
+        if self.offline {
+            findings.push(Finding::new(
+                "network",
+                Severity::Info,
+                "network checks skipped because --offline was specified",
+            ));
+        } else {
+            findings.extend(self.check_network());
+        }
+
+        findings
+    }
+}
+
+impl CliCommand for Diagnose {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let findings = self.findings();
+
+        for finding in &findings {
+            println!(
+                "[{}] {}: {}",
+                finding.severity, finding.check, finding.message
+            );
+            if let Some(remedy) = &finding.remedy {
+                println!("    -> {}", remedy);
+            }
+        }
+
+        findings_result(&findings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn severity_order() {
+        assert!(Severity::Ok < Severity::Info);
+        assert!(Severity::Info < Severity::Warning);
+        assert!(Severity::Warning < Severity::Error);
+    }
+
+    #[test]
+    fn result() {
+        assert!(findings_result(&[]).is_ok());
+
+        let mut findings = vec![
+            Finding::new("a", Severity::Ok, "ok"),
+            Finding::new("b", Severity::Info, "info"),
+            Finding::new("c", Severity::Warning, "warning"),
+        ];
+        assert!(findings_result(&findings).is_ok());
+
+        findings.push(Finding::new("d", Severity::Error, "error"));
+        findings.push(Finding::new("e", Severity::Error, "error"));
+        assert_eq!(
+            findings_result(&findings).unwrap_err().to_string(),
+            "2 problems found"
+        );
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn clock_skew() {
+        let finding = clock_finding(None);
+        assert_eq!(finding.severity, Severity::Info);
+
+        for seconds in [0, 30, -30] {
+            let finding = clock_finding(Some(("server", seconds)));
+            assert_eq!(finding.severity, Severity::Ok);
+            assert_eq!(
+                finding.message,
+                format!("local clock within {}s of the server", seconds.abs())
+            );
+        }
+
+        let finding = clock_finding(Some(("server", 31)));
+        assert_eq!(finding.severity, Severity::Warning);
+        assert_eq!(finding.message, "local clock is 31s ahead of the server");
+        assert!(finding.remedy.is_some());
+
+        let finding = clock_finding(Some(("server", -31)));
+        assert_eq!(finding.severity, Severity::Warning);
+        assert_eq!(finding.message, "local clock is 31s behind the server");
+    }
+
+    #[test]
+    fn offline() {
+        let diagnose = Diagnose::try_parse_from(["diagnose", "--offline"]).unwrap();
+
+        let network = diagnose
+            .findings()
+            .into_iter()
+            .filter(|f| f.check == "network" || f.check == "clock")
+            .map(|f| (f.severity, f.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            network,
+            vec![(
+                Severity::Info,
+                "network checks skipped because --offline was specified".to_string()
+            )]
+        );
+    }
+}
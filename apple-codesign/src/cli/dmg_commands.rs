@@ -0,0 +1,269 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use {
+    crate::{
+        cli::{CliCommand, Context},
+        error::AppleCodesignError,
+    },
+    apple_dmg::{
+        encrypt_dmg, is_encrypted_dmg, DmgFileType, DmgReader, EncryptedDmgReader,
+        EncryptionKeySize,
+    },
+    clap::{Args, Parser, Subcommand, ValueEnum},
+    log::warn,
+    std::{
+        fs::File,
+        io::{BufReader, Read, Seek},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Obtain a passphrase from the first line of a file or by prompting.
+fn read_passphrase(path: Option<&Path>, confirm: bool) -> Result<String, AppleCodesignError> {
+    if let Some(path) = path {
+        Ok(std::fs::read_to_string(path)?
+            .lines()
+            .next()
+            .ok_or_else(|| {
+                AppleCodesignError::CliGeneralError("passphrase file appears to be empty".into())
+            })?
+            .to_string())
+    } else {
+        let mut prompt = dialoguer::Password::new().with_prompt("Please enter DMG passphrase");
+        if confirm {
+            prompt =
+                prompt.with_confirmation("Please confirm DMG passphrase", "passphrases differ");
+        }
+
+        Ok(prompt.interact()?)
+    }
+}
+
+/// Arguments for unlocking encrypted DMGs.
+#[derive(Args)]
+struct DmgUnlockArgs {
+    /// Path to a file holding the passphrase of an encrypted DMG
+    ///
+    /// Only the first line of the file is used. If neither this nor a key file
+    /// is given, the passphrase of encrypted DMGs is prompted for.
+    #[arg(long, value_name = "PATH", conflicts_with = "key_file")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Path to a file holding the raw key of an encrypted DMG
+    ///
+    /// The file holds the AES key (16 or 32 bytes) followed by the 20 byte
+    /// HMAC-SHA1 key, in binary form.
+    #[arg(long, value_name = "PATH")]
+    key_file: Option<PathBuf>,
+}
+
+impl DmgUnlockArgs {
+    /// Decrypt an encrypted DMG.
+    fn decrypt<R: Read + Seek>(&self, r: R) -> Result<EncryptedDmgReader<R>, AppleCodesignError> {
+        Ok(if let Some(path) = &self.key_file {
+            EncryptedDmgReader::new_with_key(r, &std::fs::read(path)?)?
+        } else {
+            let passphrase = read_passphrase(self.passphrase_file.as_deref(), false)?;
+            EncryptedDmgReader::new_with_passphrase(r, passphrase.as_bytes())?
+        })
+    }
+}
+
+#[derive(Parser)]
+struct DmgLs {
+    /// Output format
+    #[arg(long, value_parser = ["text", "json"], default_value = "text")]
+    format: String,
+
+    #[command(flatten)]
+    unlock: DmgUnlockArgs,
+
+    /// Path to the DMG to list files of
+    path: PathBuf,
+}
+
+impl DmgLs {
+    fn run(&self) -> Result<(), AppleCodesignError> {
+        let mut fh = BufReader::new(File::open(&self.path)?);
+
+        let listings = if is_encrypted_dmg(&mut fh)? {
+            DmgReader::new(self.unlock.decrypt(fh)?)?.list_files()?
+        } else {
+            DmgReader::new(fh)?.list_files()?
+        };
+
+        match self.format.as_str() {
+            "text" => {
+                for listing in &listings {
+                    println!(
+                        "partition #{}: {} ({})",
+                        listing.index,
+                        listing.name,
+                        listing
+                            .filesystem
+                            .as_deref()
+                            .unwrap_or("unknown filesystem")
+                    );
+
+                    if let Some(reason) = &listing.unreadable_reason {
+                        println!("  unable to list files: {}", reason);
+                    }
+
+                    for file in &listing.files {
+                        let kind = match file.file_type {
+                            DmgFileType::Directory => 'd',
+                            DmgFileType::File => '-',
+                            DmgFileType::Symlink => 'l',
+                        };
+
+                        if let Some(target) = &file.symlink_target {
+                            println!(
+                                "  {}{:o} {:>12} {} -> {}",
+                                kind, file.mode, file.size, file.path, target
+                            );
+                        } else {
+                            println!("  {}{:o} {:>12} {}", kind, file.mode, file.size, file.path);
+                        }
+                    }
+                }
+            }
+            "json" => println!("{}", serde_json::to_string_pretty(&listings)?),
+            format => panic!("unhandled format: {format}"),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+struct DmgDecrypt {
+    #[command(flatten)]
+    unlock: DmgUnlockArgs,
+
+    /// Path to the encrypted DMG to decrypt
+    input_path: PathBuf,
+
+    /// Path to write the decrypted DMG to
+    output_path: PathBuf,
+}
+
+impl DmgDecrypt {
+    fn run(&self) -> Result<(), AppleCodesignError> {
+        let mut fh = BufReader::new(File::open(&self.input_path)?);
+
+        if !is_encrypted_dmg(&mut fh)? {
+            return Err(AppleCodesignError::CliGeneralError(format!(
+                "{} is not an encrypted DMG",
+                self.input_path.display()
+            )));
+        }
+
+        let mut reader = self.unlock.decrypt(fh)?;
+        // Ensure the content is a valid image before writing it out.
+        DmgReader::new(&mut reader)?;
+        reader.rewind()?;
+
+        warn!("writing {}", self.output_path.display());
+        std::io::copy(&mut reader, &mut File::create(&self.output_path)?)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum DmgKeySize {
+    #[value(name = "aes-128")]
+    Aes128,
+    #[value(name = "aes-256")]
+    Aes256,
+}
+
+impl From<DmgKeySize> for EncryptionKeySize {
+    fn from(v: DmgKeySize) -> Self {
+        match v {
+            DmgKeySize::Aes128 => Self::Aes128,
+            DmgKeySize::Aes256 => Self::Aes256,
+        }
+    }
+}
+
+#[derive(Parser)]
+struct DmgEncrypt {
+    /// Encryption to use
+    #[arg(long, value_enum, default_value_t = DmgKeySize::Aes256)]
+    encryption: DmgKeySize,
+
+    /// Path to a file holding the passphrase to protect the DMG with
+    ///
+    /// Only the first line of the file is used. If not given, the passphrase
+    /// is prompted for.
+    #[arg(long, value_name = "PATH")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Path to the DMG to encrypt
+    input_path: PathBuf,
+
+    /// Path to write the encrypted DMG to
+    output_path: PathBuf,
+}
+
+impl DmgEncrypt {
+    fn run(&self) -> Result<(), AppleCodesignError> {
+        // Ensure the input is a valid, unencrypted image.
+        DmgReader::open(&self.input_path)?;
+
+        let passphrase = read_passphrase(self.passphrase_file.as_deref(), true)?;
+        let data = std::fs::read(&self.input_path)?;
+        let encrypted = encrypt_dmg(&data, passphrase.as_bytes(), self.encryption.into())?;
+
+        warn!("writing {}", self.output_path.display());
+        std::fs::write(&self.output_path, encrypted)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+enum DmgCommand {
+    /// List files in a DMG without mounting it.
+    ///
+    /// Files in all partitions with a readable filesystem are printed along
+    /// with their sizes and modes. Only FAT filesystems can currently be read.
+    /// Partitions with other filesystems (such as HFS+ or APFS) are reported as
+    /// unreadable.
+    ///
+    /// Use `--format json` to obtain machine readable output.
+    ///
+    /// Encrypted DMGs are decrypted with a passphrase or a key file.
+    Ls(DmgLs),
+
+    /// Decrypt an encrypted DMG.
+    ///
+    /// Images encrypted with AES-128 or AES-256 (e.g. by `hdiutil create
+    /// -encryption`) can be decrypted with their passphrase or their raw key.
+    Decrypt(DmgDecrypt),
+
+    /// Encrypt a DMG with a passphrase.
+    ///
+    /// Images are encrypted in the `encrcdsa` format used by `hdiutil create
+    /// -encryption`.
+    Encrypt(DmgEncrypt),
+}
+
+#[derive(Parser)]
+pub struct Dmg {
+    #[command(subcommand)]
+    command: DmgCommand,
+}
+
+impl CliCommand for Dmg {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        match &self.command {
+            DmgCommand::Ls(c) => c.run(),
+            DmgCommand::Decrypt(c) => c.run(),
+            DmgCommand::Encrypt(c) => c.run(),
+        }
+    }
+}
@@ -10,6 +10,7 @@ use {
         embedded_signature::{Blob, CodeSigningSlot, RequirementSetBlob},
         error::AppleCodesignError,
         macho::MachFile,
+        macho_fixups::validate_chained_fixups_placement,
     },
     base64::{engine::general_purpose::STANDARD as STANDARD_ENGINE, Engine},
     clap::{Parser, Subcommand},
@@ -204,6 +205,10 @@ enum ExtractData {
     ///
     /// Useful for comparing round-tripping of code directory data.
     CodeDirectorySerializedRaw(ExtractCommon),
+    /// Summary of dyld chained fixups metadata and validation of its placement.
+    ///
+    /// Useful for debugging crashes of signed binaries during process start.
+    DyldChainedFixups(ExtractCommon),
     /// Information about the __LINKEDIT Mach-O segment.
     LinkeditInfo(ExtractCommon),
     /// Complete content of the __LINKEDIT Mach-O segment.
@@ -246,6 +251,7 @@ impl ExtractData {
             ExtractData::CodeDirectorySerializedRaw(x) => x,
             ExtractData::CodeDirectorySerialized(x) => x,
             ExtractData::CodeDirectory(x) => x,
+            ExtractData::DyldChainedFixups(x) => x,
             ExtractData::LinkeditInfo(x) => x,
             ExtractData::LinkeditSegmentRaw(x) => x,
             ExtractData::MachoHeader(x) => x,
@@ -389,6 +395,20 @@ impl CliCommand for Extract {
                     eprintln!("no code directory");
                 }
             }
+            ExtractData::DyldChainedFixups(_) => {
+                if let Some(fixups) = macho.chained_fixups()? {
+                    for line in fixups.summary_lines(macho) {
+                        println!("{line}");
+                    }
+
+                    match validate_chained_fixups_placement(macho) {
+                        Ok(()) => println!("placement: ok"),
+                        Err(e) => println!("placement: {e}"),
+                    }
+                } else {
+                    eprintln!("no chained fixups");
+                }
+            }
             ExtractData::LinkeditInfo(_) => {
                 let sig = macho
                     .find_signature_data()?
@@ -4,31 +4,37 @@
 
 pub mod certificate_source;
 pub mod config;
+#[cfg(unix)]
+pub mod daemon;
 pub mod debug_commands;
+pub mod diagnose;
+#[cfg(feature = "dmg")]
+pub mod dmg_commands;
 pub mod extract_commands;
+pub mod report;
 
 use {
     crate::{
+        bundle_patch::{stage_bundle_update, BundlePatch},
         certificate::{
             create_self_signed_code_signing_certificate, AppleCertificate, CertificateProfile,
         },
         cli::{
-            certificate_source::CertificateSource,
+            certificate_source::{CertificateSource, MacosKeychainSigningKey, SigningCertificates},
             config::{Config, ConfigBuilder},
+            report::{ReportArgs, TestOutcome, TestSuite},
         },
         code_directory::CodeSignatureFlags,
         code_requirement::CodeRequirements,
-        cryptography::DigestType,
+        cryptography::{DigestBackendType, DigestType},
         environment_constraints::EncodedEnvironmentConstraints,
         error::AppleCodesignError,
+        identity_selection::{preferred_profiles_for_path, select_signing_identity},
         macho::MachFile,
         reader::SignatureReader,
-        remote_signing::{
-            session_negotiation::{create_session_joiner, SessionJoinState},
-            RemoteSignError, UnjoinedSigningClient,
-        },
+        remote_signing::RemoteSignError,
         signing::UnifiedSigner,
-        signing_settings::{SettingsScope, SigningSettings},
+        signing_settings::{CompatibilityTarget, SettingsScope, SigningPreset, SigningSettings},
     },
     base64::{engine::general_purpose::STANDARD as STANDARD_ENGINE, Engine},
     clap::{ArgAction, Args, Parser, Subcommand},
@@ -44,8 +50,17 @@ use {
     x509_certificate::{CapturedX509Certificate, EcdsaCurve, KeyAlgorithm, X509CertificateBuilder},
 };
 
+#[cfg(feature = "remote-signing")]
+use crate::remote_signing::{
+    session_negotiation::{create_session_joiner, SessionJoinState},
+    UnjoinedSigningClient,
+};
+
 #[cfg(feature = "notarize")]
-use crate::notarization::Notarizer;
+use crate::{notarization::Notarizer, notarization_history::NotarizationHistoryEntry};
+
+#[cfg(feature = "network")]
+use crate::notarization_history::{default_history_path, NotarizationHistory};
 
 #[cfg(feature = "yubikey")]
 use {
@@ -66,8 +81,13 @@ use crate::windows::{
 pub const KEYCHAIN_DOMAINS: [&str; 4] = ["user", "system", "common", "dynamic"];
 pub const WINDOWS_STORE_NAMES: [&str; 3] = ["user", "machine", "service"];
 
+#[cfg(feature = "network")]
 const APPLE_TIMESTAMP_URL: &str = "http://timestamp.apple.com/ts01";
 
+/// Time-stamp servers can't be contacted without network support.
+#[cfg(not(feature = "network"))]
+const APPLE_TIMESTAMP_URL: &str = "none";
+
 /// Holds state to pass to CLI commands.
 pub struct Context {
     pub config: Config,
@@ -132,19 +152,34 @@ struct NotaryApi {
     #[arg(long, requires = "api_issuer")]
     /// App Store Connect API Key ID
     api_key: Option<String>,
+
+    /// Team ID recorded in the API key file
+    ///
+    /// When set, the team recorded in the API key file must match or the
+    /// operation fails. This is a local sanity check of the key file: the team
+    /// is not confirmed with Apple. Keys that don't record their team,
+    /// including keys given by issuer and key ID, are rejected.
+    #[arg(long)]
+    team_id: Option<String>,
 }
 
 #[cfg(feature = "notarize")]
 impl NotaryApi {
     /// Resolve a notarizer from arguments.
     fn notarizer(&self) -> Result<Notarizer, AppleCodesignError> {
-        if let Some(api_key_path) = &self.api_key_path {
+        let notarizer = if let Some(api_key_path) = &self.api_key_path {
             Notarizer::from_api_key(api_key_path)
         } else if let (Some(issuer), Some(key)) = (&self.api_issuer, &self.api_key) {
             Notarizer::from_api_key_id(issuer, key)
         } else {
             Err(AppleCodesignError::NotarizeNoAuthCredentials)
+        }?;
+
+        if let Some(team_id) = &self.team_id {
+            notarizer.check_key_team_id(team_id)?;
         }
+
+        Ok(notarizer)
     }
 }
 
@@ -309,24 +344,271 @@ pub fn print_session_join(sjs_base64: &str, sjs_pem: &str) -> Result<(), RemoteS
     Ok(())
 }
 
+/// Configure signing to obtain a time-stamp token from a server.
+#[cfg(feature = "network")]
+fn set_time_stamp_url(settings: &mut SigningSettings, url: &str) -> Result<(), AppleCodesignError> {
+    warn!("using time-stamp protocol server {}", url);
+    settings.set_time_stamp_url(url)
+}
+
+#[cfg(not(feature = "network"))]
+fn set_time_stamp_url(_: &mut SigningSettings, _: &str) -> Result<(), AppleCodesignError> {
+    Err(AppleCodesignError::NetworkDisabled(
+        "using a time-stamp server",
+    ))
+}
+
 #[derive(Parser)]
 struct AnalyzeCertificate {
     #[command(flatten)]
     certificate: CertificateSource,
+
+    #[command(flatten)]
+    report: ReportArgs,
 }
 
 impl CliCommand for AnalyzeCertificate {
     fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
         let certs = self.certificate.resolve_certificates(true)?.certs;
 
+        let mut suite = TestSuite::new("analyze-certificate");
+        let now = chrono::Utc::now();
+
         for (i, cert) in certs.into_iter().enumerate() {
             println!("# Certificate {i}");
             println!();
             print_certificate_info(&cert)?;
             println!();
+
+            let classname = format!(
+                "certificate {i} ({})",
+                cert.subject_common_name()
+                    .unwrap_or_else(|| "<missing>".to_string())
+            );
+
+            let not_before = cert.validity_not_before();
+            let not_after = cert.validity_not_after();
+            suite.push(
+                &classname,
+                "validity",
+                if now >= not_before && now <= not_after {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::Failed(format!(
+                        "certificate is only valid from {} to {}",
+                        not_before.to_rfc3339(),
+                        not_after.to_rfc3339()
+                    ))
+                },
+            );
+            suite.push(
+                &classname,
+                "apple-signed",
+                if cert.is_apple_root_ca() || cert.chains_to_apple_root_ca() {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::Failed("certificate does not chain to an Apple root CA".into())
+                },
+            );
+            suite.push(
+                &classname,
+                "profile",
+                if cert.apple_guess_profile().is_some() {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::Skipped("no known certificate profile".into())
+                },
+            );
         }
 
-        Ok(())
+        self.report.write(&suite)
+    }
+}
+
+#[derive(Parser)]
+struct CheckRelease {
+    /// Output format
+    #[arg(long, value_parser = ["text", "json"], default_value = "text")]
+    format: String,
+
+    /// Path to a DMG containing the application bundle
+    #[arg(long, value_name = "PATH")]
+    dmg: Option<PathBuf>,
+
+    /// Path to a flat package installer containing the application bundle
+    #[arg(long, value_name = "PATH")]
+    pkg: Option<PathBuf>,
+
+    /// Don't warn if the content of the DMG can't be verified
+    ///
+    /// Only FAT filesystems in DMGs can be read. By default, the content check
+    /// of DMGs with other filesystems, such as HFS+ and APFS, reports a warning.
+    /// With this flag, it is skipped instead.
+    #[arg(long)]
+    allow_unverified_dmg: bool,
+
+    /// Path to the signed application bundle
+    app: PathBuf,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+impl CliCommand for CheckRelease {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let report = crate::release_check::check_release(
+            &self.app,
+            self.dmg.as_deref(),
+            self.pkg.as_deref(),
+            self.allow_unverified_dmg,
+        )?;
+
+        match self.format.as_str() {
+            "text" => {
+                println!(
+                    "application version: {}",
+                    report.app_version.as_deref().unwrap_or("unknown")
+                );
+                println!(
+                    "application build: {}",
+                    report.app_build.as_deref().unwrap_or("unknown")
+                );
+                println!(
+                    "application cdhash: {}",
+                    report.app_cdhash.as_deref().unwrap_or("none")
+                );
+                for check in &report.checks {
+                    let status = match check.status {
+                        crate::release_check::ReleaseCheckStatus::Pass => "PASS",
+                        crate::release_check::ReleaseCheckStatus::Warn => "WARN",
+                        crate::release_check::ReleaseCheckStatus::Fail => "FAIL",
+                        crate::release_check::ReleaseCheckStatus::Skipped => "SKIP",
+                    };
+                    println!(
+                        "{} {} {}: {}",
+                        status,
+                        check.artifact.display(),
+                        check.check,
+                        check.message
+                    );
+                }
+            }
+            "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+            format => panic!("unhandled format: {format}"),
+        }
+
+        let mut suite = TestSuite::new("check-release");
+        for check in &report.checks {
+            let outcome = match check.status {
+                crate::release_check::ReleaseCheckStatus::Pass => TestOutcome::Passed,
+                crate::release_check::ReleaseCheckStatus::Warn => {
+                    TestOutcome::Warning(check.message.clone())
+                }
+                crate::release_check::ReleaseCheckStatus::Fail => {
+                    TestOutcome::Failed(check.message.clone())
+                }
+                crate::release_check::ReleaseCheckStatus::Skipped => {
+                    TestOutcome::Skipped(check.message.clone())
+                }
+            };
+            suite.push(check.artifact.display(), check.check, outcome);
+        }
+        self.report.write(&suite)?;
+
+        if report.is_ok() {
+            Ok(())
+        } else {
+            Err(AppleCodesignError::CliGeneralError(
+                "release artifacts are not consistent".into(),
+            ))
+        }
+    }
+}
+
+#[derive(Parser)]
+struct ComplianceReport {
+    /// Output format
+    #[arg(long, value_parser = ["text", "json"], default_value = "text")]
+    format: String,
+
+    /// Exit with an error if bitcode, deprecated frameworks, binaries without SDK metadata, or
+    /// binaries that can't be parsed are found
+    #[arg(long)]
+    fail_on_issues: bool,
+
+    /// Path to a Mach-O binary or a directory (such as a bundle) to scan
+    path: PathBuf,
+
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+impl CliCommand for ComplianceReport {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let report = crate::compliance::ComplianceReport::from_path(&self.path)?;
+
+        match self.format.as_str() {
+            "text" => print!("{}", report.to_text()),
+            "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+            format => panic!("unhandled format: {format}"),
+        }
+
+        let mut suite = TestSuite::new("compliance-report");
+        for binary in &report.binaries {
+            let classname = match binary.universal_index {
+                Some(index) => format!("{}@{}", binary.path.display(), index),
+                None => binary.path.display().to_string(),
+            };
+
+            if let Some(error) = &binary.error {
+                suite.push(&classname, "parse", TestOutcome::Failed(error.clone()));
+                continue;
+            }
+
+            suite.push(
+                &classname,
+                "bitcode",
+                if binary.has_bitcode {
+                    TestOutcome::Failed("binary contains bitcode".into())
+                } else {
+                    TestOutcome::Passed
+                },
+            );
+            suite.push(
+                &classname,
+                "deprecated-frameworks",
+                if binary.deprecated_frameworks.is_empty() {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::Failed(
+                        binary
+                            .deprecated_frameworks
+                            .iter()
+                            .map(|f| format!("links {}: {}", f.framework, f.note))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    )
+                },
+            );
+            suite.push(
+                &classname,
+                "sdk-version",
+                if binary.sdk_version.is_some() {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::Failed("binary does not record the SDK it was built with".into())
+                },
+            );
+        }
+        self.report.write(&suite)?;
+
+        if self.fail_on_issues && report.has_issues() {
+            Err(AppleCodesignError::CliGeneralError(
+                "compliance issues found".into(),
+            ))
+        } else {
+            Ok(())
+        }
     }
 }
 
@@ -426,17 +708,28 @@ struct EncodeAppStoreConnectApiKey {
 
     /// Path to a file containing the private key downloaded from Apple
     private_key_path: PathBuf,
+
+    /// Team ID the API key belongs to
+    ///
+    /// Recording the team allows notary commands to check the key file against
+    /// the intended team via `--team-id`.
+    #[arg(long)]
+    team_id: Option<String>,
 }
 
 #[cfg(feature = "notarize")]
 impl CliCommand for EncodeAppStoreConnectApiKey {
     fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
-        let unified = app_store_connect::UnifiedApiKey::from_ecdsa_pem_path(
+        let mut unified = app_store_connect::UnifiedApiKey::from_ecdsa_pem_path(
             &self.issuer_id,
             &self.key_id,
             &self.private_key_path,
         )?;
 
+        if let Some(team_id) = &self.team_id {
+            unified.set_team_id(team_id);
+        }
+
         if let Some(output_path) = &self.output_path {
             eprintln!("writing unified key JSON to {}", output_path.display());
             unified.write_json_file(output_path)?;
@@ -764,9 +1057,37 @@ impl CliCommand for MachoUniversalCreate {
     }
 }
 
+/// Parse a date or a date and time into a UTC time.
+#[cfg(feature = "notarize")]
+fn parse_date_time(s: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(s) {
+        Ok(date.with_timezone(&chrono::Utc))
+    } else if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(date.and_time(chrono::NaiveTime::MIN).and_utc())
+    } else {
+        Err(format!("{s} is not a YYYY-MM-DD date or RFC 3339 time"))
+    }
+}
+
 #[cfg(feature = "notarize")]
 #[derive(Parser)]
 struct NotaryList {
+    /// Fetch all pages of the submission history instead of only the most recent page
+    #[arg(long)]
+    all: bool,
+
+    /// Only show submissions with this status
+    #[arg(long, value_parser = ["accepted", "in-progress", "invalid", "rejected"])]
+    status: Option<String>,
+
+    /// Only show submissions created at or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date_time)]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Only show submissions created before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date_time)]
+    until: Option<chrono::DateTime<chrono::Utc>>,
+
     #[command(flatten)]
     api: NotaryApi,
 }
@@ -774,11 +1095,35 @@ struct NotaryList {
 #[cfg(feature = "notarize")]
 impl CliCommand for NotaryList {
     fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        use app_store_connect::notary_api::{SubmissionListFilter, SubmissionResponseStatus};
+
         let notarizer = self.api.notarizer()?;
 
-        let submissions = notarizer.list_submissions()?;
+        let filter = SubmissionListFilter {
+            status: self.status.as_deref().map(|status| match status {
+                "accepted" => SubmissionResponseStatus::Accepted,
+                "in-progress" => SubmissionResponseStatus::InProgress,
+                "invalid" => SubmissionResponseStatus::Invalid,
+                "rejected" => SubmissionResponseStatus::Rejected,
+                status => panic!("unhandled status: {status}"),
+            }),
+            created_after: self.since,
+            created_before: self.until,
+        };
 
-        for entry in &submissions.data {
+        let submissions = if self.all {
+            notarizer.list_all_submissions(&filter)?
+        } else {
+            let mut submissions = vec![];
+            for entry in notarizer.list_submissions()?.data {
+                if filter.matches(&entry)? {
+                    submissions.push(entry);
+                }
+            }
+            submissions
+        };
+
+        for entry in &submissions {
             println!(
                 "{} {} {} {} {}",
                 entry.id,
@@ -833,42 +1178,282 @@ struct NotarySubmit {
     #[arg(long)]
     staple: bool,
 
-    /// Path to asset to upload
-    path: PathBuf,
+    /// Paths to assets to upload
+    ///
+    /// When multiple paths are given, they are uploaded and waited on in parallel.
+    #[arg(value_name = "PATH", required = true)]
+    paths: Vec<PathBuf>,
 
     #[command(flatten)]
     api: NotaryApi,
+
+    #[command(flatten)]
+    report: ReportArgs,
 }
 
+/// The result of submitting a single path for notarization.
 #[cfg(feature = "notarize")]
-impl CliCommand for NotarySubmit {
-    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
-        let wait = self.wait || self.staple;
+struct NotarySubmitOutcome {
+    submission_id: String,
+    status: Option<String>,
+    /// The result of stapling, if requested.
+    stapled: Option<Result<(), AppleCodesignError>>,
+}
 
-        let wait_limit = if wait {
-            Some(std::time::Duration::from_secs(self.max_wait_seconds))
-        } else {
-            None
+#[cfg(feature = "notarize")]
+impl NotarySubmit {
+    /// Upload, wait on, and staple a single path.
+    ///
+    /// Only upload failures are returned as errors. Stapling failures are
+    /// recorded in the outcome so the submission isn't lost.
+    fn submit_path(
+        &self,
+        notarizer: &Notarizer,
+        path: &Path,
+        wait_limit: Option<std::time::Duration>,
+    ) -> Result<NotarySubmitOutcome, AppleCodesignError> {
+        let upload = notarizer.notarize_path(path, wait_limit)?;
+
+        let (submission_id, status) = match &upload {
+            crate::notarization::NotarizationUpload::UploadId(id) => (id.clone(), None),
+            crate::notarization::NotarizationUpload::NotaryResponse(response) => (
+                response.data.id.clone(),
+                Some(response.data.attributes.status.to_string()),
+            ),
         };
-        let notarizer = self.api.notarizer()?;
-
-        let upload = notarizer.notarize_path(&self.path, wait_limit)?;
 
-        if self.staple {
+        let stapled = if self.staple {
             match upload {
                 crate::notarization::NotarizationUpload::UploadId(_) => {
                     panic!(
                         "NotarizationUpload::UploadId should not be returned if we waited successfully"
                     );
                 }
-                crate::notarization::NotarizationUpload::NotaryResponse(_) => {
-                    let stapler = crate::stapling::Stapler::new()?;
-                    stapler.staple_path(&self.path)?;
+                crate::notarization::NotarizationUpload::NotaryResponse(_) => Some(
+                    crate::stapling::Stapler::new().and_then(|stapler| stapler.staple_path(path)),
+                ),
+            }
+        } else {
+            None
+        };
+
+        Ok(NotarySubmitOutcome {
+            submission_id,
+            status,
+            stapled,
+        })
+    }
+
+    /// Record the outcome of each submission in the local notarization history.
+    ///
+    /// This happens once all submissions are complete, as concurrent updates
+    /// of the history file would lose records.
+    fn record_history<'a>(
+        results: impl IntoIterator<
+            Item = (
+                &'a PathBuf,
+                &'a Result<NotarySubmitOutcome, AppleCodesignError>,
+            ),
+        >,
+    ) {
+        update_notarization_history(|history| {
+            for (path, result) in results {
+                let Ok(outcome) = result else {
+                    continue;
+                };
+
+                history
+                    .record_submission(NotarizationHistoryEntry::new(&outcome.submission_id, path));
+                if let Some(status) = &outcome.status {
+                    history.set_status(&outcome.submission_id, status);
+                }
+                if let Some(Ok(())) = outcome.stapled {
+                    history.mark_stapled(path);
+                }
+            }
+        });
+    }
+
+    /// Write the outcome of each submission to requested reports.
+    fn write_report<'a>(
+        &self,
+        results: impl IntoIterator<
+            Item = (
+                &'a PathBuf,
+                &'a Result<NotarySubmitOutcome, AppleCodesignError>,
+            ),
+        >,
+    ) -> Result<(), AppleCodesignError> {
+        let mut suite = TestSuite::new("notary-submit");
+
+        for (path, result) in results {
+            match result {
+                Ok(outcome) => {
+                    suite.push(
+                        path.display(),
+                        "notarization",
+                        if outcome.status.is_some() {
+                            TestOutcome::Passed
+                        } else {
+                            TestOutcome::Skipped(
+                                "uploaded without --wait; notarization result pending".into(),
+                            )
+                        },
+                    );
+                    if let Some(stapled) = &outcome.stapled {
+                        suite.push(
+                            path.display(),
+                            "stapling",
+                            match stapled {
+                                Ok(()) => TestOutcome::Passed,
+                                Err(e) => TestOutcome::Failed(e.to_string()),
+                            },
+                        );
+                    }
+                }
+                Err(e) => {
+                    suite.push(
+                        path.display(),
+                        "notarization",
+                        TestOutcome::Failed(e.to_string()),
+                    );
                 }
             }
         }
 
-        Ok(())
+        self.report.write(&suite)
+    }
+
+    /// Submit each path with `submit`.
+    ///
+    /// A single path is submitted on the current thread. Multiple paths are
+    /// submitted in parallel.
+    fn submit_paths(
+        &self,
+        submit: impl Fn(&Path) -> Result<NotarySubmitOutcome, AppleCodesignError> + Sync,
+    ) -> Vec<(&PathBuf, Result<NotarySubmitOutcome, AppleCodesignError>)> {
+        if let [path] = self.paths.as_slice() {
+            return vec![(path, submit(path))];
+        }
+
+        let submit = &submit;
+        std::thread::scope(|scope| {
+            let handles = self
+                .paths
+                .iter()
+                .map(|path| (path, scope.spawn(move || submit(path))))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|(path, handle)| {
+                    (
+                        path,
+                        handle.join().expect("notarization thread should not panic"),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// Describe the outcome of each submission.
+    ///
+    /// Each path yields one line, which is an error if its submission failed.
+    fn summary_lines(
+        results: &[(&PathBuf, Result<NotarySubmitOutcome, AppleCodesignError>)],
+    ) -> Vec<Result<String, String>> {
+        results
+            .iter()
+            .map(|(path, result)| match result {
+                Ok(outcome) => {
+                    let line = format!(
+                        "{}: submission {} {}",
+                        path.display(),
+                        outcome.submission_id,
+                        outcome.status.as_deref().unwrap_or("uploaded"),
+                    );
+
+                    match &outcome.stapled {
+                        Some(Ok(())) => Ok(format!("{} (stapled)", line)),
+                        Some(Err(e)) => Err(format!("{}; stapling failed: {}", line, e)),
+                        None => Ok(line),
+                    }
+                }
+                Err(e) => Err(format!("{}: {}", path.display(), e)),
+            })
+            .collect()
+    }
+
+    /// Turn the results of all submissions into the command's result.
+    ///
+    /// A single submission fails with its own error. Otherwise a summary is
+    /// printed and the command fails if any submission failed.
+    fn finish(
+        &self,
+        results: Vec<(&PathBuf, Result<NotarySubmitOutcome, AppleCodesignError>)>,
+    ) -> Result<(), AppleCodesignError> {
+        if results.len() == 1 {
+            let (_, result) = results.into_iter().next().expect("one result");
+
+            return match result?.stapled {
+                Some(Err(e)) => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        warn!("notarization summary:");
+        let mut failures = 0;
+
+        for line in Self::summary_lines(&results) {
+            match line {
+                Ok(line) => warn!("{}", line),
+                Err(line) => {
+                    failures += 1;
+                    error!("{}", line);
+                }
+            }
+        }
+
+        if failures > 0 {
+            Err(AppleCodesignError::CliGeneralError(format!(
+                "{} of {} notarization submissions failed",
+                failures,
+                results.len()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "notarize")]
+impl CliCommand for NotarySubmit {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        // Fail before uploading anything if any path is missing.
+        for path in &self.paths {
+            if !path.exists() {
+                return Err(AppleCodesignError::CliGeneralError(format!(
+                    "{} does not exist",
+                    path.display()
+                )));
+            }
+        }
+
+        let wait = self.wait || self.staple;
+
+        let wait_limit = if wait {
+            Some(std::time::Duration::from_secs(self.max_wait_seconds))
+        } else {
+            None
+        };
+        let notarizer = self.api.notarizer()?;
+
+        let results = self.submit_paths(|path| self.submit_path(&notarizer, path, wait_limit));
+
+        Self::record_history(results.iter().map(|(path, result)| (*path, result)));
+        self.write_report(results.iter().map(|(path, result)| (*path, result)))?;
+
+        self.finish(results)
     }
 }
 
@@ -884,6 +1469,9 @@ struct NotaryWait {
 
     #[command(flatten)]
     api: NotaryApi,
+
+    #[command(flatten)]
+    report: ReportArgs,
 }
 
 #[cfg(feature = "notarize")]
@@ -892,12 +1480,107 @@ impl CliCommand for NotaryWait {
         let wait_duration = std::time::Duration::from_secs(self.max_wait_seconds);
         let notarizer = self.api.notarizer()?;
 
-        notarizer.wait_on_notarization_and_fetch_log(&self.submission_id, wait_duration)?;
+        let result =
+            notarizer.wait_on_notarization_and_fetch_log(&self.submission_id, wait_duration);
+
+        let mut suite = TestSuite::new("notary-wait");
+        suite.push(
+            &self.submission_id,
+            "notarization",
+            match &result {
+                Ok(_) => TestOutcome::Passed,
+                Err(e) => TestOutcome::Failed(e.to_string()),
+            },
+        );
+        self.report.write(&suite)?;
+
+        let status = result?;
+
+        update_notarization_history(|history| {
+            history.set_status(&self.submission_id, status.data.attributes.status);
+        });
 
         Ok(())
     }
 }
 
+#[cfg(feature = "notarize")]
+#[derive(Parser)]
+struct NotaryStatusDocument {
+    /// Format of the document to emit
+    #[arg(long, value_parser = ["json", "markdown"], default_value = "json")]
+    format: String,
+
+    /// Path to notarization history file to read
+    ///
+    /// Defaults to the history file that notarization commands record
+    /// submissions to, which lives in the user's local data directory.
+    #[arg(long, value_name = "PATH")]
+    history_file: Option<PathBuf>,
+
+    /// Path to write the document to
+    ///
+    /// If not specified, the document is printed to stdout.
+    #[arg(long, value_name = "PATH")]
+    output_path: Option<PathBuf>,
+}
+
+#[cfg(feature = "notarize")]
+impl CliCommand for NotaryStatusDocument {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let path = if let Some(path) = &self.history_file {
+            path.clone()
+        } else {
+            default_history_path().ok_or_else(|| {
+                AppleCodesignError::CliGeneralError(
+                    "unable to resolve default notarization history path".into(),
+                )
+            })?
+        };
+
+        let history = NotarizationHistory::from_path(&path)?;
+
+        let document = match self.format.as_str() {
+            "json" => serde_json::to_string_pretty(&history.status_json())?,
+            "markdown" => history.status_markdown(),
+            format => panic!("unhandled format: {format}"),
+        };
+
+        if let Some(output_path) = &self.output_path {
+            warn!("writing status document to {}", output_path.display());
+            std::fs::write(output_path, document)?;
+        } else {
+            println!("{}", document.trim_end());
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply a change to the local notarization history.
+///
+/// Failures are logged and otherwise ignored: history is informational and
+/// shouldn't cause notarization operations to fail.
+#[cfg(feature = "network")]
+fn update_notarization_history(f: impl FnOnce(&mut NotarizationHistory)) {
+    let Some(path) = default_history_path() else {
+        return;
+    };
+
+    let res = NotarizationHistory::from_path(&path).and_then(|mut history| {
+        f(&mut history);
+        history.write_path(&path)
+    });
+
+    if let Err(e) = res {
+        warn!(
+            "unable to update notarization history {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
 #[derive(Parser)]
 struct ParseCodeSigningRequirement {
     /// Output format
@@ -930,6 +1613,76 @@ impl CliCommand for ParseCodeSigningRequirement {
     }
 }
 
+#[derive(Parser)]
+struct PrintMachoSymbols {
+    /// Which symbols to print
+    #[arg(long, value_parser = ["all", "exports", "imports"], default_value = "all")]
+    kind: String,
+
+    /// Output format
+    #[arg(long, value_parser = ["text", "json"], default_value = "text")]
+    format: String,
+
+    /// Index of Mach-O binary to operate on within a universal/fat binary
+    #[arg(long, default_value = "0")]
+    universal_index: usize,
+
+    /// Path to Mach-O binary to examine
+    path: PathBuf,
+}
+
+impl CliCommand for PrintMachoSymbols {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let data = std::fs::read(&self.path)?;
+        let mach = MachFile::parse(&data)?;
+        let macho = mach.nth_macho(self.universal_index)?;
+
+        let exports = if self.kind != "imports" {
+            macho.exported_symbols()?
+        } else {
+            vec![]
+        };
+        let imports = if self.kind != "exports" {
+            macho.imported_symbols()?
+        } else {
+            vec![]
+        };
+
+        match self.format.as_str() {
+            "text" => {
+                for export in &exports {
+                    print!(
+                        "export {} 0x{:x} {}",
+                        export.kind, export.address, export.name
+                    );
+                    if let Some(library) = &export.library {
+                        print!(" ({library})");
+                    }
+                    println!();
+                }
+                for import in &imports {
+                    println!(
+                        "import {} {}{}",
+                        import.library,
+                        import.name,
+                        if import.weak { " (weak)" } else { "" }
+                    );
+                }
+            }
+            "json" => {
+                let value = serde_json::json!({
+                    "exports": exports,
+                    "imports": imports,
+                });
+                println!("{}", serde_json::to_string_pretty(&value)?);
+            }
+            format => panic!("unhandled format: {format}"),
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Parser)]
 struct PrintSignatureInfo {
     /// Filesystem path to entity whose info to print
@@ -947,6 +1700,7 @@ impl CliCommand for PrintSignatureInfo {
     }
 }
 
+#[cfg(feature = "remote-signing")]
 #[derive(Args)]
 #[group(required = true, multiple = false)]
 struct SessionJoinString {
@@ -962,6 +1716,7 @@ struct SessionJoinString {
     session_join_string: Option<String>,
 }
 
+#[cfg(feature = "remote-signing")]
 #[derive(Parser)]
 struct RemoteSign {
     #[command(flatten)]
@@ -971,11 +1726,13 @@ struct RemoteSign {
     certificate: CertificateSource,
 }
 
+#[cfg(feature = "remote-signing")]
 impl CliCommand for RemoteSign {
     fn as_config(&self) -> Result<Option<Config>, AppleCodesignError> {
         Ok(Some(Config {
             remote_sign: config::RemoteSignConfig {
                 signer: self.certificate.clone(),
+                ..Default::default()
             },
             ..Default::default()
         }))
@@ -984,6 +1741,17 @@ impl CliCommand for RemoteSign {
     fn run(&self, context: &Context) -> Result<(), AppleCodesignError> {
         let c = &context.config.remote_sign;
 
+        if let Some(policy) = &c.key_usage_policy {
+            policy.validate()?;
+
+            // We only see the message being signed, not the signed entity.
+            if policy.constrains_entity() {
+                return Err(AppleCodesignError::KeyUsagePolicy(
+                    "remote signers can only enforce signing_hours".into(),
+                ));
+            }
+        }
+
         let session_join_string = if self.session_join_string.session_join_string_editor {
             let mut value = None;
 
@@ -1047,13 +1815,14 @@ impl CliCommand for RemoteSign {
             private.to_public_key_peer_decrypt()?,
         ))?;
 
-        let client = UnjoinedSigningClient::new_signer(
+        let mut client = UnjoinedSigningClient::new_signer(
             joiner,
             private.as_key_info_signer(),
             cert,
             certificates,
             url,
         )?;
+        client.set_key_usage_policy(c.key_usage_policy.clone());
         client.run()?;
 
         Ok(())
@@ -1114,6 +1883,21 @@ pub struct ScopedSigningArgs {
     #[arg(long = "digest", value_name = "DIGEST")]
     digests: Vec<String>,
 
+    /// Append an alternate code directory using the given digest algorithm.
+    ///
+    /// Unlike --digest, this doesn't change the primary digest, which is still
+    /// derived from the OS targeting of the signed binary unless --digest is
+    /// given. Alternate code directories are emitted in the order specified,
+    /// after any implied by --digest.
+    ///
+    /// This is useful for emitting a SHA-1 code directory for tools that still
+    /// identify binaries by their SHA-1 cdhash.
+    ///
+    /// Can be specified multiple times. At most 5 alternate code directories
+    /// can be present.
+    #[arg(long = "append-alternate-cd", value_name = "DIGEST")]
+    alternate_code_directories: Vec<String>,
+
     /// Path to a plist file containing entitlements
     #[arg(
         short = 'e',
@@ -1174,6 +1958,8 @@ pub struct ScopedSigningSettingsValues {
     pub code_signature_flags: Vec<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub digests: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_code_directories: Vec<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub entitlements_xml_file: Option<PathBuf>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -1241,6 +2027,14 @@ impl TryFrom<&ScopedSigningArgs> for ScopedSigningSettings {
             res.entry(scope).or_default().digests.push(value.into());
         }
 
+        for value in &args.alternate_code_directories {
+            let (scope, value) = split_scoped_value(value);
+            res.entry(scope)
+                .or_default()
+                .alternate_code_directories
+                .push(value.into());
+        }
+
         for value in &args.entitlements_xml_paths {
             let (scope, value) = split_scoped_value(value);
             res.entry(scope).or_default().entitlements_xml_file = Some(value.into());
@@ -1348,6 +2142,15 @@ impl ScopedSigningSettings {
                 }
             }
 
+            for value in values.alternate_code_directories {
+                let digest_type = DigestType::try_from(value.as_str())?;
+                warn!(
+                    "adding alternate code directory using {} to {}",
+                    digest_type, scope
+                );
+                settings.add_extra_digest(scope.clone(), digest_type);
+            }
+
             if let Some(path) = values.entitlements_xml_file {
                 warn!(
                     "setting entitlements XML for {} from path {}",
@@ -1421,8 +2224,11 @@ impl ScopedSigningSettings {
     }
 }
 
-#[derive(Parser)]
-struct Sign {
+/// Arguments controlling signing settings.
+///
+/// Shared by commands that sign, so they resolve settings the same way.
+#[derive(Args)]
+pub struct SigningSettingsArgs {
     #[command(flatten)]
     scoped: ScopedSigningArgs,
 
@@ -1477,112 +2283,549 @@ struct Sign {
     #[arg(long)]
     shallow: bool,
 
-    /// Indicate that the entity being signed will later be notarized.
-    ///
-    /// Notarized software is subject to specific requirements, such as enabling the
-    /// hardened runtime.
-    ///
-    /// The presence of this flag influences signing settings and engages additional
-    /// checks to help ensure that signed software can be successfully notarized.
-    ///
-    /// This flag is best effort. Notarization failures of software signed with
-    /// this flag may be indicative of bugs in this software.
+    /// Normalize binary identifiers derived from file names.
+    ///
+    /// Binary identifiers not set explicitly are derived from file names, which
+    /// can contain characters not valid in identifiers. By default, such
+    /// identifiers are used as is and a warning is emitted. When this flag is set,
+    /// invalid characters are replaced instead. e.g. `My App` becomes `My-App`.
+    #[arg(long)]
+    normalize_identifiers: bool,
+
+    /// Indicate that the entity being signed will later be notarized.
+    ///
+    /// Notarized software is subject to specific requirements, such as enabling the
+    /// hardened runtime.
+    ///
+    /// The presence of this flag influences signing settings and engages additional
+    /// checks to help ensure that signed software can be successfully notarized.
+    ///
+    /// This flag is best effort. Notarization failures of software signed with
+    /// this flag may be indicative of bugs in this software.
+    ///
+    /// The behavior of this flag is subject to change. As currently implemented,
+    /// it will:
+    ///
+    /// * Require the use of a "Developer ID" signing certificate issued by Apple.
+    /// * Require the use of a time-stamp server.
+    /// * Enable the hardened runtime code signature flag on all Mach-O binaries
+    ///   (equivalent to `--code-signature-flags runtime` for all signed paths).
+    #[arg(long)]
+    for_notarization: bool,
+
+    /// Distribution channel the signed software must conform to.
+    ///
+    /// Presets configure and validate several settings at once:
+    ///
+    /// * `developer-id` requires a `Developer ID` certificate and a time-stamp
+    ///   server and implies `--for-notarization`.
+    /// * `app-store` requires an `Apple Distribution` (or `Mac Installer
+    ///   Distribution`) certificate and the `com.apple.security.app-sandbox`
+    ///   entitlement on executables. Signatures are not notarized.
+    /// * `development` requires an `Apple Development` certificate and disables
+    ///   time-stamp tokens.
+    ///
+    /// Software signed with the `developer-id` or `app-store` presets must not
+    /// have the `com.apple.security.get-task-allow` entitlement enabled.
+    #[arg(long, value_enum, value_name = "PRESET")]
+    preset: Option<SigningPreset>,
+
+    /// Implementation of digest algorithms to use.
+    ///
+    /// Computing digests typically dominates the time it takes to sign large
+    /// entities. Which implementation is fastest varies by machine.
+    ///
+    /// The `openssl` backend is only available in builds with the `openssl`
+    /// feature enabled.
+    #[arg(long, value_enum, default_value_t = DigestBackendType::Ring)]
+    digest_backend: DigestBackendType,
+
+    /// Path to a file caching digests of bundle resource files.
+    ///
+    /// When signing bundles, digests of resource files are cached in this file,
+    /// keyed by each file's path, size, and modification time. Subsequent signing
+    /// operations reuse cached digests of unchanged files, which can significantly
+    /// speed up signing bundles with many or large resources.
+    #[arg(long, value_name = "PATH")]
+    resource_digest_cache: Option<PathBuf>,
+
+    /// Operating system releases signatures should remain compatible with.
+    ///
+    /// By default, the signature format and digests are derived from the
+    /// targeting information of the entity being signed.
+    ///
+    /// `macos-10.11` produces signatures that macOS 10.11 and earlier can
+    /// validate: code directories only contain SHA-1 digests and no fields newer
+    /// than team identifiers and `CodeResources` files only contain SHA-1
+    /// digests. The layout of `CodeResources` files is unchanged.
+    ///
+    /// `macos-10.8` additionally omits code directory fields newer than scatter
+    /// vectors and restricts `CodeResources` files to version 1 rules, which
+    /// can't seal nested code or symlinks. macOS 10.9 and later reject such
+    /// signatures.
+    ///
+    /// Legacy compatibility targets override `--digest`. SHA-1 is a weak
+    /// digest, so only use this if you need to support such old releases.
+    ///
+    /// Legacy compatibility targets can't be combined with `--for-notarization`.
+    #[arg(long, value_enum, default_value_t = CompatibilityTarget::Automatic)]
+    compatibility_target: CompatibilityTarget,
+}
+
+impl SigningSettingsArgs {
+    /// Load these arguments and the resolved configuration into signing settings.
+    ///
+    /// The signing certificate should already be loaded, as it influences other
+    /// settings.
+    fn load_into_settings(
+        &self,
+        settings: &mut SigningSettings,
+        c: &config::SignConfig,
+    ) -> Result<(), AppleCodesignError> {
+        settings.set_digest_backend(self.digest_backend);
+
+        // Doesn't make sense to set a time-stamp server URL unless we're generating
+        // CMS signatures.
+        if self.preset == Some(SigningPreset::Development) {
+            warn!("development preset disables time-stamp tokens");
+        } else if settings.signing_key().is_some() && self.timestamp_url != "none" {
+            set_time_stamp_url(settings, &self.timestamp_url)?;
+        }
+
+        if let Some(time) = &self.signing_time {
+            let time = chrono::DateTime::parse_from_rfc3339(time).map_err(|e| {
+                AppleCodesignError::CliGeneralError(format!("invalid signing time format: {}", e))
+            })?;
+            let time = time.with_timezone(&chrono::Utc);
+            settings.set_signing_time(time);
+        }
+
+        if let Some(team_id) = settings.set_team_id_from_signing_certificate() {
+            warn!(
+                "automatically setting team ID from signing certificate: {}",
+                team_id
+            );
+        }
+
+        if let Some(team_name) = &self.team_name {
+            settings.set_team_id(team_name);
+        }
+
+        settings.set_shallow(self.shallow);
+        settings.set_normalize_identifiers(self.normalize_identifiers);
+        settings.set_for_notarization(self.for_notarization);
+        settings.set_preset(self.preset);
+        settings.set_resource_digest_cache(self.resource_digest_cache.clone());
+        settings.set_compatibility_target(self.compatibility_target);
+        settings.set_key_usage_policy(c.key_usage_policy.clone())?;
+
+        for pattern in &self.exclude {
+            settings.add_path_exclusion(pattern)?;
+        }
+
+        ScopedSigningSettings(c.paths.clone()).load_into_settings(settings)?;
+
+        settings.ensure_for_notarization_settings()
+    }
+}
+
+#[derive(Parser)]
+struct Sign {
+    #[command(flatten)]
+    settings: SigningSettingsArgs,
+
+    /// Watch the input path and re-sign it whenever its content changes.
+    ///
+    /// After the initial signing operation, the input path is polled for
+    /// modifications to regular files. When a change is detected, the input
+    /// is signed again using the same settings. For bundles, only nested
+    /// bundles and Mach-O binaries affected by the change are signed again and
+    /// the bundles enclosing them are re-sealed. This is intended to keep
+    /// locally built software runnable during development and runs until the
+    /// process is interrupted.
+    ///
+    /// Files written by the signing operation itself do not trigger re-signing.
+    #[arg(long)]
+    watch: bool,
+
+    /// Automatically choose the most appropriate signing identity.
+    ///
+    /// When multiple signing identities are available (such as in the macOS
+    /// Keychain), identities whose certificates are expired or not yet valid are
+    /// ignored and the remaining identities are ranked by how appropriate their
+    /// certificate profile is for the entity being signed: installer certificates
+    /// for flat packages and application certificates for everything else. When
+    /// `--for-notarization` is used, only Developer ID certificates are considered.
+    /// Ties are broken by choosing the certificate expiring last.
+    ///
+    /// The reasoning behind the choice is printed. Use `--smartest-identity-profile`
+    /// to override the profile preference order or `--keychain-fingerprint` to
+    /// choose an identity explicitly.
+    #[arg(long)]
+    smartest_identity: bool,
+
+    /// Certificate profile to accept with `--smartest-identity`, in order of preference
+    #[arg(long, value_parser = CertificateProfile::str_names(), value_name = "PROFILE", requires = "smartest_identity")]
+    smartest_identity_profile: Vec<String>,
+
+    /// Path to the signed bundle that the input is an update to.
+    ///
+    /// When specified, the input path is a directory holding the files of an
+    /// update that differ from this bundle, laid out relative to the bundle root.
+    /// The files are applied to a copy of this bundle, the result is signed, and
+    /// an update payload is written to the output path. The payload contains the
+    /// files that differ between this bundle and the signed result (including
+    /// re-signed binaries and `CodeResources` files) plus a `patch-manifest.json`
+    /// describing every file to write, symlink, or remove.
+    ///
+    /// Applying the payload to this bundle yields a bundle with a valid signature.
+    /// It can only be applied to this bundle.
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "output_path",
+        conflicts_with = "watch"
+    )]
+    update_base: Option<PathBuf>,
+
+    /// Bundle relative path of a file or directory the update removes
+    ///
+    /// Can be specified multiple times.
+    #[arg(long, value_name = "PATH", requires = "update_base")]
+    update_remove: Vec<String>,
+
+    /// Path to Mach-O binary to sign
+    input_path: PathBuf,
+
+    /// Path to signed Mach-O binary to write
+    output_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    certificate: CertificateSource,
+}
+
+impl CliCommand for Sign {
+    fn as_config(&self) -> Result<Option<Config>, AppleCodesignError> {
+        let paths = ScopedSigningSettings::try_from(&self.settings.scoped)?;
+
+        Ok(Some(Config {
+            sign: config::SignConfig {
+                signer: self.certificate.clone(),
+                paths: paths.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+    }
+
+    fn run(&self, context: &Context) -> Result<(), AppleCodesignError> {
+        let cli = self.as_config()?.map(|c| c.sign).unwrap_or_default();
+        let c = &context.config.sign.for_artifact(&self.input_path, &cli)?;
+
+        let mut settings = SigningSettings::default();
+
+        let mut certs = c.signer.resolve_certificates(true)?;
+        if self.smartest_identity {
+            self.choose_smartest_identity(&c.signer, &mut certs)?;
+        }
+        certs.load_into_signing_settings(&mut settings)?;
+
+        self.settings.load_into_settings(&mut settings, c)?;
+
+        settings.ensure_preset_settings(
+            crate::PathType::from_path(&self.input_path)? == crate::PathType::Xar,
+        )?;
+
+        // Settings are locked in. Proceed to sign.
+
+        let signer = UnifiedSigner::new(settings);
+
+        self.sign_once(&signer)?;
+
+        if self.watch {
+            self.watch_and_sign(&signer)?;
+        }
+
+        if let Some(private) = certs.private_key_optional()? {
+            private.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Sign {
+    fn choose_smartest_identity(
+        &self,
+        source: &CertificateSource,
+        certs: &mut SigningCertificates,
+    ) -> Result<(), AppleCodesignError> {
+        let keychain = source
+            .macos_keychain_key
+            .clone()
+            .unwrap_or(MacosKeychainSigningKey {
+                domains: vec![],
+                sha256_fingerprint: None,
+            });
+
+        // An explicitly requested identity always wins.
+        if keychain.sha256_fingerprint.is_some() {
+            warn!("--keychain-fingerprint overrides --smartest-identity");
+            return Ok(());
+        }
+
+        if certs.keys.is_empty() {
+            certs.extend(keychain.resolve_all_identities()?);
+        }
+
+        if certs.keys.is_empty() {
+            return Err(AppleCodesignError::CliGeneralError(
+                "--smartest-identity did not find any signing identities".into(),
+            ));
+        }
+
+        let profiles = if self.smartest_identity_profile.is_empty() {
+            match self.settings.preset {
+                Some(preset) => preset.certificate_profiles(
+                    crate::PathType::from_path(&self.input_path)? == crate::PathType::Xar,
+                ),
+                None => preferred_profiles_for_path(
+                    &self.input_path,
+                    self.settings.for_notarization,
+                )?,
+            }
+        } else {
+            self.smartest_identity_profile
+                .iter()
+                .map(|x| CertificateProfile::from_str(x))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        let identity_count = certs.keys.len().min(certs.certs.len());
+        let selection = select_signing_identity(&certs.certs[0..identity_count], &profiles)?;
+
+        for line in &selection.explanation {
+            warn!("{}", line);
+        }
+
+        certs.retain_identity(selection.index);
+
+        Ok(())
+    }
+
+    fn sign_once(&self, signer: &UnifiedSigner) -> Result<(), AppleCodesignError> {
+        if let Some(base) = &self.update_base {
+            self.sign_update(signer, base)
+        } else if let Some(output_path) = &self.output_path {
+            warn!(
+                "signing {} to {}",
+                self.input_path.display(),
+                output_path.display()
+            );
+
+            signer.sign_path(&self.input_path, output_path)
+        } else {
+            warn!("signing {} in place", self.input_path.display());
+            signer.sign_path_in_place(&self.input_path)
+        }
+    }
+
+    /// Sign the update payload at the input path and write a differential payload.
+    fn sign_update(&self, signer: &UnifiedSigner, base: &Path) -> Result<(), AppleCodesignError> {
+        let output_path = self.output_path.as_ref().ok_or_else(|| {
+            AppleCodesignError::CliGeneralError("--update-base requires an output path".into())
+        })?;
+        let bundle_name = base.file_name().ok_or_else(|| {
+            AppleCodesignError::CliGeneralError("unable to resolve bundle name".into())
+        })?;
+
+        let temp_dir = tempfile::Builder::new()
+            .prefix("rcodesign-update-")
+            .tempdir()?;
+        let staged = temp_dir.path().join(bundle_name);
+
+        warn!(
+            "applying update files from {} to a copy of {}",
+            self.input_path.display(),
+            base.display()
+        );
+        stage_bundle_update(base, &self.input_path, &self.update_remove, &staged)?;
+
+        signer.sign_path_in_place(&staged)?;
+
+        let patch = BundlePatch::compute(base, &staged)?;
+        warn!(
+            "writing update payload with {} changes to {}",
+            patch.entries.len(),
+            output_path.display()
+        );
+
+        patch.write_payload(&staged, output_path)
+    }
+
+    /// Poll the input path for changes and re-sign when any are seen.
     ///
-    /// The behavior of this flag is subject to change. As currently implemented,
-    /// it will:
+    /// Bundles are re-signed incrementally: only nested bundles and Mach-O
+    /// binaries affected by a change are signed again. Other inputs are signed
+    /// again in full.
     ///
-    /// * Require the use of a "Developer ID" signing certificate issued by Apple.
-    /// * Require the use of a time-stamp server.
-    /// * Enable the hardened runtime code signature flag on all Mach-O binaries
-    ///   (equivalent to `--code-signature-flags runtime` for all signed paths).
-    #[arg(long)]
-    for_notarization: bool,
+    /// This only returns if taking a snapshot of the input path fails.
+    fn watch_and_sign(&self, signer: &UnifiedSigner) -> Result<(), AppleCodesignError> {
+        let incremental = crate::PathType::from_path(&self.input_path)? == crate::PathType::Bundle;
+        let mut snapshot = watch_snapshot(&self.input_path)?;
+
+        warn!(
+            "watching {} for changes; press CTRL+C to stop",
+            self.input_path.display()
+        );
 
-    /// Path to Mach-O binary to sign
-    input_path: PathBuf,
+        loop {
+            std::thread::sleep(WATCH_POLL_INTERVAL);
 
-    /// Path to signed Mach-O binary to write
-    output_path: Option<PathBuf>,
+            let current = watch_snapshot(&self.input_path)?;
+            if current == snapshot {
+                continue;
+            }
 
-    #[command(flatten)]
-    certificate: CertificateSource,
-}
+            let changed = current
+                .iter()
+                .filter(|(k, v)| snapshot.get(*k) != Some(*v))
+                .map(|(k, _)| k)
+                .chain(snapshot.keys().filter(|k| !current.contains_key(*k)))
+                .collect::<Vec<_>>();
 
-impl CliCommand for Sign {
-    fn as_config(&self) -> Result<Option<Config>, AppleCodesignError> {
-        let paths = ScopedSigningSettings::try_from(&self.scoped)?;
+            for path in &changed {
+                warn!("change detected: {}", path.display());
+            }
 
-        Ok(Some(Config {
-            sign: config::SignConfig {
-                signer: self.certificate.clone(),
-                paths: paths.0,
-            },
-            ..Default::default()
-        }))
-    }
+            let res = if incremental {
+                let changed = changed
+                    .into_iter()
+                    .filter_map(|p| p.strip_prefix(&self.input_path).ok())
+                    .map(|p| p.to_path_buf())
+                    .collect::<Vec<_>>();
+
+                self.sign_bundle_changes(signer, &changed)
+            } else {
+                self.sign_once(signer)
+            };
+
+            // Failures are expected while a build is still writing files, so
+            // they are reported and we wait for the next change.
+            if let Err(e) = res {
+                error!("error re-signing {}: {}", self.input_path.display(), e);
+            }
 
-    fn run(&self, context: &Context) -> Result<(), AppleCodesignError> {
-        let c = &context.config.sign;
+            // Capture the state after signing so our own writes are ignored.
+            snapshot = watch_snapshot(&self.input_path)?;
+        }
+    }
 
-        let mut settings = SigningSettings::default();
+    /// Re-sign the bundle being watched after the given paths changed.
+    ///
+    /// When writing to a separate output path, changed files are first
+    /// mirrored into it so the output can be updated in place.
+    fn sign_bundle_changes(
+        &self,
+        signer: &UnifiedSigner,
+        changed: &[PathBuf],
+    ) -> Result<(), AppleCodesignError> {
+        let dest = self.output_path.as_ref().unwrap_or(&self.input_path);
 
-        let certs = c.signer.resolve_certificates(true)?;
-        certs.load_into_signing_settings(&mut settings)?;
+        if dest != &self.input_path {
+            for rel in changed {
+                let source = self.input_path.join(rel);
+                let dest = dest.join(rel);
 
-        // Doesn't make sense to set a time-stamp server URL unless we're generating
-        // CMS signatures.
-        if settings.signing_key().is_some() && self.timestamp_url != "none" {
-            warn!("using time-stamp protocol server {}", self.timestamp_url);
-            settings.set_time_stamp_url(&self.timestamp_url)?;
+                if source.is_file() {
+                    if let Some(parent) = dest.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::copy(&source, &dest)?;
+                } else if dest.symlink_metadata().is_ok() {
+                    std::fs::remove_file(&dest)?;
+                }
+            }
         }
 
-        if let Some(time) = &self.signing_time {
-            let time = chrono::DateTime::parse_from_rfc3339(time).map_err(|e| {
-                AppleCodesignError::CliGeneralError(format!("invalid signing time format: {}", e))
-            })?;
-            let time = time.with_timezone(&chrono::Utc);
-            settings.set_signing_time(time);
-        }
+        signer.sign_bundle_changes_in_place(dest, changed)
+    }
+}
 
-        if let Some(team_id) = settings.set_team_id_from_signing_certificate() {
-            warn!(
-                "automatically setting team ID from signing certificate: {}",
-                team_id
+/// How often `sign --watch` polls the filesystem for changes.
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Obtain the size and modification time of all regular files under a path.
+fn watch_snapshot(
+    path: &Path,
+) -> Result<BTreeMap<PathBuf, (u64, std::time::SystemTime)>, AppleCodesignError> {
+    let mut res = BTreeMap::new();
+
+    for entry in walkdir::WalkDir::new(path) {
+        let entry = entry?;
+
+        if entry.file_type().is_file() {
+            let metadata = entry.metadata()?;
+            res.insert(
+                entry.path().to_path_buf(),
+                (metadata.len(), metadata.modified()?),
             );
         }
+    }
 
-        if let Some(team_name) = &self.team_name {
-            settings.set_team_id(team_name);
-        }
+    Ok(res)
+}
 
-        settings.set_shallow(self.shallow);
-        settings.set_for_notarization(self.for_notarization);
+#[derive(Parser)]
+struct SignDetached {
+    /// URL of time-stamp server to use to obtain a token of the CMS signature
+    ///
+    /// Can be set to the special value `none` to disable the generation of time-stamp
+    /// tokens and use of a time-stamp server.
+    #[arg(long, default_value = APPLE_TIMESTAMP_URL)]
+    timestamp_url: String,
 
-        for pattern in &self.exclude {
-            settings.add_path_exclusion(pattern)?;
-        }
+    /// Path of file to sign
+    input_path: PathBuf,
 
-        ScopedSigningSettings(c.paths.clone()).load_into_settings(&mut settings)?;
+    /// Path to write the DER encoded CMS signature to
+    ///
+    /// Defaults to the input path with a `.p7s` extension appended.
+    output_path: Option<PathBuf>,
 
-        settings.ensure_for_notarization_settings()?;
+    #[command(flatten)]
+    certificate: CertificateSource,
+}
 
-        // Settings are locked in. Proceed to sign.
+impl CliCommand for SignDetached {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let mut settings = SigningSettings::default();
 
-        let signer = UnifiedSigner::new(settings);
+        let certs = self.certificate.resolve_certificates(true)?;
+        certs.load_into_signing_settings(&mut settings)?;
 
-        if let Some(output_path) = &self.output_path {
-            warn!(
-                "signing {} to {}",
-                self.input_path.display(),
-                output_path.display()
-            );
+        if settings.signing_key().is_none() {
+            return Err(AppleCodesignError::CliGeneralError(
+                "a signing key is required to create a detached signature".into(),
+            ));
+        }
 
-            signer.sign_path(&self.input_path, output_path)?;
-        } else {
-            warn!("signing {} in place", self.input_path.display());
-            signer.sign_path_in_place(&self.input_path)?;
+        if self.timestamp_url != "none" {
+            set_time_stamp_url(&mut settings, &self.timestamp_url)?;
         }
 
+        let data = std::fs::read(&self.input_path)?;
+        let signature = crate::detached_signature::create_detached_signature(&settings, &data)?;
+
+        let output_path = self.output_path.clone().unwrap_or_else(|| {
+            let mut path = self.input_path.clone().into_os_string();
+            path.push(".p7s");
+            path.into()
+        });
+
+        warn!("writing detached signature to {}", output_path.display());
+        std::fs::write(&output_path, signature)?;
+
         if let Some(private) = certs.private_key_optional()? {
             private.finish()?;
         }
@@ -1773,45 +3016,168 @@ impl CliCommand for SmartcardImport {
 
 #[derive(Parser)]
 struct Staple {
+    /// Print the ticket already stapled to the entity instead of stapling
+    #[arg(long)]
+    verify: bool,
+
+    /// With --verify, don't fail if the ticket's signature can't be verified as Apple's
+    #[arg(long, requires = "verify")]
+    allow_untrusted: bool,
+
     /// Path to entity to attempt to staple
     path: PathBuf,
 }
 
-impl CliCommand for Staple {
-    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+impl Staple {
+    fn verify_stapled_ticket(&self) -> Result<(), AppleCodesignError> {
+        let stapled = crate::stapling::read_stapled_ticket(&self.path)?
+            .ok_or_else(|| AppleCodesignError::NotarizationTicketNotStapled(self.path.clone()))?;
+
+        let ticket = crate::notarization_ticket::NotarizationTicket::parse(&stapled.data)?;
+
+        let cdhashes = stapled
+            .record_name
+            .as_deref()
+            .and_then(crate::notarization_ticket::cdhash_from_record_name)
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let info = ticket.info(&cdhashes);
+
+        print!("{}", serde_yaml::to_string(&info)?);
+
+        if let Err(e) = ticket.verify() {
+            if self.allow_untrusted {
+                warn!("ignoring ticket signature verification failure: {}", e);
+            } else {
+                return Err(e);
+            }
+        }
+
+        if !info.uncovered_cdhashes.is_empty() {
+            return Err(AppleCodesignError::CliGeneralError(
+                "stapled notarization ticket does not cover this entity".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "network")]
+    fn staple(&self) -> Result<(), AppleCodesignError> {
         let stapler = crate::stapling::Stapler::new()?;
         stapler.staple_path(&self.path)?;
 
+        update_notarization_history(|history| {
+            history.mark_stapled(&self.path);
+        });
+
         Ok(())
     }
+
+    #[cfg(not(feature = "network"))]
+    fn staple(&self) -> Result<(), AppleCodesignError> {
+        Err(AppleCodesignError::NetworkDisabled(
+            "looking up notarization tickets",
+        ))
+    }
+}
+
+impl CliCommand for Staple {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        if self.verify {
+            self.verify_stapled_ticket()
+        } else {
+            self.staple()
+        }
+    }
 }
 
 #[derive(Parser)]
 struct Verify {
-    /// Path of Mach-O binary to examine
+    /// Verify a random sample of this many code pages per code directory.
+    ///
+    /// By default, every code page is digested and compared against the code
+    /// directory. Spot checking is much faster for large binaries but can miss
+    /// modifications to pages outside the sample. The first and last pages are
+    /// always checked.
+    #[arg(long, value_name = "PAGES")]
+    spot_check: Option<usize>,
+
+    /// Verify every code page, even if --spot-check is given
+    #[arg(long)]
+    full: bool,
+
+    /// Path of Mach-O binary or bundle to examine
     path: PathBuf,
+
+    #[command(flatten)]
+    report: ReportArgs,
 }
 
 impl CliCommand for Verify {
     fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
         let path_type = crate::PathType::from_path(&self.path)?;
 
-        if path_type != crate::PathType::MachO {
-            return Err(AppleCodesignError::CliGeneralError(format!(
-                "verify command only works on Mach-O binaries; provided path is a {:?}",
-                path_type
-            )));
-        }
-
         warn!("(the verify command is known to be buggy and gives misleading results; we highly recommend using Apple's tooling until this message is removed)");
-        let data = std::fs::read(&self.path)?;
 
-        let problems = crate::verify::verify_macho_data(data);
+        let mode = match self.spot_check {
+            Some(count) if !self.full => {
+                warn!("spot checking {} code pages per code directory", count);
+                crate::verify::CodeDigestVerification::Sample(count)
+            }
+            _ => crate::verify::CodeDigestVerification::Full,
+        };
+
+        let problems = match path_type {
+            crate::PathType::MachO => {
+                let data = std::fs::read(&self.path)?;
+
+                crate::verify::verify_macho_data_with_mode(data, mode)
+            }
+            crate::PathType::Bundle => {
+                let bundle = apple_bundles::DirectoryBundle::new_from_path(&self.path)?;
+
+                let mut problems = vec![];
+
+                if let Some(main_exe) = bundle
+                    .files(false)?
+                    .into_iter()
+                    .find(|f| matches!(f.is_main_executable(), Ok(true)))
+                {
+                    let data = std::fs::read(main_exe.absolute_path())?;
+                    problems.extend(crate::verify::verify_macho_data_with_mode(data, mode));
+                }
+
+                problems.extend(crate::verify::verify_bundle_resources(&bundle));
+
+                problems
+            }
+            _ => {
+                return Err(AppleCodesignError::CliGeneralError(format!(
+                    "verify command only works on Mach-O binaries and bundles; provided path is a {:?}",
+                    path_type
+                )));
+            }
+        };
 
         for problem in &problems {
             println!("{problem}");
         }
 
+        let mut suite = TestSuite::new("verify");
+        if problems.is_empty() {
+            suite.push(self.path.display(), "signature", TestOutcome::Passed);
+        }
+        for problem in &problems {
+            suite.push(
+                self.path.display(),
+                problem,
+                TestOutcome::Failed(problem.to_string()),
+            );
+        }
+        self.report.write(&suite)?;
+
         if problems.is_empty() {
             eprintln!("no problems detected!");
             eprintln!("(we do not verify everything so please do not assume that the signature meets Apple standards)");
@@ -1822,6 +3188,39 @@ impl CliCommand for Verify {
     }
 }
 
+#[derive(Parser)]
+struct VerifyDetached {
+    /// Path to the DER encoded CMS signature
+    ///
+    /// Defaults to the input path with a `.p7s` extension appended.
+    #[arg(long, value_name = "PATH")]
+    signature_path: Option<PathBuf>,
+
+    /// Path of signed file to verify
+    input_path: PathBuf,
+}
+
+impl CliCommand for VerifyDetached {
+    fn run(&self, _context: &Context) -> Result<(), AppleCodesignError> {
+        let signature_path = self.signature_path.clone().unwrap_or_else(|| {
+            let mut path = self.input_path.clone().into_os_string();
+            path.push(".p7s");
+            path.into()
+        });
+
+        let signature = std::fs::read(&signature_path)?;
+        let data = std::fs::read(&self.input_path)?;
+
+        let cms = crate::detached_signature::verify_detached_signature(&signature, &data)?;
+
+        print!("{}", serde_yaml::to_string(&cms)?);
+        eprintln!("signature is valid");
+        eprintln!("(the trust of the signing certificates is not verified)");
+
+        Ok(())
+    }
+}
+
 #[derive(Parser)]
 struct WindowsStoreExportCertificateChain {
     /// Windows Store to operate on
@@ -1938,9 +3337,66 @@ enum Subcommands {
     /// properties related to certificates.
     AnalyzeCertificate(AnalyzeCertificate),
 
+    /// Check that the artifacts of a release contain the same signed application
+    ///
+    /// Given a signed application bundle and the DMG and/or flat package
+    /// installer it is distributed in, this verifies that the installer
+    /// contains the same application bundle and that every artifact has a
+    /// notarization ticket stapled that covers it. This catches shipping
+    /// artifacts built or notarized from different builds.
+    ///
+    /// Installer payloads are compared bit-for-bit with the application bundle.
+    /// Only DMGs with FAT filesystems can be inspected, in which case file sizes
+    /// are compared. Content checks of other DMGs are skipped.
+    ///
+    /// The version, build number, and main executable cdhash of the application
+    /// are printed along with the result of every check. The command fails if
+    /// any check fails.
+    CheckRelease(CheckRelease),
+
+    /// Report SDK versions, bitcode presence, and deprecated framework linkage
+    ///
+    /// Every Mach-O binary within the given path is inspected. For each binary,
+    /// the targeted platform, minimum OS version, and the SDK version it was
+    /// built against are printed. Embedded bitcode (which the App Store no
+    /// longer accepts) and linkage of deprecated system frameworks (e.g.
+    /// OpenGL or AddressBook) are also reported.
+    ///
+    /// Use `--format json` to obtain machine readable output and
+    /// `--fail-on-issues` to exit non-0 when potential problems are found.
+    ComplianceReport(ComplianceReport),
+
     /// Compute code hashes for a binary
     ComputeCodeHashes(ComputeCodeHashes),
 
+    /// Keep signing state warm and sign paths on request over a local socket
+    ///
+    /// Signing certificates and keys are resolved once, when the daemon
+    /// starts, along with the signing settings defined by the arguments
+    /// accepted by `sign` and the configuration file. Requests to sign paths
+    /// are then accepted on the Unix domain socket given by `--socket`. This
+    /// eliminates per-invocation startup costs, such as unlocking a smartcard,
+    /// when signing many entities.
+    ///
+    /// Requests are JSON objects, one per line:
+    ///
+    /// * `{"command": "sign", "input": "/path/to/app"}` signs a path in place.
+    ///   An `"output"` path can be given to write the signed entity elsewhere.
+    /// * `{"command": "shutdown"}` stops the daemon.
+    ///
+    /// Each request is answered with a line holding a JSON object whose `ok`
+    /// key indicates success. Failures include an `error` message. Paths are
+    /// resolved relative to the working directory of the daemon, so absolute
+    /// paths should be used.
+    ///
+    /// Requests are processed one at a time. Settings of `[artifact]` sections
+    /// of the configuration file aren't applied.
+    ///
+    /// The socket is only accessible by the current user. Anyone able to
+    /// connect to it can sign with the configured key.
+    #[cfg(unix)]
+    Daemon(daemon::Daemon),
+
     /// Create a binary code requirements file.
     #[command(hide = true)]
     DebugCreateCodeRequirements(debug_commands::DebugCreateCodeRequirements),
@@ -1961,13 +3417,43 @@ enum Subcommands {
     #[command(hide = true)]
     DebugCreateMacho(debug_commands::DebugCreateMachO),
 
+    /// Compare the performance of digest backends.
+    #[command(hide = true)]
+    DebugDigestBenchmark(debug_commands::DebugDigestBenchmark),
+
     /// Print a filesystem tree with basic metadata.
     #[command(hide = true)]
     DebugFileTree(debug_commands::DebugFileTree),
 
+    /// Check the signing environment for common problems
+    ///
+    /// This command runs a series of checks and prints a finding for each,
+    /// along with a suggested remedy when something looks wrong. Checks
+    /// cover:
+    ///
+    /// * Whether smartcard readers and devices are accessible.
+    /// * Whether signing keys and certificates specified via the same
+    ///   arguments accepted by `sign` can be loaded, and whether the
+    ///   certificates are currently valid.
+    /// * The code signature and entitlements of `rcodesign` itself (macOS only).
+    /// * Reachability of Apple's time-stamp server, the App Store Connect API,
+    ///   and the notarization ticket lookup service.
+    /// * Skew of the local clock relative to those servers, as reported by the
+    ///   `Date` header of their responses. Clock skew can cause App Store Connect
+    ///   to reject API tokens with opaque authentication errors.
+    ///
+    /// Pass `--offline` to skip checks requiring network access.
+    ///
+    /// The command exits non-0 if any check reports an error.
+    Diagnose(diagnose::Diagnose),
+
     /// Print a diff between the signature content of two paths
     DiffSignatures(DiffSignatures),
 
+    /// Inspect DMG images.
+    #[cfg(feature = "dmg")]
+    Dmg(dmg_commands::Dmg),
+
     /// Encode App Store Connect API Key metadata to JSON
     ///
     /// App Store Connect API Keys
@@ -2056,6 +3542,18 @@ enum Subcommands {
     /// Fetch the notarization log for a previous submission
     NotaryLog(NotaryLog),
 
+    /// Emit a status document derived from the local notarization history
+    ///
+    /// Notarization commands record submissions, their status, and whether tickets
+    /// were stapled to a history file on the local machine. This command turns that
+    /// history into a small JSON or Markdown document describing the latest
+    /// submission and the notarization state of each submitted artifact, including
+    /// the code directory hashes that were notarized.
+    ///
+    /// The emitted document is suitable for publishing alongside releases.
+    #[cfg(feature = "notarize")]
+    NotaryStatusDocument(NotaryStatusDocument),
+
     /// Upload an asset to Apple for notarization and possibly staple it
     ///
     /// This command is used to submit an asset to Apple for notarization. Given
@@ -2102,6 +3600,10 @@ enum Subcommands {
     ///
     /// To automatically staple an asset after server-side processing has finished,
     /// specify `--staple`. This implies `--wait`.
+    ///
+    /// Multiple paths can be given. They are uploaded, waited on, and stapled in
+    /// parallel. A summary of all submissions is printed at the end and the
+    /// command fails if any submission failed.
     #[cfg(feature = "notarize")]
     #[command(alias = "notarize")]
     NotarySubmit(NotarySubmit),
@@ -2129,10 +3631,20 @@ enum Subcommands {
     /// in a different binary serialization.
     ParseCodeSigningRequirement(ParseCodeSigningRequirement),
 
+    /// Print symbols exported and imported by a Mach-O binary
+    ///
+    /// Exports are resolved by parsing the binary's export trie. Imports are
+    /// resolved from chained fixups or dyld bind opcodes, depending on which
+    /// the binary uses.
+    ///
+    /// Use `--format json` to obtain machine readable output.
+    PrintMachoSymbols(PrintMachoSymbols),
+
     /// Print signature information for a filesystem path
     PrintSignatureInfo(PrintSignatureInfo),
 
     /// Create signatures initiated from a remote signing operation
+    #[cfg(feature = "remote-signing")]
     RemoteSign(RemoteSign),
 
     /// Adds code signatures to a signable entity.
@@ -2143,6 +3655,7 @@ enum Subcommands {
     /// * A bundle (specified by its directory path)
     /// * A DMG disk image (specified by its path)
     /// * A XAR archive (commonly a .pkg installer file)
+    /// * A zip file or tarball (.tar or .tar.gz) containing Mach-O binaries or bundles
     ///
     /// If the input is Mach-O binary, it can be a single or multiple/fat/universal
     /// Mach-O binary. If a fat binary is given, each Mach-O within that binary will
@@ -2152,6 +3665,10 @@ enum Subcommands {
     /// bundle contains nested bundles or Mach-O binaries, those will be signed
     /// automatically.
     ///
+    /// If the input is a zip file or tarball, the bundles and Mach-O binaries
+    /// within are signed and the archive is rewritten. Entries retain their
+    /// order and metadata.
+    ///
     /// # Settings Scope
     ///
     /// The following signing settings are global and apply to all signed entities:
@@ -2359,6 +3876,16 @@ enum Subcommands {
     #[command(verbatim_doc_comment)]
     Sign(Sign),
 
+    /// Create a detached CMS signature over an arbitrary file
+    ///
+    /// Signatures are created with the same signing keys used for code signing
+    /// and can be used to sign auxiliary release artifacts, such as update
+    /// manifests. The signature is written in DER encoded CMS (`.p7s`) form and
+    /// does not contain the signed content.
+    ///
+    /// Use `verify-detached` to verify the signature.
+    SignDetached(SignDetached),
+
     /// Generate a new private key on a smartcard
     SmartcardGenerateKey(SmartcardGenerateKey),
 
@@ -2369,11 +3896,31 @@ enum Subcommands {
     SmartcardScan(SmartcardScan),
 
     /// Staples a notarization ticket to an entity
+    ///
+    /// With `--verify`, the notarization ticket already stapled to the entity is
+    /// parsed and its contents are printed instead. The command fails if no ticket
+    /// is stapled, if the ticket's signature can't be verified as Apple's, or if
+    /// the ticket doesn't cover the entity's code directory hash.
     Staple(Staple),
 
     /// Verifies code signature data
+    ///
+    /// When given a bundle, the signature of the bundle's main executable is
+    /// verified and every resource sealed by the bundle's signature is compared
+    /// against the bundle's content. Each added, removed, or modified resource
+    /// is reported along with its sealed and actual digests and the resource
+    /// rule it matched.
     Verify(Verify),
 
+    /// Verifies a detached CMS signature over an arbitrary file
+    ///
+    /// The cryptographic signature and the digest of the file content are
+    /// verified for every signer. Information about the signers and embedded
+    /// certificates is printed on success.
+    ///
+    /// The trust of the signing certificates is not evaluated.
+    VerifyDetached(VerifyDetached),
+
     /// Export CA certificates from the Windows Store
     WindowsStoreExportCertificateChain(WindowsStoreExportCertificateChain),
 
@@ -2388,14 +3935,22 @@ impl Subcommands {
     fn as_cli_command(&self) -> &dyn CliCommand {
         match self {
             Subcommands::AnalyzeCertificate(c) => c,
+            Subcommands::CheckRelease(c) => c,
+            Subcommands::ComplianceReport(c) => c,
             Subcommands::ComputeCodeHashes(c) => c,
+            #[cfg(unix)]
+            Subcommands::Daemon(c) => c,
             Subcommands::DebugCreateCodeRequirements(c) => c,
             Subcommands::DebugCreateConstraints(c) => c,
             Subcommands::DebugCreateEntitlements(c) => c,
             Subcommands::DebugCreateInfoPlist(c) => c,
             Subcommands::DebugCreateMacho(c) => c,
+            Subcommands::DebugDigestBenchmark(c) => c,
             Subcommands::DebugFileTree(c) => c,
+            Subcommands::Diagnose(c) => c,
             Subcommands::DiffSignatures(c) => c,
+            #[cfg(feature = "dmg")]
+            Subcommands::Dmg(c) => c,
             #[cfg(feature = "notarize")]
             Subcommands::EncodeAppStoreConnectApiKey(c) => c,
             Subcommands::Extract(c) => c,
@@ -2409,18 +3964,24 @@ impl Subcommands {
             #[cfg(feature = "notarize")]
             Subcommands::NotaryList(c) => c,
             #[cfg(feature = "notarize")]
+            Subcommands::NotaryStatusDocument(c) => c,
+            #[cfg(feature = "notarize")]
             Subcommands::NotarySubmit(c) => c,
             #[cfg(feature = "notarize")]
             Subcommands::NotaryWait(c) => c,
             Subcommands::ParseCodeSigningRequirement(c) => c,
+            Subcommands::PrintMachoSymbols(c) => c,
             Subcommands::PrintSignatureInfo(c) => c,
+            #[cfg(feature = "remote-signing")]
             Subcommands::RemoteSign(c) => c,
             Subcommands::Sign(c) => c,
+            Subcommands::SignDetached(c) => c,
             Subcommands::SmartcardGenerateKey(c) => c,
             Subcommands::SmartcardImport(c) => c,
             Subcommands::SmartcardScan(c) => c,
             Subcommands::Staple(c) => c,
             Subcommands::Verify(c) => c,
+            Subcommands::VerifyDetached(c) => c,
             Subcommands::WindowsStoreExportCertificateChain(c) => c,
             Subcommands::WindowsStorePrintCertificates(c) => c,
             Subcommands::X509Oids(c) => c,
@@ -2544,4 +4105,73 @@ mod test {
     fn verify_cli() {
         Cli::command().debug_assert();
     }
+
+    /// Simulates submissions, with the outcome derived from the path.
+    #[cfg(feature = "notarize")]
+    fn fake_submission(path: &Path) -> Result<NotarySubmitOutcome, AppleCodesignError> {
+        let stapled = match path.to_str() {
+            Some("bad.dmg") => {
+                return Err(AppleCodesignError::CliGeneralError("upload failed".into()));
+            }
+            Some("unstapled.dmg") => Err(AppleCodesignError::CliGeneralError("no ticket".into())),
+            _ => Ok(()),
+        };
+
+        Ok(NotarySubmitOutcome {
+            submission_id: format!("id-{}", path.display()),
+            status: Some("Accepted".into()),
+            stapled: Some(stapled),
+        })
+    }
+
+    #[cfg(feature = "notarize")]
+    #[test]
+    fn notary_submit_single_path() -> Result<(), AppleCodesignError> {
+        let submit = NotarySubmit::try_parse_from(["notary-submit", "good.dmg"]).unwrap();
+        submit.finish(submit.submit_paths(fake_submission))?;
+
+        // A failing submission returns its own error rather than a summary.
+        for (path, message) in [("bad.dmg", "upload failed"), ("unstapled.dmg", "no ticket")] {
+            let submit = NotarySubmit::try_parse_from(["notary-submit", path]).unwrap();
+            let err = submit
+                .finish(submit.submit_paths(fake_submission))
+                .unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "notarize")]
+    #[test]
+    fn notary_submit_multiple_paths() -> Result<(), AppleCodesignError> {
+        let submit =
+            NotarySubmit::try_parse_from(["notary-submit", "good.dmg", "bad.dmg", "unstapled.dmg"])
+                .unwrap();
+
+        let results = submit.submit_paths(fake_submission);
+        assert_eq!(
+            NotarySubmit::summary_lines(&results),
+            vec![
+                Ok("good.dmg: submission id-good.dmg Accepted (stapled)".to_string()),
+                Err("bad.dmg: upload failed".to_string()),
+                Err(
+                    "unstapled.dmg: submission id-unstapled.dmg Accepted; stapling failed: no ticket"
+                        .to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            submit.finish(results).unwrap_err().to_string(),
+            "2 of 3 notarization submissions failed"
+        );
+
+        let submit =
+            NotarySubmit::try_parse_from(["notary-submit", "good.dmg", "other.dmg"]).unwrap();
+        let results = submit.submit_paths(fake_submission);
+        assert_eq!(NotarySubmit::summary_lines(&results).len(), 2);
+        submit.finish(results)?;
+
+        Ok(())
+    }
 }
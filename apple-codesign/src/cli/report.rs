@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Machine readable reports of check results.
+//!
+//! Commands that validate signatures or notarization can record the outcome
+//! of each check they perform and write it out as a JUnit XML document, which
+//! CI systems like Jenkins and GitLab render as test results.
+
+use {
+    crate::error::AppleCodesignError,
+    clap::Args,
+    std::{io::Write, path::PathBuf, str::FromStr},
+    xml::writer::XmlEvent,
+};
+
+/// A destination to write a report to.
+#[derive(Clone, Debug)]
+pub enum ReportDestination {
+    /// Write a JUnit XML document to the given path.
+    JUnit(PathBuf),
+}
+
+impl FromStr for ReportDestination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("junit", path)) if !path.is_empty() => Ok(Self::JUnit(PathBuf::from(path))),
+            Some((format, _)) if format != "junit" => Err(format!(
+                "unsupported report format: {format}; expected junit"
+            )),
+            _ => Err(format!(
+                "invalid report {s}; expected FORMAT=PATH (e.g. junit=report.xml)"
+            )),
+        }
+    }
+}
+
+/// Arguments controlling the writing of reports.
+#[derive(Args, Clone, Debug, Default)]
+pub struct ReportArgs {
+    /// Write check results to a report (e.g. `junit=report.xml`)
+    ///
+    /// Each check performed by the command is recorded as a test case in the
+    /// report. `junit` is currently the only supported format.
+    ///
+    /// Can be specified multiple times.
+    #[arg(long = "report", value_name = "FORMAT=PATH")]
+    pub destinations: Vec<ReportDestination>,
+}
+
+impl ReportArgs {
+    /// Write a test suite to all requested destinations.
+    pub fn write(&self, suite: &TestSuite) -> Result<(), AppleCodesignError> {
+        for destination in &self.destinations {
+            match destination {
+                ReportDestination::JUnit(path) => {
+                    if let Some(parent) = path.parent() {
+                        if !parent.as_os_str().is_empty() {
+                            std::fs::create_dir_all(parent)?;
+                        }
+                    }
+
+                    let mut fh = std::fs::File::create(path)?;
+                    suite.write_junit(&mut fh)?;
+                    fh.flush()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The outcome of an individual test case.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TestOutcome {
+    /// The check passed.
+    Passed,
+    /// The check failed with the given message.
+    Failed(String),
+    /// The check passed with the given warning.
+    Warning(String),
+    /// The check was not performed for the given reason.
+    Skipped(String),
+}
+
+/// An individual check.
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    /// Groups related test cases. Usually the path of the checked artifact.
+    pub classname: String,
+    /// Name of the check.
+    pub name: String,
+    /// Outcome of the check.
+    pub outcome: TestOutcome,
+}
+
+/// A collection of checks performed by a single command.
+#[derive(Clone, Debug)]
+pub struct TestSuite {
+    /// Name of the suite. Usually the command name.
+    pub name: String,
+    /// Checks in this suite.
+    pub cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+    /// Construct an empty suite.
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            cases: vec![],
+        }
+    }
+
+    /// Record the outcome of a check.
+    pub fn push(&mut self, classname: impl ToString, name: impl ToString, outcome: TestOutcome) {
+        self.cases.push(TestCase {
+            classname: classname.to_string(),
+            name: name.to_string(),
+            outcome,
+        });
+    }
+
+    fn count(&self, f: impl Fn(&TestOutcome) -> bool) -> usize {
+        self.cases.iter().filter(|c| f(&c.outcome)).count()
+    }
+
+    /// Write the suite as a JUnit XML document.
+    pub fn write_junit(&self, writer: &mut impl Write) -> Result<(), AppleCodesignError> {
+        let mut w = xml::EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(writer);
+
+        let tests = self.cases.len().to_string();
+        let failures = self
+            .count(|o| matches!(o, TestOutcome::Failed(_)))
+            .to_string();
+        let skipped = self
+            .count(|o| matches!(o, TestOutcome::Skipped(_)))
+            .to_string();
+
+        let mut events = vec![
+            XmlEvent::start_element("testsuites")
+                .attr("name", &self.name)
+                .attr("tests", &tests)
+                .attr("failures", &failures)
+                .attr("errors", "0")
+                .attr("skipped", &skipped)
+                .into(),
+            XmlEvent::start_element("testsuite")
+                .attr("name", &self.name)
+                .attr("tests", &tests)
+                .attr("failures", &failures)
+                .attr("errors", "0")
+                .attr("skipped", &skipped)
+                .into(),
+        ];
+
+        for case in &self.cases {
+            events.push(
+                XmlEvent::start_element("testcase")
+                    .attr("classname", &case.classname)
+                    .attr("name", &case.name)
+                    .into(),
+            );
+
+            match &case.outcome {
+                TestOutcome::Passed => {}
+                TestOutcome::Warning(message) => {
+                    events.push(XmlEvent::start_element("system-out").into());
+                    events.push(XmlEvent::characters(&format!("warning: {message}")));
+                    events.push(XmlEvent::end_element().into());
+                }
+                TestOutcome::Failed(message) => {
+                    events.push(
+                        XmlEvent::start_element("failure")
+                            .attr("message", message)
+                            .into(),
+                    );
+                    events.push(XmlEvent::characters(message));
+                    events.push(XmlEvent::end_element().into());
+                }
+                TestOutcome::Skipped(message) => {
+                    events.push(
+                        XmlEvent::start_element("skipped")
+                            .attr("message", message)
+                            .into(),
+                    );
+                    events.push(XmlEvent::end_element().into());
+                }
+            }
+
+            events.push(XmlEvent::end_element().into());
+        }
+
+        events.push(XmlEvent::end_element().into());
+        events.push(XmlEvent::end_element().into());
+
+        for event in events {
+            w.write(event).map_err(AppleCodesignError::XmlWrite)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_destination() {
+        assert!(matches!(
+            ReportDestination::from_str("junit=out/report.xml"),
+            Ok(ReportDestination::JUnit(p)) if p == PathBuf::from("out/report.xml")
+        ));
+        assert!(ReportDestination::from_str("junit=").is_err());
+        assert!(ReportDestination::from_str("html=report.html").is_err());
+        assert!(ReportDestination::from_str("report.xml").is_err());
+    }
+
+    #[test]
+    fn write_junit() -> Result<(), AppleCodesignError> {
+        let mut suite = TestSuite::new("verify");
+        suite.push("MyApp.app", "signature", TestOutcome::Passed);
+        suite.push(
+            "MyApp.app",
+            "resource Contents/Resources/a.txt",
+            TestOutcome::Failed("sealed & actual digests differ".into()),
+        );
+        suite.push(
+            "MyApp.app",
+            "notarization",
+            TestOutcome::Skipped("not requested".into()),
+        );
+        suite.push(
+            "MyApp.app",
+            "ticket-signature",
+            TestOutcome::Warning("not signed by Apple".into()),
+        );
+
+        let mut buf = vec![];
+        suite.write_junit(&mut buf)?;
+        let xml = String::from_utf8(buf).unwrap();
+
+        assert!(xml.contains(
+            r#"<testsuite name="verify" tests="4" failures="1" errors="0" skipped="1">"#
+        ));
+        assert!(xml.contains(r#"<testcase classname="MyApp.app" name="signature" />"#));
+        assert!(xml.contains(r#"<failure message="sealed &amp; actual digests differ">"#));
+        assert!(xml.contains(r#"<skipped message="not requested" />"#));
+        assert!(xml.contains("<system-out>warning: not signed by Apple</system-out>"));
+
+        Ok(())
+    }
+}
@@ -680,6 +680,33 @@ impl<'a> CodeDirectoryBlob<'a> {
         old_version
     }
 
+    /// Lower the version of the data structure to at most the specified version.
+    ///
+    /// Fields not present in the resulting version are cleared. Fields that
+    /// merely convey optional metadata (such as the hardened runtime version) are
+    /// dropped silently. But if the code limit exceeds what older versions can
+    /// represent, an error is returned.
+    ///
+    /// Returns the old version.
+    pub fn cap_version(&mut self, max: CodeDirectoryVersion) -> Result<u32, AppleCodesignError> {
+        let old_version = self.version;
+
+        if self.version > max as u32 {
+            if self.code_limit_64.is_some()
+                && (max as u32) < CodeDirectoryVersion::SupportsCodeLimit64 as u32
+            {
+                return Err(AppleCodesignError::CompatibilityTargetUnsupported(
+                    "code limit exceeds 32 bits",
+                ));
+            }
+
+            self.version = max as u32;
+            self.clear_newer_fields();
+        }
+
+        Ok(old_version)
+    }
+
     /// Clears optional fields that are newer than the current version.
     ///
     /// The C structure is versioned and our Rust struct is a superset of
@@ -795,4 +822,34 @@ mod tests {
             CodeSignatureFlags::LINKER_SIGNED
         );
     }
+
+    #[test]
+    fn cap_version() -> Result<(), AppleCodesignError> {
+        let mut cd = CodeDirectoryBlob {
+            exec_seg_base: Some(0),
+            runtime: Some(0x0a0000),
+            ..Default::default()
+        };
+        cd.adjust_version(None);
+        assert_eq!(cd.version, CodeDirectoryVersion::SupportsRuntime as u32);
+
+        assert_eq!(
+            cd.cap_version(CodeDirectoryVersion::SupportsTeamId)?,
+            CodeDirectoryVersion::SupportsRuntime as u32
+        );
+        assert_eq!(cd.version, CodeDirectoryVersion::SupportsTeamId as u32);
+        assert!(cd.exec_seg_base.is_none());
+        assert!(cd.runtime.is_none());
+
+        let mut cd = CodeDirectoryBlob {
+            code_limit_64: Some(u32::MAX as u64 + 1),
+            ..Default::default()
+        };
+        cd.adjust_version(None);
+        assert!(cd
+            .cap_version(CodeDirectoryVersion::SupportsTeamId)
+            .is_err());
+
+        Ok(())
+    }
 }
@@ -18,17 +18,21 @@
 use {
     crate::{
         bundle_signing::{BundleSigningContext, SignedMachOInfo},
-        cryptography::{DigestType, MultiDigest},
+        cryptography::{DigestBackendType, DigestType, MultiDigest},
         error::AppleCodesignError,
     },
     apple_bundles::DirectoryBundle,
     log::{debug, error, info, warn},
     plist::{Dictionary, Value},
+    rayon::prelude::*,
+    serde::{Deserialize, Serialize},
     std::{
         cmp::Ordering,
         collections::{BTreeMap, BTreeSet},
+        fs::Metadata,
         io::Write,
-        path::Path,
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
     },
 };
 
@@ -168,6 +172,23 @@ impl std::fmt::Debug for Files2Value {
     }
 }
 
+impl Files2Value {
+    /// Describe the sealed content for humans.
+    fn describe(&self) -> String {
+        if let Some(target) = &self.symlink {
+            format!("symlink to {target}")
+        } else if let Some(cdhash) = &self.cdhash {
+            format!("cdhash {}", hex::encode(cdhash))
+        } else if let Some(hash2) = &self.hash2 {
+            format!("sha256 {}", hex::encode(hash2))
+        } else if let Some(hash) = &self.hash {
+            format!("sha1 {}", hex::encode(hash))
+        } else {
+            "no digest".to_string()
+        }
+    }
+}
+
 impl TryFrom<&Value> for Files2Value {
     type Error = AppleCodesignError;
 
@@ -756,6 +777,15 @@ impl CodeResources {
         );
     }
 
+    /// Remove the version 2 `<rules2>` and `<files2>` sections.
+    ///
+    /// Only the version 1 `<rules>` and `<files>` sections remain. This is the
+    /// layout understood by macOS 10.8 and earlier.
+    pub fn remove_version2(&mut self) {
+        self.files2.clear();
+        self.rules2.clear();
+    }
+
     /// Seal a regular file.
     ///
     /// This will digest the content specified and record that digest in the files or
@@ -862,6 +892,286 @@ impl CodeResources {
 
         Ok(())
     }
+
+    /// Obtain the `<rules2>` rules as [CodeResourcesRule] instances.
+    ///
+    /// Rules are sorted by decreasing priority.
+    pub fn rules2_rules(&self) -> Result<Vec<CodeResourcesRule>, AppleCodesignError> {
+        let mut rules = self
+            .rules2
+            .iter()
+            .map(|(pattern, value)| {
+                let mut rule = CodeResourcesRule::new(pattern)?;
+                rule.nested = value.nested == Some(true);
+                rule.omit = value.omit == Some(true);
+                rule.optional = value.optional == Some(true);
+                rule.weight = value.weight.map(|x| x as u32);
+
+                Ok(rule)
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+        rules.sort();
+
+        Ok(rules)
+    }
+
+    /// Compare the `<files2>` seals against the content of a bundle.
+    ///
+    /// The bundle's directory is walked using the `<rules2>` rules recorded
+    /// in this instance and the content of each sealable entity is compared
+    /// against its seal. Entities that are new, missing, or whose content
+    /// differs are returned.
+    ///
+    /// An empty vector means the bundle's resources match their seals. Version 1
+    /// `<files>` seals aren't compared.
+    pub fn diff_bundle(
+        &self,
+        bundle: &DirectoryBundle,
+    ) -> Result<Vec<SealDifference>, AppleCodesignError> {
+        let bundle_root = bundle.root_dir();
+
+        let mut rules = self.rules2_rules()?;
+
+        // Mirror the exclusions applied when the bundle was sealed.
+        rules.push(CodeResourcesRule::new("^_CodeSignature/")?.exclude());
+        rules.push(CodeResourcesRule::new("^CodeResources$")?.exclude());
+        rules.push(CodeResourcesRule::new("^_MASReceipt$")?.exclude());
+
+        if let Some(main_exe) = bundle
+            .files(false)?
+            .into_iter()
+            .find(|f| matches!(f.is_main_executable(), Ok(true)))
+        {
+            rules.push(
+                CodeResourcesRule::new(format!(
+                    "^{}$",
+                    regex::escape(&normalized_resources_path(main_exe.relative_path()))
+                ))?
+                .exclude(),
+            );
+        }
+
+        rules.sort();
+
+        let mut actual = BTreeMap::new();
+        let mut skipping_rel_dirs = BTreeSet::new();
+
+        for entry in walkdir::WalkDir::new(bundle_root).sort_by_file_name() {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path == bundle_root {
+                continue;
+            }
+
+            let rel_path = path
+                .strip_prefix(bundle_root)
+                .expect("stripping path prefix should always work");
+
+            if skipping_rel_dirs.iter().any(|p| rel_path.starts_with(p)) {
+                continue;
+            }
+
+            let rule = if let Some(rule) = find_rule(&rules, rel_path) {
+                rule
+            } else {
+                continue;
+            };
+
+            let rel_path_normalized = normalized_resources_path(rel_path);
+            let file_type = entry.file_type();
+
+            if rule.exclude {
+                if file_type.is_dir() {
+                    skipping_rel_dirs.insert(rel_path.to_path_buf());
+                }
+                continue;
+            }
+
+            if file_type.is_dir() {
+                if rule.nested && entry.file_name().to_string_lossy().contains('.') {
+                    skipping_rel_dirs.insert(rel_path.to_path_buf());
+
+                    let nested = DirectoryBundle::new_from_path(path)?;
+
+                    if let Some(nested_exe) = nested
+                        .files(false)?
+                        .into_iter()
+                        .find(|f| matches!(f.is_main_executable(), Ok(true)))
+                    {
+                        let macho_data = std::fs::read(nested_exe.absolute_path())?;
+
+                        actual.insert(
+                            rel_path_normalized,
+                            (macho_seal(&macho_data)?, rule.pattern),
+                        );
+                    }
+                }
+            } else if rule.omit {
+                continue;
+            } else if file_type.is_file() {
+                let value = if rule.nested && crate::reader::path_is_macho(path)? {
+                    macho_seal(&std::fs::read(path)?)?
+                } else {
+                    let digests = MultiDigest::from_path(path)?;
+
+                    Files2Value {
+                        cdhash: None,
+                        hash: Some(digests.sha1.to_vec()),
+                        hash2: Some(digests.sha256.to_vec()),
+                        optional: None,
+                        requirement: None,
+                        symlink: None,
+                    }
+                };
+
+                actual.insert(rel_path_normalized, (value, rule.pattern));
+            } else if file_type.is_symlink() {
+                let link_target = std::fs::read_link(path)?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+
+                actual.insert(
+                    rel_path_normalized,
+                    (
+                        Files2Value {
+                            cdhash: None,
+                            hash: None,
+                            hash2: None,
+                            optional: None,
+                            requirement: None,
+                            symlink: Some(link_target),
+                        },
+                        rule.pattern,
+                    ),
+                );
+            }
+        }
+
+        let mut differences = vec![];
+
+        for (path, sealed) in &self.files2 {
+            match actual.remove(path) {
+                Some((value, rule)) => {
+                    let modified = sealed.cdhash != value.cdhash
+                        || sealed.hash2 != value.hash2
+                        || sealed.symlink != value.symlink
+                        || (sealed.hash.is_some() && sealed.hash != value.hash);
+
+                    if modified {
+                        differences.push(SealDifference {
+                            path: path.clone(),
+                            kind: SealDifferenceKind::Modified,
+                            rule: Some(rule),
+                            sealed: Some(sealed.describe()),
+                            actual: Some(value.describe()),
+                        });
+                    }
+                }
+                None => {
+                    if sealed.optional == Some(true) {
+                        continue;
+                    }
+
+                    differences.push(SealDifference {
+                        path: path.clone(),
+                        kind: SealDifferenceKind::Removed,
+                        rule: find_rule(&rules, path).map(|rule| rule.pattern),
+                        sealed: Some(sealed.describe()),
+                        actual: None,
+                    });
+                }
+            }
+        }
+
+        for (path, (value, rule)) in actual {
+            differences.push(SealDifference {
+                path,
+                kind: SealDifferenceKind::Added,
+                rule: Some(rule),
+                sealed: None,
+                actual: Some(value.describe()),
+            });
+        }
+
+        differences.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(differences)
+    }
+}
+
+/// Compute the `<files2>` seal of signed Mach-O data.
+fn macho_seal(data: &[u8]) -> Result<Files2Value, AppleCodesignError> {
+    let info = SignedMachOInfo::parse_data(data)?;
+
+    Ok(Files2Value {
+        cdhash: Some(DigestType::Sha256Truncated.digest_data(&info.code_directory_blob)?),
+        hash: None,
+        hash2: None,
+        optional: None,
+        requirement: info.designated_code_requirement,
+        symlink: None,
+    })
+}
+
+/// How a sealed resource differs from the content on disk.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SealDifferenceKind {
+    /// The path exists but isn't sealed.
+    Added,
+    /// The path is sealed but doesn't exist.
+    Removed,
+    /// The path's content doesn't match its seal.
+    Modified,
+}
+
+impl std::fmt::Display for SealDifferenceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Modified => "modified",
+        })
+    }
+}
+
+/// A difference between a `CodeResources` seal and a bundle's content.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct SealDifference {
+    /// Normalized path of the resource.
+    pub path: String,
+
+    /// How the resource differs.
+    pub kind: SealDifferenceKind,
+
+    /// Pattern of the `<rules2>` rule matching the path.
+    pub rule: Option<String>,
+
+    /// Description of the sealed content.
+    pub sealed: Option<String>,
+
+    /// Description of the content on disk.
+    pub actual: Option<String>,
+}
+
+impl std::fmt::Display for SealDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.path)?;
+
+        if let Some(rule) = &self.rule {
+            write!(f, " (rule {rule})")?;
+        }
+        if let Some(sealed) = &self.sealed {
+            write!(f, "; sealed {sealed}")?;
+        }
+        if let Some(actual) = &self.actual {
+            write!(f, "; actual {actual}")?;
+        }
+
+        Ok(())
+    }
 }
 
 impl From<&CodeResources> for Value {
@@ -878,15 +1188,17 @@ impl From<&CodeResources> for Value {
             ),
         );
 
-        dict.insert(
-            "files2".to_string(),
-            Value::Dictionary(
-                cr.files2
-                    .iter()
-                    .map(|(key, value)| (key.to_string(), Value::from(value)))
-                    .collect::<Dictionary>(),
-            ),
-        );
+        if !cr.files2.is_empty() || !cr.rules2.is_empty() {
+            dict.insert(
+                "files2".to_string(),
+                Value::Dictionary(
+                    cr.files2
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), Value::from(value)))
+                        .collect::<Dictionary>(),
+                ),
+            );
+        }
 
         if !cr.rules.is_empty() {
             dict.insert(
@@ -941,6 +1253,265 @@ fn find_rule(rules: &[CodeResourcesRule], path: impl AsRef<Path>) -> Option<Code
     rules.iter().find(|rule| rule.re.is_match(&path)).cloned()
 }
 
+/// Name of the file within a bundle declaring paths to omit from sealing.
+pub const IGNORE_FILE_NAME: &str = ".rcodesignignore";
+
+/// Weight of rules derived from an ignore file.
+///
+/// This is higher than the weight of any default rule so ignore file entries
+/// take priority.
+const IGNORE_RULE_WEIGHT: u32 = 5000;
+
+/// Convert a gitignore style pattern to a regular expression.
+///
+/// Patterns are matched against normalized resources paths. Returns `None` if
+/// the line doesn't contain a pattern.
+fn ignore_pattern_regex(line: &str) -> Option<String> {
+    let pattern = line.trim_end();
+
+    if pattern.is_empty() || pattern.starts_with('#') {
+        return None;
+    }
+
+    let pattern = pattern.strip_prefix('\\').unwrap_or(pattern);
+
+    // A trailing slash only matches directories. Since only files are sealed,
+    // this means matching everything under the directory.
+    let (pattern, directory) = match pattern.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    // Patterns containing a slash are relative to the bundle root. Others match
+    // at any depth.
+    let mut regex = if pattern.contains('/') {
+        "^".to_string()
+    } else {
+        "^(.*/)?".to_string()
+    };
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex.push_str(if directory { "/" } else { "($|/)" });
+
+    Some(regex)
+}
+
+/// Parse the content of an ignore file into resource rules.
+///
+/// The file uses gitignore syntax. Each pattern becomes a rule omitting the
+/// matched paths from sealing. The matched files are still retained in the
+/// bundle. Negated patterns (`!pattern`) can't be expressed as resource rules
+/// and are ignored with a warning.
+///
+/// A rule omitting the ignore file itself is always emitted.
+pub fn ignore_file_rules(data: &str) -> Result<Vec<CodeResourcesRule>, AppleCodesignError> {
+    let mut rules = vec![
+        CodeResourcesRule::new(format!("^{}$", regex::escape(IGNORE_FILE_NAME)))?
+            .omit()
+            .weight(IGNORE_RULE_WEIGHT),
+    ];
+
+    for line in data.lines() {
+        if line.starts_with('!') {
+            warn!(
+                "{} negation patterns are not supported; ignoring {}",
+                IGNORE_FILE_NAME, line
+            );
+            continue;
+        }
+
+        if let Some(regex) = ignore_pattern_regex(line) {
+            rules.push(
+                CodeResourcesRule::new(regex)?
+                    .omit()
+                    .weight(IGNORE_RULE_WEIGHT),
+            );
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Version of the serialized [ResourceDigestCache] format.
+const DIGEST_CACHE_VERSION: u32 = 1;
+
+/// Files modified more recently than this are never added to a [ResourceDigestCache].
+///
+/// Filesystem timestamps have limited granularity. A file written again within
+/// the same timestamp tick could otherwise have a stale digest served from the cache.
+const DIGEST_CACHE_MIN_AGE: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct DigestCacheEntry {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    sha1: String,
+    sha256: String,
+}
+
+/// A persistent cache of resource file digests.
+///
+/// Entries are keyed by the file's path, size, and modification time. If any of
+/// these change, the cached digest is ignored and the file is digested again.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ResourceDigestCache {
+    version: u32,
+    entries: BTreeMap<PathBuf, DigestCacheEntry>,
+}
+
+impl Default for ResourceDigestCache {
+    fn default() -> Self {
+        Self {
+            version: DIGEST_CACHE_VERSION,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl ResourceDigestCache {
+    /// Load a cache from a file.
+    ///
+    /// A missing, unreadable, or incompatible cache file results in an empty cache.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+
+        let cache = match std::fs::read(path) {
+            Ok(data) => match serde_json::from_slice::<Self>(&data) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    warn!("ignoring invalid digest cache {}: {}", path.display(), e);
+                    return Self::default();
+                }
+            },
+            Err(_) => return Self::default(),
+        };
+
+        if cache.version != DIGEST_CACHE_VERSION {
+            warn!(
+                "ignoring digest cache {} from another version",
+                path.display()
+            );
+            Self::default()
+        } else {
+            cache
+        }
+    }
+
+    /// Write the cache to a file.
+    pub fn write_path(&self, path: impl AsRef<Path>) -> Result<(), AppleCodesignError> {
+        let path = path.as_ref();
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    /// Look up the digests of a file given its current metadata.
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<MultiDigest> {
+        let (size, mtime_secs, mtime_nanos) = cache_stamp(metadata)?;
+
+        let entry = self.entries.get(path)?;
+
+        if (entry.size, entry.mtime_secs, entry.mtime_nanos) != (size, mtime_secs, mtime_nanos) {
+            return None;
+        }
+
+        Some(MultiDigest {
+            sha1: hex::decode(&entry.sha1).ok()?.into(),
+            sha256: hex::decode(&entry.sha256).ok()?.into(),
+        })
+    }
+
+    /// Record the digests of a file.
+    ///
+    /// Files modified too recently to be reliably detected as changed are not recorded.
+    pub fn insert(&mut self, path: &Path, metadata: &Metadata, digests: &MultiDigest) {
+        let Some((size, mtime_secs, mtime_nanos)) = cache_stamp(metadata) else {
+            return;
+        };
+
+        let recent = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .map(|age| age < DIGEST_CACHE_MIN_AGE)
+            .unwrap_or(true);
+
+        if recent {
+            self.entries.remove(path);
+            return;
+        }
+
+        self.entries.insert(
+            path.to_path_buf(),
+            DigestCacheEntry {
+                size,
+                mtime_secs,
+                mtime_nanos,
+                sha1: digests.sha1.as_hex(),
+                sha256: digests.sha256.as_hex(),
+            },
+        );
+    }
+}
+
+/// Obtain the (size, mtime seconds, mtime nanoseconds) a cache entry is keyed by.
+fn cache_stamp(metadata: &Metadata) -> Option<(u64, u64, u32)> {
+    let mtime = metadata
+        .modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+
+    Some((metadata.len(), mtime.as_secs(), mtime.subsec_nanos()))
+}
+
 /// Interface for constructing a `CodeResources` instance.
 ///
 /// This type is used during bundle signing to construct a `CodeResources` instance.
@@ -952,6 +1523,11 @@ pub struct CodeResourcesBuilder {
     rules2: Vec<CodeResourcesRule>,
     resources: CodeResources,
     digests: Vec<DigestType>,
+    /// Digests of source files computed ahead of sealing.
+    file_digests: BTreeMap<PathBuf, MultiDigest>,
+    digest_cache_path: Option<PathBuf>,
+    digest_backend: DigestBackendType,
+    version1_only: bool,
 }
 
 impl Default for CodeResourcesBuilder {
@@ -961,6 +1537,10 @@ impl Default for CodeResourcesBuilder {
             rules2: vec![],
             resources: CodeResources::default(),
             digests: vec![DigestType::Sha256],
+            file_digests: BTreeMap::new(),
+            digest_cache_path: None,
+            digest_backend: DigestBackendType::default(),
+            version1_only: false,
         }
     }
 }
@@ -1062,6 +1642,26 @@ impl CodeResourcesBuilder {
         self.digests = digests.collect::<Vec<_>>();
     }
 
+    /// Set the path of a [ResourceDigestCache] file to use when digesting files.
+    ///
+    /// The cache is read before and written after [Self::walk_and_seal_directory()].
+    pub fn set_digest_cache_path(&mut self, path: Option<PathBuf>) {
+        self.digest_cache_path = path;
+    }
+
+    /// Set the [DigestBackendType] used to digest files.
+    pub fn set_digest_backend(&mut self, backend: DigestBackendType) {
+        self.digest_backend = backend;
+    }
+
+    /// Set whether to only emit version 1 rules and files.
+    ///
+    /// Version 2 rules are still used to install and sign files. But only the
+    /// `<rules>` and `<files>` sections are written.
+    pub fn set_version1_only(&mut self, value: bool) {
+        self.version1_only = value;
+    }
+
     /// Add a rule to this instance in the `<rules>` section.
     pub fn add_rule(&mut self, rule: CodeResourcesRule) {
         self.rules.push(rule.clone());
@@ -1106,6 +1706,8 @@ impl CodeResourcesBuilder {
         bundle_root: &Path,
         context: &mut BundleSigningContext,
     ) -> Result<(), AppleCodesignError> {
+        self.digest_files(bundle_root)?;
+
         let mut skipping_rel_dirs = BTreeSet::new();
 
         for entry in walkdir::WalkDir::new(bundle_root).sort_by_file_name() {
@@ -1264,14 +1866,108 @@ impl CodeResourcesBuilder {
                         continue;
                     }
 
-                    self.seal_rules1_file(path, &rel_path_normalized, rule)?;
+                    self.seal_rules1_file(path, rel_path, &rel_path_normalized, rule, context)?;
+                }
+            }
+        }
+
+        self.file_digests.clear();
+
+        Ok(())
+    }
+
+    /// Digest regular files that will likely be sealed, in parallel.
+    ///
+    /// Digesting resource files is often the most expensive part of sealing a
+    /// bundle. So we do it up front using all available cores. Files that turn
+    /// out to need different handling (e.g. Mach-O binaries that get signed) are
+    /// skipped and digested during the directory walk instead.
+    fn digest_files(&mut self, bundle_root: &Path) -> Result<(), AppleCodesignError> {
+        let mut paths = vec![];
+
+        let mut walker = walkdir::WalkDir::new(bundle_root).into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path == bundle_root {
+                continue;
+            }
+
+            let rel_path = path
+                .strip_prefix(bundle_root)
+                .expect("stripping path prefix should always work");
+            let rule = find_rule(&self.rules2, rel_path);
+
+            if entry.file_type().is_dir() {
+                // Nested bundles are sealed via their signature, not their content.
+                let nested_bundle = rule.as_ref().map(|r| r.nested).unwrap_or_default()
+                    && entry.file_name().to_string_lossy().contains('.');
+
+                if nested_bundle || rule.as_ref().map(|r| r.exclude).unwrap_or_default() {
+                    walker.skip_current_dir();
+                }
+            } else if entry.file_type().is_file()
+                && !rule
+                    .as_ref()
+                    .map(|r| r.exclude || r.nested || r.omit)
+                    .unwrap_or_default()
+            {
+                paths.push(path.to_path_buf());
+            }
+        }
+
+        let mut cache = self
+            .digest_cache_path
+            .as_ref()
+            .map(ResourceDigestCache::from_path);
+
+        let results = paths
+            .into_par_iter()
+            .map(|path| -> Result<_, AppleCodesignError> {
+                if crate::reader::path_is_macho(&path)? {
+                    return Ok(None);
                 }
+
+                let metadata = std::fs::metadata(&path)?;
+
+                let digests = if let Some(digests) =
+                    cache.as_ref().and_then(|cache| cache.get(&path, &metadata))
+                {
+                    digests
+                } else {
+                    MultiDigest::from_path_with_backend(&path, self.digest_backend)?
+                };
+
+                Ok(Some((path, metadata, digests)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (path, metadata, digests) in results.into_iter().flatten() {
+            if let Some(cache) = &mut cache {
+                cache.insert(&path, &metadata, &digests);
             }
+
+            self.file_digests.insert(path, digests);
+        }
+
+        if let (Some(cache), Some(cache_path)) = (&cache, &self.digest_cache_path) {
+            info!("writing resource digest cache to {}", cache_path.display());
+            cache.write_path(cache_path)?;
         }
 
         Ok(())
     }
 
+    /// Obtain the digests of a file, using precomputed digests if available.
+    fn file_digest(&self, path: &Path) -> Result<MultiDigest, AppleCodesignError> {
+        if let Some(digests) = self.file_digests.get(path) {
+            Ok(digests.clone())
+        } else {
+            MultiDigest::from_path_with_backend(path, self.digest_backend)
+        }
+    }
+
     /// Seal a nested bundle for rules version 2.
     fn seal_rules2_nested_bundle(
         &mut self,
@@ -1399,7 +2095,7 @@ impl CodeResourcesBuilder {
                 }
             };
 
-            let digests = MultiDigest::from_path(read_path)?;
+            let digests = self.file_digest(&read_path)?;
 
             let flavor = if self.digests.contains(&DigestType::Sha1) {
                 FilesFlavor::Rules2WithSha1
@@ -1446,14 +2142,26 @@ impl CodeResourcesBuilder {
     fn seal_rules1_file(
         &mut self,
         full_path: &Path,
+        rel_path: &Path,
         rel_path_normalized: &str,
         rule: CodeResourcesRule,
+        context: &BundleSigningContext,
     ) -> Result<(), AppleCodesignError> {
         // Version 1 doesn't handle symlinks nor nested Mach-O binaries.
         // And version 2's handler installed files. So all we have to do here
         // is record SHA-1 digests in `<files>`.
+        //
+        // Mach-O binaries may have been signed when they were installed. Seal
+        // their new content.
+        let read_path = if context.installed_paths.contains(rel_path)
+            && crate::reader::path_is_macho(full_path)?
+        {
+            context.dest_dir.join(rel_path)
+        } else {
+            full_path.to_path_buf()
+        };
 
-        let digests = MultiDigest::from_path(full_path)?;
+        let digests = self.file_digest(&read_path)?;
 
         self.resources.seal_regular_file(
             FilesFlavor::Rules,
@@ -1467,7 +2175,14 @@ impl CodeResourcesBuilder {
 
     /// Write CodeResources XML content to a writer.
     pub fn write_code_resources(&self, writer: impl Write) -> Result<(), AppleCodesignError> {
-        self.resources.to_writer_xml(writer)
+        if self.version1_only {
+            let mut resources = self.resources.clone();
+            resources.remove_version2();
+
+            resources.to_writer_xml(writer)
+        } else {
+            self.resources.to_writer_xml(writer)
+        }
     }
 }
 
@@ -1574,4 +2289,184 @@ mod tests {
 
         assert_eq!(resources, resources2);
     }
+
+    #[test]
+    fn version1_only() -> Result<(), AppleCodesignError> {
+        let mut builder = CodeResourcesBuilder::default_resources_rules()?;
+        builder.resources.seal_regular_file(
+            FilesFlavor::Rules,
+            "Resources/file",
+            MultiDigest::from_reader(&b"content"[..])?,
+            false,
+        )?;
+        builder.resources.seal_symlink("Resources/link", "file");
+
+        let mut buffer = Vec::<u8>::new();
+        builder.write_code_resources(&mut buffer)?;
+        let xml = String::from_utf8(buffer).unwrap();
+        assert!(xml.contains("<key>files2</key>"));
+        assert!(xml.contains("<key>rules2</key>"));
+
+        builder.set_version1_only(true);
+        let mut buffer = Vec::<u8>::new();
+        builder.write_code_resources(&mut buffer)?;
+        let xml = String::from_utf8(buffer.clone()).unwrap();
+        assert!(!xml.contains("<key>files2</key>"));
+        assert!(!xml.contains("<key>rules2</key>"));
+
+        let resources = CodeResources::from_xml(&buffer)?;
+        assert_eq!(resources.rules, builder.resources.rules);
+        assert_eq!(resources.files, builder.resources.files);
+        assert!(resources.files2.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn digest_cache() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("file");
+        let cache_path = temp_dir.path().join("cache.json");
+
+        std::fs::write(&path, b"content")?;
+        let digests = MultiDigest::from_path(&path)?;
+
+        // Recently modified files aren't cached.
+        let mut cache = ResourceDigestCache::default();
+        cache.insert(&path, &std::fs::metadata(&path)?, &digests);
+        assert!(cache.get(&path, &std::fs::metadata(&path)?).is_none());
+
+        let old = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(&path, old)?;
+
+        cache.insert(&path, &std::fs::metadata(&path)?, &digests);
+        cache.write_path(&cache_path)?;
+
+        let cache = ResourceDigestCache::from_path(&cache_path);
+        let cached = cache.get(&path, &std::fs::metadata(&path)?).unwrap();
+        assert_eq!(cached.sha1.to_vec(), digests.sha1.to_vec());
+        assert_eq!(cached.sha256.to_vec(), digests.sha256.to_vec());
+
+        // Changing the file invalidates the entry.
+        std::fs::write(&path, b"different content")?;
+        filetime::set_file_mtime(&path, old)?;
+        assert!(cache.get(&path, &std::fs::metadata(&path)?).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff_bundle() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path().join("MyApp.app");
+        std::fs::create_dir(&root)?;
+
+        std::fs::write(
+            root.join("Info.plist"),
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>CFBundleIdentifier</key>
+    <string>com.example.myapp</string>
+</dict>
+</plist>"#,
+        )?;
+        std::fs::write(root.join("modified"), b"original")?;
+        std::fs::write(root.join("removed"), b"removed")?;
+        std::fs::write(root.join("unchanged"), b"unchanged")?;
+
+        let mut resources = CodeResources::default();
+        resources.add_rule2(CodeResourcesRule::new("^.*")?);
+
+        for name in ["Info.plist", "modified", "removed", "unchanged"] {
+            resources.seal_regular_file(
+                FilesFlavor::Rules2,
+                name,
+                MultiDigest::from_path(root.join(name))?,
+                false,
+            )?;
+        }
+
+        let bundle = DirectoryBundle::new_from_path(&root)?;
+        assert!(resources.diff_bundle(&bundle)?.is_empty());
+
+        std::fs::write(root.join("modified"), b"changed")?;
+        std::fs::remove_file(root.join("removed"))?;
+        std::fs::write(root.join("added"), b"added")?;
+
+        let differences = resources.diff_bundle(&bundle)?;
+        assert_eq!(
+            differences
+                .iter()
+                .map(|d| (d.path.as_str(), d.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                ("added", SealDifferenceKind::Added),
+                ("modified", SealDifferenceKind::Modified),
+                ("removed", SealDifferenceKind::Removed),
+            ]
+        );
+        assert!(differences.iter().all(|d| d.rule.as_deref() == Some("^.*")));
+        assert_eq!(
+            differences[1].actual,
+            Some(format!(
+                "sha256 {}",
+                hex::encode(
+                    MultiDigest::from_path(root.join("modified"))?
+                        .sha256
+                        .to_vec()
+                )
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_patterns() {
+        assert_eq!(ignore_pattern_regex(""), None);
+        assert_eq!(ignore_pattern_regex("# comment"), None);
+        assert_eq!(
+            ignore_pattern_regex("*.log"),
+            Some("^(.*/)?[^/]*\\.log($|/)".into())
+        );
+        assert_eq!(
+            ignore_pattern_regex("/Resources/cache/"),
+            Some("^Resources/cache/".into())
+        );
+        assert_eq!(
+            ignore_pattern_regex("**/tmp/*.db"),
+            Some("^(.*/)?tmp/[^/]*\\.db($|/)".into())
+        );
+        assert_eq!(
+            ignore_pattern_regex("data/[!a]?"),
+            Some("^data/[^a][^/]($|/)".into())
+        );
+        assert_eq!(
+            ignore_pattern_regex("\\#file"),
+            Some("^(.*/)?\\#file($|/)".into())
+        );
+    }
+
+    #[test]
+    fn ignore_file() -> Result<(), AppleCodesignError> {
+        let rules = ignore_file_rules("# comment\n*.log\n!keep.log\nResources/cache/\n")?;
+        assert_eq!(rules.len(), 3);
+        assert!(rules.iter().all(|rule| rule.omit));
+
+        let mut rules2 = CodeResourcesBuilder::default_resources_rules()?.rules2;
+        rules2.extend(rules);
+        rules2.sort();
+
+        for (path, omit) in [
+            (".rcodesignignore", true),
+            ("Resources/debug.log", true),
+            ("Resources/cache/entry", true),
+            ("Resources/data", false),
+        ] {
+            assert_eq!(find_rule(&rules2, path).unwrap().omit, omit, "{path}");
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,429 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Reporting of SDK and linkage metadata relevant to App Review.
+
+Before submitting software to Apple, it is often desirable to know which
+SDK each binary was built against, whether binaries still carry embedded
+bitcode (which Apple stopped accepting with Xcode 14), and whether any
+deprecated system frameworks are linked.
+
+This module walks a filesystem path, inspects every Mach-O binary found,
+and produces a report containing this information.
+*/
+
+use {
+    crate::{
+        macho::{MachFile, MachOBinary},
+        reader::path_is_macho,
+        AppleCodesignError,
+    },
+    serde::Serialize,
+    std::{
+        fmt::Write as _,
+        path::{Path, PathBuf},
+    },
+};
+
+/// System frameworks that Apple has deprecated, with a short explanation.
+const DEPRECATED_FRAMEWORKS: &[(&str, &str)] = &[
+    ("AGL", "deprecated in macOS 10.14; use Metal"),
+    ("AddressBook", "deprecated; use Contacts"),
+    ("AddressBookUI", "deprecated; use ContactsUI"),
+    ("AssetsLibrary", "deprecated; use Photos"),
+    ("GLKit", "deprecated; use MetalKit"),
+    ("OpenCL", "deprecated in macOS 10.14; use Metal"),
+    ("OpenGL", "deprecated in macOS 10.14; use Metal"),
+    ("OpenGLES", "deprecated in iOS 12; use Metal"),
+    ("QTKit", "removed in macOS 10.15; use AVFoundation"),
+    ("Twitter", "deprecated; use Social or a third party SDK"),
+    ("iAd", "removed; the iAd service no longer exists"),
+];
+
+/// Resolve the framework name from a dylib install name.
+///
+/// e.g. `/System/Library/Frameworks/OpenGL.framework/Versions/A/OpenGL`
+/// resolves to `OpenGL`.
+fn framework_name(install_name: &str) -> Option<&str> {
+    install_name
+        .split('/')
+        .find_map(|component| component.strip_suffix(".framework"))
+}
+
+/// A deprecated framework linked by a binary.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DeprecatedFrameworkLink {
+    /// Name of the framework.
+    pub framework: String,
+    /// Install name of the linked library.
+    pub install_name: String,
+    /// Explanation of the deprecation.
+    pub note: String,
+}
+
+/// Compliance metadata for a single Mach-O binary.
+#[derive(Clone, Debug, Serialize)]
+pub struct MachOComplianceInfo {
+    /// Path of the file, relative to the scanned root.
+    pub path: PathBuf,
+    /// Index within a universal binary, if the file is a universal binary.
+    pub universal_index: Option<usize>,
+    /// Architecture name of the binary.
+    pub architecture: Option<String>,
+    /// Platform being targeted.
+    pub platform: Option<String>,
+    /// Minimum OS version required.
+    pub minimum_os_version: Option<String>,
+    /// SDK version the binary was built against.
+    pub sdk_version: Option<String>,
+    /// Whether the binary contains embedded bitcode.
+    pub has_bitcode: bool,
+    /// Install names of linked dynamic libraries.
+    pub linked_libraries: Vec<String>,
+    /// Linked frameworks that are deprecated.
+    pub deprecated_frameworks: Vec<DeprecatedFrameworkLink>,
+    /// Error encountered parsing the binary.
+    ///
+    /// If set, the binary couldn't be inspected and other fields are unset.
+    pub error: Option<String>,
+}
+
+impl MachOComplianceInfo {
+    /// Resolve compliance metadata from a parsed Mach-O binary.
+    pub fn from_macho(
+        path: impl AsRef<Path>,
+        macho: &MachOBinary,
+    ) -> Result<Self, AppleCodesignError> {
+        let target = macho.find_targeting()?;
+
+        // The first entry is always `self`.
+        let linked_libraries = macho
+            .macho
+            .libs
+            .iter()
+            .skip(1)
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+
+        let deprecated_frameworks = linked_libraries
+            .iter()
+            .filter_map(|install_name| {
+                let framework = framework_name(install_name)?;
+
+                DEPRECATED_FRAMEWORKS
+                    .iter()
+                    .find(|(name, _)| *name == framework)
+                    .map(|(name, note)| DeprecatedFrameworkLink {
+                        framework: name.to_string(),
+                        install_name: install_name.clone(),
+                        note: note.to_string(),
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            universal_index: macho.index,
+            architecture: goblin::mach::cputype::get_arch_name_from_types(
+                macho.macho.header.cputype(),
+                macho.macho.header.cpusubtype(),
+            )
+            .map(|x| x.to_string()),
+            platform: target.as_ref().map(|t| t.platform.to_string()),
+            minimum_os_version: target.as_ref().map(|t| t.minimum_os_version.to_string()),
+            sdk_version: target
+                .as_ref()
+                .filter(|t| t.sdk_version != semver::Version::new(0, 0, 0))
+                .map(|t| t.sdk_version.to_string()),
+            has_bitcode: macho_has_bitcode(macho)?,
+            linked_libraries,
+            deprecated_frameworks,
+            error: None,
+        })
+    }
+
+    /// Construct an instance for a binary that couldn't be parsed.
+    pub fn from_error(
+        path: impl AsRef<Path>,
+        universal_index: Option<usize>,
+        error: &AppleCodesignError,
+    ) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            universal_index,
+            architecture: None,
+            platform: None,
+            minimum_os_version: None,
+            sdk_version: None,
+            has_bitcode: false,
+            linked_libraries: vec![],
+            deprecated_frameworks: vec![],
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// Whether this binary has properties App Review is likely to object to.
+    ///
+    /// Binaries that couldn't be parsed are considered to have issues.
+    pub fn has_issues(&self) -> bool {
+        self.error.is_some()
+            || self.has_bitcode
+            || !self.deprecated_frameworks.is_empty()
+            || self.sdk_version.is_none()
+    }
+}
+
+/// Whether a Mach-O binary contains embedded bitcode.
+///
+/// Bitcode is stored in the `__bundle` section of the `__LLVM` segment.
+/// Some toolchains emit a placeholder `__LLVM,__bundle` section containing
+/// a marker without any actual bitcode. We treat any such section as a
+/// bitcode remnant since App Store processing rejects both.
+fn macho_has_bitcode(macho: &MachOBinary) -> Result<bool, AppleCodesignError> {
+    for segment in macho.macho.segments.iter() {
+        for (section, _) in segment.sections()? {
+            if section.segname()? == "__LLVM" && section.name()? == "__bundle" {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// A compliance report covering all Mach-O binaries within a path.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ComplianceReport {
+    /// Per-binary metadata.
+    pub binaries: Vec<MachOComplianceInfo>,
+}
+
+impl ComplianceReport {
+    /// Construct a report by scanning a filesystem path.
+    ///
+    /// The path can be a single Mach-O file or a directory, which will be
+    /// walked recursively. Non Mach-O files are ignored. Binaries that can't be
+    /// parsed are recorded with their error and don't abort the scan.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        let root = path.as_ref();
+        let mut res = Self::default();
+
+        for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() || !path_is_macho(entry.path())? {
+                continue;
+            }
+
+            let rel_path = entry
+                .path()
+                .strip_prefix(root)
+                .ok()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| entry.path());
+
+            let data = std::fs::read(entry.path())?;
+            let mach = match MachFile::parse(&data) {
+                Ok(mach) => mach,
+                Err(e) => {
+                    res.binaries
+                        .push(MachOComplianceInfo::from_error(rel_path, None, &e));
+                    continue;
+                }
+            };
+
+            for macho in mach.iter_macho() {
+                res.binaries.push(
+                    MachOComplianceInfo::from_macho(rel_path, macho).unwrap_or_else(|e| {
+                        MachOComplianceInfo::from_error(rel_path, macho.index, &e)
+                    }),
+                );
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Distinct SDK versions binaries were built against.
+    pub fn sdk_versions(&self) -> Vec<String> {
+        let mut res = self
+            .binaries
+            .iter()
+            .filter(|b| b.error.is_none())
+            .map(|b| {
+                format!(
+                    "{} {}",
+                    b.platform.as_deref().unwrap_or("unknown"),
+                    b.sdk_version.as_deref().unwrap_or("unknown")
+                )
+            })
+            .collect::<Vec<_>>();
+        res.sort();
+        res.dedup();
+
+        res
+    }
+
+    /// Whether any binary has properties App Review is likely to object to.
+    pub fn has_issues(&self) -> bool {
+        self.binaries.iter().any(|b| b.has_issues())
+    }
+
+    /// Render the report as human readable text.
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+
+        // Writing to a String cannot fail.
+        for binary in &self.binaries {
+            let _ = write!(s, "{}", binary.path.display());
+            if let Some(index) = binary.universal_index {
+                let _ = write!(s, " [{}]", index);
+            }
+            if let Some(error) = &binary.error {
+                let _ = writeln!(s, " (unable to parse)");
+                let _ = writeln!(s, "  error: {}", error);
+                continue;
+            }
+            let _ = writeln!(
+                s,
+                " ({})",
+                binary
+                    .architecture
+                    .as_deref()
+                    .unwrap_or("unknown architecture")
+            );
+            let _ = writeln!(
+                s,
+                "  platform: {}",
+                binary.platform.as_deref().unwrap_or("unknown")
+            );
+            let _ = writeln!(
+                s,
+                "  minimum OS version: {}",
+                binary.minimum_os_version.as_deref().unwrap_or("unknown")
+            );
+            let _ = writeln!(
+                s,
+                "  SDK version: {}",
+                binary.sdk_version.as_deref().unwrap_or("unknown")
+            );
+            let _ = writeln!(
+                s,
+                "  bitcode: {}",
+                if binary.has_bitcode {
+                    "present"
+                } else {
+                    "absent"
+                }
+            );
+            for link in &binary.deprecated_frameworks {
+                let _ = writeln!(
+                    s,
+                    "  deprecated framework: {} ({})",
+                    link.framework, link.note
+                );
+            }
+        }
+
+        let _ = writeln!(s, "SDK versions: {}", self.sdk_versions().join(", "));
+        let _ = writeln!(
+            s,
+            "binaries with bitcode: {}",
+            self.binaries.iter().filter(|b| b.has_bitcode).count()
+        );
+        let _ = writeln!(
+            s,
+            "binaries that couldn't be parsed: {}",
+            self.binaries.iter().filter(|b| b.error.is_some()).count()
+        );
+        let _ = writeln!(
+            s,
+            "binaries linking deprecated frameworks: {}",
+            self.binaries
+                .iter()
+                .filter(|b| !b.deprecated_frameworks.is_empty())
+                .count()
+        );
+
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        object::{write::Object, Architecture, BinaryFormat, Endianness, SectionKind},
+    };
+
+    fn object_with_section(segment: &str, section: &str) -> Vec<u8> {
+        let mut obj = Object::new(
+            BinaryFormat::MachO,
+            Architecture::X86_64,
+            Endianness::Little,
+        );
+        let id = obj.add_section(
+            segment.as_bytes().to_vec(),
+            section.as_bytes().to_vec(),
+            SectionKind::Other,
+        );
+        obj.append_section_data(id, b"\0", 1);
+
+        obj.write().unwrap()
+    }
+
+    #[test]
+    fn framework_names() {
+        assert_eq!(
+            framework_name("/System/Library/Frameworks/OpenGL.framework/Versions/A/OpenGL"),
+            Some("OpenGL")
+        );
+        assert_eq!(framework_name("@rpath/Foo.framework/Foo"), Some("Foo"));
+        assert_eq!(framework_name("/usr/lib/libSystem.B.dylib"), None);
+    }
+
+    #[test]
+    fn bitcode_section() -> Result<(), AppleCodesignError> {
+        let data = object_with_section("__LLVM", "__bundle");
+        assert!(macho_has_bitcode(&MachOBinary::parse(&data)?)?);
+
+        // Only the `__LLVM,__bundle` section holds bitcode.
+        let data = object_with_section("__LLVM", "__cmdline");
+        assert!(!macho_has_bitcode(&MachOBinary::parse(&data)?)?);
+        let data = object_with_section("__DATA", "__bundle");
+        assert!(!macho_has_bitcode(&MachOBinary::parse(&data)?)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors_reported_per_binary() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+
+        // Mach-O magic followed by a truncated header.
+        std::fs::write(td.path().join("broken"), b"\xcf\xfa\xed\xfe\x07\x00")?;
+        std::fs::write(
+            td.path().join("valid"),
+            object_with_section("__LLVM", "__bundle"),
+        )?;
+
+        let report = ComplianceReport::from_path(td.path())?;
+        assert_eq!(report.binaries.len(), 2);
+
+        let broken = &report.binaries[0];
+        assert_eq!(broken.path, PathBuf::from("broken"));
+        assert!(broken.error.is_some());
+        assert!(broken.has_issues());
+
+        let valid = &report.binaries[1];
+        assert!(valid.error.is_none());
+        assert!(valid.has_bitcode);
+
+        let text = report.to_text();
+        assert!(text.contains("broken (unable to parse)"));
+        assert!(text.contains("binaries that couldn't be parsed: 1"));
+
+        Ok(())
+    }
+}
@@ -577,28 +577,34 @@ impl DigestType {
         Ok(self.digest_data(&[])?.len())
     }
 
-    /// Obtain a hasher for this digest type.
-    pub fn as_hasher(&self) -> Result<ring::digest::Context, AppleCodesignError> {
-        match self {
-            Self::None => Err(AppleCodesignError::DigestUnknownAlgorithm),
-            Self::Sha1 => Ok(ring::digest::Context::new(
-                &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
-            )),
-            Self::Sha256 | Self::Sha256Truncated => {
-                Ok(ring::digest::Context::new(&ring::digest::SHA256))
-            }
-            Self::Sha384 => Ok(ring::digest::Context::new(&ring::digest::SHA384)),
-            Self::Sha512 => Ok(ring::digest::Context::new(&ring::digest::SHA512)),
-            Self::Unknown(_) => Err(AppleCodesignError::DigestUnknownAlgorithm),
-        }
+    /// Obtain a hasher for this digest type from the default [DigestBackend].
+    pub fn as_hasher(&self) -> Result<Box<dyn DigestHasher>, AppleCodesignError> {
+        self.as_hasher_with_backend(DigestBackendType::default())
+    }
+
+    /// Obtain a hasher for this digest type from a specific [DigestBackend].
+    pub fn as_hasher_with_backend(
+        &self,
+        backend: DigestBackendType,
+    ) -> Result<Box<dyn DigestHasher>, AppleCodesignError> {
+        backend.backend().hasher(*self)
     }
 
-    /// Digest data given the configured hasher.
+    /// Digest data using the default [DigestBackend].
     pub fn digest_data(&self, data: &[u8]) -> Result<Vec<u8>, AppleCodesignError> {
-        let mut hasher = self.as_hasher()?;
+        self.digest_data_with_backend(DigestBackendType::default(), data)
+    }
+
+    /// Digest data using a specific [DigestBackend].
+    pub fn digest_data_with_backend(
+        &self,
+        backend: DigestBackendType,
+        data: &[u8],
+    ) -> Result<Vec<u8>, AppleCodesignError> {
+        let mut hasher = self.as_hasher_with_backend(backend)?;
 
         hasher.update(data);
-        let mut hash = hasher.finish().as_ref().to_vec();
+        let mut hash = hasher.finish();
 
         if matches!(self, Self::Sha256Truncated) {
             hash.truncate(20);
@@ -608,6 +614,184 @@ impl DigestType {
     }
 }
 
+/// An incremental digest computation.
+pub trait DigestHasher: Send {
+    /// Feed data into the digest.
+    fn update(&mut self, data: &[u8]);
+
+    /// Finish the computation and obtain the raw digest.
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+impl DigestHasher for ring::digest::Context {
+    fn update(&mut self, data: &[u8]) {
+        ring::digest::Context::update(self, data)
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        ring::digest::Context::finish(*self).as_ref().to_vec()
+    }
+}
+
+/// Adapts a RustCrypto hasher to [DigestHasher].
+struct RustCryptoHasher<D>(D);
+
+impl<D: digest::Digest + Send> DigestHasher for RustCryptoHasher<D> {
+    fn update(&mut self, data: &[u8]) {
+        digest::Digest::update(&mut self.0, data)
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+/// Provides implementations of digest algorithms.
+///
+/// Hashing often dominates the time it takes to sign large entities. Different
+/// implementations have different performance characteristics depending on the
+/// machine, so the implementation is pluggable.
+pub trait DigestBackend: Send + Sync {
+    /// Obtain the name of this backend.
+    fn name(&self) -> &'static str;
+
+    /// Obtain a hasher for a digest type.
+    ///
+    /// [DigestType::Sha256Truncated] yields a full SHA-256 hasher: truncation
+    /// is the responsibility of the caller.
+    fn hasher(&self, digest_type: DigestType) -> Result<Box<dyn DigestHasher>, AppleCodesignError>;
+}
+
+/// A [DigestBackend] using the `ring` crate.
+pub struct RingDigestBackend;
+
+impl DigestBackend for RingDigestBackend {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn hasher(&self, digest_type: DigestType) -> Result<Box<dyn DigestHasher>, AppleCodesignError> {
+        let algorithm = match digest_type {
+            DigestType::Sha1 => &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+            DigestType::Sha256 | DigestType::Sha256Truncated => &ring::digest::SHA256,
+            DigestType::Sha384 => &ring::digest::SHA384,
+            DigestType::Sha512 => &ring::digest::SHA512,
+            DigestType::None | DigestType::Unknown(_) => {
+                return Err(AppleCodesignError::DigestUnknownAlgorithm)
+            }
+        };
+
+        Ok(Box::new(ring::digest::Context::new(algorithm)))
+    }
+}
+
+/// A [DigestBackend] using the pure Rust RustCrypto crates (`sha1` and `sha2`).
+pub struct RustCryptoDigestBackend;
+
+impl DigestBackend for RustCryptoDigestBackend {
+    fn name(&self) -> &'static str {
+        "rust-crypto"
+    }
+
+    fn hasher(&self, digest_type: DigestType) -> Result<Box<dyn DigestHasher>, AppleCodesignError> {
+        match digest_type {
+            DigestType::Sha1 => Ok(Box::new(RustCryptoHasher(sha1::Sha1::default()))),
+            DigestType::Sha256 | DigestType::Sha256Truncated => {
+                Ok(Box::new(RustCryptoHasher(sha2::Sha256::default())))
+            }
+            DigestType::Sha384 => Ok(Box::new(RustCryptoHasher(sha2::Sha384::default()))),
+            DigestType::Sha512 => Ok(Box::new(RustCryptoHasher(sha2::Sha512::default()))),
+            DigestType::None | DigestType::Unknown(_) => {
+                Err(AppleCodesignError::DigestUnknownAlgorithm)
+            }
+        }
+    }
+}
+
+/// Adapts an OpenSSL hasher to [DigestHasher].
+#[cfg(feature = "openssl")]
+struct OpensslHasher(openssl::hash::Hasher);
+
+#[cfg(feature = "openssl")]
+impl DigestHasher for OpensslHasher {
+    fn update(&mut self, data: &[u8]) {
+        // Only fails on allocation failure or misuse of the API.
+        self.0
+            .update(data)
+            .expect("updating OpenSSL digest should not fail")
+    }
+
+    fn finish(mut self: Box<Self>) -> Vec<u8> {
+        self.0
+            .finish()
+            .expect("finishing OpenSSL digest should not fail")
+            .to_vec()
+    }
+}
+
+/// A [DigestBackend] using OpenSSL.
+///
+/// Requires the `openssl` crate feature. Without it, obtaining a hasher fails.
+pub struct OpensslDigestBackend;
+
+impl DigestBackend for OpensslDigestBackend {
+    fn name(&self) -> &'static str {
+        "openssl"
+    }
+
+    #[cfg(feature = "openssl")]
+    fn hasher(&self, digest_type: DigestType) -> Result<Box<dyn DigestHasher>, AppleCodesignError> {
+        use openssl::hash::{Hasher, MessageDigest};
+
+        let digest = match digest_type {
+            DigestType::Sha1 => MessageDigest::sha1(),
+            DigestType::Sha256 | DigestType::Sha256Truncated => MessageDigest::sha256(),
+            DigestType::Sha384 => MessageDigest::sha384(),
+            DigestType::Sha512 => MessageDigest::sha512(),
+            DigestType::None | DigestType::Unknown(_) => {
+                return Err(AppleCodesignError::DigestUnknownAlgorithm)
+            }
+        };
+
+        Ok(Box::new(OpensslHasher(Hasher::new(digest)?)))
+    }
+
+    #[cfg(not(feature = "openssl"))]
+    fn hasher(&self, _: DigestType) -> Result<Box<dyn DigestHasher>, AppleCodesignError> {
+        Err(AppleCodesignError::FeatureDisabled(
+            "the OpenSSL digest backend",
+            "openssl",
+        ))
+    }
+}
+
+/// Selects a [DigestBackend] implementation.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum DigestBackendType {
+    #[default]
+    Ring,
+    RustCrypto,
+    Openssl,
+}
+
+impl Display for DigestBackendType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.backend().name())
+    }
+}
+
+impl DigestBackendType {
+    /// Obtain the [DigestBackend] implementation for this type.
+    pub fn backend(&self) -> &'static dyn DigestBackend {
+        match self {
+            Self::Ring => &RingDigestBackend,
+            Self::RustCrypto => &RustCryptoDigestBackend,
+            Self::Openssl => &OpensslDigestBackend,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Digest<'a> {
     pub data: Cow<'a, [u8]>,
 }
@@ -646,6 +830,7 @@ impl<'a> From<Vec<u8>> for Digest<'a> {
 }
 
 /// Holds multiple computed digests for content.
+#[derive(Clone, Debug)]
 pub struct MultiDigest {
     pub sha1: Digest<'static>,
     pub sha256: Digest<'static>,
@@ -655,9 +840,17 @@ impl MultiDigest {
     /// Compute the multi digests for any stream reader.
     ///
     /// This will read the stream until EOF.
-    pub fn from_reader(mut reader: impl std::io::Read) -> Result<Self, AppleCodesignError> {
-        let mut sha1 = DigestType::Sha1.as_hasher()?;
-        let mut sha256 = DigestType::Sha256.as_hasher()?;
+    pub fn from_reader(reader: impl std::io::Read) -> Result<Self, AppleCodesignError> {
+        Self::from_reader_with_backend(reader, DigestBackendType::default())
+    }
+
+    /// Compute the multi digests for any stream reader using a specific [DigestBackend].
+    pub fn from_reader_with_backend(
+        mut reader: impl std::io::Read,
+        backend: DigestBackendType,
+    ) -> Result<Self, AppleCodesignError> {
+        let mut sha1 = DigestType::Sha1.as_hasher_with_backend(backend)?;
+        let mut sha256 = DigestType::Sha256.as_hasher_with_backend(backend)?;
 
         let mut buffer = [0u8; 16384];
 
@@ -671,8 +864,8 @@ impl MultiDigest {
             sha256.update(&buffer[0..read]);
         }
 
-        let sha1 = sha1.finish().as_ref().to_vec();
-        let sha256 = sha256.finish().as_ref().to_vec();
+        let sha1 = sha1.finish();
+        let sha256 = sha256.finish();
 
         Ok(Self {
             sha1: sha1.into(),
@@ -682,8 +875,16 @@ impl MultiDigest {
 
     /// Compute the multi digest of a filesystem path.
     pub fn from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        Self::from_path_with_backend(path, DigestBackendType::default())
+    }
+
+    /// Compute the multi digest of a filesystem path using a specific [DigestBackend].
+    pub fn from_path_with_backend(
+        path: impl AsRef<Path>,
+        backend: DigestBackendType,
+    ) -> Result<Self, AppleCodesignError> {
         let fh = std::fs::File::open(path.as_ref())?;
-        Self::from_reader(fh)
+        Self::from_reader_with_backend(fh, backend)
     }
 }
 
@@ -1024,4 +1225,63 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn digest_backends_agree() -> Result<(), AppleCodesignError> {
+        let data = b"hello, world".repeat(1000);
+
+        for digest_type in [
+            DigestType::Sha1,
+            DigestType::Sha256,
+            DigestType::Sha384,
+            DigestType::Sha512,
+        ] {
+            let mut ring = RingDigestBackend.hasher(digest_type)?;
+            let mut rust_crypto = RustCryptoDigestBackend.hasher(digest_type)?;
+
+            ring.update(&data);
+            rust_crypto.update(&data);
+
+            let ring = ring.finish();
+            assert_eq!(ring.len(), digest_type.hash_len()?);
+            assert_eq!(ring, rust_crypto.finish());
+        }
+
+        assert!(RingDigestBackend.hasher(DigestType::None).is_err());
+        assert!(RustCryptoDigestBackend.hasher(DigestType::None).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn openssl_digest_backend() -> Result<(), AppleCodesignError> {
+        let data = b"hello, world".repeat(1000);
+
+        for digest_type in [
+            DigestType::Sha1,
+            DigestType::Sha256,
+            DigestType::Sha256Truncated,
+            DigestType::Sha384,
+            DigestType::Sha512,
+        ] {
+            assert_eq!(
+                digest_type.digest_data_with_backend(DigestBackendType::Openssl, &data)?,
+                digest_type.digest_data_with_backend(DigestBackendType::Ring, &data)?
+            );
+        }
+
+        assert!(OpensslDigestBackend.hasher(DigestType::None).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "openssl"))]
+    #[test]
+    fn openssl_digest_backend_disabled() {
+        assert!(matches!(
+            DigestType::Sha256.as_hasher_with_backend(DigestBackendType::Openssl),
+            Err(AppleCodesignError::FeatureDisabled(_, "openssl"))
+        ));
+    }
 }
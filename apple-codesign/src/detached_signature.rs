@@ -0,0 +1,127 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Detached CMS signatures over arbitrary content.
+
+Code signatures are embedded in the entities they sign. Release pipelines
+often produce auxiliary artifacts - update manifests, configuration files,
+checksum lists - that have no place to hold a signature. This module
+produces and verifies detached CMS signatures (commonly stored in `.p7s`
+files) over such content using the same signing keys used for code signing.
+
+The CMS `SignedData` does not contain the signed content. Instead, its
+signed attributes hold a digest of the content, so the original content is
+needed to verify the signature.
+*/
+
+use {
+    crate::{
+        embedded_signature_builder::signer_with_time_stamp_url, error::AppleCodesignError,
+        reader::CmsSignature, signing_settings::SigningSettings,
+    },
+    cryptographic_message_syntax::{SignedData, SignedDataBuilder, SignerBuilder},
+    log::info,
+};
+
+/// Create a detached CMS signature over arbitrary data.
+///
+/// The signing key, certificate chain, time-stamp server, and signing time
+/// are taken from [SigningSettings]. Other settings are ignored.
+///
+/// Returns the DER encoded CMS `SignedData`.
+pub fn create_detached_signature(
+    settings: &SigningSettings,
+    data: &[u8],
+) -> Result<Vec<u8>, AppleCodesignError> {
+    let (signing_key, signing_cert) = settings
+        .signing_key()
+        .ok_or(AppleCodesignError::NoSigningCertificate)?;
+
+    settings.ensure_key_usage_allowed(None)?;
+
+    let signer = SignerBuilder::new(signing_key, signing_cert.clone());
+
+    let signer = if let Some(time_stamp_url) = settings.time_stamp_url() {
+        signer_with_time_stamp_url(signer, time_stamp_url)?
+    } else {
+        signer
+    };
+
+    let builder = SignedDataBuilder::default()
+        .content_external(data.to_vec())
+        .signer(signer)
+        .certificates(settings.certificate_chain().iter().cloned());
+
+    let builder = if let Some(time) = settings.signing_time() {
+        info!("Using signing time {}", time.to_rfc3339());
+        builder.signing_time(time.into())
+    } else {
+        builder
+    };
+
+    Ok(builder.build_der()?)
+}
+
+/// Verify a detached CMS signature over arbitrary data.
+///
+/// Every signer must have a valid cryptographic signature and a message
+/// digest matching `data`. Signing certificates must be present in the
+/// CMS data.
+///
+/// This does not establish trust in the signing certificates. Callers should
+/// inspect the returned certificates to decide whether the signer is trusted.
+pub fn verify_detached_signature(
+    signature: &[u8],
+    data: &[u8],
+) -> Result<CmsSignature, AppleCodesignError> {
+    let signed_data = SignedData::parse_ber(signature)?;
+
+    if signed_data.signers().next().is_none() {
+        return Err(AppleCodesignError::DetachedSignatureNoSigners);
+    }
+
+    for signer in signed_data.signers() {
+        signer.verify_signature_with_signed_data(&signed_data)?;
+        signer.verify_message_digest_with_content(data)?;
+    }
+
+    signed_data.try_into()
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::certificate::{create_self_signed_code_signing_certificate, CertificateProfile},
+        x509_certificate::KeyAlgorithm,
+    };
+
+    #[test]
+    fn round_trip() -> Result<(), AppleCodesignError> {
+        let (cert, key) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team",
+            "Joe Developer",
+            "US",
+            chrono::Duration::hours(1),
+        )?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&key, cert);
+
+        let signature = create_detached_signature(&settings, b"hello, world")?;
+
+        let signed_data = SignedData::parse_ber(&signature)?;
+        assert!(signed_data.signed_content().is_none());
+
+        let cms = verify_detached_signature(&signature, b"hello, world")?;
+        assert_eq!(cms.signers.len(), 1);
+        assert_eq!(cms.certificates.len(), 1);
+
+        assert!(verify_detached_signature(&signature, b"hello, world!").is_err());
+
+        Ok(())
+    }
+}
@@ -46,7 +46,7 @@ and invalidate prior signatures.
 use {
     crate::{
         code_directory::{CodeDirectoryBlob, CodeSignatureFlags},
-        cryptography::{Digest, DigestType},
+        cryptography::{Digest, DigestBackendType, DigestType},
         embedded_signature::{BlobData, CodeSigningSlot, EmbeddedSignature, RequirementSetBlob},
         embedded_signature_builder::EmbeddedSignatureBuilder,
         AppleCodesignError, SettingsScope, SigningSettings,
@@ -195,6 +195,7 @@ impl DmgReader {
     /// Digest an arbitrary slice of the file.
     fn digest_slice_with<R: Read + Seek>(
         &self,
+        backend: DigestBackendType,
         digest: DigestType,
         reader: &mut R,
         offset: u64,
@@ -204,7 +205,7 @@ impl DmgReader {
 
         let mut reader = reader.take(length);
 
-        let mut d = digest.as_hasher()?;
+        let mut d = digest.as_hasher_with_backend(backend)?;
 
         loop {
             let mut buffer = [0u8; 16384];
@@ -218,7 +219,7 @@ impl DmgReader {
         }
 
         Ok(Digest {
-            data: d.finish().as_ref().to_vec().into(),
+            data: d.finish().into(),
         })
     }
 
@@ -229,14 +230,24 @@ impl DmgReader {
         &self,
         digest: DigestType,
         reader: &mut R,
+    ) -> Result<Digest<'static>, AppleCodesignError> {
+        self.digest_content_with_backend(DigestBackendType::default(), digest, reader)
+    }
+
+    /// Digest the content of the DMG using a specific [DigestBackendType].
+    pub fn digest_content_with_backend<R: Read + Seek>(
+        &self,
+        backend: DigestBackendType,
+        digest: DigestType,
+        reader: &mut R,
     ) -> Result<Digest<'static>, AppleCodesignError> {
         if self.koly.code_signature_offset != 0 {
-            self.digest_slice_with(digest, reader, 0, self.koly.code_signature_offset)
+            self.digest_slice_with(backend, digest, reader, 0, self.koly.code_signature_offset)
         } else {
             reader.seek(SeekFrom::End(-KOLY_SIZE))?;
             let size = reader.stream_position()?;
 
-            self.digest_slice_with(digest, reader, 0, size)
+            self.digest_slice_with(backend, digest, reader, 0, size)
         }
     }
 }
@@ -339,6 +350,8 @@ impl DmgSigner {
         )?;
 
         if let Some((signing_key, signing_cert)) = settings.signing_key() {
+            settings.ensure_key_usage_allowed(settings.binary_identifier(SettingsScope::Main))?;
+
             builder.create_cms_signature(
                 signing_key,
                 signing_cert,
@@ -385,7 +398,8 @@ impl DmgSigner {
 
         let digest_type = settings.digest_type(SettingsScope::Main);
 
-        let code_hashes = vec![reader.digest_content_with(digest_type, fh)?];
+        let code_hashes =
+            vec![reader.digest_content_with_backend(settings.digest_backend(), digest_type, fh)?];
 
         let koly_digest = reader.koly().digest_for_code_directory(digest_type)?;
 
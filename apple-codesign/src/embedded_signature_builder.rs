@@ -17,13 +17,37 @@ use {
     bytes::Bytes,
     cryptographic_message_syntax::{asn1::rfc5652::OID_ID_DATA, SignedDataBuilder, SignerBuilder},
     log::{info, warn},
-    reqwest::Url,
     std::collections::BTreeMap,
+    url::Url,
     x509_certificate::{
         rfc5652::AttributeValue, CapturedX509Certificate, DigestAlgorithm, KeyInfoSigner,
     },
 };
 
+/// Configure a CMS signer to obtain a time-stamp token from a server.
+///
+/// Time-stamp servers are contacted over the network. So this fails without the
+/// `network` feature.
+#[cfg(feature = "network")]
+pub(crate) fn signer_with_time_stamp_url<'a>(
+    signer: SignerBuilder<'a>,
+    url: &Url,
+) -> Result<SignerBuilder<'a>, AppleCodesignError> {
+    info!("Using time-stamp server {}", url);
+
+    Ok(signer.time_stamp_url(url.clone())?)
+}
+
+#[cfg(not(feature = "network"))]
+pub(crate) fn signer_with_time_stamp_url<'a>(
+    _signer: SignerBuilder<'a>,
+    _url: &Url,
+) -> Result<SignerBuilder<'a>, AppleCodesignError> {
+    Err(AppleCodesignError::NetworkDisabled(
+        "obtaining time-stamp tokens",
+    ))
+}
+
 /// OID for signed attribute containing plist of code directory digests.
 ///
 /// 1.2.840.113635.100.9.1.
@@ -283,8 +307,7 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
         let signer = signer.signed_attribute(Oid(CD_DIGESTS_OID.as_ref().into()), attributes);
 
         let signer = if let Some(time_stamp_url) = time_stamp_url {
-            info!("Using time-stamp server {}", time_stamp_url);
-            signer.time_stamp_url(time_stamp_url.clone())?
+            signer_with_time_stamp_url(signer, time_stamp_url)?
         } else {
             signer
         };
@@ -3,7 +3,10 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
-    crate::{macho_universal::UniversalMachOError, remote_signing::RemoteSignError},
+    crate::{
+        macho_universal::UniversalMachOError, remote_signing::RemoteSignError,
+        signing_settings::SigningPreset,
+    },
     cryptographic_message_syntax::CmsError,
     std::path::PathBuf,
     thiserror::Error,
@@ -91,6 +94,7 @@ pub enum AppleCodesignError {
     #[error("XAR error: {0}")]
     Xar(#[from] apple_xar::Error),
 
+    #[cfg(feature = "pkg")]
     #[error("Apple flat package error: {0}")]
     FlatPackage(#[from] apple_flat_package::Error),
 
@@ -166,6 +170,9 @@ pub enum AppleCodesignError {
     #[error("__LINKEDIT isn't final Mach-O segment")]
     LinkeditNotLast,
 
+    #[error("chained fixups problem: {0}")]
+    ChainedFixups(String),
+
     #[error("__LINKEDIT segment contains data after signature")]
     DataAfterSignature,
 
@@ -181,12 +188,16 @@ pub enum AppleCodesignError {
     #[error("no signing certificate")]
     NoSigningCertificate,
 
+    #[error("detached signature has no signers")]
+    DetachedSignatureNoSigners,
+
     #[error("signature data too large (please report this issue)")]
     SignatureDataTooLarge,
 
     #[error("invalid builder operation: {0}")]
     SignatureBuilder(&'static str),
 
+    #[cfg(feature = "network")]
     #[error("HTTP error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
@@ -289,12 +300,30 @@ pub enum AppleCodesignError {
     #[error("signing settings are not compatible with notarization")]
     ForNotarizationInvalidSettings,
 
+    #[error("signature cannot be made compatible with compatibility target: {0}")]
+    CompatibilityTargetUnsupported(&'static str),
+
+    #[error("signing settings are not compatible with the {0} preset")]
+    PresetInvalidSettings(SigningPreset),
+
+    #[error("entitlements are not compatible with the {0} preset: {1}")]
+    PresetEntitlements(SigningPreset, &'static str),
+
+    #[error("signing key usage policy violation: {0}")]
+    KeyUsagePolicy(String),
+
     #[error("do not know how to notarize {0}")]
     NotarizeUnsupportedPath(PathBuf),
 
     #[error("no authentication credentials to perform notarization request")]
     NotarizeNoAuthCredentials,
 
+    #[error("API key belongs to team {1}, not the requested team {0}")]
+    NotarizeTeamMismatch(String, String),
+
+    #[error("API key does not record its team; unable to verify it belongs to team {0} (record it with `rcodesign encode-app-store-connect-api-key --team-id`)")]
+    NotarizeTeamUnknown(String),
+
     #[error("reached time limit waiting for notarization to complete")]
     NotarizeWaitLimitReached,
 
@@ -340,6 +369,15 @@ pub enum AppleCodesignError {
     #[error("do not know how to staple {0}")]
     StapleUnsupportedPath(PathBuf),
 
+    #[error("notarization ticket is malformed: {0}")]
+    NotarizationTicketMalformed(&'static str),
+
+    #[error("notarization ticket is not signed by Apple")]
+    NotarizationTicketUntrusted,
+
+    #[error("no notarization ticket is stapled to {0}")]
+    NotarizationTicketNotStapled(PathBuf),
+
     #[error("bad header magic in DMG; not a DMG file?")]
     DmgBadMagic,
 
@@ -371,6 +409,16 @@ pub enum AppleCodesignError {
     #[error("remote signing error: {0}")]
     RemoteSign(#[from] RemoteSignError),
 
+    #[error("{0} requires network support, which is not enabled in this build")]
+    NetworkDisabled(&'static str),
+
+    #[error("{0} requires the `{1}` crate feature, which is not enabled in this build")]
+    FeatureDisabled(&'static str, &'static str),
+
+    #[cfg(feature = "openssl")]
+    #[error("OpenSSL error: {0}")]
+    Openssl(#[from] openssl::error::ErrorStack),
+
     #[cfg(feature = "notarize")]
     #[error("bytestream creation error: {0}")]
     AwsByteStream(#[from] aws_smithy_types::byte_stream::error::Error),
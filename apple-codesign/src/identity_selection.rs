@@ -0,0 +1,229 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Heuristics for choosing a signing identity.
+
+Certificate stores frequently contain several code signing identities: an
+`Apple Development` certificate for local builds, an `Apple Distribution`
+certificate for App Store submissions, and `Developer ID` certificates for
+software distributed outside the App Store. Often there are expired or
+revoked leftovers as well.
+
+This module implements logic for choosing the most appropriate identity
+for a given signing operation and for explaining why it was chosen.
+*/
+
+use {
+    crate::{
+        certificate::{AppleCertificate, CertificateProfile},
+        reader::PathType,
+        AppleCodesignError,
+    },
+    std::path::Path,
+    x509_certificate::CapturedX509Certificate,
+};
+
+/// Resolve the certificate profiles appropriate for signing a path, in order of preference.
+///
+/// Flat packages (`.pkg` installers) require installer certificates. Everything
+/// else requires application signing certificates. When signing for notarization,
+/// only `Developer ID` certificates are acceptable.
+pub fn preferred_profiles_for_path(
+    path: impl AsRef<Path>,
+    for_notarization: bool,
+) -> Result<Vec<CertificateProfile>, AppleCodesignError> {
+    let installer = PathType::from_path(path)? == PathType::Xar;
+
+    Ok(match (installer, for_notarization) {
+        (true, true) => vec![CertificateProfile::DeveloperIdInstaller],
+        (true, false) => vec![
+            CertificateProfile::DeveloperIdInstaller,
+            CertificateProfile::MacInstallerDistribution,
+        ],
+        (false, true) => vec![CertificateProfile::DeveloperIdApplication],
+        (false, false) => vec![
+            CertificateProfile::DeveloperIdApplication,
+            CertificateProfile::AppleDistribution,
+            CertificateProfile::AppleDevelopment,
+        ],
+    })
+}
+
+/// The result of choosing a signing identity.
+#[derive(Clone, Debug)]
+pub struct IdentitySelection {
+    /// Index of the chosen certificate in the candidates list.
+    pub index: usize,
+
+    /// Profile of the chosen certificate.
+    pub profile: CertificateProfile,
+
+    /// Human readable lines explaining how each candidate was evaluated.
+    pub explanation: Vec<String>,
+}
+
+/// Describe a certificate for use in selection explanations.
+fn describe_certificate(cert: &CapturedX509Certificate) -> String {
+    format!(
+        "{} (SHA-256 {})",
+        cert.subject_common_name()
+            .unwrap_or_else(|| "<unknown>".to_string()),
+        cert.sha256_fingerprint()
+            .map(|x| hex::encode(x.as_ref()))
+            .unwrap_or_else(|_| "<unknown>".to_string())
+    )
+}
+
+/// Choose the most appropriate signing certificate from a set of candidates.
+///
+/// Candidates are disqualified if they are outside their validity window or
+/// if their certificate profile isn't in `preferred_profiles`. Of the remaining
+/// candidates, the one whose profile appears earliest in `preferred_profiles`
+/// wins. Ties are broken by choosing the certificate expiring last.
+///
+/// Errors if no candidate is acceptable.
+pub fn select_signing_identity(
+    candidates: &[CapturedX509Certificate],
+    preferred_profiles: &[CertificateProfile],
+) -> Result<IdentitySelection, AppleCodesignError> {
+    let mut explanation = vec![];
+    let mut best: Option<(usize, usize, CertificateProfile)> = None;
+
+    for (index, cert) in candidates.iter().enumerate() {
+        let description = describe_certificate(cert);
+
+        if !cert.time_constraints_valid(None) {
+            explanation.push(format!(
+                "rejected {}: not valid at current time (expires {})",
+                description,
+                cert.validity_not_after().to_rfc3339()
+            ));
+            continue;
+        }
+
+        let Some(profile) = cert.apple_guess_profile() else {
+            explanation.push(format!(
+                "rejected {}: not an Apple code signing certificate",
+                description
+            ));
+            continue;
+        };
+
+        let Some(rank) = preferred_profiles.iter().position(|p| *p == profile) else {
+            explanation.push(format!(
+                "rejected {}: {} certificates are not appropriate for this artifact",
+                description, profile
+            ));
+            continue;
+        };
+
+        explanation.push(format!(
+            "candidate {}: {} certificate expiring {}",
+            description,
+            profile,
+            cert.validity_not_after().to_rfc3339()
+        ));
+
+        let better = match best {
+            None => true,
+            Some((best_index, best_rank, _)) => {
+                rank < best_rank
+                    || (rank == best_rank
+                        && cert.validity_not_after() > candidates[best_index].validity_not_after())
+            }
+        };
+
+        if better {
+            best = Some((index, rank, profile));
+        }
+    }
+
+    if let Some((index, _, profile)) = best {
+        explanation.push(format!(
+            "chose {}: {} is the most preferred profile available",
+            describe_certificate(&candidates[index]),
+            profile
+        ));
+
+        Ok(IdentitySelection {
+            index,
+            profile,
+            explanation,
+        })
+    } else {
+        Err(AppleCodesignError::CliGeneralError(format!(
+            "no suitable signing identity found among {} candidates (wanted one of: {})",
+            candidates.len(),
+            preferred_profiles
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*, crate::certificate::create_self_signed_code_signing_certificate,
+        x509_certificate::KeyAlgorithm,
+    };
+
+    fn certificate(profile: CertificateProfile, days: i64) -> CapturedX509Certificate {
+        create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            profile,
+            "team",
+            "person",
+            "US",
+            chrono::Duration::days(days),
+        )
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn selection() -> Result<(), AppleCodesignError> {
+        let development = certificate(CertificateProfile::AppleDevelopment, 365);
+        let developer_id_short = certificate(CertificateProfile::DeveloperIdApplication, 30);
+        let developer_id_long = certificate(CertificateProfile::DeveloperIdApplication, 365);
+        let installer = certificate(CertificateProfile::DeveloperIdInstaller, 365);
+
+        assert_eq!(
+            development.apple_guess_profile(),
+            Some(CertificateProfile::AppleDevelopment)
+        );
+
+        let candidates = vec![
+            development,
+            developer_id_short,
+            developer_id_long,
+            installer,
+        ];
+
+        let selection = select_signing_identity(
+            &candidates,
+            &[
+                CertificateProfile::DeveloperIdApplication,
+                CertificateProfile::AppleDevelopment,
+            ],
+        )?;
+        assert_eq!(selection.index, 2);
+        assert_eq!(
+            selection.profile,
+            CertificateProfile::DeveloperIdApplication
+        );
+
+        let selection =
+            select_signing_identity(&candidates, &[CertificateProfile::AppleDevelopment])?;
+        assert_eq!(selection.index, 0);
+
+        assert!(
+            select_signing_identity(&candidates, &[CertificateProfile::AppleDistribution]).is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Constraints on the use of a signing key.
+//!
+//! A [KeyUsagePolicy] restricts what a signing key may be used to sign. When
+//! a key is shared (e.g. by a central signing service), the policy prevents
+//! the key from vouching for arbitrary code.
+//!
+//! The policy is only as trustworthy as the process enforcing it. It must be
+//! configured on the side holding the key: `rcodesign sign` and
+//! `rcodesign daemon` enforce the policy of their own configuration. The
+//! remote signer (`rcodesign remote-sign`) only sees the message to sign, so it
+//! can only enforce [KeyUsagePolicy::signing_hours]. A policy in the
+//! configuration of a remote signing initiator is advisory.
+
+use {
+    crate::error::AppleCodesignError,
+    chrono::{DateTime, NaiveTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeSet,
+};
+
+/// Constraints that must be met for a signing key to be used.
+///
+/// Constraints only apply when producing cryptographic signatures. Ad-hoc
+/// signing is never restricted.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyUsagePolicy {
+    /// Glob patterns that identifiers of signed code must match.
+    ///
+    /// If empty, all identifiers are allowed. Otherwise entities without an
+    /// identifier (such as installer packages) are refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_identifiers: Vec<String>,
+
+    /// Entitlement keys that signed code may request.
+    ///
+    /// If not set, all entitlements are allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_entitlements: Option<BTreeSet<String>>,
+
+    /// Time window in UTC during which signing is allowed, as `HH:MM-HH:MM`.
+    ///
+    /// Windows ending before they start span midnight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_hours: Option<String>,
+}
+
+impl KeyUsagePolicy {
+    /// Validate that the policy is well-formed.
+    pub fn validate(&self) -> Result<(), AppleCodesignError> {
+        for pattern in &self.allowed_identifiers {
+            glob::Pattern::new(pattern)?;
+        }
+
+        self.signing_hours()?;
+
+        Ok(())
+    }
+
+    /// Whether the policy constrains the signed entity itself.
+    ///
+    /// Such constraints can only be enforced with knowledge of the entity
+    /// being signed.
+    pub fn constrains_entity(&self) -> bool {
+        !self.allowed_identifiers.is_empty() || self.allowed_entitlements.is_some()
+    }
+
+    /// Resolve the start and end times of the signing window, if defined.
+    pub fn signing_hours(&self) -> Result<Option<(NaiveTime, NaiveTime)>, AppleCodesignError> {
+        let Some(value) = &self.signing_hours else {
+            return Ok(None);
+        };
+
+        let parse = |s: &str| {
+            NaiveTime::parse_from_str(s.trim(), "%H:%M").map_err(|_| {
+                AppleCodesignError::KeyUsagePolicy(format!(
+                    "invalid signing hours {value}; expected HH:MM-HH:MM"
+                ))
+            })
+        };
+
+        let (start, end) = value.split_once('-').ok_or_else(|| {
+            AppleCodesignError::KeyUsagePolicy(format!(
+                "invalid signing hours {value}; expected HH:MM-HH:MM"
+            ))
+        })?;
+
+        Ok(Some((parse(start)?, parse(end)?)))
+    }
+
+    /// Verify that signing an entity is allowed by this policy.
+    ///
+    /// `identifier` is the identifier of the entity being signed. `entitlements`
+    /// are the entitlements it requests. `now` is the time of the signing
+    /// operation.
+    pub fn check(
+        &self,
+        identifier: Option<&str>,
+        entitlements: Option<&plist::Value>,
+        now: DateTime<Utc>,
+    ) -> Result<(), AppleCodesignError> {
+        if !self.allowed_identifiers.is_empty() {
+            let identifier = identifier.ok_or_else(|| {
+                AppleCodesignError::KeyUsagePolicy(
+                    "entity has no identifier to match against allowed identifiers".into(),
+                )
+            })?;
+
+            let mut allowed = false;
+            for pattern in &self.allowed_identifiers {
+                if glob::Pattern::new(pattern)?.matches(identifier) {
+                    allowed = true;
+                    break;
+                }
+            }
+
+            if !allowed {
+                return Err(AppleCodesignError::KeyUsagePolicy(format!(
+                    "identifier {identifier} is not allowed"
+                )));
+            }
+        }
+
+        if let (Some(allowed), Some(entitlements)) = (
+            &self.allowed_entitlements,
+            entitlements.and_then(|x| x.as_dictionary()),
+        ) {
+            if let Some(key) = entitlements.keys().find(|k| !allowed.contains(*k)) {
+                return Err(AppleCodesignError::KeyUsagePolicy(format!(
+                    "entitlement {key} is not allowed"
+                )));
+            }
+        }
+
+        self.check_signing_time(now)
+    }
+
+    /// Verify that signing at `now` is within the signing window.
+    pub fn check_signing_time(&self, now: DateTime<Utc>) -> Result<(), AppleCodesignError> {
+        if let Some((start, end)) = self.signing_hours()? {
+            let time = now.time();
+
+            let allowed = if start <= end {
+                time >= start && time < end
+            } else {
+                time >= start || time < end
+            };
+
+            if !allowed {
+                return Err(AppleCodesignError::KeyUsagePolicy(format!(
+                    "signing is only allowed between {} and {} UTC",
+                    start.format("%H:%M"),
+                    end.format("%H:%M")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, chrono::TimeZone};
+
+    #[test]
+    fn identifiers() -> Result<(), AppleCodesignError> {
+        let policy = KeyUsagePolicy {
+            allowed_identifiers: vec!["com.example.*".into()],
+            ..Default::default()
+        };
+        let now = Utc::now();
+
+        policy.check(Some("com.example.app"), None, now)?;
+        assert!(policy.check(Some("com.other.app"), None, now).is_err());
+        assert!(policy.check(None, None, now).is_err());
+        KeyUsagePolicy::default().check(None, None, now)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn entitlements() -> Result<(), AppleCodesignError> {
+        let policy = KeyUsagePolicy {
+            allowed_entitlements: Some(["com.apple.security.app-sandbox".to_string()].into()),
+            ..Default::default()
+        };
+        let now = Utc::now();
+
+        let mut dict = plist::Dictionary::new();
+        dict.insert("com.apple.security.app-sandbox".into(), true.into());
+        policy.check(None, Some(&dict.clone().into()), now)?;
+
+        dict.insert("com.apple.security.get-task-allow".into(), true.into());
+        assert!(policy.check(None, Some(&dict.into()), now).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn signing_hours() -> Result<(), AppleCodesignError> {
+        let at = |h, m| Utc.with_ymd_and_hms(2024, 1, 1, h, m, 0).unwrap();
+
+        let policy = KeyUsagePolicy {
+            signing_hours: Some("09:00-17:00".into()),
+            ..Default::default()
+        };
+        policy.validate()?;
+        policy.check(None, None, at(9, 0))?;
+        policy.check_signing_time(at(16, 59))?;
+        assert!(!policy.constrains_entity());
+        assert!(policy.check(None, None, at(17, 0)).is_err());
+        assert!(policy.check_signing_time(at(17, 0)).is_err());
+        assert!(policy.check(None, None, at(3, 30)).is_err());
+
+        let policy = KeyUsagePolicy {
+            signing_hours: Some("22:00-06:00".into()),
+            ..Default::default()
+        };
+        policy.check(None, None, at(23, 15))?;
+        policy.check(None, None, at(5, 59))?;
+        assert!(policy.check(None, None, at(12, 0)).is_err());
+
+        let policy = KeyUsagePolicy {
+            signing_hours: Some("9am-5pm".into()),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+
+        Ok(())
+    }
+}
@@ -111,6 +111,8 @@
 
 mod apple_certificates;
 pub use apple_certificates::*;
+pub mod archive_signing;
+pub mod bundle_patch;
 mod bundle_signing;
 pub use bundle_signing::*;
 mod certificate;
@@ -122,7 +124,9 @@ pub mod code_requirement;
 pub use code_requirement::*;
 mod code_resources;
 pub use code_resources::*;
+pub mod compliance;
 pub mod cryptography;
+pub mod detached_signature;
 pub mod dmg;
 pub mod embedded_signature;
 pub use embedded_signature::*;
@@ -132,9 +136,13 @@ pub mod entitlements;
 pub mod environment_constraints;
 mod error;
 pub use error::*;
+pub mod identity_selection;
+mod key_usage_policy;
+pub use key_usage_policy::*;
 mod macho;
 pub use macho::*;
 pub mod macho_builder;
+pub mod macho_fixups;
 #[cfg(target_os = "macos")]
 #[allow(non_upper_case_globals)]
 mod macos;
@@ -148,11 +156,14 @@ pub use macho_universal::UniversalBinaryBuilder;
 pub mod notarization;
 #[cfg(feature = "notarize")]
 pub use notarization::*;
+pub mod notarization_history;
+pub mod notarization_ticket;
 pub mod plist_der;
 mod policy;
 pub use policy::*;
 mod reader;
 pub use reader::*;
+pub mod release_check;
 pub mod remote_signing;
 mod signing_settings;
 pub use signing_settings::*;
@@ -160,6 +171,7 @@ mod signing;
 pub use signing::*;
 pub mod specification;
 pub mod stapling;
+#[cfg(feature = "network")]
 pub mod ticket_lookup;
 mod verify;
 pub use verify::*;
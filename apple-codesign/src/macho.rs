@@ -14,10 +14,14 @@ data.
 
 use {
     crate::{
-        cryptography::DigestType, embedded_signature::EmbeddedSignature, error::AppleCodesignError,
+        cryptography::{DigestBackendType, DigestType},
+        embedded_signature::EmbeddedSignature,
+        error::AppleCodesignError,
+        macho_fixups::ChainedFixups,
     },
     goblin::mach::{
         constants::{SEG_LINKEDIT, SEG_TEXT},
+        exports::ExportInfo,
         header::MH_EXECUTE,
         load_command::{
             CommandVariant, LinkeditDataCommand, LC_BUILD_VERSION, SIZEOF_LINKEDIT_DATA_COMMAND,
@@ -28,6 +32,7 @@ use {
     },
     rayon::prelude::*,
     scroll::Pread,
+    serde::Serialize,
 };
 
 /// A Mach-O binary.
@@ -250,6 +255,16 @@ impl<'a> MachOBinary<'a> {
         &self,
         digest: DigestType,
         page_size: usize,
+    ) -> Result<Vec<Vec<u8>>, AppleCodesignError> {
+        self.code_digests_with_backend(DigestBackendType::default(), digest, page_size)
+    }
+
+    /// Compute digests over code in this binary using a specific digest backend.
+    pub fn code_digests_with_backend(
+        &self,
+        backend: DigestBackendType,
+        digest: DigestType,
+        page_size: usize,
     ) -> Result<Vec<Vec<u8>>, AppleCodesignError> {
         let data = self.digested_code_data()?;
 
@@ -257,16 +272,43 @@ impl<'a> MachOBinary<'a> {
         // So only do parallel digests if we have enough data to warrant it.
         if data.len() > 64 * 1024 * 1024 {
             data.par_chunks(page_size)
-                .map(|c| digest.digest_data(c))
+                .map(|c| digest.digest_data_with_backend(backend, c))
                 .collect::<Result<Vec<_>, AppleCodesignError>>()
         } else {
-            self.digested_code_data()?
-                .chunks(page_size)
-                .map(|chunk| digest.digest_data(chunk))
+            data.chunks(page_size)
+                .map(|chunk| digest.digest_data_with_backend(backend, chunk))
                 .collect::<Result<Vec<_>, AppleCodesignError>>()
         }
     }
 
+    /// Compute digests over specific code pages in this binary.
+    ///
+    /// Returns the index and digest of each requested page. Indices beyond the
+    /// last page are ignored.
+    pub fn code_page_digests(
+        &self,
+        digest: DigestType,
+        page_size: usize,
+        indices: impl IntoIterator<Item = usize>,
+    ) -> Result<Vec<(usize, Vec<u8>)>, AppleCodesignError> {
+        let data = self.digested_code_data()?;
+
+        indices
+            .into_iter()
+            .filter_map(|index| {
+                let start = index.checked_mul(page_size)?;
+
+                if start >= data.len() {
+                    return None;
+                }
+
+                let end = (start + page_size).min(data.len());
+
+                Some(digest.digest_data(&data[start..end]).map(|d| (index, d)))
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()
+    }
+
     /// Resolve the load command for the code signature.
     pub fn code_signature_load_command(&self) -> Option<LinkeditDataCommand> {
         self.macho.load_commands.iter().find_map(|lc| {
@@ -278,6 +320,114 @@ impl<'a> MachOBinary<'a> {
         })
     }
 
+    /// Resolve the load command for chained fixups.
+    pub fn chained_fixups_load_command(&self) -> Option<LinkeditDataCommand> {
+        self.macho.load_commands.iter().find_map(|lc| {
+            if let CommandVariant::DyldChainedFixups(command) = lc.command {
+                Some(command)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Obtain the raw chained fixups data referenced by `LC_DYLD_CHAINED_FIXUPS`.
+    ///
+    /// Returns `Ok(None)` if the binary doesn't use chained fixups.
+    pub fn chained_fixups_data(&self) -> Result<Option<&'a [u8]>, AppleCodesignError> {
+        if let Some(command) = self.chained_fixups_load_command() {
+            let start = command.dataoff as usize;
+            let end = start + command.datasize as usize;
+
+            Ok(Some(self.data.get(start..end).ok_or_else(|| {
+                AppleCodesignError::ChainedFixups(
+                    "load command references data beyond end of binary".into(),
+                )
+            })?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parse chained fixups metadata, if present.
+    pub fn chained_fixups(&self) -> Result<Option<ChainedFixups>, AppleCodesignError> {
+        self.chained_fixups_data()?
+            .map(ChainedFixups::parse)
+            .transpose()
+    }
+
+    /// Obtain symbols exported by this binary.
+    ///
+    /// Exports are parsed from the export trie referenced by `LC_DYLD_INFO` or
+    /// `LC_DYLD_EXPORTS_TRIE`.
+    pub fn exported_symbols(&self) -> Result<Vec<MachOExport>, AppleCodesignError> {
+        Ok(self
+            .macho
+            .exports()?
+            .into_iter()
+            .map(|export| {
+                let (kind, library) = match &export.info {
+                    ExportInfo::Regular { .. } => ("regular", None),
+                    ExportInfo::Reexport { lib, .. } => ("reexport", Some(lib.to_string())),
+                    ExportInfo::Stub { .. } => ("stub", None),
+                };
+
+                MachOExport {
+                    name: export.name,
+                    kind,
+                    address: export.offset,
+                    library,
+                }
+            })
+            .collect())
+    }
+
+    /// Obtain symbols imported by this binary.
+    ///
+    /// Imports are resolved from chained fixups if present or from dyld bind
+    /// opcodes otherwise.
+    pub fn imported_symbols(&self) -> Result<Vec<MachOImport>, AppleCodesignError> {
+        if let Some(fixups) = self.chained_fixups()? {
+            Ok(fixups
+                .imports
+                .into_iter()
+                .map(|import| MachOImport {
+                    library: self.library_for_ordinal(import.lib_ordinal),
+                    name: import.name,
+                    weak: import.weak_import,
+                })
+                .collect())
+        } else {
+            Ok(self
+                .macho
+                .imports()?
+                .into_iter()
+                .map(|import| MachOImport {
+                    name: import.name.to_string(),
+                    library: import.dylib.to_string(),
+                    weak: import.is_weak,
+                })
+                .collect())
+        }
+    }
+
+    /// Resolve the name of a library from a dyld library ordinal.
+    fn library_for_ordinal(&self, ordinal: i32) -> String {
+        match ordinal {
+            0 => "<self>",
+            -1 => "<main executable>",
+            -2 => "<flat lookup>",
+            -3 => "<weak lookup>",
+            n => self
+                .macho
+                .libs
+                .get(n as usize)
+                .copied()
+                .unwrap_or("<unknown>"),
+        }
+        .to_string()
+    }
+
     /// Attempt to locate embedded Info.plist data.
     pub fn embedded_info_plist(&self) -> Result<Option<Vec<u8>>, AppleCodesignError> {
         // Mach-O binaries can have the Info.plist data in an `__info_plist` section
@@ -399,6 +549,30 @@ impl<'a> MachOBinary<'a> {
     }
 }
 
+/// A symbol exported by a Mach-O binary.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct MachOExport {
+    /// Name of the symbol.
+    pub name: String,
+    /// Type of export: `regular`, `reexport`, or `stub`.
+    pub kind: &'static str,
+    /// Address of the symbol relative to the start of the binary.
+    pub address: u64,
+    /// For re-exports, the library the symbol is re-exported from.
+    pub library: Option<String>,
+}
+
+/// A symbol imported by a Mach-O binary.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct MachOImport {
+    /// Name of the symbol.
+    pub name: String,
+    /// Library the symbol is bound from.
+    pub library: String,
+    /// Whether the import is weak, meaning it may be missing at runtime.
+    pub weak: bool,
+}
+
 /// Describes signature data embedded within a Mach-O binary.
 pub struct MachOSignatureData<'a> {
     /// Which segment offset is the `__LINKEDIT` segment.
@@ -0,0 +1,485 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Mach-O chained fixups.
+
+Binaries targeting macOS 12+ / iOS 15+ typically describe rebases and binds
+via *chained fixups* instead of the legacy dyld info opcodes. An
+`LC_DYLD_CHAINED_FIXUPS` load command points at a data structure in the
+`__LINKEDIT` segment which dyld walks at load time.
+
+Since signing rewrites the `__LINKEDIT` segment, a bug in that process can
+corrupt this data structure, resulting in crashes early in process start.
+This module implements parsing of the chained fixups header so that data
+can be inspected and validated before and after signing.
+*/
+
+use {
+    crate::{error::AppleCodesignError, macho::MachOBinary},
+    log::warn,
+    scroll::{Pread, SizeWith},
+};
+
+/// Value of a page start indicating the page has no fixups.
+pub const DYLD_CHAINED_PTR_START_NONE: u16 = 0xffff;
+
+/// Value of a page start flag indicating multiple starts on the page.
+pub const DYLD_CHAINED_PTR_START_MULTI: u16 = 0x8000;
+
+/// The header of the `LC_DYLD_CHAINED_FIXUPS` payload.
+///
+/// This is `struct dyld_chained_fixups_header` from `mach-o/fixup-chains.h`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Pread, SizeWith)]
+pub struct DyldChainedFixupsHeader {
+    /// Version of the fixups format. Currently 0.
+    pub fixups_version: u32,
+    /// Offset of `dyld_chained_starts_in_image` within the payload.
+    pub starts_offset: u32,
+    /// Offset of the imports table within the payload.
+    pub imports_offset: u32,
+    /// Offset of the symbol strings within the payload.
+    pub symbols_offset: u32,
+    /// Number of entries in the imports table.
+    pub imports_count: u32,
+    /// Format of entries in the imports table.
+    pub imports_format: u32,
+    /// Format of symbol strings. 0 means uncompressed.
+    pub symbols_format: u32,
+}
+
+/// Describes the chained fixups within a single segment.
+///
+/// This is `struct dyld_chained_starts_in_segment` from `mach-o/fixup-chains.h`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChainedStartsInSegment {
+    /// Index of the segment these fixups apply to.
+    pub segment_index: usize,
+    /// Size of the serialized structure.
+    pub size: u32,
+    /// Page size used by the chains, typically 0x1000 or 0x4000.
+    pub page_size: u16,
+    /// Pointer format of the chains. `DYLD_CHAINED_PTR_*`.
+    pub pointer_format: u16,
+    /// Offset of the segment's start from the mach header.
+    pub segment_offset: u64,
+    /// For 32-bit pointer formats, the maximum valid pointer value.
+    pub max_valid_pointer: u32,
+    /// Offset of the first fixup in each page or [DYLD_CHAINED_PTR_START_NONE].
+    pub page_starts: Vec<u16>,
+}
+
+impl ChainedStartsInSegment {
+    /// The number of pages in this segment having fixups.
+    pub fn pages_with_fixups(&self) -> usize {
+        self.page_starts
+            .iter()
+            .filter(|x| **x != DYLD_CHAINED_PTR_START_NONE)
+            .count()
+    }
+}
+
+/// A symbol imported via chained fixups.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChainedImport {
+    /// Library ordinal the symbol is bound from.
+    ///
+    /// Positive values are 1-based indices into the binary's dylib load commands.
+    /// 0 is the binary itself, -1 the main executable, -2 flat lookup, and -3 weak
+    /// lookup.
+    pub lib_ordinal: i32,
+    /// Whether the import is weak, meaning it may be missing at runtime.
+    pub weak_import: bool,
+    /// Name of the imported symbol.
+    pub name: String,
+    /// Addend applied to the symbol's address.
+    pub addend: i64,
+}
+
+/// Parsed chained fixups metadata.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChainedFixups {
+    /// The header of the chained fixups data.
+    pub header: DyldChainedFixupsHeader,
+    /// Per-segment chain start information for segments having fixups.
+    pub segments: Vec<ChainedStartsInSegment>,
+    /// Symbols imported by the fixups.
+    pub imports: Vec<ChainedImport>,
+}
+
+/// Resolve a library ordinal stored in a field whose maximum value is `max`.
+///
+/// Like dyld, only the top 15 values of the field denote special negative
+/// ordinals. Other values are regular dylib indices.
+fn lib_ordinal(value: u32, max: u32) -> i32 {
+    if value > max - 0xf {
+        value as i32 - max as i32 - 1
+    } else {
+        value as i32
+    }
+}
+
+impl ChainedFixups {
+    /// Parse the payload of an `LC_DYLD_CHAINED_FIXUPS` load command.
+    ///
+    /// Offsets and counts in the data are validated to be within bounds.
+    pub fn parse(data: &[u8]) -> Result<Self, AppleCodesignError> {
+        let header = data.pread_with::<DyldChainedFixupsHeader>(0, scroll::LE)?;
+
+        let len = data.len() as u64;
+        let import_size = match header.imports_format {
+            1 => 4,
+            2 => 8,
+            3 => 16,
+            v => {
+                return Err(problem(format!("unknown imports format {v}")));
+            }
+        };
+
+        if header.starts_offset as u64 + 4 > len {
+            return Err(problem("starts offset beyond end of data".into()));
+        }
+        if header.imports_offset as u64 + header.imports_count as u64 * import_size > len {
+            return Err(problem("imports table extends beyond end of data".into()));
+        }
+        if header.symbols_offset as u64 > len {
+            return Err(problem("symbols offset beyond end of data".into()));
+        }
+
+        let mut offset = header.starts_offset as usize;
+        let seg_count = data.gread_with::<u32>(&mut offset, scroll::LE)?;
+
+        let mut segments = vec![];
+
+        for segment_index in 0..seg_count as usize {
+            let seg_info_offset = data.gread_with::<u32>(&mut offset, scroll::LE)?;
+
+            // 0 means the segment has no fixups.
+            if seg_info_offset == 0 {
+                continue;
+            }
+
+            let mut seg_offset = header.starts_offset as usize + seg_info_offset as usize;
+            if seg_offset as u64 >= len {
+                return Err(problem(format!(
+                    "segment #{segment_index} starts info beyond end of data"
+                )));
+            }
+
+            let size = data.gread_with::<u32>(&mut seg_offset, scroll::LE)?;
+            let page_size = data.gread_with::<u16>(&mut seg_offset, scroll::LE)?;
+            let pointer_format = data.gread_with::<u16>(&mut seg_offset, scroll::LE)?;
+            let segment_offset = data.gread_with::<u64>(&mut seg_offset, scroll::LE)?;
+            let max_valid_pointer = data.gread_with::<u32>(&mut seg_offset, scroll::LE)?;
+            let page_count = data.gread_with::<u16>(&mut seg_offset, scroll::LE)?;
+
+            let page_starts = (0..page_count)
+                .map(|_| data.gread_with::<u16>(&mut seg_offset, scroll::LE))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            segments.push(ChainedStartsInSegment {
+                segment_index,
+                size,
+                page_size,
+                pointer_format,
+                segment_offset,
+                max_valid_pointer,
+                page_starts,
+            });
+        }
+
+        let imports = Self::parse_imports(data, &header)?;
+
+        Ok(Self {
+            header,
+            segments,
+            imports,
+        })
+    }
+
+    fn parse_imports(
+        data: &[u8],
+        header: &DyldChainedFixupsHeader,
+    ) -> Result<Vec<ChainedImport>, AppleCodesignError> {
+        let mut offset = header.imports_offset as usize;
+        let mut imports = vec![];
+
+        for _ in 0..header.imports_count {
+            let (lib_ordinal, weak_import, name_offset, addend) = match header.imports_format {
+                1 | 2 => {
+                    let v = data.gread_with::<u32>(&mut offset, scroll::LE)?;
+                    let addend = if header.imports_format == 2 {
+                        data.gread_with::<i32>(&mut offset, scroll::LE)? as i64
+                    } else {
+                        0
+                    };
+
+                    (lib_ordinal(v & 0xff, 0xff), v & 0x100 != 0, v >> 9, addend)
+                }
+                _ => {
+                    let v = data.gread_with::<u64>(&mut offset, scroll::LE)?;
+                    let addend = data.gread_with::<i64>(&mut offset, scroll::LE)?;
+
+                    (
+                        lib_ordinal((v & 0xffff) as u32, 0xffff),
+                        v & 0x10000 != 0,
+                        (v >> 32) as u32,
+                        addend,
+                    )
+                }
+            };
+
+            let name_start = header.symbols_offset as usize + name_offset as usize;
+            let name = data
+                .get(name_start..)
+                .and_then(|d| d.split(|b| *b == 0).next())
+                .ok_or_else(|| problem("import name beyond end of data".into()))?;
+
+            imports.push(ChainedImport {
+                lib_ordinal,
+                weak_import,
+                name: String::from_utf8_lossy(name).to_string(),
+                addend,
+            });
+        }
+
+        Ok(imports)
+    }
+
+    /// Obtain a human readable summary of the chained fixups.
+    pub fn summary_lines(&self, macho: &MachOBinary) -> Vec<String> {
+        let mut lines = vec![
+            format!("fixups version: {}", self.header.fixups_version),
+            format!(
+                "imports: {} ({})",
+                self.header.imports_count,
+                imports_format_name(self.header.imports_format)
+            ),
+            format!(
+                "symbols format: {}",
+                if self.header.symbols_format == 0 {
+                    "uncompressed"
+                } else {
+                    "compressed"
+                }
+            ),
+            format!("segments with fixups: {}", self.segments.len()),
+        ];
+
+        for segment in &self.segments {
+            let name = macho
+                .macho
+                .segments
+                .get(segment.segment_index)
+                .and_then(|s| s.name().ok())
+                .unwrap_or("<unknown>");
+
+            lines.push(format!(
+                "segment #{} ({}); pointer format {} ({}); segment offset 0x{:x}; page size 0x{:x}; pages {}; pages with fixups {}",
+                segment.segment_index,
+                name,
+                segment.pointer_format,
+                pointer_format_name(segment.pointer_format),
+                segment.segment_offset,
+                segment.page_size,
+                segment.page_starts.len(),
+                segment.pages_with_fixups(),
+            ));
+        }
+
+        lines
+    }
+}
+
+fn problem(message: String) -> AppleCodesignError {
+    AppleCodesignError::ChainedFixups(message)
+}
+
+/// Resolve the name of an imports format value.
+pub fn imports_format_name(format: u32) -> &'static str {
+    match format {
+        1 => "DYLD_CHAINED_IMPORT",
+        2 => "DYLD_CHAINED_IMPORT_ADDEND",
+        3 => "DYLD_CHAINED_IMPORT_ADDEND64",
+        _ => "unknown",
+    }
+}
+
+/// Resolve the name of a `DYLD_CHAINED_PTR_*` pointer format value.
+pub fn pointer_format_name(format: u16) -> &'static str {
+    match format {
+        1 => "DYLD_CHAINED_PTR_ARM64E",
+        2 => "DYLD_CHAINED_PTR_64",
+        3 => "DYLD_CHAINED_PTR_32",
+        4 => "DYLD_CHAINED_PTR_32_CACHE",
+        5 => "DYLD_CHAINED_PTR_32_FIRMWARE",
+        6 => "DYLD_CHAINED_PTR_64_OFFSET",
+        7 => "DYLD_CHAINED_PTR_ARM64E_KERNEL",
+        8 => "DYLD_CHAINED_PTR_64_KERNEL_CACHE",
+        9 => "DYLD_CHAINED_PTR_ARM64E_USERLAND",
+        10 => "DYLD_CHAINED_PTR_ARM64E_FIRMWARE",
+        11 => "DYLD_CHAINED_PTR_X86_64_KERNEL_CACHE",
+        12 => "DYLD_CHAINED_PTR_ARM64E_USERLAND24",
+        _ => "unknown",
+    }
+}
+
+/// Validate the placement of chained fixups data within a Mach-O binary.
+///
+/// The data must reside within the `__LINKEDIT` segment and must not overlap
+/// the code signature.
+///
+/// The data is also parsed. Since our parser may not understand every format
+/// newer linkers emit, parse failures are only logged as a warning.
+pub fn validate_chained_fixups_placement(macho: &MachOBinary) -> Result<(), AppleCodesignError> {
+    let Some(command) = macho.chained_fixups_load_command() else {
+        return Ok(());
+    };
+
+    let linkedit = macho
+        .linkedit_segment()
+        .ok_or(AppleCodesignError::MissingLinkedit)?;
+
+    let start = command.dataoff as u64;
+    let end = start + command.datasize as u64;
+    let linkedit_start = linkedit.fileoff;
+    let linkedit_end = linkedit.fileoff + linkedit.data.len() as u64;
+
+    if start < linkedit_start || end > linkedit_end {
+        return Err(problem(format!(
+            "data at 0x{start:x}-0x{end:x} is outside __LINKEDIT at 0x{linkedit_start:x}-0x{linkedit_end:x}"
+        )));
+    }
+
+    if let Some(signature) = macho.code_signature_load_command() {
+        let signature_start = signature.dataoff as u64;
+        let signature_end = signature_start + signature.datasize as u64;
+
+        if start < signature_end && signature_start < end {
+            return Err(problem(format!(
+                "data at 0x{start:x}-0x{end:x} overlaps code signature at 0x{signature_start:x}-0x{signature_end:x}"
+            )));
+        }
+    }
+
+    if let Some(data) = macho.chained_fixups_data()? {
+        if let Err(e) = ChainedFixups::parse(data) {
+            warn!("unable to parse chained fixups data; not validating its content: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate that signing preserved chained fixups data.
+///
+/// The raw chained fixups payload of the signed binary must be identical to
+/// that of the original binary and it must be placed correctly. Byte equality
+/// is the authoritative check: it holds even for payloads we cannot parse.
+pub fn validate_chained_fixups_preserved(
+    original: &MachOBinary,
+    signed: &MachOBinary,
+) -> Result<(), AppleCodesignError> {
+    if original.chained_fixups_data()? != signed.chained_fixups_data()? {
+        return Err(problem(
+            "chained fixups data changed as part of signing".into(),
+        ));
+    }
+
+    validate_chained_fixups_placement(signed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixups_data() -> Vec<u8> {
+        let mut data = vec![];
+
+        // Header: version, starts offset, imports offset, symbols offset,
+        // imports count, imports format, symbols format.
+        for v in [0u32, 28, 66, 70, 1, 1, 0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+
+        // dyld_chained_starts_in_image: 2 segments, only the second has fixups.
+        for v in [2u32, 0, 12] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+
+        // dyld_chained_starts_in_segment.
+        data.extend_from_slice(&26u32.to_le_bytes());
+        data.extend_from_slice(&0x4000u16.to_le_bytes());
+        data.extend_from_slice(&6u16.to_le_bytes());
+        data.extend_from_slice(&0x4000u64.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&DYLD_CHAINED_PTR_START_NONE.to_le_bytes());
+
+        // Imports and symbols.
+        // Ordinal 2, weak, name offset 1.
+        data.extend_from_slice(&0x302u32.to_le_bytes());
+        data.extend_from_slice(b"\0_foo\0\0\0");
+
+        data
+    }
+
+    #[test]
+    fn parse_chained_fixups() -> Result<(), AppleCodesignError> {
+        let data = fixups_data();
+        let fixups = ChainedFixups::parse(&data)?;
+
+        assert_eq!(fixups.header.imports_count, 1);
+        assert_eq!(fixups.segments.len(), 1);
+
+        let segment = &fixups.segments[0];
+        assert_eq!(segment.segment_index, 1);
+        assert_eq!(segment.page_size, 0x4000);
+        assert_eq!(
+            pointer_format_name(segment.pointer_format),
+            "DYLD_CHAINED_PTR_64_OFFSET"
+        );
+        assert_eq!(segment.page_starts, vec![0, DYLD_CHAINED_PTR_START_NONE]);
+        assert_eq!(segment.pages_with_fixups(), 1);
+
+        assert_eq!(
+            fixups.imports,
+            vec![ChainedImport {
+                lib_ordinal: 2,
+                weak_import: true,
+                name: "_foo".into(),
+                addend: 0,
+            }]
+        );
+
+        // Truncated data is rejected.
+        assert!(ChainedFixups::parse(&data[0..60]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_lib_ordinals() -> Result<(), AppleCodesignError> {
+        assert_eq!(lib_ordinal(0x80, 0xff), 128);
+        assert_eq!(lib_ordinal(0xf0, 0xff), 240);
+        assert_eq!(lib_ordinal(0xff, 0xff), -1);
+        assert_eq!(lib_ordinal(0xfd, 0xff), -3);
+        assert_eq!(lib_ordinal(0x8000, 0xffff), 0x8000);
+        assert_eq!(lib_ordinal(0xfff0, 0xffff), 0xfff0);
+        assert_eq!(lib_ordinal(0xfffe, 0xffff), -2);
+
+        // Ordinal 200 of a binary linking many dylibs.
+        let mut data = fixups_data();
+        data[66..70].copy_from_slice(&0x2c8u32.to_le_bytes());
+        let fixups = ChainedFixups::parse(&data)?;
+        assert_eq!(fixups.imports[0].lib_ordinal, 200);
+
+        // Ordinal -2 (flat lookup).
+        data[66..70].copy_from_slice(&0x2feu32.to_le_bytes());
+        let fixups = ChainedFixups::parse(&data)?;
+        assert_eq!(fixups.imports[0].lib_ordinal, -2);
+
+        Ok(())
+    }
+}
@@ -10,7 +10,7 @@ use {
     crate::{
         code_directory::{CodeDirectoryBlob, CodeSignatureFlags, ExecutableSegmentFlags},
         code_requirement::{CodeRequirementExpression, CodeRequirements, RequirementType},
-        cryptography::Digest,
+        cryptography::{Digest, DigestBackendType, DigestType},
         embedded_signature::{
             Blob, BlobData, CodeSigningSlot, ConstraintsDerBlob, EntitlementsBlob,
             EntitlementsDerBlob, RequirementSetBlob,
@@ -19,10 +19,12 @@ use {
         entitlements::plist_to_executable_segment_flags,
         error::AppleCodesignError,
         macho::{semver_to_macho_target_version, MachFile, MachOBinary},
+        macho_fixups::validate_chained_fixups_preserved,
         macho_universal::create_universal_macho,
         policy::derive_designated_requirements,
         signing_settings::{DesignatedRequirementMode, SettingsScope, SigningSettings},
     },
+    apple_bundles::validate_identifier,
     goblin::mach::{
         constants::{SEG_LINKEDIT, SEG_PAGEZERO},
         load_command::{
@@ -32,6 +34,7 @@ use {
         parse_magic_and_ctx,
     },
     log::{debug, info, warn},
+    rayon::prelude::*,
     scroll::{ctx::SizeWith, IOwrite},
     std::{borrow::Cow, cmp::Ordering, collections::HashMap, io::Write, path::Path},
 };
@@ -290,6 +293,35 @@ pub fn write_macho_file(
     Ok(())
 }
 
+/// Size of code pages digested in code directories we emit.
+const CODE_PAGE_SIZE: usize = 4096;
+
+/// Digests of code pages computed ahead of signature creation.
+///
+/// Digests are keyed by digest type and page size.
+#[derive(Clone, Debug, Default)]
+struct CodeDigests(Vec<((DigestType, usize), Vec<Vec<u8>>)>);
+
+impl CodeDigests {
+    /// Obtain code digests of a given type, computing them if not already available.
+    fn resolve(
+        &self,
+        macho: &MachOBinary,
+        backend: DigestBackendType,
+        digest_type: DigestType,
+        page_size: usize,
+    ) -> Result<Vec<Vec<u8>>, AppleCodesignError> {
+        match self
+            .0
+            .iter()
+            .find(|(key, _)| *key == (digest_type, page_size))
+        {
+            Some((_, digests)) => Ok(digests.clone()),
+            None => macho.code_digests_with_backend(backend, digest_type, page_size),
+        }
+    }
+}
+
 /// Mach-O binary signer.
 ///
 /// This type provides a high-level interface for signing Mach-O binaries.
@@ -336,7 +368,7 @@ impl<'data> MachOSigner<'data> {
         // of all binaries so fat header offsets and sizes can be written first. We take
         // the easy road and buffer individual Mach-O binaries internally.
 
-        let binaries = self
+        let slices = self
             .machos
             .iter()
             .enumerate()
@@ -350,33 +382,100 @@ impl<'data> MachOSigner<'data> {
 
                 // Derive an intermediate Mach-O with placeholder NULLs for signature
                 // data so Code Directory digests over the load commands are correct.
-                let placeholder_signature_data = b"\0".repeat(signature_len);
-
                 let intermediate_macho_data =
-                    create_macho_with_signature(original_macho, &placeholder_signature_data)?;
+                    create_macho_with_signature(original_macho, &b"\0".repeat(signature_len))?;
 
-                // A nice side-effect of this is that it catches bugs if we write malformed Mach-O!
-                let intermediate_macho = MachOBinary::parse(&intermediate_macho_data)?;
+                Ok((settings, signature_len, intermediate_macho_data))
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
 
-                let mut signature_data = self.create_superblob(&settings, &intermediate_macho)?;
-                info!("total signature size: {} bytes", signature_data.len());
+        // Digesting code pages dominates signing time and doesn't involve the signing
+        // key. So digest the slices of universal binaries concurrently.
+        let code_digests = slices
+            .iter()
+            .map(|(settings, _, data)| {
+                let mut digest_types = vec![settings.digest_type(SettingsScope::Main)];
+                digest_types.extend(
+                    settings
+                        .extra_digests(SettingsScope::Main)
+                        .into_iter()
+                        .flatten()
+                        .copied(),
+                );
 
-                // The Mach-O writer adjusts load commands based on the signature length. So pad
-                // with NULLs to get to our placeholder length.
-                match signature_data.len().cmp(&placeholder_signature_data.len()) {
-                    Ordering::Greater => {
-                        return Err(AppleCodesignError::SignatureDataTooLarge);
-                    }
-                    Ordering::Equal => {}
-                    Ordering::Less => {
-                        signature_data.extend_from_slice(
-                            &b"\0".repeat(placeholder_signature_data.len() - signature_data.len()),
-                        );
+                (digest_types, settings.digest_backend(), data.as_slice())
+            })
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(digest_types, backend, data)| {
+                // A nice side-effect of this is that it catches bugs if we write malformed Mach-O!
+                let macho = MachOBinary::parse(data)?;
+
+                digest_types
+                    .into_iter()
+                    .map(|digest_type| {
+                        Ok((
+                            (digest_type, CODE_PAGE_SIZE),
+                            macho.code_digests_with_backend(
+                                backend,
+                                digest_type,
+                                CODE_PAGE_SIZE,
+                            )?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, AppleCodesignError>>()
+                    .map(CodeDigests)
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+        // Signing keys may not be usable from multiple threads (e.g. smartcards and
+        // remote signers). So signatures are created and slices assembled serially.
+        let binaries = self
+            .machos
+            .iter()
+            .zip(slices)
+            .zip(code_digests)
+            .map(
+                |(
+                    (original_macho, (settings, signature_len, intermediate_macho_data)),
+                    digests,
+                )| {
+                    let intermediate_macho = MachOBinary::parse(&intermediate_macho_data)?;
+
+                    let mut signature_data = self.create_superblob_with_digests(
+                        &settings,
+                        &intermediate_macho,
+                        &digests,
+                    )?;
+                    info!("total signature size: {} bytes", signature_data.len());
+
+                    // The Mach-O writer adjusts load commands based on the signature length. So
+                    // pad with NULLs to get to our placeholder length.
+                    match signature_data.len().cmp(&signature_len) {
+                        Ordering::Greater => {
+                            return Err(AppleCodesignError::SignatureDataTooLarge);
+                        }
+                        Ordering::Equal => {}
+                        Ordering::Less => {
+                            signature_data.extend_from_slice(
+                                &b"\0".repeat(signature_len - signature_data.len()),
+                            );
+                        }
                     }
-                }
 
-                create_macho_with_signature(&intermediate_macho, &signature_data)
-            })
+                    let signed_macho_data =
+                        create_macho_with_signature(&intermediate_macho, &signature_data)?;
+
+                    // Signing rewrites __LINKEDIT. Make sure dyld's chained fixups
+                    // metadata survived that.
+                    validate_chained_fixups_preserved(
+                        original_macho,
+                        &MachOBinary::parse(&signed_macho_data)?,
+                    )?;
+
+                    Ok(signed_macho_data)
+                },
+            )
             .collect::<Result<Vec<_>, AppleCodesignError>>()?;
 
         if binaries.len() > 1 {
@@ -400,6 +499,16 @@ impl<'data> MachOSigner<'data> {
         &self,
         settings: &SigningSettings,
         macho: &MachOBinary,
+    ) -> Result<Vec<u8>, AppleCodesignError> {
+        self.create_superblob_with_digests(settings, macho, &CodeDigests::default())
+    }
+
+    /// Create the SuperBlob, using code digests computed ahead of time when available.
+    fn create_superblob_with_digests(
+        &self,
+        settings: &SigningSettings,
+        macho: &MachOBinary,
+        code_digests: &CodeDigests,
     ) -> Result<Vec<u8>, AppleCodesignError> {
         let mut builder = EmbeddedSignatureBuilder::default();
 
@@ -407,13 +516,24 @@ impl<'data> MachOSigner<'data> {
             builder.add_blob(slot, blob)?;
         }
 
-        let code_directory = self.create_code_directory(settings, macho)?;
+        let code_directory =
+            self.create_code_directory_with_digests(settings, macho, code_digests)?;
         info!("code directory version: {}", code_directory.version);
 
         builder.add_code_directory(CodeSigningSlot::CodeDirectory, code_directory)?;
 
         if let Some(digests) = settings.extra_digests(SettingsScope::Main) {
             for digest_type in digests {
+                // The primary digest may have been derived after the extra digests were
+                // registered. There's no point in emitting the same code directory twice.
+                if *digest_type == settings.digest_type(SettingsScope::Main) {
+                    info!(
+                        "not adding alternative code directory using primary digest {:?}",
+                        digest_type
+                    );
+                    continue;
+                }
+
                 // Since everything consults settings for the digest to use, just make a new settings
                 // with a different digest.
                 let mut alt_settings = settings.clone();
@@ -423,13 +543,16 @@ impl<'data> MachOSigner<'data> {
                     "adding alternative code directory using digest {:?}",
                     digest_type
                 );
-                let cd = self.create_code_directory(&alt_settings, macho)?;
+                let cd =
+                    self.create_code_directory_with_digests(&alt_settings, macho, code_digests)?;
 
                 builder.add_alternative_code_directory(cd)?;
             }
         }
 
         if let Some((signing_key, signing_cert)) = settings.signing_key() {
+            settings.ensure_key_usage_allowed(settings.binary_identifier(SettingsScope::Main))?;
+
             builder.create_cms_signature(
                 signing_key,
                 signing_cert,
@@ -453,6 +576,16 @@ impl<'data> MachOSigner<'data> {
         &self,
         settings: &SigningSettings,
         macho: &MachOBinary,
+    ) -> Result<CodeDirectoryBlob<'static>, AppleCodesignError> {
+        self.create_code_directory_with_digests(settings, macho, &CodeDigests::default())
+    }
+
+    /// Create the `CodeDirectory`, using code digests computed ahead of time when available.
+    fn create_code_directory_with_digests(
+        &self,
+        settings: &SigningSettings,
+        macho: &MachOBinary,
+        code_digests: &CodeDigests,
     ) -> Result<CodeDirectoryBlob<'static>, AppleCodesignError> {
         // TODO support defining or filling in proper values for fields with
         // static values.
@@ -500,7 +633,7 @@ impl<'data> MachOSigner<'data> {
         };
 
         let platform = 0;
-        let page_size = 4096u32;
+        let page_size = CODE_PAGE_SIZE as u32;
 
         let (exec_seg_base, exec_seg_limit) = macho.executable_segment_boundary()?;
         let (exec_seg_base, exec_seg_limit) = (Some(exec_seg_base), Some(exec_seg_limit));
@@ -563,9 +696,10 @@ impl<'data> MachOSigner<'data> {
         };
 
         let digest_type = settings.digest_type(SettingsScope::Main);
+        let backend = settings.digest_backend();
 
-        let code_hashes = macho
-            .code_digests(digest_type, page_size as _)?
+        let code_hashes = code_digests
+            .resolve(macho, backend, digest_type, page_size as _)?
             .into_iter()
             .map(|v| Digest { data: v.into() })
             .collect::<Vec<_>>();
@@ -578,7 +712,7 @@ impl<'data> MachOSigner<'data> {
             special_hashes.insert(
                 CodeSigningSlot::Info,
                 Digest {
-                    data: digest_type.digest_data(data)?.into(),
+                    data: digest_type.digest_data_with_backend(backend, data)?.into(),
                 },
             );
         }
@@ -589,7 +723,7 @@ impl<'data> MachOSigner<'data> {
             special_hashes.insert(
                 CodeSigningSlot::ResourceDir,
                 Digest {
-                    data: digest_type.digest_data(data)?.into(),
+                    data: digest_type.digest_data_with_backend(backend, data)?.into(),
                 }
                 .to_owned(),
             );
@@ -602,6 +736,10 @@ impl<'data> MachOSigner<'data> {
                 .to_string(),
         );
 
+        if let Err(e) = validate_identifier(&ident) {
+            warn!("{}; the signature may be rejected by Apple's tooling", e);
+        }
+
         // Team should only be included when signing with an Apple signed
         // certificate. This logic is handled in [SigningSettings]. But emit
         // a warning if the constraint is violated.
@@ -636,6 +774,24 @@ impl<'data> MachOSigner<'data> {
         cd.adjust_version(target);
         cd.clear_newer_fields();
 
+        let compatibility_target = settings.compatibility_target();
+
+        if let Some(max_version) = compatibility_target.max_code_directory_version() {
+            if cd.flags.contains(CodeSignatureFlags::RUNTIME) {
+                warn!(
+                    "hardened runtime is not supported by {}; it will only be enforced by newer releases",
+                    compatibility_target.description()
+                );
+            }
+
+            if cd.cap_version(max_version)? > max_version as u32 {
+                info!(
+                    "lowered code directory version for {} compatibility",
+                    compatibility_target.description()
+                );
+            }
+        }
+
         Ok(cd)
     }
 
@@ -694,6 +850,8 @@ impl<'data> MachOSigner<'data> {
 
         res.push((CodeSigningSlot::RequirementSet, blob.into()));
 
+        settings.ensure_preset_entitlements(is_executable)?;
+
         if let Some(entitlements) = settings.entitlements_xml(SettingsScope::Main)? {
             let blob = EntitlementsBlob::from_string(&entitlements);
 
@@ -754,11 +912,12 @@ impl<'data> MachOSigner<'data> {
         let mut size = 1024 * code_directory_count;
 
         // Reserve room for the code digests, which are proportional to binary size.
-        size += macho.code_digests_size(settings.digest_type(SettingsScope::Main), 4096)?;
+        size +=
+            macho.code_digests_size(settings.digest_type(SettingsScope::Main), CODE_PAGE_SIZE)?;
 
         if let Some(digests) = settings.extra_digests(SettingsScope::Main) {
             for digest in digests {
-                size += macho.code_digests_size(*digest, 4096)?;
+                size += macho.code_digests_size(*digest, CODE_PAGE_SIZE)?;
             }
         }
 
@@ -793,3 +952,71 @@ impl<'data> MachOSigner<'data> {
         Ok(size)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::macho_builder::MachOBuilder};
+
+    fn settings() -> SigningSettings<'static> {
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "test");
+        settings.add_extra_digest(SettingsScope::Main, DigestType::Sha1);
+
+        settings
+    }
+
+    fn sign(data: &[u8]) -> Result<Vec<u8>, AppleCodesignError> {
+        let mut signed = vec![];
+        MachOSigner::new(data)?.write_signed_binary(&settings(), &mut signed)?;
+
+        Ok(signed)
+    }
+
+    #[test]
+    fn code_digests_page_size() -> Result<(), AppleCodesignError> {
+        let data = MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?;
+        let macho = MachOBinary::parse(&data)?;
+
+        let digests = CodeDigests(vec![(
+            (DigestType::Sha256, CODE_PAGE_SIZE),
+            macho.code_digests(DigestType::Sha256, CODE_PAGE_SIZE)?,
+        )]);
+
+        for (digest_type, page_size) in [
+            (DigestType::Sha256, CODE_PAGE_SIZE),
+            (DigestType::Sha256, 16384),
+            (DigestType::Sha1, CODE_PAGE_SIZE),
+        ] {
+            assert_eq!(
+                digests.resolve(&macho, DigestBackendType::Ring, digest_type, page_size)?,
+                macho.code_digests(digest_type, page_size)?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn universal_matches_single() -> Result<(), AppleCodesignError> {
+        let thin = [
+            MachOBuilder::new_x86_64(object::macho::MH_EXECUTE).write_macho()?,
+            MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?,
+        ];
+
+        let mut fat = vec![];
+        create_universal_macho(&mut fat, thin.iter().map(|x| x.as_slice()))?;
+
+        // Slices of universal binaries have their code digested concurrently
+        // ahead of signing. The result must be what signing each slice on its
+        // own produces.
+        let signed_fat = sign(&fat)?;
+        let mach = MachFile::parse(&signed_fat)?;
+        assert_eq!(mach.iter_macho().count(), thin.len());
+
+        for (macho, data) in mach.iter_macho().zip(thin.iter()) {
+            assert_eq!(macho.data, sign(data)?.as_slice());
+        }
+
+        Ok(())
+    }
+}
@@ -15,7 +15,9 @@ and waiting on the availability of a notarization ticket.
 
 use {
     crate::{reader::PathType, AppleCodesignError},
-    app_store_connect::{notary_api, AppStoreConnectClient, ConnectTokenEncoder, UnifiedApiKey},
+    app_store_connect::{
+        notary_api, AppStoreConnectClient, ConnectTokenEncoder, TokenValidity, UnifiedApiKey,
+    },
     apple_bundles::DirectoryBundle,
     aws_sdk_s3::config::{Credentials, Region},
     aws_smithy_types::byte_stream::ByteStream,
@@ -139,6 +141,9 @@ enum UploadKind {
 pub struct Notarizer {
     token_encoder: ConnectTokenEncoder,
 
+    /// The team the API key belongs to, if known.
+    key_team_id: Option<String>,
+
     /// How long to wait between polling the server for upload status.
     wait_poll_interval: Duration,
 }
@@ -148,6 +153,7 @@ impl Notarizer {
     fn new(token_encoder: ConnectTokenEncoder) -> Self {
         Self {
             token_encoder,
+            key_team_id: None,
             wait_poll_interval: Duration::from_secs(3),
         }
     }
@@ -165,7 +171,37 @@ impl Notarizer {
 
     /// Construct an instance from a file containing a JSON encoded API key.
     pub fn from_api_key(path: &Path) -> Result<Self, AppleCodesignError> {
-        Ok(Self::new(UnifiedApiKey::from_json_path(path)?.try_into()?))
+        let key = UnifiedApiKey::from_json_path(path)?;
+        let key_team_id = key.team_id().map(|x| x.to_string());
+
+        let mut notarizer = Self::new(key.try_into()?);
+        notarizer.key_team_id = key_team_id;
+
+        Ok(notarizer)
+    }
+
+    /// Ensure the team recorded in the API key file matches the given team.
+    ///
+    /// This is a local sanity check of the key configuration. It only compares
+    /// against the team recorded in the key's JSON file and doesn't consult
+    /// the App Store Connect API. Keys not recording their team are rejected.
+    pub fn check_key_team_id(&self, team_id: &str) -> Result<(), AppleCodesignError> {
+        match &self.key_team_id {
+            Some(key_team_id) if key_team_id == team_id => Ok(()),
+            Some(key_team_id) => Err(AppleCodesignError::NotarizeTeamMismatch(
+                team_id.to_string(),
+                key_team_id.clone(),
+            )),
+            None => Err(AppleCodesignError::NotarizeTeamUnknown(team_id.to_string())),
+        }
+    }
+
+    /// Set the validity window of App Store Connect API tokens used by this instance.
+    pub fn set_token_validity(
+        &mut self,
+        validity: TokenValidity,
+    ) -> Result<(), AppleCodesignError> {
+        Ok(self.token_encoder.set_token_validity(validity)?)
     }
 
     /// Attempt to notarize an asset defined by a filesystem path.
@@ -440,4 +476,12 @@ impl Notarizer {
     ) -> Result<notary_api::ListSubmissionResponse, AppleCodesignError> {
         Ok(self.client()?.list_submissions()?)
     }
+
+    /// List all previous submissions matching a filter, following pagination.
+    pub fn list_all_submissions(
+        &self,
+        filter: &notary_api::SubmissionListFilter,
+    ) -> Result<Vec<notary_api::SubmissionResponseData>, AppleCodesignError> {
+        Ok(self.client()?.list_all_submissions(filter)?)
+    }
 }
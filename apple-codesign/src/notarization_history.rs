@@ -0,0 +1,339 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Local history of notarization submissions.
+
+Apple's Notary API only tells you about submissions and their status. It
+doesn't know which files on your machine those submissions correspond to,
+whether tickets were stapled, or what code directory hashes were notarized.
+
+This module implements a small JSON backed store recording that information
+as notarization operations are performed. The store can be used to emit
+status documents suitable for publishing alongside software releases.
+*/
+
+use {
+    crate::{
+        bundle_signing::SignedMachOInfo, reader::PathType,
+        stapling::record_name_from_executable_bundle, AppleCodesignError,
+    },
+    apple_bundles::DirectoryBundle,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::Write as _,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Name of the file holding notarization history in the default location.
+const HISTORY_FILENAME: &str = "notarization-history.json";
+
+/// Resolve the default path of the notarization history file.
+///
+/// This is a file in the per-user local data directory. `None` is returned
+/// if that directory cannot be resolved.
+pub fn default_history_path() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|p| p.join("rcodesign").join(HISTORY_FILENAME))
+}
+
+/// A single notarization submission recorded in the history.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NotarizationHistoryEntry {
+    /// The Notary API submission ID.
+    pub submission_id: String,
+
+    /// Filesystem path of the entity that was submitted.
+    pub path: PathBuf,
+
+    /// RFC 3339 time the submission was created.
+    pub submitted_at: String,
+
+    /// Last known status of the submission.
+    ///
+    /// `None` if we never observed a status from the server.
+    pub status: Option<String>,
+
+    /// Whether a notarization ticket was stapled to the entity.
+    pub stapled: bool,
+
+    /// Hex encoded (truncated) code directory hashes that were notarized.
+    pub cdhashes: Vec<String>,
+}
+
+impl NotarizationHistoryEntry {
+    /// Construct a new entry for a submission made now.
+    pub fn new(submission_id: impl ToString, path: impl AsRef<Path>) -> Self {
+        let path = normalize_path(path.as_ref());
+
+        Self {
+            submission_id: submission_id.to_string(),
+            cdhashes: cdhashes_for_path(&path),
+            path,
+            submitted_at: chrono::Utc::now().to_rfc3339(),
+            status: None,
+            stapled: false,
+        }
+    }
+
+    /// Obtain the file name of the submitted entity.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+/// A collection of notarization submissions.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NotarizationHistory {
+    /// Recorded submissions, in the order they were created.
+    pub entries: Vec<NotarizationHistoryEntry>,
+}
+
+impl NotarizationHistory {
+    /// Load history from a JSON file.
+    ///
+    /// An empty instance is returned if the file does not exist.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = std::fs::read(path)?;
+
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Write history to a JSON file, creating parent directories as necessary.
+    pub fn write_path(&self, path: impl AsRef<Path>) -> Result<(), AppleCodesignError> {
+        let path = path.as_ref();
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Record a new submission.
+    pub fn record_submission(&mut self, entry: NotarizationHistoryEntry) {
+        self.entries
+            .retain(|e| e.submission_id != entry.submission_id);
+        self.entries.push(entry);
+    }
+
+    /// Update the status of a submission.
+    ///
+    /// Returns whether a submission with the given ID was found.
+    pub fn set_status(&mut self, submission_id: &str, status: impl ToString) -> bool {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|e| e.submission_id == submission_id)
+        {
+            entry.status = Some(status.to_string());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Mark the most recent submission of a path as stapled.
+    ///
+    /// Returns whether a submission for the given path was found.
+    pub fn mark_stapled(&mut self, path: impl AsRef<Path>) -> bool {
+        let path = normalize_path(path.as_ref());
+
+        if let Some(entry) = self.entries.iter_mut().rev().find(|e| e.path == path) {
+            entry.stapled = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Obtain the most recent submission.
+    pub fn latest(&self) -> Option<&NotarizationHistoryEntry> {
+        self.entries.last()
+    }
+
+    /// Obtain the most recent submission for every distinct submitted path.
+    ///
+    /// Entries are sorted by file name.
+    pub fn latest_per_path(&self) -> Vec<&NotarizationHistoryEntry> {
+        let mut res: Vec<&NotarizationHistoryEntry> = vec![];
+
+        for entry in self.entries.iter().rev() {
+            if !res.iter().any(|e| e.path == entry.path) {
+                res.push(entry);
+            }
+        }
+
+        res.sort_by_key(|e| e.name());
+
+        res
+    }
+
+    /// Produce a JSON status document summarizing the history.
+    pub fn status_json(&self) -> serde_json::Value {
+        let latest = self.latest();
+
+        serde_json::json!({
+            "latest_submission_id": latest.map(|e| e.submission_id.clone()),
+            "status": latest.and_then(|e| e.status.clone()),
+            "submitted_at": latest.map(|e| e.submitted_at.clone()),
+            "stapled_artifacts": self
+                .latest_per_path()
+                .into_iter()
+                .filter(|e| e.stapled)
+                .map(|e| e.name())
+                .collect::<Vec<_>>(),
+            "artifacts": self
+                .latest_per_path()
+                .into_iter()
+                .map(|e| serde_json::json!({
+                    "name": e.name(),
+                    "submission_id": e.submission_id,
+                    "status": e.status,
+                    "stapled": e.stapled,
+                    "cdhashes": e.cdhashes,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Produce a Markdown status document summarizing the history.
+    pub fn status_markdown(&self) -> String {
+        let mut s = String::from("# Notarization Status\n\n");
+
+        let Some(latest) = self.latest() else {
+            s.push_str("No notarization submissions recorded.\n");
+            return s;
+        };
+
+        let status = latest.status.as_deref().unwrap_or("unknown");
+
+        // Writing to a String cannot fail.
+        let _ = writeln!(s, "Latest submission: `{}`\n", latest.submission_id);
+        let _ = writeln!(s, "Status: **{}** ({})\n", status, latest.submitted_at);
+
+        s.push_str("| Artifact | Submission | Status | Stapled | CDHashes |\n");
+        s.push_str("| --- | --- | --- | --- | --- |\n");
+
+        for entry in self.latest_per_path() {
+            let _ = writeln!(
+                s,
+                "| {} | `{}` | {} | {} | {} |",
+                entry.name(),
+                entry.submission_id,
+                entry.status.as_deref().unwrap_or("unknown"),
+                if entry.stapled { "yes" } else { "no" },
+                entry
+                    .cdhashes
+                    .iter()
+                    .map(|h| format!("`{h}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        s
+    }
+}
+
+/// Resolve the absolute path of an entity so entries can be matched across invocations.
+fn normalize_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Attempt to resolve the notarized code directory hashes of a path.
+///
+/// This is best effort: entities without a code signature or whose type we
+/// can't resolve hashes for yield an empty list.
+fn cdhashes_for_path(path: &Path) -> Vec<String> {
+    let record_name = match PathType::from_path(path) {
+        Ok(PathType::Bundle) => DirectoryBundle::new_from_path(path)
+            .map_err(AppleCodesignError::DirectoryBundle)
+            .and_then(|bundle| record_name_from_executable_bundle(&bundle))
+            .ok(),
+        Ok(PathType::MachO) => std::fs::read(path)
+            .map_err(AppleCodesignError::from)
+            .and_then(|data| SignedMachOInfo::parse_data(&data)?.notarization_ticket_record_name())
+            .ok(),
+        _ => None,
+    };
+
+    // Record names have the form `2/<digest type>/<digest>`.
+    record_name
+        .and_then(|name| name.rsplit('/').next().map(|x| x.to_string()))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(id: &str, path: &str) -> NotarizationHistoryEntry {
+        NotarizationHistoryEntry {
+            submission_id: id.to_string(),
+            path: PathBuf::from(path),
+            submitted_at: "2024-01-01T00:00:00+00:00".to_string(),
+            status: None,
+            stapled: false,
+            cdhashes: vec![],
+        }
+    }
+
+    #[test]
+    fn history_roundtrip() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("nested").join(HISTORY_FILENAME);
+
+        assert_eq!(
+            NotarizationHistory::from_path(&path)?,
+            NotarizationHistory::default()
+        );
+
+        let mut history = NotarizationHistory::default();
+        history.record_submission(entry("a", "/dist/MyApp.dmg"));
+        history.write_path(&path)?;
+
+        assert_eq!(NotarizationHistory::from_path(&path)?, history);
+
+        Ok(())
+    }
+
+    #[test]
+    fn status_document() {
+        let mut history = NotarizationHistory::default();
+
+        assert!(history.status_json()["latest_submission_id"].is_null());
+        assert!(history.status_markdown().contains("No notarization"));
+
+        history.record_submission(entry("a", "/dist/MyApp.dmg"));
+        history.record_submission(entry("b", "/dist/MyApp.pkg"));
+        history.record_submission(entry("c", "/dist/MyApp.dmg"));
+
+        assert!(history.set_status("c", "accepted"));
+        assert!(!history.set_status("missing", "accepted"));
+        assert!(history.mark_stapled("/dist/MyApp.dmg"));
+
+        let doc = history.status_json();
+        assert_eq!(doc["latest_submission_id"], "c");
+        assert_eq!(doc["status"], "accepted");
+        assert_eq!(doc["stapled_artifacts"], serde_json::json!(["MyApp.dmg"]));
+        assert_eq!(doc["artifacts"].as_array().unwrap().len(), 2);
+
+        let md = history.status_markdown();
+        assert!(md.contains("Latest submission: `c`"));
+        assert!(md.contains("| MyApp.dmg | `c` | accepted | yes |  |"));
+        assert!(md.contains("| MyApp.pkg | `b` | unknown | no |  |"));
+    }
+}
@@ -0,0 +1,581 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Notarization ticket parsing.
+
+Notarization tickets are issued by Apple after a successful notarization and
+are stapled to the notarized entity. The format isn't documented by Apple.
+
+Tickets begin with a small header consisting of the magic `s8ch` and a
+version number. The remainder of the ticket is a sequence of DER encoded
+elements. One of them is a CMS signature attesting to the ticket's
+authenticity. The signature's content is either encapsulated within it or
+is the ticket data preceding the signature. The signed content holds the
+code directory hashes covered by the ticket as DER `OCTET STRING`s.
+
+This layout was reverse engineered. The `lookup_ticket` test in the
+`ticket_lookup` module checks it against a ticket issued by Apple.
+
+Only the header is required to parse. If the body doesn't have the expected
+structure, [NotarizationTicket::structure_error] records why and the ticket
+isn't considered to cover any code directory hash. Use
+[NotarizationTicket::verify()] to verify the signature and that it was made
+by a certificate chaining to Apple's root certificate authority.
+*/
+
+use {
+    crate::{certificate::AppleCertificate, AppleCodesignError},
+    cryptographic_message_syntax::SignedData,
+    serde::Serialize,
+    x509_certificate::CapturedX509Certificate,
+};
+
+/// Magic bytes at the beginning of a notarization ticket.
+pub const NOTARIZATION_TICKET_MAGIC: [u8; 4] = *b"s8ch";
+
+/// Size of the notarization ticket header.
+const HEADER_SIZE: usize = 8;
+
+/// Size of code directory hashes in ticket record names.
+const CDHASH_SIZE: usize = 20;
+
+/// DER tag of an `OCTET STRING`.
+const TAG_OCTET_STRING: u8 = 0x04;
+/// DER tag of a `SEQUENCE`.
+const TAG_SEQUENCE: u8 = 0x30;
+/// Bit set in DER tags of constructed values.
+const TAG_CONSTRUCTED: u8 = 0x20;
+
+/// A DER encoded element.
+struct DerElement<'a> {
+    tag: u8,
+    /// The content octets.
+    content: &'a [u8],
+    /// The full encoding, including tag and length.
+    encoded: &'a [u8],
+}
+
+/// Parse the DER element at the beginning of data.
+///
+/// Returns the element and the data following it.
+fn parse_der_element(data: &[u8]) -> Result<(DerElement<'_>, &[u8]), AppleCodesignError> {
+    let malformed = AppleCodesignError::NotarizationTicketMalformed;
+
+    let (&tag, rest) = data
+        .split_first()
+        .ok_or(malformed("DER element truncated"))?;
+    if tag & 0x1f == 0x1f {
+        return Err(malformed("unsupported DER tag"));
+    }
+
+    let (&length, mut rest) = rest
+        .split_first()
+        .ok_or(malformed("DER element truncated"))?;
+
+    let length = if length < 0x80 {
+        length as usize
+    } else {
+        let count = (length & 0x7f) as usize;
+        if count == 0 {
+            return Err(malformed("indefinite DER length"));
+        }
+        if count > 4 || count > rest.len() {
+            return Err(malformed("invalid DER length"));
+        }
+
+        let (bytes, remaining) = rest.split_at(count);
+        rest = remaining;
+
+        bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize)
+    };
+
+    if length > rest.len() {
+        return Err(malformed("DER element truncated"));
+    }
+
+    let (content, rest) = rest.split_at(length);
+    let encoded = &data[..data.len() - rest.len()];
+
+    Ok((
+        DerElement {
+            tag,
+            content,
+            encoded,
+        },
+        rest,
+    ))
+}
+
+/// Parse data consisting entirely of DER elements.
+fn parse_der_elements(mut data: &[u8]) -> Result<Vec<DerElement<'_>>, AppleCodesignError> {
+    let mut res = vec![];
+
+    while !data.is_empty() {
+        let (element, rest) = parse_der_element(data)?;
+        res.push(element);
+        data = rest;
+    }
+
+    Ok(res)
+}
+
+/// Collect the values of `OCTET STRING`s within DER elements, recursively.
+fn collect_octet_strings(
+    elements: &[DerElement],
+    res: &mut Vec<Vec<u8>>,
+) -> Result<(), AppleCodesignError> {
+    for element in elements {
+        if element.tag == TAG_OCTET_STRING {
+            res.push(element.content.to_vec());
+        } else if element.tag & TAG_CONSTRUCTED != 0 {
+            collect_octet_strings(&parse_der_elements(element.content)?, res)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The parsed body of a notarization ticket.
+struct TicketBody {
+    signed_data: SignedData,
+    certificates: Vec<CapturedX509Certificate>,
+    cdhashes: Vec<Vec<u8>>,
+    signed_content: Vec<u8>,
+}
+
+impl TicketBody {
+    /// Parse the body of ticket data, following its header.
+    fn parse(data: &[u8]) -> Result<Self, AppleCodesignError> {
+        let elements = parse_der_elements(&data[HEADER_SIZE..])?;
+
+        let mut signature = None;
+        let mut certificates = vec![];
+
+        for (index, element) in elements.iter().enumerate() {
+            if element.tag != TAG_SEQUENCE {
+                continue;
+            }
+
+            if let Ok(cert) = CapturedX509Certificate::from_der(element.encoded.to_vec()) {
+                certificates.push(cert);
+            } else if let Ok(sd) = SignedData::parse_ber(element.encoded) {
+                if signature.is_some() {
+                    return Err(AppleCodesignError::NotarizationTicketMalformed(
+                        "multiple CMS signatures",
+                    ));
+                }
+
+                signature = Some((index, sd));
+            }
+        }
+
+        let (signature_index, signed_data) = signature.ok_or(
+            AppleCodesignError::NotarizationTicketMalformed("no CMS signature"),
+        )?;
+
+        certificates.extend(signed_data.certificates().cloned());
+
+        // The signature covers either its encapsulated content or the ticket
+        // elements preceding it.
+        let mut cdhashes = vec![];
+        let signed_content = if let Some(content) = signed_data.signed_content() {
+            collect_octet_strings(&parse_der_elements(content)?, &mut cdhashes)?;
+
+            content.to_vec()
+        } else {
+            let end = data.len()
+                - elements[signature_index..]
+                    .iter()
+                    .map(|element| element.encoded.len())
+                    .sum::<usize>();
+
+            collect_octet_strings(&elements[..signature_index], &mut cdhashes)?;
+
+            data[..end].to_vec()
+        };
+
+        Ok(Self {
+            signed_data,
+            certificates,
+            cdhashes,
+            signed_content,
+        })
+    }
+}
+
+/// A parsed notarization ticket.
+pub struct NotarizationTicket<'a> {
+    /// The raw ticket data.
+    pub data: &'a [u8],
+
+    /// Ticket format version.
+    pub version: u32,
+
+    /// CMS signature of the ticket, if the body could be parsed.
+    pub signed_data: Option<SignedData>,
+
+    /// X.509 certificates found in the ticket.
+    ///
+    /// Includes certificates embedded in the CMS signature.
+    pub certificates: Vec<CapturedX509Certificate>,
+
+    /// Code directory hashes listed in the signed content of the ticket.
+    ///
+    /// Empty if the body couldn't be parsed.
+    pub cdhashes: Vec<Vec<u8>>,
+
+    /// Why the ticket body couldn't be parsed, if it couldn't.
+    pub structure_error: Option<String>,
+
+    /// The content signed by the CMS signature.
+    signed_content: Option<Vec<u8>>,
+}
+
+impl<'a> NotarizationTicket<'a> {
+    /// Parse notarization ticket data.
+    ///
+    /// This does not verify the ticket's signature. See [Self::verify()].
+    pub fn parse(data: &'a [u8]) -> Result<Self, AppleCodesignError> {
+        if data.len() < HEADER_SIZE {
+            return Err(AppleCodesignError::NotarizationTicketMalformed(
+                "data too short",
+            ));
+        }
+
+        if data[0..4] != NOTARIZATION_TICKET_MAGIC {
+            return Err(AppleCodesignError::NotarizationTicketMalformed(
+                "bad header magic",
+            ));
+        }
+
+        let version = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+
+        Ok(match TicketBody::parse(data) {
+            Ok(body) => Self {
+                data,
+                version,
+                signed_data: Some(body.signed_data),
+                certificates: body.certificates,
+                cdhashes: body.cdhashes,
+                structure_error: None,
+                signed_content: Some(body.signed_content),
+            },
+            Err(e) => Self {
+                data,
+                version,
+                signed_data: None,
+                certificates: vec![],
+                cdhashes: vec![],
+                structure_error: Some(e.to_string()),
+                signed_content: None,
+            },
+        })
+    }
+
+    /// Verify the cryptographic signature of the ticket.
+    ///
+    /// This only verifies that the signature is valid for the ticket content. It
+    /// does not verify who issued it. See [Self::verify()].
+    pub fn verify_signature(&self) -> Result<(), AppleCodesignError> {
+        let (Some(signed_data), Some(signed_content)) = (&self.signed_data, &self.signed_content)
+        else {
+            return Err(AppleCodesignError::NotarizationTicketMalformed(
+                "no CMS signature",
+            ));
+        };
+
+        let mut signers = 0;
+
+        for signer in signed_data.signers() {
+            signer.verify_signature_with_signed_data_and_content(
+                signed_data,
+                &signer.signed_content(Some(signed_content)),
+            )?;
+
+            if signer.signed_attributes().is_some() {
+                signer.verify_message_digest_with_content(signed_content)?;
+            }
+
+            signers += 1;
+        }
+
+        if signers == 0 {
+            Err(AppleCodesignError::NotarizationTicketMalformed(
+                "CMS signature has no signers",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether every signer's certificate chains to Apple's root certificate authority.
+    pub fn signed_by_apple(&self) -> bool {
+        let Some(signed_data) = &self.signed_data else {
+            return false;
+        };
+
+        let mut signers = signed_data.signers().peekable();
+
+        signers.peek().is_some()
+            && signers.all(|signer| {
+                signer
+                    .certificate_issuer_and_serial()
+                    .and_then(|(issuer, serial)| {
+                        self.certificates.iter().find(|cert| {
+                            cert.issuer_name() == issuer && cert.serial_number_asn1() == serial
+                        })
+                    })
+                    .map(|cert| cert.chains_to_apple_root_ca())
+                    .unwrap_or(false)
+            })
+    }
+
+    /// Verify the ticket was signed by Apple.
+    pub fn verify(&self) -> Result<(), AppleCodesignError> {
+        self.verify_signature()?;
+
+        if self.signed_by_apple() {
+            Ok(())
+        } else {
+            Err(AppleCodesignError::NotarizationTicketUntrusted)
+        }
+    }
+
+    /// Whether the ticket covers a code directory hash.
+    ///
+    /// Ticket record names use code directory digests truncated to 20 bytes.
+    /// So only the first 20 bytes of the passed digest are considered.
+    ///
+    /// Only code directory hashes found in the signed content count. This
+    /// doesn't verify the signature. See [Self::verify()].
+    pub fn covers_cdhash(&self, cdhash: &[u8]) -> bool {
+        let needle = &cdhash[0..cdhash.len().min(CDHASH_SIZE)];
+
+        !needle.is_empty() && self.cdhashes.iter().any(|cdhash| cdhash == needle)
+    }
+
+    /// Resolve the signing time of the ticket from its CMS signature.
+    pub fn signing_time(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.signed_data.as_ref().and_then(|sd| {
+            sd.signers()
+                .find_map(|signer| signer.signed_attributes()?.signing_time().copied())
+        })
+    }
+
+    /// Obtain a summary of this ticket, suitable for display.
+    ///
+    /// `cdhashes` are code directory hashes the ticket is expected to cover.
+    pub fn info(&self, cdhashes: &[Vec<u8>]) -> NotarizationTicketInfo {
+        let (covered, uncovered): (Vec<_>, Vec<_>) = cdhashes
+            .iter()
+            .partition(|cdhash| self.covers_cdhash(cdhash));
+
+        NotarizationTicketInfo {
+            size: self.data.len(),
+            version: self.version,
+            signing_time: self.signing_time().map(|t| t.to_rfc3339()),
+            structure_error: self.structure_error.clone(),
+            signature: match self.verify() {
+                Ok(()) => "valid".into(),
+                Err(e) => format!("unverified: {e}"),
+            },
+            certificates: self
+                .certificates
+                .iter()
+                .map(|cert| {
+                    cert.subject_common_name()
+                        .unwrap_or_else(|| "<unknown>".to_string())
+                })
+                .collect::<Vec<_>>(),
+            covered_cdhashes: covered.into_iter().map(hex::encode).collect::<Vec<_>>(),
+            uncovered_cdhashes: uncovered.into_iter().map(hex::encode).collect::<Vec<_>>(),
+        }
+    }
+}
+
+/// Describes the content of a notarization ticket.
+#[derive(Clone, Debug, Serialize)]
+pub struct NotarizationTicketInfo {
+    /// Size in bytes of the ticket.
+    pub size: usize,
+    /// Ticket format version.
+    pub version: u32,
+    /// Time the ticket was signed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_time: Option<String>,
+    /// Why the ticket body couldn't be parsed, if it couldn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structure_error: Option<String>,
+    /// Result of verifying the ticket's signature.
+    pub signature: String,
+    /// Common names of certificates in the ticket.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub certificates: Vec<String>,
+    /// Expected code directory hashes the ticket covers.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub covered_cdhashes: Vec<String>,
+    /// Expected code directory hashes the ticket does not cover.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uncovered_cdhashes: Vec<String>,
+}
+
+/// Resolve the code directory hash from a notarization ticket record name.
+///
+/// Record names have the form `2/<digest type>/<hex digest>`.
+pub fn cdhash_from_record_name(record_name: &str) -> Option<Vec<u8>> {
+    record_name
+        .rsplit('/')
+        .next()
+        .and_then(|digest| hex::decode(digest).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::certificate::{create_self_signed_code_signing_certificate, CertificateProfile},
+        cryptographic_message_syntax::{SignedDataBuilder, SignerBuilder},
+        x509_certificate::KeyAlgorithm,
+    };
+
+    /// DER encoding of a `SEQUENCE` of `OCTET STRING`s.
+    fn cdhashes_der(cdhashes: &[&[u8]]) -> Vec<u8> {
+        let mut content = vec![];
+        for cdhash in cdhashes {
+            content.push(TAG_OCTET_STRING);
+            content.push(cdhash.len() as u8);
+            content.extend_from_slice(cdhash);
+        }
+
+        let mut res = vec![TAG_SEQUENCE, 0x81, content.len() as u8];
+        res.extend(content);
+        res
+    }
+
+    fn header() -> Vec<u8> {
+        let mut data = NOTARIZATION_TICKET_MAGIC.to_vec();
+        data.extend(1u32.to_le_bytes());
+        data
+    }
+
+    fn signature(content: Vec<u8>, inline: bool) -> Result<Vec<u8>, AppleCodesignError> {
+        let (cert, key) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team",
+            "Joe Developer",
+            "US",
+            chrono::Duration::hours(1),
+        )?;
+
+        let builder = SignedDataBuilder::default().certificate(cert.clone());
+        let builder = if inline {
+            builder.content_inline(content)
+        } else {
+            builder.content_external(content)
+        };
+
+        Ok(builder.signer(SignerBuilder::new(&key, cert)).build_der()?)
+    }
+
+    #[test]
+    fn der_elements() {
+        assert!(parse_der_elements(&[0x04, 0x02, 0xaa]).is_err());
+        assert!(parse_der_elements(&[0x30, 0x80, 0x00, 0x00]).is_err());
+        assert!(parse_der_elements(&[0x04, 0x85, 0, 0, 0, 0, 1]).is_err());
+
+        let elements = parse_der_elements(&[0x04, 0x81, 0x01, 0xaa, 0x05, 0x00]).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].content, &[0xaa]);
+        assert_eq!(elements[0].encoded, &[0x04, 0x81, 0x01, 0xaa]);
+        assert_eq!(elements[1].tag, 0x05);
+    }
+
+    #[test]
+    fn parse_ticket() -> Result<(), AppleCodesignError> {
+        let covered = [0xaa; 20];
+
+        // Detached signature over the ticket data preceding it.
+        let mut detached = header();
+        detached.extend(cdhashes_der(&[&covered]));
+        let cms = signature(detached.clone(), false)?;
+        detached.extend(cms);
+
+        // Encapsulated content.
+        let mut inline = header();
+        inline.extend(signature(cdhashes_der(&[&covered]), true)?);
+
+        for data in [&detached, &inline] {
+            let ticket = NotarizationTicket::parse(data)?;
+            assert_eq!(ticket.version, 1);
+            assert_eq!(ticket.cdhashes, vec![covered.to_vec()]);
+            assert_eq!(ticket.certificates.len(), 1);
+            ticket.verify_signature()?;
+
+            // Our self-signed certificate isn't Apple's.
+            assert!(!ticket.signed_by_apple());
+            assert!(matches!(
+                ticket.verify(),
+                Err(AppleCodesignError::NotarizationTicketUntrusted)
+            ));
+
+            assert!(ticket.covers_cdhash(&[0xaa; 32]));
+            assert!(!ticket.covers_cdhash(&[0xaa; 10]));
+            assert!(!ticket.covers_cdhash(&[0xbb; 20]));
+
+            let info = ticket.info(&[vec![0xaa; 20], vec![0xbb; 20]]);
+            assert_eq!(info.covered_cdhashes, vec![hex::encode([0xaa; 20])]);
+            assert_eq!(info.uncovered_cdhashes, vec![hex::encode([0xbb; 20])]);
+        }
+
+        // Tampering with the signed content invalidates the signature.
+        let offset = detached
+            .windows(covered.len())
+            .position(|w| w == covered)
+            .unwrap();
+        let mut tampered = detached.clone();
+        tampered[offset] = 0xbb;
+        let ticket = NotarizationTicket::parse(&tampered)?;
+        assert!(ticket.verify_signature().is_err());
+
+        // Once cdhashes are found in the signed content, data outside an
+        // OCTET STRING isn't matched.
+        let other = [0xcc; 20];
+        let mut data = header();
+        data.extend(cdhashes_der(&[&covered]));
+        data.push(0x0c);
+        data.push(other.len() as u8);
+        data.extend(other);
+        let cms = signature(data.clone(), false)?;
+        data.extend(cms);
+        let ticket = NotarizationTicket::parse(&data)?;
+        assert!(ticket.structure_error.is_none());
+        assert!(ticket.covers_cdhash(&covered));
+        assert!(!ticket.covers_cdhash(&other));
+
+        // Without a signature, the body structure is unknown. The ticket still
+        // parses but doesn't cover anything, even cdhashes in its raw data.
+        let mut data = header();
+        data.extend(cdhashes_der(&[&covered]));
+        let ticket = NotarizationTicket::parse(&data)?;
+        assert!(ticket.structure_error.is_some());
+        assert!(ticket.signed_data.is_none());
+        assert!(!ticket.covers_cdhash(&covered));
+        assert!(ticket.verify().is_err());
+        assert!(ticket.info(&[]).signature.starts_with("unverified"));
+
+        // Likewise for a body that isn't DER.
+        let mut data = detached.clone();
+        data.push(0x30);
+        let ticket = NotarizationTicket::parse(&data)?;
+        assert!(ticket.structure_error.is_some());
+        assert!(!ticket.covers_cdhash(&covered));
+
+        assert!(NotarizationTicket::parse(b"bad magic").is_err());
+
+        assert_eq!(cdhash_from_record_name("2/2/0102"), Some(vec![0x01, 0x02]));
+
+        Ok(())
+    }
+}
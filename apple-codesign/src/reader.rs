@@ -10,10 +10,14 @@ use {
         code_directory::CodeDirectoryBlob,
         cryptography::DigestType,
         dmg::{path_is_dmg, DmgReader},
-        embedded_signature::{BlobEntry, EmbeddedSignature},
+        embedded_signature::{Blob, BlobEntry, CodeSigningSlot, EmbeddedSignature},
         embedded_signature_builder::{CD_DIGESTS_OID, CD_DIGESTS_PLIST_OID},
         error::{AppleCodesignError, Result},
         macho::{MachFile, MachOBinary},
+        notarization_ticket::{
+            cdhash_from_record_name, NotarizationTicket, NotarizationTicketInfo,
+        },
+        stapling::record_name_from_executable_bundle,
     },
     apple_bundles::{DirectoryBundle, DirectoryBundleFile},
     apple_xar::{
@@ -497,6 +501,8 @@ pub struct CodeSignature {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub code_requirements: Vec<String>,
     pub cms: Option<CmsSignature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notarization_ticket: Option<NotarizationTicketInfo>,
 }
 
 impl<'a> TryFrom<EmbeddedSignature<'a>> for CodeSignature {
@@ -511,6 +517,7 @@ impl<'a> TryFrom<EmbeddedSignature<'a>> for CodeSignature {
         let mut library_constraints = vec![];
         let mut code_requirements = vec![];
         let mut cms = None;
+        let mut notarization_ticket = None;
 
         let code_directory = if let Some(cd) = sig.code_directory()? {
             Some(CodeDirectory::try_from(*cd)?)
@@ -574,6 +581,20 @@ impl<'a> TryFrom<EmbeddedSignature<'a>> for CodeSignature {
             cms = Some(signed_data.try_into()?);
         }
 
+        // Stapled tickets are only present on DMGs, whose ticket record name is
+        // derived from the primary code directory.
+        if let Some(entry) = sig.find_slot(CodeSigningSlot::Ticket) {
+            if let Ok(ticket) = NotarizationTicket::parse(entry.payload()?) {
+                let mut cdhashes = vec![];
+
+                if let Some(cd) = sig.code_directory()? {
+                    cdhashes.push(cd.digest_with(cd.digest_type)?);
+                }
+
+                notarization_ticket = Some(ticket.info(&cdhashes));
+            }
+        }
+
         Ok(Self {
             superblob_length: format_integer(sig.length),
             blob_count: sig.count,
@@ -592,6 +613,7 @@ impl<'a> TryFrom<EmbeddedSignature<'a>> for CodeSignature {
             library_constraints,
             code_requirements,
             cms,
+            notarization_ticket,
         })
     }
 }
@@ -619,7 +641,7 @@ pub struct DmgEntity {
 #[derive(Clone, Debug, Serialize)]
 pub enum CodeSignatureFile {
     ResourcesXml(Vec<String>),
-    NotarizationTicket,
+    NotarizationTicket(Option<NotarizationTicketInfo>),
     Other,
 }
 
@@ -1055,8 +1077,23 @@ impl SignatureReader {
                 entities.push(default_entity);
             }
         } else if file_name == "CodeResources" {
-            default_entity.entity =
-                SignatureEntity::BundleCodeSignatureFile(CodeSignatureFile::NotarizationTicket);
+            let data = std::fs::read(file.absolute_path())?;
+
+            // The ticket should cover the main executable of the bundle.
+            let cdhashes = DirectoryBundle::new_from_path(&base_path)
+                .ok()
+                .and_then(|bundle| record_name_from_executable_bundle(&bundle).ok())
+                .and_then(|record_name| cdhash_from_record_name(&record_name))
+                .into_iter()
+                .collect::<Vec<_>>();
+
+            let info = NotarizationTicket::parse(&data)
+                .ok()
+                .map(|ticket| ticket.info(&cdhashes));
+
+            default_entity.entity = SignatureEntity::BundleCodeSignatureFile(
+                CodeSignatureFile::NotarizationTicket(info),
+            );
 
             entities.push(default_entity);
         } else {
@@ -0,0 +1,733 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Consistency checks between the artifacts of a release.
+
+A release of macOS software commonly consists of a signed and notarized
+`.app` bundle plus a `.dmg` and/or `.pkg` containing that bundle. Each
+artifact is notarized separately. A classic mistake is to notarize or
+package a different build of the application than the one being shipped.
+
+This module compares the artifacts of a release and reports whether they
+contain the same application and whether each artifact has a notarization
+ticket stapled that covers it.
+
+Content of `.pkg` installers and DMGs is compared bit-for-bit with the
+application bundle. DMG filesystems can only be inspected when they use FAT.
+The content of HFS+ and APFS images can't be verified, which is reported as a
+warning unless unverifiable images are explicitly allowed.
+
+Stapled tickets are checked for covering the code directory hash in the
+ticket's record name. Tickets whose structure or signature can't be verified
+are reported as a warning, since the ticket format isn't documented.
+*/
+
+use {
+    crate::{
+        bundle_signing::SignedMachOInfo,
+        cryptography::DigestType,
+        error::AppleCodesignError,
+        notarization_ticket::{cdhash_from_record_name, NotarizationTicket},
+        stapling::read_stapled_ticket,
+    },
+    apple_bundles::DirectoryBundle,
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    },
+};
+
+#[cfg(feature = "dmg")]
+use {
+    apple_dmg::{DmgFileType, DmgReader},
+    sha2::Digest,
+};
+
+#[cfg(feature = "pkg")]
+use {apple_flat_package::PkgReader, std::fs::File};
+
+#[cfg(any(feature = "dmg", feature = "pkg"))]
+use std::io::Read;
+
+/// Outcome of an individual check.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReleaseCheckStatus {
+    /// The check passed.
+    Pass,
+    /// The check found a problem that doesn't fail the release.
+    Warn,
+    /// The check failed.
+    Fail,
+    /// The check could not be performed.
+    Skipped,
+}
+
+/// The result of an individual check.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReleaseCheck {
+    /// Path of the artifact that was checked.
+    pub artifact: PathBuf,
+    /// Short name of the check.
+    pub check: &'static str,
+    /// Outcome of the check.
+    pub status: ReleaseCheckStatus,
+    /// Human readable explanation of the outcome.
+    pub message: String,
+}
+
+/// Results of checking the artifacts of a release.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReleaseCheckReport {
+    /// Version of the application (`CFBundleShortVersionString`).
+    pub app_version: Option<String>,
+    /// Build number of the application (`CFBundleVersion`).
+    pub app_build: Option<String>,
+    /// Code directory hash of the application's main executable.
+    pub app_cdhash: Option<String>,
+    /// Results of individual checks.
+    pub checks: Vec<ReleaseCheck>,
+}
+
+impl ReleaseCheckReport {
+    /// Whether no check failed.
+    pub fn is_ok(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|c| c.status == ReleaseCheckStatus::Fail)
+    }
+
+    fn push(
+        &mut self,
+        artifact: &Path,
+        check: &'static str,
+        status: ReleaseCheckStatus,
+        message: impl ToString,
+    ) {
+        self.checks.push(ReleaseCheck {
+            artifact: artifact.to_path_buf(),
+            check,
+            status,
+            message: message.to_string(),
+        });
+    }
+}
+
+/// A file within an application bundle.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum BundleEntry {
+    File { size: u64, sha256: Vec<u8> },
+    Symlink(String),
+}
+
+/// Files in an application bundle keyed by bundle relative path.
+type BundleSnapshot = BTreeMap<String, BundleEntry>;
+
+/// Bundle relative path of a stapled notarization ticket.
+///
+/// Bundles may be stapled after being packaged, so the ticket is ignored
+/// when comparing content.
+const STAPLED_TICKET_PATH: &str = "Contents/CodeResources";
+
+fn snapshot_directory(root: &Path) -> Result<BundleSnapshot, AppleCodesignError> {
+    let mut res = BundleSnapshot::new();
+
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+        let rel = entry
+            .path()
+            .strip_prefix(root)
+            .expect("walked path should be under root")
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        if entry.file_type().is_symlink() {
+            let target = std::fs::read_link(entry.path())?;
+            res.insert(
+                rel,
+                BundleEntry::Symlink(target.to_string_lossy().to_string()),
+            );
+        } else if entry.file_type().is_file() {
+            let data = std::fs::read(entry.path())?;
+            res.insert(
+                rel,
+                BundleEntry::File {
+                    size: data.len() as u64,
+                    sha256: DigestType::Sha256.digest_data(&data)?,
+                },
+            );
+        }
+    }
+
+    Ok(res)
+}
+
+/// Resolve the bundle relative path of a path in a package payload or image.
+///
+/// Returns `None` if the path isn't within a bundle named `bundle_name`.
+#[cfg_attr(not(any(feature = "dmg", feature = "pkg")), allow(dead_code))]
+fn bundle_relative_path(path: &str, bundle_name: &str) -> Option<String> {
+    let mut components = path.split('/').filter(|c| !c.is_empty() && *c != ".");
+
+    components.by_ref().find(|c| *c == bundle_name)?;
+
+    let rel = components.collect::<Vec<_>>().join("/");
+
+    if rel.is_empty() {
+        None
+    } else {
+        Some(rel)
+    }
+}
+
+#[cfg(feature = "pkg")]
+fn snapshot_pkg(path: &Path, bundle_name: &str) -> Result<BundleSnapshot, AppleCodesignError> {
+    let mut pkg = PkgReader::new(File::open(path)?)?;
+
+    let mut components = pkg.component_packages()?;
+    components.extend(pkg.root_component()?);
+
+    let mut res = BundleSnapshot::new();
+
+    for component in components {
+        let Some(mut payload) = component.payload_reader()? else {
+            continue;
+        };
+
+        while let Some(header) = payload
+            .read_next()
+            .map_err(apple_flat_package::Error::from)?
+        {
+            let Some(rel) = bundle_relative_path(header.name(), bundle_name) else {
+                continue;
+            };
+
+            let mut data = vec![];
+            payload.read_to_end(&mut data)?;
+
+            match header.mode() & 0o170000 {
+                0o120000 => {
+                    res.insert(
+                        rel,
+                        BundleEntry::Symlink(String::from_utf8_lossy(&data).to_string()),
+                    );
+                }
+                0o100000 => {
+                    res.insert(
+                        rel,
+                        BundleEntry::File {
+                            size: data.len() as u64,
+                            sha256: DigestType::Sha256.digest_data(&data)?,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(not(feature = "pkg"))]
+fn snapshot_pkg(_: &Path, _: &str) -> Result<BundleSnapshot, AppleCodesignError> {
+    Err(AppleCodesignError::FeatureDisabled(
+        "reading flat package installers",
+        "pkg",
+    ))
+}
+
+/// Describe differences between two bundle snapshots.
+fn compare_snapshots(expected: &BundleSnapshot, actual: &BundleSnapshot) -> Vec<String> {
+    let mut problems = vec![];
+
+    for (path, entry) in expected {
+        if path == STAPLED_TICKET_PATH {
+            continue;
+        }
+
+        match (entry, actual.get(path)) {
+            (_, None) => problems.push(format!("{path} is missing")),
+            (
+                BundleEntry::File { size, sha256 },
+                Some(BundleEntry::File {
+                    size: other_size,
+                    sha256: other_sha256,
+                }),
+            ) => {
+                if size != other_size {
+                    problems.push(format!("{path} is {other_size} bytes; expected {size}"));
+                } else if sha256 != other_sha256 {
+                    problems.push(format!("{path} content differs"));
+                }
+            }
+            (BundleEntry::Symlink(a), Some(BundleEntry::Symlink(b))) => {
+                if a != b {
+                    problems.push(format!("{path} points to {b}; expected {a}"));
+                }
+            }
+            (_, Some(_)) => problems.push(format!("{path} has a different file type")),
+        }
+    }
+
+    for path in actual.keys() {
+        if path != STAPLED_TICKET_PATH && !expected.contains_key(path) {
+            problems.push(format!("{path} is unexpected"));
+        }
+    }
+
+    problems
+}
+
+fn check_content(
+    report: &mut ReleaseCheckReport,
+    artifact: &Path,
+    expected: &BundleSnapshot,
+    actual: &BundleSnapshot,
+) {
+    if actual.is_empty() {
+        report.push(
+            artifact,
+            "content",
+            ReleaseCheckStatus::Fail,
+            "application bundle not found in artifact",
+        );
+        return;
+    }
+
+    let problems = compare_snapshots(expected, actual);
+
+    if problems.is_empty() {
+        report.push(
+            artifact,
+            "content",
+            ReleaseCheckStatus::Pass,
+            format!("{} files identical to application bundle", actual.len()),
+        );
+    } else {
+        report.push(
+            artifact,
+            "content",
+            ReleaseCheckStatus::Fail,
+            format!(
+                "content differs from application bundle: {}",
+                problems.join("; ")
+            ),
+        );
+    }
+}
+
+fn check_stapled(report: &mut ReleaseCheckReport, artifact: &Path) {
+    let stapled = match read_stapled_ticket(artifact) {
+        Ok(Some(stapled)) => stapled,
+        Ok(None) => {
+            report.push(
+                artifact,
+                "stapled",
+                ReleaseCheckStatus::Fail,
+                "no notarization ticket stapled",
+            );
+            return;
+        }
+        Err(e) => {
+            report.push(artifact, "stapled", ReleaseCheckStatus::Fail, e);
+            return;
+        }
+    };
+
+    let cdhash = stapled
+        .record_name
+        .as_deref()
+        .and_then(cdhash_from_record_name);
+
+    let ticket = match NotarizationTicket::parse(&stapled.data) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            report.push(
+                artifact,
+                "stapled",
+                ReleaseCheckStatus::Fail,
+                format!("ticket stapled but it could not be parsed: {e}"),
+            );
+            return;
+        }
+    };
+
+    let verified = match (&ticket.structure_error, ticket.verify()) {
+        (Some(e), _) => {
+            report.push(
+                artifact,
+                "ticket-signature",
+                ReleaseCheckStatus::Fail,
+                format!("stapled ticket has an unexpected structure: {e}"),
+            );
+            false
+        }
+        (None, Err(e)) => {
+            report.push(
+                artifact,
+                "ticket-signature",
+                ReleaseCheckStatus::Fail,
+                format!("unable to verify stapled ticket signature: {e}"),
+            );
+            false
+        }
+        (None, Ok(())) => {
+            report.push(
+                artifact,
+                "ticket-signature",
+                ReleaseCheckStatus::Pass,
+                "stapled ticket is signed by Apple",
+            );
+            true
+        }
+    };
+
+    let (status, message) = match cdhash {
+        None => (
+            ReleaseCheckStatus::Skipped,
+            "ticket stapled but the signature of the artifact could not be resolved".into(),
+        ),
+        Some(_) if !verified => (
+            ReleaseCheckStatus::Fail,
+            "stapled ticket can't be trusted to cover the artifact".into(),
+        ),
+        Some(cdhash) if ticket.covers_cdhash(&cdhash) => (
+            ReleaseCheckStatus::Pass,
+            format!("stapled ticket covers cdhash {}", hex::encode(cdhash)),
+        ),
+        Some(cdhash) => (
+            ReleaseCheckStatus::Fail,
+            format!(
+                "stapled ticket does not cover cdhash {}; was a different build notarized?",
+                hex::encode(cdhash)
+            ),
+        ),
+    };
+
+    report.push(artifact, "stapled", status, message);
+}
+
+#[cfg(feature = "dmg")]
+fn check_dmg(
+    report: &mut ReleaseCheckReport,
+    path: &Path,
+    bundle_name: &str,
+    expected: &BundleSnapshot,
+    allow_unverified: bool,
+) -> Result<(), AppleCodesignError> {
+    let mut actual = BundleSnapshot::new();
+    let mut unreadable = vec![];
+
+    let listings = DmgReader::open(path)?.list_files_with_content(|file, r| {
+        if let Some(rel) = bundle_relative_path(&file.path, bundle_name) {
+            let mut data = vec![];
+            r.read_to_end(&mut data)?;
+
+            actual.insert(
+                rel,
+                BundleEntry::File {
+                    size: data.len() as u64,
+                    sha256: sha2::Sha256::digest(&data).to_vec(),
+                },
+            );
+        }
+
+        Ok(())
+    })?;
+
+    for listing in listings {
+        if let Some(reason) = listing.unreadable_reason {
+            unreadable.push(format!("partition #{}: {}", listing.index, reason));
+        }
+
+        for file in listing.files {
+            let Some(rel) = bundle_relative_path(&file.path, bundle_name) else {
+                continue;
+            };
+
+            // Regular files were recorded when their content was read.
+            if let (DmgFileType::Symlink, Some(target)) = (file.file_type, file.symlink_target) {
+                actual.insert(rel, BundleEntry::Symlink(target));
+            }
+        }
+    }
+
+    if actual.is_empty() && !unreadable.is_empty() {
+        if allow_unverified {
+            report.push(
+                path,
+                "content",
+                ReleaseCheckStatus::Skipped,
+                format!("unable to read image content: {}", unreadable.join("; ")),
+            );
+        } else {
+            report.push(
+                path,
+                "content",
+                ReleaseCheckStatus::Warn,
+                format!(
+                    "unable to read image content: {}; only FAT filesystems can be read",
+                    unreadable.join("; ")
+                ),
+            );
+        }
+    } else {
+        check_content(report, path, expected, &actual);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "dmg"))]
+fn check_dmg(
+    _: &mut ReleaseCheckReport,
+    _: &Path,
+    _: &str,
+    _: &BundleSnapshot,
+    _: bool,
+) -> Result<(), AppleCodesignError> {
+    Err(AppleCodesignError::FeatureDisabled(
+        "reading DMG images",
+        "dmg",
+    ))
+}
+
+/// Check the artifacts of a release for consistency.
+///
+/// `app` is the path to the signed application bundle. `dmg` and `pkg` are
+/// optional paths to a disk image and flat package installer that should
+/// contain this bundle.
+///
+/// DMGs whose filesystem can't be read get a warning from the content check,
+/// unless `allow_unverified_dmg` is set, in which case the check is skipped.
+///
+/// Only errors reading the application bundle are returned as errors.
+/// Problems with other artifacts are recorded in the returned report.
+pub fn check_release(
+    app: &Path,
+    dmg: Option<&Path>,
+    pkg: Option<&Path>,
+    allow_unverified_dmg: bool,
+) -> Result<ReleaseCheckReport, AppleCodesignError> {
+    let bundle =
+        DirectoryBundle::new_from_path(app).map_err(AppleCodesignError::DirectoryBundle)?;
+    let bundle_name = app
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .ok_or_else(|| AppleCodesignError::CliGeneralError("invalid bundle path".into()))?;
+
+    let mut report = ReleaseCheckReport {
+        app_version: bundle
+            .info_plist_key_string("CFBundleShortVersionString")
+            .map_err(AppleCodesignError::DirectoryBundle)?,
+        app_build: bundle
+            .info_plist_key_string("CFBundleVersion")
+            .map_err(AppleCodesignError::DirectoryBundle)?,
+        ..Default::default()
+    };
+
+    if let Some(exe) = bundle
+        .files(false)
+        .map_err(AppleCodesignError::DirectoryBundle)?
+        .into_iter()
+        .find(|file| matches!(file.is_main_executable(), Ok(true)))
+    {
+        let data = std::fs::read(exe.absolute_path())?;
+
+        report.app_cdhash = SignedMachOInfo::parse_data(&data)
+            .and_then(|info| info.notarization_ticket_record_name())
+            .ok()
+            .and_then(|name| cdhash_from_record_name(&name))
+            .map(hex::encode);
+    }
+
+    if report.app_cdhash.is_none() {
+        report.push(
+            app,
+            "signed",
+            ReleaseCheckStatus::Fail,
+            "main executable is not signed",
+        );
+    }
+
+    check_stapled(&mut report, app);
+
+    let expected = snapshot_directory(app)?;
+
+    if let Some(path) = pkg {
+        match snapshot_pkg(path, &bundle_name) {
+            Ok(actual) => check_content(&mut report, path, &expected, &actual),
+            Err(e) => report.push(path, "content", ReleaseCheckStatus::Fail, e),
+        }
+
+        check_stapled(&mut report, path);
+    }
+
+    if let Some(path) = dmg {
+        if let Err(e) = check_dmg(
+            &mut report,
+            path,
+            &bundle_name,
+            &expected,
+            allow_unverified_dmg,
+        ) {
+            report.push(path, "content", ReleaseCheckStatus::Fail, e);
+        }
+
+        check_stapled(&mut report, path);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            macho_builder::MachOBuilder, notarization_ticket::NOTARIZATION_TICKET_MAGIC,
+            signing::UnifiedSigner, signing_settings::SigningSettings,
+        },
+    };
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            bundle_relative_path("./Applications/My.app/Contents/Info.plist", "My.app"),
+            Some("Contents/Info.plist".into())
+        );
+        assert_eq!(bundle_relative_path("Volume/My.app", "My.app"), None);
+        assert_eq!(bundle_relative_path("Volume/Other.app/foo", "My.app"), None);
+    }
+
+    #[test]
+    fn snapshot_differences() {
+        let file = |size, digest: u8| BundleEntry::File {
+            size,
+            sha256: vec![digest],
+        };
+
+        let expected = BundleSnapshot::from([
+            ("Contents/Info.plist".to_string(), file(10, 1)),
+            ("Contents/MacOS/app".to_string(), file(20, 2)),
+        ]);
+
+        assert!(compare_snapshots(&expected, &expected).is_empty());
+
+        let actual = BundleSnapshot::from([
+            ("Contents/Info.plist".to_string(), file(10, 3)),
+            ("Contents/extra".to_string(), file(1, 4)),
+        ]);
+
+        assert_eq!(
+            compare_snapshots(&expected, &actual),
+            vec![
+                "Contents/Info.plist content differs".to_string(),
+                "Contents/MacOS/app is missing".to_string(),
+                "Contents/extra is unexpected".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn stapled_bundle() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let app = td.path().join("My.app");
+        std::fs::create_dir_all(app.join("Contents/MacOS"))?;
+        std::fs::write(
+            app.join("Contents/Info.plist"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <plist version=\"1.0\"><dict>\
+             <key>CFBundleIdentifier</key><string>com.example.my</string>\
+             <key>CFBundlePackageType</key><string>APPL</string>\
+             <key>CFBundleExecutable</key><string>my</string>\
+             </dict></plist>\n",
+        )?;
+        std::fs::write(
+            app.join("Contents/MacOS/my"),
+            MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?,
+        )?;
+        UnifiedSigner::new(SigningSettings::default()).sign_path_in_place(&app)?;
+
+        assert!(read_stapled_ticket(&app)?.is_none());
+
+        let record_name = crate::stapling::record_name_from_executable_bundle(
+            &DirectoryBundle::new_from_path(&app).map_err(AppleCodesignError::DirectoryBundle)?,
+        )?;
+        let cdhash = cdhash_from_record_name(&record_name).unwrap();
+
+        let check = |ticket: &[u8]| -> Result<Vec<_>, AppleCodesignError> {
+            std::fs::write(app.join("Contents/CodeResources"), ticket)?;
+            let mut report = ReleaseCheckReport::default();
+            check_stapled(&mut report, &app);
+            Ok(report
+                .checks
+                .iter()
+                .map(|check| (check.check, check.status))
+                .collect())
+        };
+
+        // A ticket of unknown structure fails, even if its raw data contains
+        // the cdhash of the bundle.
+        let mut ticket = NOTARIZATION_TICKET_MAGIC.to_vec();
+        ticket.extend(1u32.to_le_bytes());
+        ticket.extend(&cdhash);
+        assert_eq!(
+            check(&ticket)?,
+            vec![
+                ("ticket-signature", ReleaseCheckStatus::Fail),
+                ("stapled", ReleaseCheckStatus::Fail),
+            ]
+        );
+
+        // Data that isn't a ticket fails.
+        assert_eq!(
+            check(b"garbage")?,
+            vec![("stapled", ReleaseCheckStatus::Fail)]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "dmg")]
+    #[test]
+    fn dmg_content() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let app = td.path().join("My.app");
+        std::fs::create_dir_all(app.join("Contents/MacOS"))?;
+        std::fs::write(app.join("Contents/MacOS/app"), b"good")?;
+        let expected = snapshot_directory(&app)?;
+
+        let check = |dmg: &Path, allow_unverified| -> Result<_, AppleCodesignError> {
+            let mut report = ReleaseCheckReport::default();
+            check_dmg(&mut report, dmg, "My.app", &expected, allow_unverified)?;
+            assert_eq!(report.checks.len(), 1);
+            Ok(report.checks[0].status)
+        };
+
+        let good = td.path().join("good.dmg");
+        apple_dmg::create_dmg(&app, &good, "My", 65536)?;
+        assert_eq!(check(&good, false)?, ReleaseCheckStatus::Pass);
+
+        // Content is compared, not just sizes.
+        std::fs::write(app.join("Contents/MacOS/app"), b"evil")?;
+        let bad = td.path().join("bad.dmg");
+        apple_dmg::create_dmg(&app, &bad, "My", 65536)?;
+        assert_eq!(check(&bad, false)?, ReleaseCheckStatus::Fail);
+
+        let mut hfs = vec![0; 4096];
+        hfs[1024..1026].copy_from_slice(b"H+");
+        let unreadable = td.path().join("hfs.dmg");
+        let mut writer = apple_dmg::DmgWriter::create(&unreadable)?;
+        writer.add_partition("Apple_HFS", &hfs)?;
+        writer.finish()?;
+        assert_eq!(check(&unreadable, false)?, ReleaseCheckStatus::Warn);
+        assert_eq!(check(&unreadable, true)?, ReleaseCheckStatus::Skipped);
+
+        Ok(())
+    }
+}
@@ -6,16 +6,22 @@
 
 use {
     crate::{
+        archive_signing::{ArchiveFormat, ArchiveSigner},
         bundle_signing::BundleSigner,
         dmg::DmgSigner,
         error::AppleCodesignError,
         macho_signing::{write_macho_file, MachOSigner},
-        reader::PathType,
+        reader::{path_is_macho, PathType},
         signing_settings::{SettingsScope, SigningSettings},
     },
+    apple_bundles::{normalize_identifier, validate_identifier, DirectoryBundle},
     apple_xar::{reader::XarReader, signing::XarSigner},
+    glob::Pattern,
     log::{info, warn},
-    std::{fs::File, path::Path},
+    std::{
+        fs::File,
+        path::{Path, PathBuf},
+    },
 };
 
 /// An entity for performing signing that is able to handle all supported target types.
@@ -42,7 +48,13 @@ impl<'key> UnifiedSigner<'key> {
             PathType::Dmg => self.sign_dmg(input_path, output_path),
             PathType::MachO => self.sign_macho(input_path, output_path),
             PathType::Xar => self.sign_xar(input_path, output_path),
-            PathType::Zip | PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
+            PathType::Zip | PathType::Other => {
+                if ArchiveFormat::from_path(input_path)?.is_some() {
+                    self.sign_archive(input_path, output_path)
+                } else {
+                    Err(AppleCodesignError::UnrecognizedPathType)
+                }
+            }
         }
     }
 
@@ -73,7 +85,10 @@ impl<'key> UnifiedSigner<'key> {
         settings.import_settings_from_macho(&macho_data)?;
 
         if settings.binary_identifier(SettingsScope::Main).is_none() {
-            let identifier = path_identifier(input_path)?;
+            let identifier = normalize_derived_identifier(
+                &path_identifier(input_path)?,
+                settings.normalize_identifiers(),
+            )?;
 
             warn!("setting binary identifier to {}", identifier);
             settings.set_binary_identifier(SettingsScope::Main, identifier);
@@ -112,12 +127,14 @@ impl<'key> UnifiedSigner<'key> {
                     AppleCodesignError::CliGeneralError("unable to resolve file name of DMG".into())
                 })?
                 .to_string_lossy();
+            let identifier =
+                normalize_derived_identifier(&file_name, settings.normalize_identifiers())?;
 
             warn!(
                 "setting binary identifier to {} (derived from file name)",
-                file_name
+                identifier
             );
-            settings.set_binary_identifier(SettingsScope::Main, file_name);
+            settings.set_binary_identifier(SettingsScope::Main, identifier);
         }
 
         // The DMG signer signs in place because it needs a `File` handle. So if
@@ -163,6 +180,57 @@ impl<'key> UnifiedSigner<'key> {
         Ok(())
     }
 
+    /// Re-sign a signed bundle in place after some of its files changed.
+    ///
+    /// `changed` holds paths relative to the bundle root that were modified,
+    /// added, or removed since the bundle was last signed. Nested bundles not
+    /// containing any of these paths are copied with their existing signature
+    /// and Mach-O binaries that didn't change aren't signed again. Bundles
+    /// enclosing a change have their main executable and `CodeResources`
+    /// regenerated so they seal the new content.
+    pub fn sign_bundle_changes_in_place(
+        &self,
+        path: impl AsRef<Path>,
+        changed: &[PathBuf],
+    ) -> Result<(), AppleCodesignError> {
+        let path = path.as_ref();
+
+        let mut settings = self.settings.clone();
+        for unchanged in unchanged_code_paths(path, changed)? {
+            settings.add_path_exclusion(&Pattern::escape(&unchanged))?;
+        }
+
+        warn!(
+            "re-signing {} after {} changed files",
+            path.display(),
+            changed.len()
+        );
+        let mut signer = BundleSigner::new_from_path(path)?;
+        signer.collect_nested_bundles()?;
+        signer.write_signed_bundle(path, &settings)?;
+
+        Ok(())
+    }
+
+    /// Sign the Mach-O binaries and bundles within a zip file or tarball.
+    pub fn sign_archive(
+        &self,
+        input_path: impl AsRef<Path>,
+        output_path: impl AsRef<Path>,
+    ) -> Result<(), AppleCodesignError> {
+        let input_path = input_path.as_ref();
+
+        let signer = ArchiveSigner::new(input_path)?;
+        warn!(
+            "signing content of {:?} archive {}",
+            signer.format(),
+            input_path.display()
+        );
+        signer.write_signed_archive(self, output_path)?;
+
+        Ok(())
+    }
+
     pub fn sign_xar(
         &self,
         input_path: impl AsRef<Path>,
@@ -192,6 +260,9 @@ impl<'key> UnifiedSigner<'key> {
             .signing_key()
             .ok_or(AppleCodesignError::XarNoAdhoc)?;
 
+        // Installer packages don't have an identifier.
+        self.settings.ensure_key_usage_allowed(None)?;
+
         {
             let reader = XarReader::new(File::open(input_path)?)?;
             let mut signer = XarSigner::new(reader);
@@ -291,9 +362,148 @@ pub fn path_identifier(path: impl AsRef<Path>) -> Result<String, AppleCodesignEr
     }
 }
 
+/// Resolve the identifier to use for an identifier derived from a file name.
+///
+/// File names can contain characters not allowed in identifiers. If `normalize`
+/// is set, such identifiers are normalized via [normalize_identifier]. Otherwise
+/// they are used as is.
+pub fn normalize_derived_identifier(
+    identifier: &str,
+    normalize: bool,
+) -> Result<String, AppleCodesignError> {
+    if validate_identifier(identifier).is_ok() {
+        return Ok(identifier.to_string());
+    }
+
+    if !normalize {
+        warn!(
+            "derived identifier {} contains characters not valid in identifiers; consider setting one via --binary-identifier or normalizing it via --normalize-identifiers",
+            identifier
+        );
+
+        return Ok(identifier.to_string());
+    }
+
+    let normalized = normalize_identifier(identifier).ok_or_else(|| {
+        AppleCodesignError::PathIdentifier(format!(
+            "unable to derive identifier from {}",
+            identifier
+        ))
+    })?;
+
+    warn!(
+        "normalized derived identifier {} to {} to only contain valid characters",
+        identifier, normalized
+    );
+
+    Ok(normalized)
+}
+
+/// Resolve bundle relative paths of code unaffected by changes to the given paths.
+///
+/// These are the nested bundles not containing a changed path and the Mach-O
+/// binaries that didn't change.
+fn unchanged_code_paths(
+    bundle_path: &Path,
+    changed: &[PathBuf],
+) -> Result<Vec<String>, AppleCodesignError> {
+    let bundle = DirectoryBundle::new_from_path(bundle_path)?;
+
+    let mut res = vec![];
+
+    for (rel, _) in bundle.nested_bundles(true)? {
+        if !changed.iter().any(|p| p.starts_with(&rel)) {
+            res.push(rel);
+        }
+    }
+
+    for file in bundle.files(true)? {
+        if file.absolute_path().is_symlink() || changed.iter().any(|p| p == file.relative_path()) {
+            continue;
+        }
+
+        if path_is_macho(file.absolute_path())? {
+            res.push(file.relative_path().to_string_lossy().to_string());
+        }
+    }
+
+    Ok(res)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+
+    #[test]
+    fn unchanged_code() -> Result<(), AppleCodesignError> {
+        let td = tempfile::TempDir::new()?;
+        let root = td.path().join("App.app");
+
+        let write_bundle = |path: &Path, identifier: &str| -> std::io::Result<()> {
+            std::fs::create_dir_all(path.join("Contents/MacOS"))?;
+            std::fs::write(
+                path.join("Contents/Info.plist"),
+                format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <plist version=\"1.0\"><dict>\
+                     <key>CFBundleIdentifier</key><string>{identifier}</string>\
+                     <key>CFBundlePackageType</key><string>APPL</string>\
+                     <key>CFBundleExecutable</key><string>main</string>\
+                     </dict></plist>\n"
+                ),
+            )?;
+            std::fs::write(path.join("Contents/MacOS/main"), [0xcf, 0xfa, 0xed, 0xfe])
+        };
+
+        write_bundle(&root, "com.example.app")?;
+        write_bundle(&root.join("Contents/Library/A.app"), "com.example.a")?;
+        write_bundle(&root.join("Contents/Library/B.app"), "com.example.b")?;
+        std::fs::create_dir_all(root.join("Contents/Resources"))?;
+        std::fs::write(root.join("Contents/Resources/data"), b"data")?;
+
+        let unchanged = |changed: &[&str]| -> Result<Vec<String>, AppleCodesignError> {
+            let changed = changed.iter().map(PathBuf::from).collect::<Vec<_>>();
+            let mut res = unchanged_code_paths(&root, &changed)?;
+            res.sort();
+            Ok(res)
+        };
+
+        // A resource change only affects the main bundle.
+        assert_eq!(
+            unchanged(&["Contents/Resources/data"])?,
+            vec![
+                "Contents/Library/A.app",
+                "Contents/Library/A.app/Contents/MacOS/main",
+                "Contents/Library/B.app",
+                "Contents/Library/B.app/Contents/MacOS/main",
+                "Contents/MacOS/main",
+            ]
+        );
+
+        // A change in a nested bundle marks it as changed.
+        assert_eq!(
+            unchanged(&["Contents/Library/A.app/Contents/MacOS/main"])?,
+            vec![
+                "Contents/Library/B.app",
+                "Contents/Library/B.app/Contents/MacOS/main",
+                "Contents/MacOS/main",
+            ]
+        );
+
+        // Removed files affect the bundle that contained them.
+        assert_eq!(
+            unchanged(&["Contents/Library/B.app/Contents/Resources/gone"])?,
+            vec![
+                "Contents/Library/A.app",
+                "Contents/Library/A.app/Contents/MacOS/main",
+                "Contents/Library/B.app/Contents/MacOS/main",
+                "Contents/MacOS/main",
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn path_identifier_normalization() {
         assert_eq!(path_identifier("foo").unwrap(), "foo");
@@ -320,4 +530,25 @@ mod test {
         assert_eq!(path_identifier("foo.1.2.dylib").unwrap(), "foo.1");
         assert_eq!(path_identifier("foo.1.dylib").unwrap(), "foo.1");
     }
+
+    #[test]
+    fn derived_identifier_normalization() {
+        assert_eq!(
+            normalize_derived_identifier("foo.1", true).unwrap(),
+            "foo.1"
+        );
+        assert_eq!(
+            normalize_derived_identifier("My App", true).unwrap(),
+            "My-App"
+        );
+        assert_eq!(normalize_derived_identifier(".foo", true).unwrap(), "foo");
+        assert!(normalize_derived_identifier("", true).is_err());
+
+        // Identifiers are only changed on request.
+        assert_eq!(
+            normalize_derived_identifier("My App", false).unwrap(),
+            "My App"
+        );
+        assert_eq!(normalize_derived_identifier(".foo", false).unwrap(), ".foo");
+    }
 }
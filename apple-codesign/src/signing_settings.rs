@@ -6,13 +6,14 @@
 
 use {
     crate::{
-        certificate::{AppleCertificate, CodeSigningCertificateExtension},
-        code_directory::CodeSignatureFlags,
+        certificate::{AppleCertificate, CertificateProfile, CodeSigningCertificateExtension},
+        code_directory::{CodeDirectoryVersion, CodeSignatureFlags},
         code_requirement::CodeRequirementExpression,
-        cryptography::DigestType,
+        cryptography::{DigestBackendType, DigestType},
         embedded_signature::{Blob, RequirementBlob},
         environment_constraints::EncodedEnvironmentConstraints,
         error::AppleCodesignError,
+        key_usage_policy::KeyUsagePolicy,
         macho::{parse_version_nibbles, MachFile},
     },
     glob::Pattern,
@@ -20,14 +21,18 @@ use {
         CpuType, CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_X86_64,
     },
     log::{error, info},
-    reqwest::{IntoUrl, Url},
     std::{
-        collections::{BTreeMap, BTreeSet},
+        collections::BTreeMap,
         fmt::Formatter,
+        path::{Path, PathBuf},
     },
+    url::Url,
     x509_certificate::{CapturedX509Certificate, KeyInfoSigner},
 };
 
+#[cfg(feature = "network")]
+use reqwest::IntoUrl;
+
 /// Denotes the scope for a setting.
 ///
 /// Settings have an associated scope defined by this type. This allows settings
@@ -230,6 +235,138 @@ pub enum DesignatedRequirementMode {
     Explicit(Vec<Vec<u8>>),
 }
 
+/// Operating system releases that signatures should remain compatible with.
+///
+/// By default, signatures are generated according to the targeting information
+/// of the entity being signed. This allows forcing an older signature format
+/// for all signed entities.
+///
+/// When targeting macOS 10.11, code directories only contain SHA-1 digests and
+/// don't use fields newer than team identifiers, such as the hardened runtime
+/// version. `CodeResources` files only contain SHA-1 digests. They still
+/// contain both the version 1 and version 2 rules and files, as usual.
+///
+/// When targeting macOS 10.8, code directories additionally don't use fields
+/// newer than scatter vectors and `CodeResources` files only contain version 1
+/// rules and files. Version 1 resource rules can't seal nested code or
+/// symlinks. macOS 10.9 and later require version 2 rules, so such signatures
+/// are rejected by newer releases.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum CompatibilityTarget {
+    /// Derive the signature format from the entity being signed
+    #[default]
+    Automatic,
+
+    /// Signatures compatible with macOS 10.11 and earlier
+    #[value(name = "macos-10.11")]
+    MacOs1011,
+
+    /// Signatures compatible with macOS 10.8 and earlier only
+    #[value(name = "macos-10.8")]
+    MacOs108,
+}
+
+impl CompatibilityTarget {
+    /// Whether this targets releases older than the entity being signed may require.
+    pub fn is_legacy(&self) -> bool {
+        *self != Self::Automatic
+    }
+
+    /// A human readable description of the targeted releases.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Automatic => "automatic",
+            Self::MacOs1011 => "macOS 10.11",
+            Self::MacOs108 => "macOS 10.8",
+        }
+    }
+
+    /// The newest code directory version the targeted releases support.
+    pub fn max_code_directory_version(&self) -> Option<CodeDirectoryVersion> {
+        match self {
+            Self::Automatic => None,
+            Self::MacOs1011 => Some(CodeDirectoryVersion::SupportsTeamId),
+            Self::MacOs108 => Some(CodeDirectoryVersion::SupportsScatter),
+        }
+    }
+
+    /// Whether `CodeResources` files should only contain version 1 rules and files.
+    pub fn version1_resources_only(&self) -> bool {
+        *self == Self::MacOs108
+    }
+}
+
+/// High-level presets describing how signed software will be distributed.
+///
+/// A preset bundles expectations about the signing certificate, time-stamp
+/// tokens, entitlements, and notarization that Apple enforces for a given
+/// distribution channel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum SigningPreset {
+    /// Software distributed outside the App Store and notarized by Apple
+    DeveloperId,
+
+    /// Software submitted to the App Store
+    AppStore,
+
+    /// Software signed for local development and testing
+    Development,
+}
+
+impl SigningPreset {
+    /// The name of this preset as used on the command line.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DeveloperId => "developer-id",
+            Self::AppStore => "app-store",
+            Self::Development => "development",
+        }
+    }
+
+    /// Certificate profiles acceptable for this preset, in order of preference.
+    ///
+    /// `installer` indicates whether a flat package installer is being signed.
+    pub fn certificate_profiles(&self, installer: bool) -> Vec<CertificateProfile> {
+        match (self, installer) {
+            (Self::DeveloperId, true) => vec![CertificateProfile::DeveloperIdInstaller],
+            (Self::DeveloperId, false) => vec![CertificateProfile::DeveloperIdApplication],
+            (Self::AppStore, true) => vec![CertificateProfile::MacInstallerDistribution],
+            (Self::AppStore, false) => vec![CertificateProfile::AppleDistribution],
+            (Self::Development, _) => vec![CertificateProfile::AppleDevelopment],
+        }
+    }
+
+    /// Whether signatures need a time-stamp token from a time-stamp server.
+    ///
+    /// Development signatures are never time-stamped.
+    pub fn requires_time_stamp(&self) -> bool {
+        matches!(self, Self::DeveloperId)
+    }
+
+    /// Whether software signed with this preset is expected to be notarized.
+    pub fn for_notarization(&self) -> bool {
+        matches!(self, Self::DeveloperId)
+    }
+
+    /// Whether executables must have the App Sandbox entitlement.
+    pub fn requires_app_sandbox(&self) -> bool {
+        matches!(self, Self::AppStore)
+    }
+
+    /// Whether the `get-task-allow` entitlement is permitted.
+    ///
+    /// Apple rejects distributed software that allows debuggers to attach.
+    pub fn allows_get_task_allow(&self) -> bool {
+        matches!(self, Self::Development)
+    }
+}
+
+impl std::fmt::Display for SigningPreset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Describes the type of a scoped setting.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ScopedSetting {
@@ -303,7 +440,13 @@ pub struct SigningSettings<'key> {
     signing_time: Option<chrono::DateTime<chrono::Utc>>,
     path_exclusion_patterns: Vec<Pattern>,
     shallow: bool,
+    normalize_identifiers: bool,
     for_notarization: bool,
+    preset: Option<SigningPreset>,
+    key_usage_policy: Option<KeyUsagePolicy>,
+    resource_digest_cache: Option<PathBuf>,
+    digest_backend: DigestBackendType,
+    compatibility_target: CompatibilityTarget,
 
     // Scope-specific settings.
     // These are BTreeMap so when we filter the keys, keys with higher precedence come
@@ -317,7 +460,7 @@ pub struct SigningSettings<'key> {
     runtime_version: BTreeMap<SettingsScope, semver::Version>,
     info_plist_data: BTreeMap<SettingsScope, Vec<u8>>,
     code_resources_data: BTreeMap<SettingsScope, Vec<u8>>,
-    extra_digests: BTreeMap<SettingsScope, BTreeSet<DigestType>>,
+    extra_digests: BTreeMap<SettingsScope, Vec<DigestType>>,
     launch_constraints_self: BTreeMap<SettingsScope, EncodedEnvironmentConstraints>,
     launch_constraints_parent: BTreeMap<SettingsScope, EncodedEnvironmentConstraints>,
     launch_constraints_responsible: BTreeMap<SettingsScope, EncodedEnvironmentConstraints>,
@@ -435,6 +578,10 @@ impl<'key> SigningSettings<'key> {
     /// the signing key-pair produced the cryptographic signature at a given time. It
     /// facilitates validation of the signing time via an independent (presumably trusted)
     /// entity.
+    ///
+    /// Only available with the `network` feature. Without it, signatures never contain
+    /// Time-Stamp Tokens.
+    #[cfg(feature = "network")]
     pub fn set_time_stamp_url(&mut self, url: impl IntoUrl) -> Result<(), AppleCodesignError> {
         self.time_stamp_url = Some(url.into_url()?);
 
@@ -527,6 +674,19 @@ impl<'key> SigningSettings<'key> {
         self.shallow = v;
     }
 
+    /// Whether to normalize invalid binary identifiers derived from file names.
+    ///
+    /// When not set, derived identifiers are used as is and a warning is emitted
+    /// if they aren't valid.
+    pub fn normalize_identifiers(&self) -> bool {
+        self.normalize_identifiers
+    }
+
+    /// Set whether to normalize invalid binary identifiers derived from file names.
+    pub fn set_normalize_identifiers(&mut self, v: bool) {
+        self.normalize_identifiers = v;
+    }
+
     /// Whether the signed asset will later be notarized.
     ///
     /// This serves as a hint to engage additional signing settings that are required
@@ -540,8 +700,111 @@ impl<'key> SigningSettings<'key> {
         self.for_notarization = v;
     }
 
+    /// The distribution preset signed software must conform to.
+    pub fn preset(&self) -> Option<SigningPreset> {
+        self.preset
+    }
+
+    /// Set the distribution preset signed software must conform to.
+    ///
+    /// The `developer-id` preset also engages notarization compatibility mode.
+    pub fn set_preset(&mut self, preset: Option<SigningPreset>) {
+        self.preset = preset;
+
+        if preset.map(|p| p.for_notarization()).unwrap_or_default() {
+            self.for_notarization = true;
+        }
+    }
+
+    /// Constraints on the use of the signing key.
+    pub fn key_usage_policy(&self) -> Option<&KeyUsagePolicy> {
+        self.key_usage_policy.as_ref()
+    }
+
+    /// Set constraints on the use of the signing key.
+    ///
+    /// Errors if the policy is malformed.
+    pub fn set_key_usage_policy(
+        &mut self,
+        policy: Option<KeyUsagePolicy>,
+    ) -> Result<(), AppleCodesignError> {
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+
+        self.key_usage_policy = policy;
+
+        Ok(())
+    }
+
+    /// Ensure the key usage policy allows signing an entity with the signing key.
+    ///
+    /// `identifier` is the identifier of the entity being signed. Entitlements
+    /// are taken from the main scope. This is a no-op when there is no signing key
+    /// or key usage policy.
+    ///
+    /// The check runs in the process creating the signature request. With a
+    /// remote signing key, the policy is advisory: the remote signer only
+    /// enforces its own policy.
+    pub fn ensure_key_usage_allowed(
+        &self,
+        identifier: Option<&str>,
+    ) -> Result<(), AppleCodesignError> {
+        if let (Some(_), Some(policy)) = (&self.signing_key, &self.key_usage_policy) {
+            // The actual time is used even when the signing time is overridden so the
+            // signing window can't be bypassed.
+            policy.check(
+                identifier,
+                self.entitlements_plist(SettingsScope::Main),
+                chrono::Utc::now(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Path to a file caching digests of bundle resource files.
+    ///
+    /// When set, digests of resource files are cached across signing operations,
+    /// keyed by the file's path, size, and modification time.
+    pub fn resource_digest_cache(&self) -> Option<&Path> {
+        self.resource_digest_cache.as_deref()
+    }
+
+    /// Set the path to a file caching digests of bundle resource files.
+    pub fn set_resource_digest_cache(&mut self, path: Option<PathBuf>) {
+        self.resource_digest_cache = path;
+    }
+
+    /// The implementation of digest algorithms used to digest signed content.
+    pub fn digest_backend(&self) -> DigestBackendType {
+        self.digest_backend
+    }
+
+    /// Set the implementation of digest algorithms used to digest signed content.
+    pub fn set_digest_backend(&mut self, backend: DigestBackendType) {
+        self.digest_backend = backend;
+    }
+
+    /// The operating system releases signatures should remain compatible with.
+    pub fn compatibility_target(&self) -> CompatibilityTarget {
+        self.compatibility_target
+    }
+
+    /// Set the operating system releases signatures should remain compatible with.
+    ///
+    /// Targeting older releases overrides digest settings: only SHA-1 digests
+    /// are emitted.
+    pub fn set_compatibility_target(&mut self, target: CompatibilityTarget) {
+        self.compatibility_target = target;
+    }
+
     /// Obtain the primary digest type to use.
     pub fn digest_type(&self, scope: impl AsRef<SettingsScope>) -> DigestType {
+        if self.compatibility_target.is_legacy() {
+            return DigestType::Sha1;
+        }
+
         self.digest_type
             .get(scope.as_ref())
             .copied()
@@ -829,7 +1092,14 @@ impl<'key> SigningSettings<'key> {
     }
 
     /// Obtain extra digests to include in signatures.
-    pub fn extra_digests(&self, scope: impl AsRef<SettingsScope>) -> Option<&BTreeSet<DigestType>> {
+    ///
+    /// Digests are in the order they were registered, which is the order
+    /// alternate code directories are emitted in.
+    pub fn extra_digests(&self, scope: impl AsRef<SettingsScope>) -> Option<&Vec<DigestType>> {
+        if self.compatibility_target.is_legacy() {
+            return None;
+        }
+
         self.extra_digests.get(scope.as_ref())
     }
 
@@ -842,11 +1112,14 @@ impl<'key> SigningSettings<'key> {
     /// A common use case for this is to have the primary digest contain a legacy
     /// digest type (namely SHA-1) but include stronger digests as well. This enables
     /// signatures to have compatibility with older operating systems but still be modern.
+    ///
+    /// Registering a digest that is already registered is a no-op.
     pub fn add_extra_digest(&mut self, scope: SettingsScope, digest_type: DigestType) {
-        self.extra_digests
-            .entry(scope)
-            .or_default()
-            .insert(digest_type);
+        let digests = self.extra_digests.entry(scope).or_default();
+
+        if !digests.contains(&digest_type) {
+            digests.push(digest_type);
+        }
     }
 
     /// Obtain all configured digests for a scope.
@@ -1273,7 +1546,13 @@ impl<'key> SigningSettings<'key> {
             team_id: self.team_id.clone(),
             path_exclusion_patterns: self.path_exclusion_patterns.clone(),
             shallow: self.shallow,
+            normalize_identifiers: self.normalize_identifiers,
             for_notarization: self.for_notarization,
+            preset: self.preset,
+            key_usage_policy: self.key_usage_policy.clone(),
+            resource_digest_cache: self.resource_digest_cache.clone(),
+            digest_backend: self.digest_backend,
+            compatibility_target: self.compatibility_target,
             digest_type: self
                 .digest_type
                 .clone()
@@ -1392,6 +1671,12 @@ impl<'key> SigningSettings<'key> {
 
         let mut have_error = false;
 
+        if self.compatibility_target.is_legacy() {
+            error!("--for-notarization requires the hardened runtime, which legacy compatibility targets don't support");
+            error!("hint: remove --compatibility-target");
+            have_error = true;
+        }
+
         if let Some((_, cert)) = self.signing_key() {
             if !cert.chains_to_apple_root_ca() && !cert.is_test_apple_signed_certificate() {
                 error!("--for-notarization requires use of an Apple-issued signing certificate; current certificate is not signed by Apple");
@@ -1424,6 +1709,113 @@ impl<'key> SigningSettings<'key> {
             Ok(())
         }
     }
+
+    /// Validate that global settings conform to the configured [SigningPreset].
+    ///
+    /// `installer` indicates whether a flat package installer is being signed.
+    ///
+    /// On error, logs errors at error level and returns an Err.
+    pub fn ensure_preset_settings(&self, installer: bool) -> Result<(), AppleCodesignError> {
+        let Some(preset) = self.preset else {
+            return Ok(());
+        };
+
+        let mut have_error = false;
+        let profiles = preset.certificate_profiles(installer);
+
+        if let Some((_, cert)) = self.signing_key() {
+            match cert.apple_guess_profile() {
+                Some(profile) if profiles.contains(&profile) => {}
+                Some(profile) => {
+                    error!(
+                        "--preset {} does not accept {} certificates",
+                        preset, profile
+                    );
+                    have_error = true;
+                }
+                None => {
+                    error!(
+                        "--preset {} requires an Apple-issued code signing certificate",
+                        preset
+                    );
+                    have_error = true;
+                }
+            }
+
+            if have_error {
+                error!(
+                    "hint: use a certificate of type {}",
+                    profiles
+                        .iter()
+                        .map(|p| format!("`{p}`"))
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                );
+            }
+
+            if preset.requires_time_stamp() && self.time_stamp_url().is_none() {
+                error!(
+                    "--preset {} requires use of a time-stamp protocol server; none configured",
+                    preset
+                );
+                have_error = true;
+            }
+        } else {
+            error!(
+                "--preset {} requires a signing certificate; none was provided",
+                preset
+            );
+            have_error = true;
+        }
+
+        if have_error {
+            Err(AppleCodesignError::PresetInvalidSettings(preset))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Validate that entitlements for the main scope conform to the configured [SigningPreset].
+    ///
+    /// `is_executable` indicates whether the entity being signed is a main executable.
+    /// Only executables are subject to App Sandbox requirements.
+    pub fn ensure_preset_entitlements(
+        &self,
+        is_executable: bool,
+    ) -> Result<(), AppleCodesignError> {
+        let Some(preset) = self.preset else {
+            return Ok(());
+        };
+
+        let entitlements = self
+            .entitlements_plist(SettingsScope::Main)
+            .and_then(|v| v.as_dictionary());
+        let enabled = |key: &str| {
+            entitlements
+                .and_then(|d| d.get(key))
+                .and_then(|v| v.as_boolean())
+                .unwrap_or_default()
+        };
+
+        if !preset.allows_get_task_allow() && enabled("com.apple.security.get-task-allow") {
+            return Err(AppleCodesignError::PresetEntitlements(
+                preset,
+                "com.apple.security.get-task-allow must not be enabled",
+            ));
+        }
+
+        if preset.requires_app_sandbox()
+            && is_executable
+            && !enabled("com.apple.security.app-sandbox")
+        {
+            return Err(AppleCodesignError::PresetEntitlements(
+                preset,
+                "executables must enable com.apple.security.app-sandbox",
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1675,6 +2067,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn preset_entitlements() -> Result<(), AppleCodesignError> {
+        const SANDBOX: &str = indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <plist version="1.0">
+            <dict>
+                <key>com.apple.security.app-sandbox</key>
+                <true/>
+            </dict>
+            </plist>
+        "#};
+        const SANDBOX_DEBUG: &str = indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <plist version="1.0">
+            <dict>
+                <key>com.apple.security.app-sandbox</key>
+                <true/>
+                <key>com.apple.security.get-task-allow</key>
+                <true/>
+            </dict>
+            </plist>
+        "#};
+
+        let mut settings = SigningSettings::default();
+        settings.set_preset(Some(SigningPreset::AppStore));
+        assert!(!settings.for_notarization());
+
+        assert!(settings.ensure_preset_entitlements(false).is_ok());
+        assert!(settings.ensure_preset_entitlements(true).is_err());
+
+        settings.set_entitlements_xml(SettingsScope::Main, SANDBOX)?;
+        assert!(settings.ensure_preset_entitlements(true).is_ok());
+
+        settings.set_entitlements_xml(SettingsScope::Main, SANDBOX_DEBUG)?;
+        assert!(settings.ensure_preset_entitlements(true).is_err());
+
+        settings.set_preset(Some(SigningPreset::Development));
+        assert!(settings.ensure_preset_entitlements(true).is_ok());
+
+        settings.set_preset(Some(SigningPreset::DeveloperId));
+        assert!(settings.for_notarization());
+        assert!(settings.ensure_preset_entitlements(true).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn for_notarization_handling() -> Result<(), AppleCodesignError> {
         let mut settings = SigningSettings::default();
@@ -1694,4 +2132,21 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn extra_digests_order() {
+        let mut settings = SigningSettings::default();
+        settings.add_extra_digest(SettingsScope::Main, DigestType::Sha384);
+        settings.add_extra_digest(SettingsScope::Main, DigestType::Sha1);
+        settings.add_extra_digest(SettingsScope::Main, DigestType::Sha384);
+
+        assert_eq!(
+            settings.extra_digests(SettingsScope::Main),
+            Some(&vec![DigestType::Sha384, DigestType::Sha1])
+        );
+        assert_eq!(
+            settings.all_digests(SettingsScope::Main),
+            vec![DigestType::Sha256, DigestType::Sha384, DigestType::Sha1]
+        );
+    }
 }
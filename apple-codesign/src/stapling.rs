@@ -15,16 +15,14 @@ use {
     crate::{
         bundle_signing::SignedMachOInfo,
         cryptography::DigestType,
-        dmg::{DmgReader, DmgSigner},
-        embedded_signature::Blob,
+        dmg::DmgReader,
+        embedded_signature::{Blob, CodeSigningSlot},
         reader::PathType,
-        ticket_lookup::{default_client, lookup_notarization_ticket},
         AppleCodesignError,
     },
     apple_bundles::DirectoryBundle,
     apple_xar::reader::XarReader,
-    log::{error, info, warn},
-    reqwest::blocking::Client,
+    log::{info, warn},
     scroll::{IOread, IOwrite, Pread, Pwrite, SizeWith},
     std::{
         fmt::Debug,
@@ -34,6 +32,16 @@ use {
     },
 };
 
+#[cfg(feature = "network")]
+use {
+    crate::{
+        dmg::DmgSigner,
+        ticket_lookup::{default_client, lookup_notarization_ticket},
+    },
+    log::error,
+    reqwest::blocking::Client,
+};
+
 /// Resolve the notarization ticket record name from a bundle.
 ///
 /// The record name is derived from the digest of the code directory of the
@@ -62,6 +70,43 @@ pub fn record_name_from_executable_bundle(
     Ok(record_name)
 }
 
+/// Resolve the notarization ticket record name from a DMG.
+///
+/// The record name is derived from the code directory digest from the signature
+/// in the DMG.
+pub fn record_name_from_dmg(dmg: &DmgReader) -> Result<String, AppleCodesignError> {
+    let signature = dmg
+        .embedded_signature()?
+        .ok_or(AppleCodesignError::DmgStapleNoSignature)?;
+    let cd = signature
+        .code_directory()?
+        .ok_or(AppleCodesignError::DmgStapleNoSignature)?;
+
+    let mut digest = cd.digest_with(cd.digest_type)?;
+    digest.truncate(20);
+    let digest = hex::encode(digest);
+
+    let digest_type: u8 = cd.digest_type.into();
+
+    Ok(format!("2/{digest_type}/{digest}"))
+}
+
+/// Resolve the notarization ticket record name from a XAR archive.
+///
+/// The record name is derived from the checksum of the table of contents.
+pub fn record_name_from_xar<R: Read + Seek + Sized + Debug>(
+    reader: &mut XarReader<R>,
+) -> Result<String, AppleCodesignError> {
+    let mut digest = reader.checksum_data()?;
+    digest.truncate(20);
+    let digest = hex::encode(digest);
+
+    let digest_type = DigestType::try_from(reader.table_of_contents().checksum.style)?;
+    let digest_type: u8 = digest_type.into();
+
+    Ok(format!("2/{digest_type}/{digest}"))
+}
+
 /// Staple a ticket to a bundle as defined by the path to a directory.
 ///
 /// Stapling a bundle (e.g. `MyApp.app`) is literally just writing a
@@ -132,11 +177,100 @@ pub fn xar_notarization_trailer(ticket_data: &[u8]) -> Result<Vec<u8>, AppleCode
     Ok(cursor.into_inner())
 }
 
+/// A notarization ticket stapled to an entity.
+pub struct StapledTicket {
+    /// The raw ticket data.
+    pub data: Vec<u8>,
+
+    /// The record name of the entity the ticket is stapled to.
+    ///
+    /// `None` if it could not be resolved.
+    pub record_name: Option<String>,
+}
+
+/// Read the notarization ticket stapled to an entity at a filesystem path.
+///
+/// Returns `Ok(None)` if no ticket is stapled or if the entity type doesn't
+/// support stapling.
+pub fn read_stapled_ticket(
+    path: impl AsRef<Path>,
+) -> Result<Option<StapledTicket>, AppleCodesignError> {
+    let path = path.as_ref();
+
+    match PathType::from_path(path)? {
+        PathType::Bundle => {
+            let bundle = DirectoryBundle::new_from_path(path)
+                .map_err(AppleCodesignError::DirectoryBundle)?;
+            let ticket_path = bundle.resolve_path("CodeResources");
+
+            if !ticket_path.is_file() {
+                return Ok(None);
+            }
+
+            Ok(Some(StapledTicket {
+                data: std::fs::read(ticket_path)?,
+                record_name: record_name_from_executable_bundle(&bundle).ok(),
+            }))
+        }
+        PathType::Dmg => {
+            let dmg = DmgReader::new(&mut File::open(path)?)?;
+
+            let mut data = None;
+            if let Some(signature) = dmg.embedded_signature()? {
+                if let Some(entry) = signature.find_slot(CodeSigningSlot::Ticket) {
+                    data = Some(entry.payload()?.to_vec());
+                }
+            }
+
+            Ok(data.map(|data| StapledTicket {
+                data,
+                record_name: record_name_from_dmg(&dmg).ok(),
+            }))
+        }
+        PathType::Xar => {
+            let mut fh = File::open(path)?;
+
+            let trailer_size = std::mem::size_of::<XarNotarizationTrailer>();
+            if fh.metadata()?.len() < trailer_size as u64 {
+                return Ok(None);
+            }
+
+            fh.seek(SeekFrom::End(-(trailer_size as i64)))?;
+            let trailer = fh.ioread_with::<XarNotarizationTrailer>(scroll::LE)?;
+
+            if trailer.magic != XAR_NOTARIZATION_TRAILER_MAGIC
+                || trailer.typ != XarNotarizationTrailerType::Ticket as u16
+            {
+                return Ok(None);
+            }
+
+            fh.seek(SeekFrom::End(
+                -(trailer_size as i64) - trailer.length as i64,
+            ))?;
+            let mut data = vec![0u8; trailer.length as usize];
+            fh.read_exact(&mut data)?;
+
+            let mut xar = XarReader::new(File::open(path)?)?;
+
+            Ok(Some(StapledTicket {
+                data,
+                record_name: record_name_from_xar(&mut xar).ok(),
+            }))
+        }
+        PathType::MachO | PathType::Zip | PathType::Other => Ok(None),
+    }
+}
+
 /// Handles stapling operations.
+///
+/// Tickets are looked up from Apple's servers, so this requires the `network`
+/// feature.
+#[cfg(feature = "network")]
 pub struct Stapler {
     client: Client,
 }
 
+#[cfg(feature = "network")]
 impl Stapler {
     /// Construct a new instance with defaults.
     pub fn new() -> Result<Self, AppleCodesignError> {
@@ -186,22 +320,7 @@ impl Stapler {
 
     /// Look up ticket data for DMG file.
     pub fn lookup_ticket_for_dmg(&self, dmg: &DmgReader) -> Result<Vec<u8>, AppleCodesignError> {
-        // The ticket is derived from the code directory digest from the signature in the
-        // DMG.
-        let signature = dmg
-            .embedded_signature()?
-            .ok_or(AppleCodesignError::DmgStapleNoSignature)?;
-        let cd = signature
-            .code_directory()?
-            .ok_or(AppleCodesignError::DmgStapleNoSignature)?;
-
-        let mut digest = cd.digest_with(cd.digest_type)?;
-        digest.truncate(20);
-        let digest = hex::encode(digest);
-
-        let digest_type: u8 = cd.digest_type.into();
-
-        let record_name = format!("2/{digest_type}/{digest}");
+        let record_name = record_name_from_dmg(dmg)?;
 
         let response = lookup_notarization_ticket(&self.client, &record_name)?;
 
@@ -232,14 +351,7 @@ impl Stapler {
         &self,
         reader: &mut XarReader<R>,
     ) -> Result<Vec<u8>, AppleCodesignError> {
-        let mut digest = reader.checksum_data()?;
-        digest.truncate(20);
-        let digest = hex::encode(digest);
-
-        let digest_type = DigestType::try_from(reader.table_of_contents().checksum.style)?;
-        let digest_type: u8 = digest_type.into();
-
-        let record_name = format!("2/{digest_type}/{digest}");
+        let record_name = record_name_from_xar(reader)?;
 
         let response = lookup_notarization_ticket(&self.client, &record_name)?;
 
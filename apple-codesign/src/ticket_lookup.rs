@@ -216,7 +216,10 @@ pub fn lookup_notarization_ticket(
 
 #[cfg(test)]
 mod test {
-    use super::*;
+    use {
+        super::*,
+        crate::notarization_ticket::{cdhash_from_record_name, NotarizationTicket},
+    };
 
     const PYOXIDIZER_APP_RECORD: &str = "2/2/1b747faf223750de74febed7929f14a73af8c933";
     const DEADBEEF: &str = "2/2/deadbeefdeadbeefdeadbeefdeadbeefdeadbeef";
@@ -235,6 +238,19 @@ mod test {
         let ticket = res.signed_ticket(PYOXIDIZER_APP_RECORD)?;
         assert_eq!(&ticket[0..4], b"s8ch");
 
+        // Check ticket parsing against a real ticket issued by Apple.
+        let parsed = NotarizationTicket::parse(&ticket)?;
+        assert!(
+            parsed.structure_error.is_none(),
+            "{:?}",
+            parsed.structure_error
+        );
+        assert!(parsed.signing_time().is_some());
+        parsed.verify()?;
+        let cdhash = cdhash_from_record_name(PYOXIDIZER_APP_RECORD).unwrap();
+        assert!(parsed.covers_cdhash(&cdhash));
+        assert!(!parsed.covers_cdhash(&[0xde, 0xad, 0xbe, 0xef]));
+
         let res = lookup_notarization_ticket(&client, DEADBEEF)?;
         assert!(matches!(
             &res.records[0],
@@ -5,7 +5,7 @@
 //! Code signing verification.
 //!
 //! This module implements functionality for verifying code signatures on
-//! Mach-O binaries.
+//! Mach-O binaries and the resources sealed by bundle signatures.
 //!
 //! # Verification Caveats
 //!
@@ -24,15 +24,32 @@
 use {
     crate::{
         code_directory::CodeDirectoryBlob,
+        code_resources::{CodeResources, SealDifference},
         embedded_signature::{CodeSigningSlot, EmbeddedSignature},
         error::AppleCodesignError,
         macho::{MachFile, MachOBinary},
     },
+    apple_bundles::DirectoryBundle,
     cryptographic_message_syntax::{CmsError, SignedData},
+    rand::seq::index::sample,
     std::path::PathBuf,
     x509_certificate::{DigestAlgorithm, SignatureAlgorithm},
 };
 
+/// Controls how many code pages are digested when verifying code directories.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CodeDigestVerification {
+    /// Digest every code page.
+    #[default]
+    Full,
+
+    /// Digest a random sample of this many code pages.
+    ///
+    /// The first and last pages are always included. This is much faster for
+    /// large binaries but can miss modifications to pages outside the sample.
+    Sample(usize),
+}
+
 /// Context for a verification issue.
 #[derive(Clone, Debug)]
 pub struct VerificationContext {
@@ -41,6 +58,11 @@ pub struct VerificationContext {
 
     /// Index of Mach-O binary within a fat binary that is problematic.
     pub fat_index: Option<usize>,
+
+    /// Slot of the code directory that is problematic.
+    ///
+    /// Only set for alternate code directories.
+    pub code_directory: Option<CodeSigningSlot>,
 }
 
 /// Describes a problem with verification.
@@ -65,6 +87,9 @@ pub enum VerificationProblemType {
     ExtraSlotDigest(CodeSigningSlot, Vec<u8>),
     SlotDigestMismatch(CodeSigningSlot, Vec<u8>, Vec<u8>),
     SlotDigestError(AppleCodesignError),
+    NoCodeResources,
+    CodeResourcesError(AppleCodesignError),
+    ResourceSealMismatch(SealDifference),
 }
 
 #[derive(Debug)]
@@ -82,6 +107,12 @@ impl std::fmt::Display for VerificationProblem {
             (Some(path), Some(index)) => Some(format!("{}@{}", path.display(), index)),
         };
 
+        let context = match (context, &self.context.code_directory) {
+            (context, None) => context,
+            (None, Some(slot)) => Some(format!("{slot:?}")),
+            (Some(context), Some(slot)) => Some(format!("{context} ({slot:?})")),
+        };
+
         let message = match &self.problem {
             VerificationProblemType::IoError(e) => format!("I/O error: {e}"),
             VerificationProblemType::MachOParseError(e) => format!("Mach-O parse failure: {e}"),
@@ -154,6 +185,15 @@ impl std::fmt::Display for VerificationProblem {
             VerificationProblemType::SlotDigestError(e) => {
                 format!("error computing slot digest: {e:?}")
             }
+            VerificationProblemType::NoCodeResources => {
+                "bundle has no _CodeSignature/CodeResources file".to_string()
+            }
+            VerificationProblemType::CodeResourcesError(e) => {
+                format!("error verifying sealed resources: {e}")
+            }
+            VerificationProblemType::ResourceSealMismatch(difference) => {
+                format!("sealed resource mismatch: {difference}")
+            }
         };
 
         match context {
@@ -163,6 +203,52 @@ impl std::fmt::Display for VerificationProblem {
     }
 }
 
+/// Verifies the resources sealed by a bundle's `_CodeSignature/CodeResources` file.
+///
+/// Every sealed resource that was added, removed, or modified since the bundle
+/// was signed is reported as a separate problem.
+///
+/// This does not verify the signatures of Mach-O binaries within the bundle.
+pub fn verify_bundle_resources(bundle: &DirectoryBundle) -> Vec<VerificationProblem> {
+    let context = VerificationContext {
+        path: Some(bundle.root_dir().to_path_buf()),
+        fat_index: None,
+        code_directory: None,
+    };
+
+    let resources_path = bundle.resolve_path("_CodeSignature/CodeResources");
+
+    let data = match std::fs::read(resources_path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::NoCodeResources,
+            }];
+        }
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::IoError(e),
+            }];
+        }
+    };
+
+    match CodeResources::from_xml(&data).and_then(|resources| resources.diff_bundle(bundle)) {
+        Ok(differences) => differences
+            .into_iter()
+            .map(|difference| VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::ResourceSealMismatch(difference),
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => vec![VerificationProblem {
+            context,
+            problem: VerificationProblemType::CodeResourcesError(e),
+        }],
+    }
+}
+
 /// Verifies unparsed Mach-O data.
 ///
 /// Returns a vector of problems detected. An empty vector means no
@@ -171,14 +257,33 @@ pub fn verify_macho_data(data: impl AsRef<[u8]>) -> Vec<VerificationProblem> {
     let context = VerificationContext {
         path: None,
         fat_index: None,
+        code_directory: None,
     };
 
-    verify_macho_data_internal(data, context)
+    verify_macho_data_internal(data, context, CodeDigestVerification::Full)
+}
+
+/// Verifies unparsed Mach-O data, controlling how code pages are verified.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_macho_data_with_mode(
+    data: impl AsRef<[u8]>,
+    mode: CodeDigestVerification,
+) -> Vec<VerificationProblem> {
+    let context = VerificationContext {
+        path: None,
+        fat_index: None,
+        code_directory: None,
+    };
+
+    verify_macho_data_internal(data, context, mode)
 }
 
 fn verify_macho_data_internal(
     data: impl AsRef<[u8]>,
     context: VerificationContext,
+    mode: CodeDigestVerification,
 ) -> Vec<VerificationProblem> {
     match MachFile::parse(data.as_ref()) {
         Ok(mach) => {
@@ -188,7 +293,7 @@ fn verify_macho_data_internal(
                 let mut context = context.clone();
                 context.fat_index = macho.index;
 
-                problems.extend(verify_macho_internal(&macho, context));
+                problems.extend(verify_macho_internal(&macho, context, mode));
             }
 
             problems
@@ -207,18 +312,32 @@ fn verify_macho_data_internal(
 /// Returns a vector of problems detected. An empty vector means no
 /// problems were found.
 pub fn verify_macho(macho: &MachOBinary) -> Vec<VerificationProblem> {
+    verify_macho_with_mode(macho, CodeDigestVerification::Full)
+}
+
+/// Verifies a parsed Mach-O binary, controlling how code pages are verified.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_macho_with_mode(
+    macho: &MachOBinary,
+    mode: CodeDigestVerification,
+) -> Vec<VerificationProblem> {
     verify_macho_internal(
         macho,
         VerificationContext {
             path: None,
             fat_index: None,
+            code_directory: None,
         },
+        mode,
     )
 }
 
 fn verify_macho_internal(
     macho: &MachOBinary,
     context: VerificationContext,
+    mode: CodeDigestVerification,
 ) -> Vec<VerificationProblem> {
     let signature_data = match macho.find_signature_data() {
         Ok(Some(data)) => data,
@@ -288,14 +407,39 @@ fn verify_macho_internal(
 
     match signature.code_directory() {
         Ok(Some(cd)) => {
-            problems.extend(verify_code_directory(macho, &signature, &cd, context));
+            problems.extend(verify_code_directory(
+                macho,
+                &signature,
+                &cd,
+                context.clone(),
+                mode,
+            ));
         }
         Ok(None) => {
             problems.push(VerificationProblem {
-                context,
+                context: context.clone(),
                 problem: VerificationProblemType::NoCodeDirectory,
             });
         }
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::MachOSignatureError(e),
+            });
+        }
+    }
+
+    // Alternate code directories are verified independently because some consumers
+    // only consult the code directory having a specific digest type.
+    match signature.alternate_code_directories() {
+        Ok(cds) => {
+            for (slot, cd) in cds {
+                let mut context = context.clone();
+                context.code_directory = Some(slot);
+
+                problems.extend(verify_code_directory(macho, &signature, &cd, context, mode));
+            }
+        }
         Err(e) => {
             problems.push(VerificationProblem {
                 context,
@@ -376,57 +520,16 @@ fn verify_code_directory(
     signature: &EmbeddedSignature,
     cd: &CodeDirectoryBlob,
     context: VerificationContext,
+    mode: CodeDigestVerification,
 ) -> Vec<VerificationProblem> {
     let mut problems = vec![];
 
-    match macho.code_digests(cd.digest_type, cd.page_size as _) {
-        Ok(digests) => {
-            let mut cd_iter = cd.code_digests.iter().enumerate();
-            let mut actual_iter = digests.iter().enumerate();
-
-            loop {
-                match (cd_iter.next(), actual_iter.next()) {
-                    (None, None) => {
-                        break;
-                    }
-                    (Some((cd_index, cd_digest)), Some((_, actual_digest))) => {
-                        if &cd_digest.data != actual_digest {
-                            problems.push(VerificationProblem {
-                                context: context.clone(),
-                                problem: VerificationProblemType::CodeDigestMismatch(
-                                    cd_index,
-                                    cd_digest.to_vec(),
-                                    actual_digest.clone(),
-                                ),
-                            });
-                        }
-                    }
-                    (None, Some((actual_index, actual_digest))) => {
-                        problems.push(VerificationProblem {
-                            context: context.clone(),
-                            problem: VerificationProblemType::CodeDigestMissingEntry(
-                                actual_index,
-                                actual_digest.clone(),
-                            ),
-                        });
-                    }
-                    (Some((cd_index, cd_digest)), None) => {
-                        problems.push(VerificationProblem {
-                            context: context.clone(),
-                            problem: VerificationProblemType::CodeDigestExtraEntry(
-                                cd_index,
-                                cd_digest.to_vec(),
-                            ),
-                        });
-                    }
-                }
-            }
+    match mode {
+        CodeDigestVerification::Full => {
+            problems.extend(verify_code_digests_full(macho, cd, &context));
         }
-        Err(e) => {
-            problems.push(VerificationProblem {
-                context: context.clone(),
-                problem: VerificationProblemType::CodeDigestError(e),
-            });
+        CodeDigestVerification::Sample(count) => {
+            problems.extend(verify_code_digests_sample(macho, cd, &context, count));
         }
     }
 
@@ -508,3 +611,283 @@ fn verify_code_directory(
 
     problems
 }
+
+/// Verify every code digest in a code directory.
+fn verify_code_digests_full(
+    macho: &MachOBinary,
+    cd: &CodeDirectoryBlob,
+    context: &VerificationContext,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+
+    match macho.code_digests(cd.digest_type, cd.page_size as _) {
+        Ok(digests) => {
+            let mut cd_iter = cd.code_digests.iter().enumerate();
+            let mut actual_iter = digests.iter().enumerate();
+
+            loop {
+                match (cd_iter.next(), actual_iter.next()) {
+                    (None, None) => {
+                        break;
+                    }
+                    (Some((cd_index, cd_digest)), Some((_, actual_digest))) => {
+                        if &cd_digest.data != actual_digest {
+                            problems.push(VerificationProblem {
+                                context: context.clone(),
+                                problem: VerificationProblemType::CodeDigestMismatch(
+                                    cd_index,
+                                    cd_digest.to_vec(),
+                                    actual_digest.clone(),
+                                ),
+                            });
+                        }
+                    }
+                    (None, Some((actual_index, actual_digest))) => {
+                        problems.push(VerificationProblem {
+                            context: context.clone(),
+                            problem: VerificationProblemType::CodeDigestMissingEntry(
+                                actual_index,
+                                actual_digest.clone(),
+                            ),
+                        });
+                    }
+                    (Some((cd_index, cd_digest)), None) => {
+                        problems.push(VerificationProblem {
+                            context: context.clone(),
+                            problem: VerificationProblemType::CodeDigestExtraEntry(
+                                cd_index,
+                                cd_digest.to_vec(),
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::CodeDigestError(e),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Verify a random sample of code digests in a code directory.
+///
+/// If the code directory doesn't have a digest for every page, all digests
+/// are verified so missing and extra entries are reported accurately.
+fn verify_code_digests_sample(
+    macho: &MachOBinary,
+    cd: &CodeDirectoryBlob,
+    context: &VerificationContext,
+    count: usize,
+) -> Vec<VerificationProblem> {
+    let page_count = match macho.digested_code_data() {
+        Ok(data) => data.len().div_ceil(cd.page_size as usize),
+        Err(e) => {
+            return vec![VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::CodeDigestError(e),
+            }];
+        }
+    };
+
+    if page_count != cd.code_digests.len() || page_count == 0 {
+        return verify_code_digests_full(macho, cd, context);
+    }
+
+    // The first and last pages are the most likely to be tampered with, as they
+    // hold the load commands and the tail of __LINKEDIT.
+    let mut indices = sample(&mut rand::thread_rng(), page_count, count.min(page_count))
+        .into_iter()
+        .chain([0, page_count - 1])
+        .collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices.dedup();
+
+    match macho.code_page_digests(cd.digest_type, cd.page_size as _, indices) {
+        Ok(digests) => digests
+            .into_iter()
+            .filter_map(|(index, actual_digest)| {
+                let cd_digest = &cd.code_digests[index];
+
+                if cd_digest.data != actual_digest {
+                    Some(VerificationProblem {
+                        context: context.clone(),
+                        problem: VerificationProblemType::CodeDigestMismatch(
+                            index,
+                            cd_digest.to_vec(),
+                            actual_digest,
+                        ),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => vec![VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::CodeDigestError(e),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            cryptography::DigestType,
+            macho_builder::MachOBuilder,
+            macho_signing::MachOSigner,
+            signing_settings::{SettingsScope, SigningSettings},
+        },
+    };
+
+    /// Produce an ad-hoc signed binary and its number of code pages.
+    fn signed_macho() -> Result<(Vec<u8>, usize), AppleCodesignError> {
+        let data = MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "test");
+
+        let mut signed = vec![];
+        MachOSigner::new(&data)?.write_signed_binary(&settings, &mut signed)?;
+
+        let page_count = {
+            let mach = MachFile::parse(&signed)?;
+            let macho = mach.nth_macho(0)?;
+            let cd = macho
+                .code_signature()?
+                .expect("binary should be signed")
+                .code_directory()?
+                .expect("code directory should be present");
+
+            cd.code_digests.len()
+        };
+
+        Ok((signed, page_count))
+    }
+
+    /// Flip a byte in a code page that doesn't affect parsing of the binary.
+    fn tamper(data: &[u8], page: usize, page_count: usize) -> Vec<u8> {
+        let mut data = data.to_vec();
+
+        // Early pages hold the header and load commands followed by padding.
+        // The last page starts with the unused symbol table.
+        let offset = if page == page_count - 1 {
+            page * 4096
+        } else {
+            page * 4096 + 4000
+        };
+        data[offset] ^= 0xff;
+
+        data
+    }
+
+    fn mismatched_pages(data: &[u8], mode: CodeDigestVerification) -> Vec<usize> {
+        verify_macho_data_with_mode(data, mode)
+            .into_iter()
+            .filter_map(|problem| match problem.problem {
+                VerificationProblemType::CodeDigestMismatch(index, _, _) => Some(index),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sampled_code_digests() -> Result<(), AppleCodesignError> {
+        let (signed, page_count) = signed_macho()?;
+        assert!(page_count >= 3);
+
+        for mode in [
+            CodeDigestVerification::Full,
+            CodeDigestVerification::Sample(0),
+            CodeDigestVerification::Sample(1),
+        ] {
+            assert!(mismatched_pages(&signed, mode).is_empty());
+        }
+
+        // The first and last pages are always sampled.
+        for page in [0, page_count - 1] {
+            let tampered = tamper(&signed, page, page_count);
+
+            for mode in [
+                CodeDigestVerification::Full,
+                CodeDigestVerification::Sample(0),
+                CodeDigestVerification::Sample(1),
+            ] {
+                assert_eq!(mismatched_pages(&tampered, mode), vec![page]);
+            }
+        }
+
+        // Other pages are caught when sampled and always caught in full mode.
+        let tampered = tamper(&signed, 1, page_count);
+        assert!(mismatched_pages(&tampered, CodeDigestVerification::Sample(0)).is_empty());
+        assert_eq!(
+            mismatched_pages(&tampered, CodeDigestVerification::Sample(page_count)),
+            vec![1]
+        );
+        assert_eq!(
+            mismatched_pages(&tampered, CodeDigestVerification::Full),
+            vec![1]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn alternate_code_directories() -> Result<(), AppleCodesignError> {
+        let data = MachOBuilder::new_aarch64(object::macho::MH_EXECUTE).write_macho()?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "test");
+        settings.set_digest_type(SettingsScope::Main, DigestType::Sha256);
+        settings.add_extra_digest(SettingsScope::Main, DigestType::Sha1);
+
+        let mut signed = vec![];
+        MachOSigner::new(&data)?.write_signed_binary(&settings, &mut signed)?;
+
+        let (slot, page_count) = {
+            let mach = MachFile::parse(&signed)?;
+            let macho = mach.nth_macho(0)?;
+            let signature = macho.code_signature()?.expect("binary should be signed");
+
+            let cd = signature
+                .code_directory()?
+                .expect("code directory should be present");
+            assert_eq!(cd.digest_type, DigestType::Sha256);
+
+            let alternates = signature.alternate_code_directories()?;
+            assert_eq!(alternates.len(), 1);
+            let (slot, alternate) = &alternates[0];
+            assert_eq!(alternate.digest_type, DigestType::Sha1);
+
+            (*slot, cd.code_digests.len())
+        };
+
+        // Ad-hoc signatures lack a CMS signature. Everything else should verify.
+        let problems = verify_macho_data(&signed)
+            .into_iter()
+            .filter(|p| !matches!(p.problem, VerificationProblemType::NoCryptographicSignature))
+            .collect::<Vec<_>>();
+        assert!(problems.is_empty(), "{problems:?}");
+
+        // Tampering is detected by both code directories.
+        let tampered = tamper(&signed, 0, page_count);
+        let mismatches = verify_macho_data(&tampered)
+            .into_iter()
+            .filter_map(|p| match p.problem {
+                VerificationProblemType::CodeDigestMismatch(index, _, _) => {
+                    Some((p.context.code_directory, index))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(mismatches, vec![(None, 0), (Some(slot), 0)]);
+
+        Ok(())
+    }
+}
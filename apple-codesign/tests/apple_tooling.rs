@@ -0,0 +1,398 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Compare rcodesign's verification against Apple's tooling.
+//!
+//! These tests produce signed bundles, DMGs, and flat packages with rcodesign,
+//! install them into a temporary prefix the way end-users would (mounting the
+//! DMG, expanding the installer), then run Apple's `codesign`, `pkgutil`, and
+//! `spctl` against the results. Artifacts that `rcodesign verify` supports are
+//! also verified with it. Any disagreement between Apple's verdict and ours is
+//! reported as drift and fails the test.
+//!
+//! Apple's tools are only available on macOS, so these tests only run there.
+
+#![cfg(target_os = "macos")]
+
+use {
+    anyhow::{anyhow, Result},
+    std::{
+        path::{Path, PathBuf},
+        process::Command,
+    },
+};
+
+const RCODESIGN: &str = env!("CARGO_BIN_EXE_rcodesign");
+
+/// Self-signed certificate and key used to sign flat packages.
+const INSTALLER_PEM: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/testdata/self-signed-rsa-developer-id-installer.pem"
+);
+
+/// Common name of the certificate in [INSTALLER_PEM].
+const INSTALLER_SIGNER: &str = "Developer ID Installer: RSA Developer ID Installer (test)";
+
+const INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleExecutable</key>
+    <string>MyApp</string>
+    <key>CFBundleIdentifier</key>
+    <string>com.example.myapp</string>
+    <key>CFBundleName</key>
+    <string>MyApp</string>
+    <key>CFBundlePackageType</key>
+    <string>APPL</string>
+    <key>CFBundleVersion</key>
+    <string>1.0</string>
+</dict>
+</plist>
+"#;
+
+/// Outcome of running a command.
+struct Outcome {
+    success: bool,
+    output: String,
+}
+
+fn run(program: &str, args: &[&str]) -> Result<Outcome> {
+    let output = Command::new(program).args(args).output()?;
+
+    Ok(Outcome {
+        success: output.status.success(),
+        output: format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+    })
+}
+
+fn run_checked(program: &str, args: &[&str]) -> Result<()> {
+    let outcome = run(program, args)?;
+
+    if outcome.success {
+        Ok(())
+    } else {
+        Err(anyhow!("{} {:?} failed: {}", program, args, outcome.output))
+    }
+}
+
+/// Collects verdicts of Apple's tooling and rcodesign on artifacts.
+#[derive(Default)]
+struct DriftReport {
+    entries: Vec<String>,
+    drift: Vec<String>,
+}
+
+impl DriftReport {
+    /// Compare the verdicts of `codesign --verify` and `rcodesign verify` on a path.
+    fn compare_verify(&mut self, label: &str, path: &Path) -> Result<()> {
+        let path = path.to_string_lossy();
+
+        let apple = run("codesign", &["--verify", "--strict", "--verbose=2", &path])?;
+        let ours = run(RCODESIGN, &["verify", &path])?;
+
+        self.entries.push(format!(
+            "{label}: codesign {}, rcodesign {}",
+            verdict(apple.success),
+            verdict(ours.success)
+        ));
+
+        if apple.success != ours.success {
+            self.drift.push(format!(
+                "{label}: codesign {} but rcodesign {}\ncodesign output:\n{}\nrcodesign output:\n{}",
+                verdict(apple.success),
+                verdict(ours.success),
+                apple.output,
+                ours.output
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Compare the verdicts of `pkgutil --check-signature` and rcodesign on a flat package.
+    ///
+    /// pkgutil must report a signature by `signer`, the common name of the
+    /// signing certificate. Its trust evaluation is ignored, as test
+    /// certificates are self-signed. rcodesign must verify the table of
+    /// contents checksum and both of its signatures.
+    fn compare_package_signature(&mut self, label: &str, path: &Path, signer: &str) -> Result<()> {
+        let path = path.to_string_lossy();
+
+        let apple = run("pkgutil", &["--check-signature", &path])?;
+        let apple_signed = apple.output.contains("Status: signed") && apple.output.contains(signer);
+
+        let ours = run(RCODESIGN, &["print-signature-info", &path])?;
+        let ours_signed = ours.success
+            && [
+                "checksum_verifies",
+                "rsa_signature_verifies",
+                "cms_signature_verifies",
+            ]
+            .iter()
+            .all(|key| ours.output.contains(&format!("{key}: true")));
+
+        self.entries.push(format!(
+            "{label}: pkgutil {}, rcodesign {}",
+            verdict(apple_signed),
+            verdict(ours_signed)
+        ));
+
+        if apple_signed != ours_signed {
+            self.drift.push(format!(
+                "{label}: pkgutil {} but rcodesign {}\npkgutil output:\n{}\nrcodesign output:\n{}",
+                verdict(apple_signed),
+                verdict(ours_signed),
+                apple.output,
+                ours.output
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Record the verdict of `codesign --verify` on a path.
+    ///
+    /// For artifacts `rcodesign verify` doesn't support. Rejection is reported
+    /// as drift since rcodesign produced the signature.
+    fn record_verify(&mut self, label: &str, path: &Path) -> Result<()> {
+        let outcome = run(
+            "codesign",
+            &[
+                "--verify",
+                "--strict",
+                "--verbose=2",
+                &path.to_string_lossy(),
+            ],
+        )?;
+
+        self.entries
+            .push(format!("{label}: codesign {}", verdict(outcome.success)));
+
+        if !outcome.success {
+            self.drift.push(format!(
+                "{label}: codesign rejected signature produced by rcodesign:\n{}",
+                outcome.output
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Record the Gatekeeper assessment of a path.
+    ///
+    /// Ad-hoc signed and un-notarized artifacts are always rejected by
+    /// Gatekeeper, so the assessment is recorded for information only.
+    fn record_assessment(&mut self, label: &str, assessment_type: &str, path: &Path) -> Result<()> {
+        let path = path.to_string_lossy();
+
+        let mut args = vec!["--assess", "--type", assessment_type, "--verbose"];
+
+        // Disk images are assessed by their own signature, not their content.
+        if assessment_type == "open" {
+            args.extend(["--context", "context:primary-signature"]);
+        }
+
+        args.push(&path);
+
+        let outcome = run("spctl", &args)?;
+
+        self.entries.push(format!(
+            "{label}: spctl --type {assessment_type} {}: {}",
+            verdict(outcome.success),
+            outcome.output.trim()
+        ));
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        for entry in &self.entries {
+            eprintln!("{entry}");
+        }
+
+        if self.drift.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "verification drift detected:\n{}",
+                self.drift.join("\n")
+            ))
+        }
+    }
+}
+
+fn verdict(success: bool) -> &'static str {
+    if success {
+        "accepted"
+    } else {
+        "rejected"
+    }
+}
+
+/// Create an ad-hoc signed application bundle in `dir`.
+fn create_signed_app(dir: &Path) -> Result<PathBuf> {
+    let app = dir.join("MyApp.app");
+    let macos = app.join("Contents").join("MacOS");
+    let resources = app.join("Contents").join("Resources");
+
+    std::fs::create_dir_all(&macos)?;
+    std::fs::create_dir_all(&resources)?;
+    std::fs::write(app.join("Contents").join("Info.plist"), INFO_PLIST)?;
+    std::fs::write(resources.join("data.txt"), "resource data")?;
+    std::fs::copy(RCODESIGN, macos.join("MyApp"))?;
+
+    run_checked(RCODESIGN, &["sign", &app.to_string_lossy()])?;
+
+    Ok(app)
+}
+
+#[test]
+fn app_bundle() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let app = create_signed_app(temp_dir.path())?;
+
+    let mut report = DriftReport::default();
+    report.compare_verify("signed app", &app)?;
+    report.record_assessment("signed app", "execute", &app)?;
+
+    // Tampering with a sealed resource must be caught by both.
+    std::fs::write(
+        app.join("Contents").join("Resources").join("data.txt"),
+        "modified",
+    )?;
+    report.compare_verify("tampered app", &app)?;
+
+    report.finish()
+}
+
+#[test]
+fn dmg() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let source_dir = temp_dir.path().join("source");
+    std::fs::create_dir(&source_dir)?;
+    create_signed_app(&source_dir)?;
+
+    let dmg = temp_dir.path().join("MyApp.dmg");
+    run_checked(
+        "hdiutil",
+        &[
+            "create",
+            "-quiet",
+            "-volname",
+            "MyApp",
+            "-srcfolder",
+            &source_dir.to_string_lossy(),
+            "-format",
+            "UDZO",
+            &dmg.to_string_lossy(),
+        ],
+    )?;
+    run_checked(RCODESIGN, &["sign", &dmg.to_string_lossy()])?;
+
+    let mut report = DriftReport::default();
+    report.record_verify("signed dmg", &dmg)?;
+    report.record_assessment("signed dmg", "open", &dmg)?;
+
+    // Install the application from the image like a user would.
+    let mount_point = temp_dir.path().join("mount");
+    std::fs::create_dir(&mount_point)?;
+    run_checked(
+        "hdiutil",
+        &[
+            "attach",
+            "-quiet",
+            "-nobrowse",
+            "-readonly",
+            "-mountpoint",
+            &mount_point.to_string_lossy(),
+            &dmg.to_string_lossy(),
+        ],
+    )?;
+
+    let prefix = temp_dir.path().join("prefix");
+    std::fs::create_dir(&prefix)?;
+    let copied = run_checked(
+        "ditto",
+        &[
+            &mount_point.join("MyApp.app").to_string_lossy(),
+            &prefix.join("MyApp.app").to_string_lossy(),
+        ],
+    );
+    run_checked(
+        "hdiutil",
+        &["detach", "-quiet", &mount_point.to_string_lossy()],
+    )?;
+    copied?;
+
+    report.compare_verify("app installed from dmg", &prefix.join("MyApp.app"))?;
+
+    report.finish()
+}
+
+#[test]
+fn flat_package() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let root = temp_dir.path().join("root");
+    std::fs::create_dir(&root)?;
+    create_signed_app(&root)?;
+
+    let pkg = temp_dir.path().join("MyApp.pkg");
+    run_checked(
+        "pkgbuild",
+        &[
+            "--quiet",
+            "--root",
+            &root.to_string_lossy(),
+            "--identifier",
+            "com.example.myapp.pkg",
+            "--version",
+            "1.0",
+            "--install-location",
+            "/Applications",
+            &pkg.to_string_lossy(),
+        ],
+    )?;
+
+    let signed_pkg = temp_dir.path().join("MyApp-signed.pkg");
+    run_checked(
+        RCODESIGN,
+        &[
+            "sign",
+            "--pem-file",
+            INSTALLER_PEM,
+            "--timestamp-url",
+            "none",
+            &pkg.to_string_lossy(),
+            &signed_pkg.to_string_lossy(),
+        ],
+    )?;
+
+    let mut report = DriftReport::default();
+    report.compare_package_signature("signed pkg", &signed_pkg, INSTALLER_SIGNER)?;
+    report.record_assessment("signed pkg", "install", &signed_pkg)?;
+
+    // Expand the installer payload into a prefix rather than installing it
+    // system-wide, which would require root.
+    let prefix = temp_dir.path().join("prefix");
+    run_checked(
+        "pkgutil",
+        &[
+            "--expand-full",
+            &signed_pkg.to_string_lossy(),
+            &prefix.to_string_lossy(),
+        ],
+    )?;
+
+    report.compare_verify(
+        "app installed from pkg",
+        &prefix.join("Payload").join("MyApp.app"),
+    )?;
+
+    report.finish()
+}
@@ -278,6 +278,30 @@ fn cli_tests() {
         cases.skip("tests/cmd/notary*.trycmd");
     }
 
+    // Remote signing and time-stamp servers are compiled out without network support.
+    // This changes the default time-stamp URL in help output.
+    if cfg!(not(feature = "network")) {
+        cases.skip("tests/cmd/remote-sign.trycmd");
+        cases.skip("tests/cmd/sign.trycmd");
+        cases.skip("tests/cmd/sign-for-notarization-timestamp.trycmd");
+    }
+
+    // The remote-sign command only exists with the remote-signing feature.
+    if cfg!(not(feature = "remote-signing")) {
+        cases.skip("tests/cmd/help.trycmd");
+        cases.skip("tests/cmd/remote-sign.trycmd");
+    }
+
+    // The dmg command only exists with the dmg feature.
+    if cfg!(not(feature = "dmg")) {
+        cases.skip("tests/cmd/help.trycmd");
+    }
+
+    // The daemon command requires Unix domain sockets, changing help output.
+    if cfg!(not(unix)) {
+        cases.skip("tests/cmd/help.trycmd");
+    }
+
     // Tests with `ln -s` may not work on Windows. So just skip them.
     if cfg!(windows) {
         cases.skip("tests/cmd/sign-bundle-framework.trycmd");
@@ -66,6 +66,15 @@ impl From<UdifChecksum> for u32 {
 
 const KOLY_SIZE: i64 = 512;
 
+/// Trailer flag indicating the image is stored in a single file.
+pub const KOLY_FLAG_FLATTENED: u32 = 0x1;
+
+/// Trailer flag set by `hdiutil internet-enable`.
+///
+/// Historically, macOS copied the content of internet-enabled images next to
+/// the image and moved the image to the trash after downloading it.
+pub const KOLY_FLAG_INTERNET_ENABLED: u32 = 0x4;
+
 /// DMG trailer describing file content.
 ///
 /// This is the main structure defining a DMG.
@@ -100,7 +109,7 @@ impl Default for KolyTrailer {
     fn default() -> Self {
         Self {
             version: 4,
-            flags: 1,
+            flags: KOLY_FLAG_FLATTENED,
             running_data_fork_offset: 0,
             data_fork_offset: 0,
             data_fork_length: 0,
@@ -147,6 +156,20 @@ impl KolyTrailer {
         }
     }
 
+    /// Whether the image is internet-enabled.
+    pub fn internet_enabled(&self) -> bool {
+        self.flags & KOLY_FLAG_INTERNET_ENABLED != 0
+    }
+
+    /// Set whether the image is internet-enabled.
+    pub fn set_internet_enabled(&mut self, value: bool) {
+        if value {
+            self.flags |= KOLY_FLAG_INTERNET_ENABLED;
+        } else {
+            self.flags &= !KOLY_FLAG_INTERNET_ENABLED;
+        }
+    }
+
     /// Construct an instance by reading from a seekable reader.
     ///
     /// The trailer is the final 512 bytes of the seekable stream.
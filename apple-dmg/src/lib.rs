@@ -13,15 +13,19 @@ use {
     std::{
         fs::File,
         io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
-        path::Path,
+        path::{Path, PathBuf},
     },
 };
 
 mod blkx;
+mod encryption;
 mod koly;
+mod limits;
+mod listing;
+mod sparse;
 mod xml;
 
-pub use crate::{blkx::*, koly::*, xml::*};
+pub use crate::{blkx::*, encryption::*, koly::*, limits::*, listing::*, sparse::*, xml::*};
 
 pub struct DmgReader<R: Read + Seek> {
     koly: KolyTrailer,
@@ -38,6 +42,9 @@ impl DmgReader<BufReader<File>> {
 
 impl<R: Read + Seek> DmgReader<R> {
     pub fn new(mut r: R) -> Result<Self> {
+        if is_encrypted_dmg(&mut r)? {
+            anyhow::bail!("image is encrypted; use EncryptedDmgReader to decrypt it");
+        }
         let koly = KolyTrailer::read_from(&mut r)?;
         r.seek(SeekFrom::Start(koly.plist_offset))?;
         let mut xml = Vec::with_capacity(koly.plist_length as usize);
@@ -70,11 +77,16 @@ impl<R: Read + Seek> DmgReader<R> {
 
     pub fn data_checksum(&mut self) -> Result<u32> {
         self.r.seek(SeekFrom::Start(self.koly.data_fork_offset))?;
-        let mut data_fork = Vec::with_capacity(self.koly.data_fork_length as usize);
-        (&mut self.r)
-            .take(self.koly.data_fork_length)
-            .read_to_end(&mut data_fork)?;
-        Ok(crc32fast::hash(&data_fork))
+        let mut data_fork = (&mut self.r).take(self.koly.data_fork_length);
+        let mut hasher = Hasher::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            match data_fork.read(&mut buffer)? {
+                0 => break,
+                n => hasher.update(&buffer[..n]),
+            }
+        }
+        Ok(hasher.finalize())
     }
 
     pub fn partition_table(&self, i: usize) -> Result<BlkxTable> {
@@ -86,12 +98,22 @@ impl<R: Read + Seek> DmgReader<R> {
     }
 
     pub fn partition_data(&mut self, i: usize) -> Result<Vec<u8>> {
-        let table = self.plist().partitions()[i].table()?;
         let mut partition = vec![];
+        self.copy_partition_data(i, &mut partition)?;
+        Ok(partition)
+    }
+
+    /// Write the decompressed data of a partition to a writer.
+    ///
+    /// Unlike [Self::partition_data()], only a single chunk is held in memory at
+    /// a time.
+    pub fn copy_partition_data(&mut self, i: usize, w: &mut impl Write) -> Result<u64> {
+        let table = self.plist().partitions()[i].table()?;
+        let mut size = 0;
         for chunk in &table.chunks {
-            std::io::copy(&mut self.sector(chunk)?, &mut partition)?;
+            size += std::io::copy(&mut self.sector(chunk)?, w)?;
         }
-        Ok(partition)
+        Ok(size)
     }
 }
 
@@ -102,6 +124,8 @@ pub struct DmgWriter<W: Write + Seek> {
     main_hasher: Hasher,
     sector_number: u64,
     compressed_offset: u64,
+    chunk_size: usize,
+    internet_enabled: bool,
 }
 
 impl DmgWriter<BufWriter<File>> {
@@ -120,29 +144,81 @@ impl<W: Write + Seek> DmgWriter<W> {
             main_hasher: Hasher::new(),
             sector_number: 0,
             compressed_offset: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            internet_enabled: false,
         }
     }
 
-    pub fn create_fat32(mut self, fat32: &[u8]) -> Result<()> {
+    /// Set the size of chunks that partition data is compressed in.
+    ///
+    /// Smaller chunks reduce memory usage at the expense of compression ratio.
+    /// The size must be a non-zero multiple of 512 bytes.
+    pub fn set_chunk_size(&mut self, size: usize) -> Result<()> {
+        anyhow::ensure!(
+            size > 0 && size % 512 == 0,
+            "chunk size not a multiple of 512 bytes"
+        );
+        self.chunk_size = size;
+        Ok(())
+    }
+
+    /// Set whether the image is internet-enabled.
+    ///
+    /// This is the equivalent of `hdiutil internet-enable`.
+    pub fn set_internet_enabled(&mut self, value: bool) {
+        self.internet_enabled = value;
+    }
+
+    pub fn create_fat32(self, fat32: &[u8]) -> Result<()> {
         anyhow::ensure!(fat32.len() % 512 == 0);
         let sector_count = fat32.len() as u64 / 512;
+        self.create_fat32_from_reader(fat32, sector_count)
+    }
+
+    /// Write an image holding a FAT32 filesystem read from a reader.
+    ///
+    /// `sector_count` is the size of the filesystem in 512 byte sectors.
+    pub fn create_fat32_from_reader(mut self, fat32: impl Read, sector_count: u64) -> Result<()> {
         let mut mbr = ProtectiveMBR::new();
         let mut partition = PartRecord::new_protective(Some(sector_count.try_into()?));
         partition.os_type = 11;
         mbr.set_partition(0, partition);
         let mbr = mbr.to_bytes().to_vec();
         self.add_partition("Master Boot Record (MBR : 0)", &mbr)?;
-        self.add_partition("FAT32 (FAT32 : 1)", fat32)?;
+        self.add_partition_from_reader("FAT32 (FAT32 : 1)", fat32.take(sector_count * 512))?;
         self.finish()?;
         Ok(())
     }
 
     pub fn add_partition(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
         anyhow::ensure!(bytes.len() % 512 == 0);
+        self.add_partition_from_reader(name, bytes)
+    }
+
+    /// Add a partition with content read from a reader.
+    ///
+    /// Data is consumed in chunks, so the partition doesn't have to fit in
+    /// memory. The amount of data must be a multiple of 512 bytes.
+    pub fn add_partition_from_reader(&mut self, name: &str, mut r: impl Read) -> Result<()> {
         let id = self.xml.partitions().len() as u32;
         let name = name.to_string();
-        let mut table = BlkxTable::new(id, self.sector_number, crc32fast::hash(bytes));
-        for chunk in bytes.chunks(2048 * 512) {
+        let mut table = BlkxTable::new(id, self.sector_number, 0);
+        let mut partition_hasher = Hasher::new();
+        let mut chunk = vec![0; self.chunk_size];
+        loop {
+            let mut len = 0;
+            while len < chunk.len() {
+                match r.read(&mut chunk[len..])? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            if len == 0 {
+                break;
+            }
+            anyhow::ensure!(len % 512 == 0, "partition size not a multiple of 512 bytes");
+            let chunk = &chunk[..len];
+            partition_hasher.update(chunk);
             let mut encoder = ZlibEncoder::new(chunk, Compression::best());
             let mut compressed = vec![];
             encoder.read_to_end(&mut compressed)?;
@@ -160,6 +236,7 @@ impl<W: Write + Seek> DmgWriter<W> {
             self.sector_number += sector_count;
             self.compressed_offset += compressed_length;
         }
+        table.checksum = UdifChecksum::new(partition_hasher.finalize());
         table.add_chunk(BlkxChunk::term(self.sector_number, self.compressed_offset));
         self.main_hasher.update(&table.checksum.data[..4]);
         self.xml
@@ -173,7 +250,7 @@ impl<W: Write + Seek> DmgWriter<W> {
         let pos = self.w.stream_position()?;
         let data_digest = self.data_hasher.finalize();
         let main_digest = self.main_hasher.finalize();
-        let koly = KolyTrailer::new(
+        let mut koly = KolyTrailer::new(
             pos,
             self.sector_number,
             pos,
@@ -181,6 +258,7 @@ impl<W: Write + Seek> DmgWriter<W> {
             data_digest,
             main_digest,
         );
+        koly.set_internet_enabled(self.internet_enabled);
         self.w.write_all(&xml)?;
         koly.write_to(&mut self.w)?;
         Ok(())
@@ -224,24 +302,148 @@ fn add_dir<T: ReadWriteSeek>(src: &Path, dest: &Dir<'_, T>) -> Result<()> {
     Ok(())
 }
 
+/// Finder flag indicating an item has a custom icon.
+const FINDER_FLAG_HAS_CUSTOM_ICON: u16 = 0x0400;
+
+/// Produce an AppleDouble file holding Finder info with the given flags.
+///
+/// macOS stores metadata of items on FAT volumes in AppleDouble files. The
+/// metadata of the volume root lives in a `._.` file at the root.
+fn apple_double_finder_info(finder_flags: u16) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 26 + 2 * 12;
+    const FINDER_INFO_SIZE: u32 = 32;
+
+    let mut data = vec![];
+    data.extend_from_slice(&0x0005_1607u32.to_be_bytes());
+    data.extend_from_slice(&0x0002_0000u32.to_be_bytes());
+    data.extend_from_slice(&[0; 16]);
+    data.extend_from_slice(&2u16.to_be_bytes());
+    // Finder info entry.
+    data.extend_from_slice(&9u32.to_be_bytes());
+    data.extend_from_slice(&HEADER_SIZE.to_be_bytes());
+    data.extend_from_slice(&FINDER_INFO_SIZE.to_be_bytes());
+    // Empty resource fork entry.
+    data.extend_from_slice(&2u32.to_be_bytes());
+    data.extend_from_slice(&(HEADER_SIZE + FINDER_INFO_SIZE).to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes());
+
+    let mut finder_info = [0; FINDER_INFO_SIZE as usize];
+    finder_info[8..10].copy_from_slice(&finder_flags.to_be_bytes());
+    data.extend_from_slice(&finder_info);
+
+    data
+}
+
+fn format_fat32<T: ReadWriteSeek>(
+    disk: T,
+    dir: &Path,
+    volume_label: &str,
+    total_sectors: u32,
+    volume_icon: Option<&Path>,
+) -> Result<()> {
+    let mut volume_label_bytes = [0; 11];
+    let end = std::cmp::min(volume_label_bytes.len(), volume_label.len());
+    volume_label_bytes[..end].copy_from_slice(&volume_label.as_bytes()[..end]);
+    let volume_options = FormatVolumeOptions::new()
+        .volume_label(volume_label_bytes)
+        .bytes_per_sector(512)
+        .total_sectors(total_sectors);
+    let mut disk = BufStream::new(disk);
+    fatfs::format_volume(&mut disk, volume_options)?;
+    let fs = FileSystem::new(disk, FsOptions::new())?;
+    if let Some(icon) = volume_icon {
+        let mut f = fs.root_dir().create_file(".VolumeIcon.icns")?;
+        std::io::copy(&mut File::open(icon)?, &mut f)?;
+        let finder_info = apple_double_finder_info(FINDER_FLAG_HAS_CUSTOM_ICON);
+        let mut f = fs.root_dir().create_file("._.")?;
+        std::io::copy(&mut &finder_info[..], &mut f)?;
+    }
+    let file_name = dir.file_name().unwrap().to_str().unwrap();
+    let dest = fs.root_dir().create_dir(file_name)?;
+    add_dir(dir, &dest)?;
+    Ok(())
+}
+
 pub fn create_dmg(dir: &Path, dmg: &Path, volume_label: &str, total_sectors: u32) -> Result<()> {
-    let mut fat32 = vec![0; total_sectors as usize * 512];
-    {
-        let mut volume_label_bytes = [0; 11];
-        let end = std::cmp::min(volume_label_bytes.len(), volume_label.len());
-        volume_label_bytes[..end].copy_from_slice(&volume_label.as_bytes()[..end]);
-        let volume_options = FormatVolumeOptions::new()
-            .volume_label(volume_label_bytes)
-            .bytes_per_sector(512)
-            .total_sectors(total_sectors);
-        let mut disk = BufStream::new(Cursor::new(&mut fat32));
-        fatfs::format_volume(&mut disk, volume_options)?;
-        let fs = FileSystem::new(disk, FsOptions::new())?;
-        let file_name = dir.file_name().unwrap().to_str().unwrap();
-        let dest = fs.root_dir().create_dir(file_name)?;
-        add_dir(dir, &dest)?;
+    create_dmg_with_limits(
+        dir,
+        dmg,
+        volume_label,
+        total_sectors,
+        &ResourceLimits::default(),
+    )
+}
+
+/// Create an image from a directory while respecting memory and disk budgets.
+///
+/// When the filesystem doesn't fit in the memory budget, it is assembled in a
+/// temporary file and streamed into the image.
+pub fn create_dmg_with_limits(
+    dir: &Path,
+    dmg: &Path,
+    volume_label: &str,
+    total_sectors: u32,
+    limits: &ResourceLimits,
+) -> Result<()> {
+    create_dmg_with_options(
+        dir,
+        dmg,
+        volume_label,
+        total_sectors,
+        &DmgOptions {
+            limits: limits.clone(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Options influencing the creation of images.
+#[derive(Clone, Debug, Default)]
+pub struct DmgOptions {
+    /// Memory and disk budgets.
+    pub limits: ResourceLimits,
+
+    /// Path to an `.icns` file to use as the icon of the mounted volume.
+    ///
+    /// The icon is stored as `.VolumeIcon.icns` at the root of the volume and
+    /// the custom icon Finder flag is set on the volume.
+    pub volume_icon: Option<PathBuf>,
+
+    /// Whether to mark the image as internet-enabled.
+    pub internet_enabled: bool,
+}
+
+/// Create an image from a directory with the given options.
+pub fn create_dmg_with_options(
+    dir: &Path,
+    dmg: &Path,
+    volume_label: &str,
+    total_sectors: u32,
+    options: &DmgOptions,
+) -> Result<()> {
+    let limits = &options.limits;
+    let volume_icon = options.volume_icon.as_deref();
+    let size = total_sectors as u64 * 512;
+    let mut writer = DmgWriter::create(dmg)?;
+    writer.set_chunk_size(limits.chunk_size())?;
+    writer.set_internet_enabled(options.internet_enabled);
+
+    if limits.fits_in_memory(size) {
+        let mut fat32 = vec![0; size as usize];
+        format_fat32(
+            Cursor::new(&mut fat32),
+            dir,
+            volume_label,
+            total_sectors,
+            volume_icon,
+        )?;
+        writer.create_fat32(&fat32)
+    } else {
+        let mut fat32 = limits.temp_file(size)?;
+        format_fat32(&mut fat32, dir, volume_label, total_sectors, volume_icon)?;
+        fat32.seek(SeekFrom::Start(0))?;
+        writer.create_fat32_from_reader(BufReader::new(fat32), total_sectors as u64)
     }
-    DmgWriter::create(dmg)?.create_fat32(&fat32)
 }
 
 #[cfg(test)]
@@ -333,6 +535,130 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn create_with_limits() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let src = temp.path().join("content");
+        std::fs::create_dir(&src)?;
+        std::fs::write(src.join("file.txt"), b"hello, world")?;
+
+        let in_memory = temp.path().join("in-memory.dmg");
+        create_dmg(&src, &in_memory, "test", 70000)?;
+
+        let limits = ResourceLimits {
+            temp_dir: Some(temp.path().to_path_buf()),
+            ..ResourceLimits::with_max_memory(64 * 1024)
+        };
+        assert_eq!(limits.chunk_size(), 16 * 1024);
+        let constrained = temp.path().join("constrained.dmg");
+        create_dmg_with_limits(&src, &constrained, "test", 70000, &limits)?;
+
+        let mut a = DmgReader::open(&in_memory)?;
+        let mut b = DmgReader::open(&constrained)?;
+        assert_eq!(a.partition_data(1)?, b.partition_data(1)?);
+        assert!(b.partition_table(1)?.chunks.len() > a.partition_table(1)?.chunks.len());
+        assert_eq!(
+            UdifChecksum::new(b.data_checksum()?),
+            b.koly().data_fork_digest
+        );
+
+        let limits = ResourceLimits {
+            max_disk: Some(1024),
+            ..ResourceLimits::with_max_memory(0)
+        };
+        assert!(create_dmg_with_limits(&src, &constrained, "test", 70000, &limits).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn volume_icon_and_internet_enable() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let src = temp.path().join("content");
+        std::fs::create_dir(&src)?;
+        std::fs::write(src.join("file.txt"), b"hello, world")?;
+        let icon = temp.path().join("icon.icns");
+        std::fs::write(&icon, b"icns\0\0\0\x08")?;
+
+        let dmg_path = temp.path().join("icon.dmg");
+        create_dmg_with_options(
+            &src,
+            &dmg_path,
+            "test",
+            70000,
+            &DmgOptions {
+                volume_icon: Some(icon),
+                internet_enabled: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut dmg = DmgReader::open(&dmg_path)?;
+        assert!(dmg.koly().internet_enabled());
+        let fs = FileSystem::new(Cursor::new(dmg.partition_data(1)?), FsOptions::new())?;
+        let mut data = vec![];
+        fs.root_dir()
+            .open_file(".VolumeIcon.icns")?
+            .read_to_end(&mut data)?;
+        assert_eq!(data, b"icns\0\0\0\x08");
+        let mut data = vec![];
+        fs.root_dir().open_file("._.")?.read_to_end(&mut data)?;
+        assert_eq!(&data[..4], &[0, 5, 0x16, 7]);
+        assert_eq!(&data[58..60], &FINDER_FLAG_HAS_CUSTOM_ICON.to_be_bytes());
+
+        let in_memory = temp.path().join("plain.dmg");
+        create_dmg(&src, &in_memory, "test", 70000)?;
+        assert!(!DmgReader::open(&in_memory)?.koly().internet_enabled());
+
+        Ok(())
+    }
+
+    /// Check that `hdiutil` accepts our images and agrees on the internet-enable flag.
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn hdiutil_internet_enable() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let src = temp.path().join("content");
+        std::fs::create_dir(&src)?;
+        std::fs::write(src.join("file.txt"), b"hello, world")?;
+
+        let hdiutil = |args: &[&str], path: &Path| -> Result<()> {
+            let status = std::process::Command::new("hdiutil")
+                .args(args)
+                .arg(path)
+                .status()?;
+            anyhow::ensure!(status.success(), "hdiutil {} failed", args.join(" "));
+            Ok(())
+        };
+
+        for internet_enabled in [false, true] {
+            let path = temp.path().join(format!("{internet_enabled}.dmg"));
+            create_dmg_with_options(
+                &src,
+                &path,
+                "test",
+                70000,
+                &DmgOptions {
+                    internet_enabled,
+                    ..Default::default()
+                },
+            )?;
+            hdiutil(&["verify", "-quiet"], &path)?;
+            hdiutil(&["imageinfo"], &path)?;
+
+            // hdiutil flips the same trailer flag we set.
+            let flip = if internet_enabled { "-no" } else { "-yes" };
+            hdiutil(&["internet-enable", "-quiet", flip], &path)?;
+            assert_eq!(
+                DmgReader::open(&path)?.koly().internet_enabled(),
+                !internet_enabled
+            );
+            hdiutil(&["verify", "-quiet"], &path)?;
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn checksum() -> Result<()> {
         let mut dmg = DmgReader::new(Cursor::new(DMG))?;
@@ -0,0 +1,97 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Memory and disk budgets for creating dmg images.
+
+use {
+    anyhow::Result,
+    std::{fs::File, path::PathBuf},
+};
+
+/// Default size of chunks that partition data is compressed in.
+pub const DEFAULT_CHUNK_SIZE: usize = 2048 * 512;
+
+/// Memory and disk budgets for creating images.
+///
+/// By default, images are assembled in memory and no limits apply. When the
+/// filesystem image exceeds `max_memory`, it is assembled in a temporary file
+/// instead and partition data is compressed in smaller chunks. This allows
+/// creating large images on machines with little memory.
+#[derive(Clone, Debug)]
+pub struct ResourceLimits {
+    /// Maximum number of bytes of image data to hold in memory.
+    pub max_memory: u64,
+
+    /// Maximum number of bytes of temporary files to create.
+    ///
+    /// `None` means no limit.
+    pub max_disk: Option<u64>,
+
+    /// Directory in which to create temporary files.
+    ///
+    /// `None` uses the system's temporary directory.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_memory: u64::MAX,
+            max_disk: None,
+            temp_dir: None,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Limits holding at most `max_memory` bytes of image data in memory.
+    pub fn with_max_memory(max_memory: u64) -> Self {
+        Self {
+            max_memory,
+            ..Default::default()
+        }
+    }
+
+    /// Whether `size` bytes of image data can be held in memory.
+    pub fn fits_in_memory(&self, size: u64) -> bool {
+        size <= self.max_memory
+    }
+
+    /// The size of chunks to compress partition data in.
+    ///
+    /// Compression buffers hold roughly twice the chunk size, so chunks are
+    /// limited to a quarter of the memory budget. The result is always a
+    /// multiple of 512 bytes.
+    pub fn chunk_size(&self) -> usize {
+        let budget = (self.max_memory / 4).min(DEFAULT_CHUNK_SIZE as u64) as usize;
+
+        (budget / 512 * 512).max(512)
+    }
+
+    /// Create a temporary file able to hold `size` bytes.
+    ///
+    /// Errors if `size` exceeds the disk budget. The file is deleted when
+    /// closed.
+    pub fn temp_file(&self, size: u64) -> Result<File> {
+        if let Some(max_disk) = self.max_disk {
+            anyhow::ensure!(
+                size <= max_disk,
+                "image of {} bytes exceeds disk budget of {} bytes",
+                size,
+                max_disk
+            );
+        }
+
+        let file = if let Some(dir) = &self.temp_dir {
+            tempfile::tempfile_in(dir)?
+        } else {
+            tempfile::tempfile()?
+        };
+        file.set_len(size)?;
+
+        Ok(file)
+    }
+}
@@ -0,0 +1,281 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Listing files in dmg images without mounting them.
+
+use {
+    crate::{ChunkType, DmgReader},
+    anyhow::Result,
+    fatfs::{Dir, FileAttributes, FileSystem, FsOptions, ReadWriteSeek},
+    serde::Serialize,
+    std::io::{Cursor, Read, Seek},
+};
+
+/// Size of files representing symlinks on FAT filesystems.
+const XSYM_SIZE: u64 = 1067;
+
+/// The type of a file in a dmg filesystem.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmgFileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A file in a dmg filesystem.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DmgFileEntry {
+    /// Path relative to the filesystem root, using `/` as a separator.
+    pub path: String,
+    /// The type of the file.
+    pub file_type: DmgFileType,
+    /// Size in bytes. 0 for directories and symlinks.
+    pub size: u64,
+    /// Unix permissions.
+    ///
+    /// FAT filesystems don't store permissions, so they are derived from the
+    /// file type and the read-only attribute.
+    pub mode: u32,
+    /// The target of a symlink.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_target: Option<String>,
+}
+
+/// Files within a single partition of a dmg.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DmgPartitionListing {
+    /// Index of the partition.
+    pub index: usize,
+    /// Name of the partition.
+    pub name: String,
+    /// The filesystem in the partition, if recognized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filesystem: Option<String>,
+    /// Why files in the partition couldn't be listed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unreadable_reason: Option<String>,
+    /// Files in the partition, sorted by path.
+    pub files: Vec<DmgFileEntry>,
+}
+
+/// Resolve the target of a symlink stored in the `XSym` format.
+fn parse_xsym(data: &[u8]) -> Option<String> {
+    let mut lines = data.strip_prefix(b"XSym\n")?.split(|c| *c == b'\n');
+    let len = std::str::from_utf8(lines.next()?).ok()?;
+    let len = len.parse::<usize>().ok()?;
+    let _digest = lines.next()?;
+    let target = lines.next()?;
+
+    if target.len() == len {
+        String::from_utf8(target.to_vec()).ok()
+    } else {
+        None
+    }
+}
+
+/// Identify filesystems we can't list by their magic values.
+fn identify_filesystem(data: &[u8]) -> Option<&'static str> {
+    if matches!(data.get(1024..1026), Some(b"H+" | b"HX")) {
+        Some("HFS+")
+    } else if matches!(data.get(32..36), Some(b"NXSB")) {
+        Some("APFS")
+    } else {
+        None
+    }
+}
+
+/// Receives the content of regular files while listing.
+type ContentVisitor<'a> = dyn FnMut(&DmgFileEntry, &mut dyn Read) -> Result<()> + 'a;
+
+fn walk_fat<T: ReadWriteSeek>(
+    dir: &Dir<'_, T>,
+    prefix: &str,
+    files: &mut Vec<DmgFileEntry>,
+    visit: &mut ContentVisitor<'_>,
+) -> Result<()> {
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+
+        if name == "." || name == ".." || entry.attributes().contains(FileAttributes::VOLUME_ID) {
+            continue;
+        }
+
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let read_only = entry.attributes().contains(FileAttributes::READ_ONLY);
+
+        if entry.is_dir() {
+            files.push(DmgFileEntry {
+                path: path.clone(),
+                file_type: DmgFileType::Directory,
+                size: 0,
+                mode: if read_only { 0o555 } else { 0o755 },
+                symlink_target: None,
+            });
+            walk_fat(&entry.to_dir(), &path, files, visit)?;
+            continue;
+        }
+
+        let symlink_target = if entry.len() == XSYM_SIZE {
+            let mut data = vec![];
+            entry.to_file().read_to_end(&mut data)?;
+            parse_xsym(&data)
+        } else {
+            None
+        };
+
+        files.push(if let Some(target) = symlink_target {
+            DmgFileEntry {
+                path,
+                file_type: DmgFileType::Symlink,
+                size: 0,
+                mode: 0o777,
+                symlink_target: Some(target),
+            }
+        } else {
+            let file = DmgFileEntry {
+                path,
+                file_type: DmgFileType::File,
+                size: entry.len(),
+                mode: if read_only { 0o444 } else { 0o644 },
+                symlink_target: None,
+            };
+            visit(&file, &mut entry.to_file())?;
+            file
+        });
+    }
+
+    Ok(())
+}
+
+impl<R: Read + Seek> DmgReader<R> {
+    /// List files in all partitions of the image.
+    ///
+    /// Files can only be listed in FAT filesystems. Other partitions are
+    /// reported with the reason they couldn't be read.
+    pub fn list_files(&mut self) -> Result<Vec<DmgPartitionListing>> {
+        self.list_files_with_content(|_, _| Ok(()))
+    }
+
+    /// List files in all partitions of the image and read their content.
+    ///
+    /// Like [Self::list_files()], but `visit` is called with each regular file
+    /// and a reader of its content.
+    pub fn list_files_with_content(
+        &mut self,
+        mut visit: impl FnMut(&DmgFileEntry, &mut dyn Read) -> Result<()>,
+    ) -> Result<Vec<DmgPartitionListing>> {
+        let mut res = vec![];
+
+        for index in 0..self.plist().partitions().len() {
+            let name = self.partition_name(index).to_string();
+            let table = self.partition_table(index)?;
+
+            let unsupported = table.chunks.iter().find_map(|chunk| match chunk.ty() {
+                Some(ChunkType::Adc | ChunkType::Bzlib | ChunkType::Lzfse) | None => {
+                    Some(chunk.r#type)
+                }
+                _ => None,
+            });
+
+            let mut listing = DmgPartitionListing {
+                index,
+                name,
+                filesystem: None,
+                unreadable_reason: None,
+                files: vec![],
+            };
+
+            if let Some(ty) = unsupported {
+                listing.unreadable_reason =
+                    Some(format!("unsupported chunk compression 0x{:08x}", ty));
+                res.push(listing);
+                continue;
+            }
+
+            let data = self.partition_data(index)?;
+
+            if let Some(filesystem) = identify_filesystem(&data) {
+                listing.filesystem = Some(filesystem.to_string());
+                listing.unreadable_reason = Some(format!(
+                    "{} filesystems not supported; only FAT filesystems can be read",
+                    filesystem
+                ));
+            } else if let Ok(fs) = FileSystem::new(Cursor::new(data), FsOptions::new()) {
+                listing.filesystem = Some(format!("{:?}", fs.fat_type()));
+                walk_fat(&fs.root_dir(), "", &mut listing.files, &mut visit)?;
+                listing.files.sort_by(|a, b| a.path.cmp(&b.path));
+            } else {
+                listing.unreadable_reason =
+                    Some("no recognized filesystem; only FAT filesystems can be read".to_string());
+            }
+
+            res.push(listing);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_created_dmg() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let source = td.path().join("MyApp");
+        std::fs::create_dir_all(source.join("bin"))?;
+        std::fs::write(source.join("bin/tool"), b"tool")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("bin/tool", source.join("tool"))?;
+
+        let dmg_path = td.path().join("test.dmg");
+        crate::create_dmg(&source, &dmg_path, "MyApp", 65536)?;
+
+        let listings = DmgReader::open(&dmg_path)?.list_files()?;
+        assert_eq!(listings.len(), 2);
+        assert!(listings[0]
+            .unreadable_reason
+            .as_deref()
+            .unwrap()
+            .contains("only FAT filesystems can be read"));
+        assert!(listings[1].filesystem.is_some());
+
+        let files = &listings[1].files;
+        let tool = files.iter().find(|f| f.path == "MyApp/bin/tool").unwrap();
+        assert_eq!(tool.file_type, DmgFileType::File);
+        assert_eq!(tool.size, 4);
+        assert_eq!(tool.mode, 0o644);
+
+        #[cfg(unix)]
+        {
+            let link = files.iter().find(|f| f.path == "MyApp/tool").unwrap();
+            assert_eq!(link.file_type, DmgFileType::Symlink);
+            assert_eq!(link.symlink_target.as_deref(), Some("bin/tool"));
+        }
+
+        let mut content = vec![];
+        let with_content = DmgReader::open(&dmg_path)?.list_files_with_content(|file, r| {
+            let mut data = vec![];
+            r.read_to_end(&mut data)?;
+            content.push((file.path.clone(), data));
+            Ok(())
+        })?;
+        assert_eq!(with_content, listings);
+        assert_eq!(
+            content,
+            vec![("MyApp/bin/tool".to_string(), b"tool".to_vec())]
+        );
+
+        Ok(())
+    }
+}
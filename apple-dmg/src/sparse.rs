@@ -0,0 +1,428 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sparse bundles and sparse images.
+//!
+//! Sparse bundles (`.sparsebundle`) are directories holding an `Info.plist`
+//! and a `bands` directory. The disk content is split into bands of a fixed
+//! size, each stored in a file named after the hex index of the band. Missing
+//! bands and data beyond the end of a band file are zeros.
+//!
+//! Sparse images (`.sparseimage`, `UDSP`) are single files starting with a
+//! `sprs` header. The header is followed by the bands allocated so far, in
+//! order of allocation. The header records which band of the disk each
+//! allocated band holds. When the header runs out of entries, another header
+//! (index node) is appended and subsequent bands follow that node.
+//!
+//! Both formats hold a raw disk. The readers in this module implement [Read]
+//! and [Seek] over that disk, which can be converted to a regular image with
+//! [convert_to_udif].
+
+use {
+    crate::DmgWriter,
+    anyhow::{anyhow, Result},
+    byteorder::{ReadBytesExt, BE},
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        fs::File,
+        io::{Read, Seek, SeekFrom},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Value of `diskimage-bundle-type` in the `Info.plist` of sparse bundles.
+pub const SPARSE_BUNDLE_TYPE: &str = "com.apple.diskimage.sparsebundle";
+
+/// Magic at the beginning of sparse images.
+pub const SPARSE_IMAGE_MAGIC: &[u8; 4] = b"sprs";
+
+/// Size of an index node in sparse images.
+const INDEX_NODE_SIZE: u64 = 4096;
+/// Size of the fixed portion of an index node.
+const INDEX_NODE_HEADER_SIZE: u64 = 64;
+
+#[derive(Clone, Debug, Deserialize)]
+struct SparseBundleInfo {
+    #[serde(rename = "band-size")]
+    band_size: u64,
+    #[serde(rename = "diskimage-bundle-type")]
+    bundle_type: String,
+    size: u64,
+}
+
+/// Clamp a read of a band to the band and the disk.
+fn band_read_len(position: u64, size: u64, band_size: u64, buf_len: usize) -> usize {
+    let remaining = (size - position).min(band_size - position % band_size);
+    buf_len.min(remaining as usize)
+}
+
+/// Provides access to the disk held by a sparse bundle.
+pub struct SparseBundleReader {
+    bands_path: PathBuf,
+    band_size: u64,
+    size: u64,
+    position: u64,
+    band: Option<(u64, Option<File>)>,
+}
+
+impl SparseBundleReader {
+    /// Open the sparse bundle at the given directory.
+    pub fn open(path: &Path) -> Result<Self> {
+        let info: SparseBundleInfo = plist::from_file(path.join("Info.plist"))?;
+
+        if info.bundle_type != SPARSE_BUNDLE_TYPE {
+            return Err(anyhow!("unsupported bundle type {}", info.bundle_type));
+        }
+        anyhow::ensure!(info.band_size > 0, "invalid band size 0");
+
+        Ok(Self {
+            bands_path: path.join("bands"),
+            band_size: info.band_size,
+            size: info.size,
+            position: 0,
+            band: None,
+        })
+    }
+
+    /// Size of the disk in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Size of bands in bytes.
+    pub fn band_size(&self) -> u64 {
+        self.band_size
+    }
+}
+
+impl Read for SparseBundleReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self.position / self.band_size;
+        let offset = self.position % self.band_size;
+        let len = band_read_len(self.position, self.size, self.band_size, buf.len());
+        let buf = &mut buf[..len];
+
+        if !matches!(&self.band, Some((cached, _)) if *cached == index) {
+            let file = match File::open(self.bands_path.join(format!("{:x}", index))) {
+                Ok(file) => Some(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            self.band = Some((index, file));
+        }
+
+        let mut read = 0;
+        if let Some((_, Some(file))) = &mut self.band {
+            file.seek(SeekFrom::Start(offset))?;
+            while read < buf.len() {
+                match file.read(&mut buf[read..])? {
+                    0 => break,
+                    n => read += n,
+                }
+            }
+        }
+        buf[read..].fill(0);
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for SparseBundleReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(pos, self.position, self.size)?;
+        Ok(self.position)
+    }
+}
+
+fn seek_position(pos: SeekFrom, position: u64, size: u64) -> std::io::Result<u64> {
+    let position = match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(offset) => size.checked_add_signed(offset),
+        SeekFrom::Current(offset) => position.checked_add_signed(offset),
+    };
+
+    position.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+/// Whether a reader holds a sparse image.
+pub fn is_sparse_image<R: Read + Seek>(r: &mut R) -> Result<bool> {
+    r.seek(SeekFrom::Start(0))?;
+    let mut magic = [0; 4];
+
+    Ok(match r.read_exact(&mut magic) {
+        Ok(()) => &magic == SPARSE_IMAGE_MAGIC,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e.into()),
+    })
+}
+
+/// Provides access to the disk held by a sparse image.
+pub struct SparseImageReader<R: Read + Seek> {
+    band_size: u64,
+    size: u64,
+    /// Maps band indices to the offset of their data.
+    bands: BTreeMap<u64, u64>,
+    position: u64,
+    r: R,
+}
+
+impl SparseImageReader<File> {
+    /// Open the sparse image at the given path.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> SparseImageReader<R> {
+    pub fn new(mut r: R) -> Result<Self> {
+        let mut bands = BTreeMap::new();
+        let mut node_offset = 0;
+        let mut band_size = 0;
+        let mut size = 0;
+
+        // Each index node describes the bands following it.
+        loop {
+            r.seek(SeekFrom::Start(node_offset))?;
+            let mut magic = [0; 4];
+            r.read_exact(&mut magic)?;
+            anyhow::ensure!(&magic == SPARSE_IMAGE_MAGIC, "not a sparse image");
+            let version = r.read_u32::<BE>()?;
+            anyhow::ensure!(version == 3, "unsupported sparse image version {}", version);
+            let sectors_per_band = r.read_u32::<BE>()?;
+            let _flags = r.read_u32::<BE>()?;
+            let _sector_count = r.read_u32::<BE>()?;
+            let next_node_offset = r.read_u64::<BE>()?;
+            let sector_count = r.read_u64::<BE>()?;
+
+            if node_offset == 0 {
+                anyhow::ensure!(sectors_per_band > 0, "invalid band size 0");
+                band_size = sectors_per_band as u64 * 512;
+                size = sector_count * 512;
+            }
+
+            r.seek(SeekFrom::Start(node_offset + INDEX_NODE_HEADER_SIZE))?;
+            let data_offset = node_offset + INDEX_NODE_SIZE;
+            let entries = (INDEX_NODE_SIZE - INDEX_NODE_HEADER_SIZE) / 4;
+            for position in 0..entries {
+                // Band numbers are 1-based. 0 marks unallocated entries.
+                match r.read_u32::<BE>()? {
+                    0 => {}
+                    band => {
+                        bands.insert(band as u64 - 1, data_offset + position * band_size);
+                    }
+                }
+            }
+
+            if next_node_offset == 0 {
+                break;
+            }
+            anyhow::ensure!(
+                next_node_offset > node_offset,
+                "invalid index node offset {}",
+                next_node_offset
+            );
+            node_offset = next_node_offset;
+        }
+
+        Ok(Self {
+            band_size,
+            size,
+            bands,
+            position: 0,
+            r,
+        })
+    }
+
+    /// Size of the disk in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Size of bands in bytes.
+    pub fn band_size(&self) -> u64 {
+        self.band_size
+    }
+}
+
+impl<R: Read + Seek> Read for SparseImageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self.position / self.band_size;
+        let offset = self.position % self.band_size;
+        let len = band_read_len(self.position, self.size, self.band_size, buf.len());
+
+        if let Some(band_offset) = self.bands.get(&index) {
+            self.r.seek(SeekFrom::Start(band_offset + offset))?;
+            self.r.read_exact(&mut buf[..len])?;
+        } else {
+            buf[..len].fill(0);
+        }
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SparseImageReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(pos, self.position, self.size)?;
+        Ok(self.position)
+    }
+}
+
+/// Write a raw disk to a regular compressed image.
+///
+/// `disk` is typically a [SparseBundleReader] or [SparseImageReader]. The
+/// disk is stored as a single partition and its size must be a multiple of
+/// 512 bytes.
+pub fn convert_to_udif(disk: impl Read, dmg: &Path) -> Result<()> {
+    let mut writer = DmgWriter::create(dmg)?;
+    writer.add_partition_from_reader("whole disk (unknown partition : 0)", disk)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::DmgReader,
+        byteorder::WriteBytesExt,
+        std::io::{Cursor, Write},
+    };
+
+    static DMG: &[u8] = include_bytes!("../assets/example.dmg");
+
+    fn disk() -> Result<Vec<u8>> {
+        DmgReader::new(Cursor::new(DMG))?.partition_data(1)
+    }
+
+    fn read_disk<R: Read + Seek>(mut r: R) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        r.read_to_end(&mut data)?;
+
+        // Reads spanning bands and seeks must agree with sequential reads.
+        let mut middle = vec![0; 1000];
+        r.seek(SeekFrom::Start(data.len() as u64 / 2))?;
+        r.read_exact(&mut middle)?;
+        assert_eq!(middle, data[data.len() / 2..data.len() / 2 + 1000]);
+
+        Ok(data)
+    }
+
+    fn check_conversion<R: Read>(disk: R, expected: &[u8]) -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let dmg_path = td.path().join("converted.dmg");
+        convert_to_udif(disk, &dmg_path)?;
+
+        let mut dmg = DmgReader::open(&dmg_path)?;
+        assert_eq!(dmg.plist().partitions().len(), 1);
+        assert_eq!(dmg.partition_data(0)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_bundle() -> Result<()> {
+        let disk = disk()?;
+        let band_size = 64 * 1024;
+
+        let td = tempfile::tempdir()?;
+        let bundle = td.path().join("test.sparsebundle");
+        std::fs::create_dir_all(bundle.join("bands"))?;
+
+        let mut info = plist::Dictionary::new();
+        info.insert("band-size".into(), plist::Value::from(band_size as u64));
+        info.insert("diskimage-bundle-type".into(), SPARSE_BUNDLE_TYPE.into());
+        info.insert("size".into(), plist::Value::from(disk.len() as u64));
+        plist::Value::Dictionary(info).to_file_xml(bundle.join("Info.plist"))?;
+
+        // Bands of zeros are omitted and trailing zeros are trimmed.
+        for (index, band) in disk.chunks(band_size).enumerate() {
+            let len = band.iter().rposition(|b| *b != 0).map_or(0, |pos| pos + 1);
+            if len > 0 {
+                std::fs::write(
+                    bundle.join("bands").join(format!("{:x}", index)),
+                    &band[..len],
+                )?;
+            }
+        }
+
+        let reader = SparseBundleReader::open(&bundle)?;
+        assert_eq!(reader.size(), disk.len() as u64);
+        assert_eq!(read_disk(reader)?, disk);
+
+        check_conversion(SparseBundleReader::open(&bundle)?, &disk)
+    }
+
+    #[test]
+    fn sparse_image() -> Result<()> {
+        let disk = disk()?;
+        let sectors_per_band = 8u32;
+        let band_size = sectors_per_band as usize * 512;
+
+        // Store non-zero bands in reverse order, spread over index nodes.
+        let bands = disk
+            .chunks(band_size)
+            .enumerate()
+            .filter(|(_, band)| band.iter().any(|b| *b != 0))
+            .rev()
+            .collect::<Vec<_>>();
+        let entries = ((INDEX_NODE_SIZE - INDEX_NODE_HEADER_SIZE) / 4) as usize;
+
+        let mut image = Cursor::new(vec![]);
+        for (i, node) in bands.chunks(entries).enumerate() {
+            let node_offset = image.position();
+            let next_node_offset = if (i + 1) * entries < bands.len() {
+                node_offset + INDEX_NODE_SIZE + (node.len() * band_size) as u64
+            } else {
+                0
+            };
+
+            image.write_all(SPARSE_IMAGE_MAGIC)?;
+            image.write_u32::<BE>(3)?;
+            image.write_u32::<BE>(sectors_per_band)?;
+            image.write_u32::<BE>(1)?;
+            image.write_u32::<BE>((disk.len() / 512) as u32)?;
+            image.write_u64::<BE>(next_node_offset)?;
+            image.write_u64::<BE>(disk.len() as u64 / 512)?;
+            image.write_all(&[0; 28])?;
+            for (index, _) in node {
+                image.write_u32::<BE>(*index as u32 + 1)?;
+            }
+            image.write_all(&vec![0; (entries - node.len()) * 4])?;
+
+            for (_, band) in node {
+                let mut band = band.to_vec();
+                band.resize(band_size, 0);
+                image.write_all(&band)?;
+            }
+        }
+        let image = image.into_inner();
+
+        assert!(is_sparse_image(&mut Cursor::new(&image))?);
+        assert!(!is_sparse_image(&mut Cursor::new(DMG))?);
+
+        let reader = SparseImageReader::new(Cursor::new(&image))?;
+        assert_eq!(reader.size(), disk.len() as u64);
+        assert_eq!(read_disk(reader)?, disk);
+
+        check_conversion(SparseImageReader::new(Cursor::new(&image))?, &disk)
+    }
+}
@@ -92,6 +92,8 @@ pub mod package_info;
 pub use package_info::PackageInfo;
 pub mod reader;
 pub use reader::{PkgFlavor, PkgReader};
+pub mod rewriter;
+pub use rewriter::ProductRewriter;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -112,6 +114,18 @@ pub enum Error {
 
     #[error("failed to resolve known component (this should not happen)")]
     ComponentResolution,
+
+    #[error("not a product package")]
+    NotProductPackage,
+
+    #[error("not a component package")]
+    NotComponentPackage,
+
+    #[error("component not found: {0}")]
+    ComponentNotFound(String),
+
+    #[error("file not found: {0}")]
+    FileNotFound(String),
 }
 
 /// Result type for this crate.
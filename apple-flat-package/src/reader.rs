@@ -105,27 +105,24 @@ impl<R: Read + Seek + Sized + Debug> PkgReader<R> {
             .into_iter()
             .filter(|(filename, _)| filename.starts_with(&prefix))
         {
-            let mut data = Vec::<u8>::with_capacity(file.size.unwrap_or(0) as _);
-            self.xar
-                .write_file_data_decoded_from_file(&file, &mut data)?;
-
             let filename = filename.strip_prefix(&prefix).expect("prefix should match");
 
-            match filename {
-                "Bom" => {
-                    bom_data = Some(data);
-                }
-                "PackageInfo" => {
-                    package_info_data = Some(data);
-                }
-                "Payload" => {
-                    payload_data = Some(data);
-                }
-                "Scripts" => {
-                    scripts_data = Some(data);
-                }
-                _ => {}
-            }
+            let dest = match filename {
+                "Bom" => &mut bom_data,
+                "PackageInfo" => &mut package_info_data,
+                "Payload" => &mut payload_data,
+                "Scripts" => &mut scripts_data,
+                _ => continue,
+            };
+
+            // Stream the decoded data rather than trusting the size recorded in
+            // the table of contents for a preallocation.
+            let mut data = vec![];
+            self.xar
+                .file_data_decoded_reader(&file)?
+                .read_to_end(&mut data)?;
+
+            *dest = Some(data);
         }
 
         if bom_data.is_some()
@@ -0,0 +1,519 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Rewriting of *product* flat packages.
+//!
+//! A *product* flat package holds its *components* as `*.pkg/` directories
+//! within a single XAR archive. [ProductRewriter] allows extracting these
+//! components as standalone *component* packages, replacing them (e.g. with
+//! a rebuilt or re-branded component), replacing individual files such as the
+//! `Distribution` XML, and re-assembling the product archive.
+//!
+//! File checksums are regenerated for replaced content and all heap offsets
+//! are recomputed. Existing file data is copied verbatim.
+//!
+//! Components within a product archive can't carry their own signatures:
+//! only the outer XAR table of contents is signed. So signatures of
+//! replacement components are discarded and the archive emitted by
+//! [ProductRewriter::write()] is unsigned. Sign it afterwards (e.g. with
+//! `rcodesign sign`) to produce a distributable installer.
+
+use {
+    crate::{Error, PkgResult},
+    apple_xar::{
+        reader::XarReader,
+        table_of_contents::{
+            Checksum, ChecksumType, File, FileChecksum, FileData, FileEncoding, FileType,
+        },
+    },
+    flate2::{write::ZlibEncoder, Compression},
+    scroll::IOwrite,
+    std::{
+        collections::BTreeMap,
+        fmt::Debug,
+        io::{Cursor, Read, Seek, Write},
+    },
+};
+
+/// Where the heap data of a file in a rewritten archive comes from.
+enum HeapSource {
+    /// A file in the original archive.
+    Original(File),
+    /// A file in a replacement component.
+    Component(String, File),
+    /// Replacement file content, keyed by path.
+    Replaced(String),
+}
+
+/// Origin of a file tree being laid out.
+#[derive(Clone, Copy)]
+enum Origin<'a> {
+    Original,
+    Component(&'a str),
+}
+
+/// Rewrites a *product* flat package.
+///
+/// Construct an instance from the original product archive, register
+/// replacements, then call [Self::write()] to emit the new archive.
+pub struct ProductRewriter<R: Read + Seek + Sized + Debug> {
+    xar: XarReader<R>,
+    components: BTreeMap<String, XarReader<Cursor<Vec<u8>>>>,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl<R: Read + Seek + Sized + Debug> ProductRewriter<R> {
+    /// Construct an instance from a reader of a product flat package.
+    pub fn new(reader: R) -> PkgResult<Self> {
+        let xar = XarReader::new(reader)?;
+
+        if xar.find_file("Distribution")?.is_none() {
+            return Err(Error::NotProductPackage);
+        }
+
+        Ok(Self {
+            xar,
+            components: BTreeMap::new(),
+            files: BTreeMap::new(),
+        })
+    }
+
+    /// Names of the component packages in the product.
+    ///
+    /// These are the `*.pkg` directories in the root of the archive.
+    pub fn component_names(&self) -> PkgResult<Vec<String>> {
+        Ok(self
+            .xar
+            .table_of_contents()
+            .files
+            .iter()
+            .filter(|f| matches!(f.file_type, FileType::Directory))
+            .filter_map(|f| f.names.last())
+            .filter(|name| name.ends_with(".pkg"))
+            .cloned()
+            .collect::<Vec<_>>())
+    }
+
+    /// Replace a component package.
+    ///
+    /// `data` holds a standalone *component* flat package, such as one produced
+    /// by `pkgbuild` or [Self::extract_component()]. Its files replace the
+    /// content of the component directory `name`. Any signature on the
+    /// replacement is discarded.
+    pub fn replace_component(&mut self, name: &str, data: Vec<u8>) -> PkgResult<()> {
+        if !self.component_names()?.iter().any(|n| n == name) {
+            return Err(Error::ComponentNotFound(name.to_string()));
+        }
+
+        let component = XarReader::new(Cursor::new(data))?;
+
+        if component.find_file("Distribution")?.is_some() {
+            return Err(Error::NotComponentPackage);
+        }
+
+        self.components.insert(name.to_string(), component);
+
+        Ok(())
+    }
+
+    /// Replace the content of a file in the archive.
+    ///
+    /// `path` is the full path of an existing file, e.g. `Distribution` or
+    /// `Foo.pkg/PackageInfo`. Replacements are stored uncompressed.
+    ///
+    /// Replacing a file within a replaced component isn't supported.
+    pub fn replace_file(&mut self, path: &str, data: Vec<u8>) -> PkgResult<()> {
+        match self.xar.find_file(path)? {
+            Some(file) if matches!(file.file_type, FileType::File) => {
+                self.files.insert(path.to_string(), data);
+                Ok(())
+            }
+            _ => Err(Error::FileNotFound(path.to_string())),
+        }
+    }
+
+    /// Write a component of the product as a standalone component package.
+    ///
+    /// Registered replacements are reflected in the output. The emitted
+    /// archive is unsigned.
+    pub fn extract_component(&mut self, name: &str, writer: &mut impl Write) -> PkgResult<()> {
+        let (mut files, origin) = if let Some(component) = self.components.get(name) {
+            (
+                component.table_of_contents().files.clone(),
+                Origin::Component(name),
+            )
+        } else {
+            let file = self
+                .xar
+                .table_of_contents()
+                .files
+                .iter()
+                .find(|f| f.names.last().map(|n| n.as_str()) == Some(name))
+                .ok_or_else(|| Error::ComponentNotFound(name.to_string()))?;
+
+            (file.files.clone(), Origin::Original)
+        };
+
+        let sources = self.layout(&mut files, Some(name), origin)?;
+
+        self.write_archive(files, sources, writer)
+    }
+
+    /// Write the rewritten product archive.
+    ///
+    /// The emitted archive is unsigned.
+    pub fn write(&mut self, writer: &mut impl Write) -> PkgResult<()> {
+        let mut files = self.xar.table_of_contents().files.clone();
+
+        let sources = self.layout(&mut files, None, Origin::Original)?;
+
+        self.write_archive(files, sources, writer)
+    }
+
+    fn checksum_type(&self) -> ChecksumType {
+        match self.xar.table_of_contents().checksum.style {
+            ChecksumType::None => ChecksumType::Sha1,
+            style => style,
+        }
+    }
+
+    /// Assign file IDs and heap offsets to a tree of files.
+    ///
+    /// Returns the sources of heap data in heap order. Offsets start after
+    /// the table of contents checksum.
+    fn layout<'a>(
+        &'a self,
+        files: &mut [File],
+        directory: Option<&str>,
+        origin: Origin<'a>,
+    ) -> PkgResult<Vec<HeapSource>> {
+        let checksum_type = self.checksum_type();
+        let mut next_id = 1;
+        let mut offset = checksum_type.digest_data(b"")?.len() as u64;
+        let mut sources = vec![];
+
+        self.layout_files(
+            files,
+            directory,
+            origin,
+            checksum_type,
+            &mut next_id,
+            &mut offset,
+            &mut sources,
+        )?;
+
+        Ok(sources)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn layout_files<'a>(
+        &'a self,
+        files: &mut [File],
+        directory: Option<&str>,
+        origin: Origin<'a>,
+        checksum_type: ChecksumType,
+        next_id: &mut u64,
+        offset: &mut u64,
+        sources: &mut Vec<HeapSource>,
+    ) -> PkgResult<()> {
+        for file in files.iter_mut() {
+            let name = file
+                .names
+                .last()
+                .ok_or(apple_xar::Error::TableOfContentsCorrupted(
+                    "missing file name",
+                ))?
+                .clone();
+            let path = match directory {
+                Some(directory) => format!("{directory}/{name}"),
+                None => name.clone(),
+            };
+
+            file.id = *next_id;
+            *next_id += 1;
+
+            let mut origin = origin;
+
+            if directory.is_none() && matches!(origin, Origin::Original) {
+                if let Some((name, component)) = self.components.get_key_value(&name) {
+                    file.files = component.table_of_contents().files.clone();
+                    origin = Origin::Component(name);
+                }
+            }
+
+            let replaced = matches!(origin, Origin::Original) && self.files.contains_key(&path);
+
+            if file.data.is_some() {
+                let source = if replaced {
+                    let content = &self.files[&path];
+                    let checksum = FileChecksum {
+                        style: checksum_type,
+                        checksum: hex_digest(&checksum_type.digest_data(content)?),
+                    };
+
+                    file.data = Some(FileData {
+                        offset: 0,
+                        size: content.len() as _,
+                        length: content.len() as _,
+                        extracted_checksum: checksum.clone(),
+                        archived_checksum: checksum,
+                        encoding: FileEncoding {
+                            style: "application/octet-stream".to_string(),
+                        },
+                    });
+                    file.size = Some(content.len() as _);
+
+                    HeapSource::Replaced(path.clone())
+                } else {
+                    match origin {
+                        Origin::Original => HeapSource::Original(file.clone()),
+                        Origin::Component(name) => {
+                            HeapSource::Component(name.to_string(), file.clone())
+                        }
+                    }
+                };
+
+                let data = file
+                    .data
+                    .as_mut()
+                    .expect("file data presence checked above");
+                data.offset = *offset;
+                *offset += data.length;
+
+                sources.push(source);
+            }
+
+            self.layout_files(
+                &mut file.files,
+                Some(&path),
+                origin,
+                checksum_type,
+                next_id,
+                offset,
+                sources,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_archive(
+        &mut self,
+        files: Vec<File>,
+        sources: Vec<HeapSource>,
+        writer: &mut impl Write,
+    ) -> PkgResult<()> {
+        let checksum_type = self.checksum_type();
+        let digest_size = checksum_type.digest_data(b"")?.len() as u64;
+
+        let mut toc = self.xar.table_of_contents().clone();
+        toc.checksum = Checksum {
+            style: checksum_type,
+            offset: 0,
+            size: digest_size,
+        };
+        toc.signature = None;
+        toc.x_signature = None;
+        toc.files = files;
+
+        let toc_data = toc.to_xml()?;
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&toc_data)?;
+        let toc_compressed = zlib.finish()?;
+
+        let toc_digest = checksum_type.digest_data(&toc_compressed)?;
+
+        let mut header = *self.xar.header();
+        header.checksum_algorithm_id = apple_xar::format::XarChecksum::from(checksum_type).into();
+        header.toc_length_compressed = toc_compressed.len() as _;
+        header.toc_length_uncompressed = toc_data.len() as _;
+
+        writer.iowrite_with(header, scroll::BE)?;
+        writer.write_all(&toc_compressed)?;
+        writer.write_all(&toc_digest)?;
+
+        for source in sources {
+            match source {
+                HeapSource::Original(file) => {
+                    self.xar.write_file_data_heap_from_file(&file, writer)?;
+                }
+                HeapSource::Component(name, file) => {
+                    self.components
+                        .get_mut(&name)
+                        .expect("component should be registered")
+                        .write_file_data_heap_from_file(&file, writer)?;
+                }
+                HeapSource::Replaced(path) => {
+                    writer.write_all(&self.files[&path])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a digest as lowercase hex, as stored in file checksums.
+fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        apple_xar::format::{XarChecksum, XarHeader},
+    };
+
+    const PRODUCT_TOC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xar>
+  <toc>
+    <creation-time>2024-01-01T00:00:00</creation-time>
+    <checksum style="sha1">
+      <offset>0</offset>
+      <size>20</size>
+    </checksum>
+    <file id="1">
+      <name>Distribution</name>
+      <type>file</type>
+      <data>
+        <length>5</length>
+        <offset>20</offset>
+        <size>5</size>
+        <encoding style="application/octet-stream"/>
+        <extracted-checksum style="sha1">0</extracted-checksum>
+        <archived-checksum style="sha1">0</archived-checksum>
+      </data>
+    </file>
+    <file id="2">
+      <name>foo.pkg</name>
+      <type>directory</type>
+      <file id="3">
+        <name>PackageInfo</name>
+        <type>file</type>
+        <data>
+          <length>3</length>
+          <offset>25</offset>
+          <size>3</size>
+          <encoding style="application/octet-stream"/>
+          <extracted-checksum style="sha1">0</extracted-checksum>
+          <archived-checksum style="sha1">0</archived-checksum>
+        </data>
+      </file>
+    </file>
+  </toc>
+</xar>
+"#;
+
+    const COMPONENT_TOC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xar>
+  <toc>
+    <creation-time>2024-01-01T00:00:00</creation-time>
+    <checksum style="sha1">
+      <offset>0</offset>
+      <size>20</size>
+    </checksum>
+    <file id="1">
+      <name>PackageInfo</name>
+      <type>file</type>
+      <data>
+        <length>3</length>
+        <offset>20</offset>
+        <size>3</size>
+        <encoding style="application/octet-stream"/>
+        <extracted-checksum style="sha1">0</extracted-checksum>
+        <archived-checksum style="sha1">0</archived-checksum>
+      </data>
+    </file>
+    <file id="2">
+      <name>Payload</name>
+      <type>file</type>
+      <data>
+        <length>7</length>
+        <offset>23</offset>
+        <size>7</size>
+        <encoding style="application/octet-stream"/>
+        <extracted-checksum style="sha1">0</extracted-checksum>
+        <archived-checksum style="sha1">0</archived-checksum>
+      </data>
+    </file>
+  </toc>
+</xar>
+"#;
+
+    fn archive(toc: &str, heap: &[u8]) -> PkgResult<Vec<u8>> {
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(toc.as_bytes())?;
+        let toc_compressed = zlib.finish()?;
+
+        let header = XarHeader {
+            magic: 0x78617221,
+            size: 28,
+            version: 1,
+            toc_length_compressed: toc_compressed.len() as _,
+            toc_length_uncompressed: toc.len() as _,
+            checksum_algorithm_id: XarChecksum::Sha1.into(),
+        };
+
+        let mut data = vec![];
+        data.iowrite_with(header, scroll::BE)?;
+        data.extend_from_slice(&toc_compressed);
+        data.extend_from_slice(&ChecksumType::Sha1.digest_data(&toc_compressed)?);
+        data.extend_from_slice(heap);
+
+        Ok(data)
+    }
+
+    #[test]
+    fn replace_component() -> PkgResult<()> {
+        let product = archive(PRODUCT_TOC, b"dist!old")?;
+        let component = archive(COMPONENT_TOC, b"newpayload")?;
+
+        let mut rewriter = ProductRewriter::new(Cursor::new(product))?;
+        assert_eq!(rewriter.component_names()?, vec!["foo.pkg".to_string()]);
+
+        let mut extracted = vec![];
+        rewriter.extract_component("foo.pkg", &mut extracted)?;
+        let mut reader = XarReader::new(Cursor::new(extracted))?;
+        assert!(reader.verify_table_of_contents_checksum()?);
+        assert_eq!(
+            reader.get_file_data_from_path("PackageInfo")?,
+            Some(b"old".to_vec())
+        );
+
+        assert!(matches!(
+            rewriter.replace_component("bar.pkg", component.clone()),
+            Err(Error::ComponentNotFound(_))
+        ));
+        rewriter.replace_component("foo.pkg", component)?;
+        rewriter.replace_file("Distribution", b"distribution".to_vec())?;
+
+        let mut rewritten = vec![];
+        rewriter.write(&mut rewritten)?;
+
+        let mut reader = XarReader::new(Cursor::new(rewritten))?;
+        assert!(reader.verify_table_of_contents_checksum()?);
+        assert_eq!(
+            reader.get_file_data_from_path("Distribution")?,
+            Some(b"distribution".to_vec())
+        );
+        assert_eq!(
+            reader.get_file_data_from_path("foo.pkg/PackageInfo")?,
+            Some(b"new".to_vec())
+        );
+        assert_eq!(
+            reader.get_file_data_from_path("foo.pkg/Payload")?,
+            Some(b"payload".to_vec())
+        );
+
+        let distribution = reader.find_file("Distribution")?.unwrap();
+        assert_eq!(
+            distribution.data.unwrap().extracted_checksum.checksum,
+            hex_digest(&ChecksumType::Sha1.digest_data(b"distribution")?)
+        );
+
+        Ok(())
+    }
+}
@@ -30,6 +30,9 @@ pub enum Error {
     #[error("XML write error: {0}")]
     XmlWrite(#[from] xml::writer::Error),
 
+    #[error("header is corrupted: {0}")]
+    HeaderCorrupted(&'static str),
+
     #[error("Invalid file ID")]
     InvalidFileId,
 
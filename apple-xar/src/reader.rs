@@ -10,7 +10,6 @@ use {
     },
     scroll::IOread,
     std::{
-        cmp::min,
         fmt::Debug,
         io::{Cursor, Read, Seek, SeekFrom, Write},
         path::Path,
@@ -42,6 +41,10 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     pub fn new(mut reader: R) -> XarResult<Self> {
         let header = reader.ioread_with::<XarHeader>(scroll::BE)?;
 
+        if header.size < 28 {
+            return Err(Error::HeaderCorrupted("header size too small"));
+        }
+
         let mut header_extra = vec![0u8; header.size as usize - 28];
         reader.read_exact(&mut header_extra)?;
 
@@ -93,7 +96,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         let mut writer = flate2::write::ZlibDecoder::new(vec![]);
         self.write_file_slice(
             self.header.size as _,
-            self.header.toc_length_compressed as _,
+            self.header.toc_length_compressed,
             &mut writer,
         )?;
 
@@ -104,11 +107,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     pub fn checksum_data(&mut self) -> XarResult<Vec<u8>> {
         let mut buf = Vec::with_capacity(self.toc.checksum.size as _);
 
-        self.write_heap_slice(
-            self.toc.checksum.offset,
-            self.toc.checksum.size as _,
-            &mut buf,
-        )?;
+        self.write_heap_slice(self.toc.checksum.offset, self.toc.checksum.size, &mut buf)?;
 
         Ok(buf)
     }
@@ -118,7 +117,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         let mut writer = Cursor::new(vec![]);
         self.write_file_slice(
             self.header.size as _,
-            self.header.toc_length_compressed as _,
+            self.header.toc_length_compressed,
             &mut writer,
         )?;
 
@@ -139,42 +138,80 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
             .find_map(|(path, file)| if path == filename { Some(file) } else { None }))
     }
 
+    /// Obtain a reader of a slice of the file.
+    ///
+    /// Offsets are relative from beginning of the file.
+    fn file_slice_reader(&mut self, offset: u64, size: u64) -> XarResult<impl Read + '_> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+
+        Ok((&mut self.reader).take(size))
+    }
+
     /// Write a slice of the file to a writer.
     ///
     /// Offsets are relative from beginning of the file.
     fn write_file_slice(
         &mut self,
         offset: u64,
-        size: usize,
+        size: u64,
         writer: &mut impl Write,
     ) -> XarResult<()> {
-        self.reader.seek(SeekFrom::Start(offset))?;
-
-        let mut remaining = size;
-        let mut buffer = Vec::with_capacity(32768);
-        buffer.resize(min(remaining, buffer.capacity()), 0);
-
-        while remaining > 0 {
-            self.reader.read_exact(&mut buffer)?;
-            remaining -= buffer.len();
-            writer.write_all(&buffer)?;
+        let written = std::io::copy(&mut self.file_slice_reader(offset, size)?, writer)?;
 
-            unsafe {
-                buffer.set_len(min(remaining, buffer.capacity()));
-            }
+        if written == size {
+            Ok(())
+        } else {
+            Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
         }
+    }
 
-        Ok(())
+    /// Resolve the absolute offset of data in the heap.
+    fn heap_offset(&self, offset: u64, size: u64) -> XarResult<u64> {
+        self.heap_start_offset
+            .checked_add(offset)
+            .filter(|start| start.checked_add(size).is_some())
+            .ok_or(Error::TableOfContentsCorrupted("heap offset out of range"))
     }
 
     /// Write a slice of the heap to a writer.
     fn write_heap_slice(
         &mut self,
         offset: u64,
-        size: usize,
+        size: u64,
         writer: &mut impl Write,
     ) -> XarResult<()> {
-        self.write_file_slice(self.heap_start_offset + offset, size, writer)
+        self.write_file_slice(self.heap_offset(offset, size)?, size, writer)
+    }
+
+    /// Obtain a reader of the heap file data for a given file record.
+    ///
+    /// Like [Self::write_file_data_heap_from_file], the raw data as stored
+    /// in the heap is returned. Data is read from the underlying reader on
+    /// demand, without intermediate buffering.
+    pub fn file_data_heap_reader(&mut self, file: &File) -> XarResult<impl Read + '_> {
+        let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+        let offset = self.heap_offset(data.offset, data.length)?;
+
+        self.file_slice_reader(offset, data.length)
+    }
+
+    /// Obtain a reader of the decoded file data for a given file record.
+    ///
+    /// This is the streaming equivalent of [Self::write_file_data_decoded_from_file].
+    pub fn file_data_decoded_reader(&mut self, file: &File) -> XarResult<Box<dyn Read + '_>> {
+        let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+        let reader = self.file_data_heap_reader(file)?;
+
+        Ok(match data.encoding.style.as_str() {
+            "application/octet-stream" => Box::new(reader),
+            "application/x-bzip2" => Box::new(bzip2::read::BzDecoder::new(reader)),
+            // The media type is arguably wrong, as there is no gzip header.
+            "application/x-gzip" => Box::new(flate2::read::ZlibDecoder::new(reader)),
+            "application/x-lzma" => Box::new(xz2::read::XzDecoder::new(reader)),
+            encoding => {
+                return Err(Error::UnimplementedFileEncoding(encoding.to_string()));
+            }
+        })
     }
 
     /// Write heap file data for a given file record to a writer.
@@ -187,12 +224,12 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         &mut self,
         file: &File,
         writer: &mut impl Write,
-    ) -> XarResult<usize> {
+    ) -> XarResult<u64> {
         let data = file.data.as_ref().ok_or(Error::FileNoData)?;
 
-        self.write_heap_slice(data.offset, data.length as _, writer)?;
+        self.write_heap_slice(data.offset, data.length, writer)?;
 
-        Ok(data.length as _)
+        Ok(data.length)
     }
 
     /// Write heap file data for a given file ID to a writer.
@@ -203,7 +240,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         &mut self,
         id: u64,
         writer: &mut impl Write,
-    ) -> XarResult<usize> {
+    ) -> XarResult<u64> {
         let file = self
             .toc
             .files()?
@@ -223,7 +260,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         &mut self,
         file: &File,
         writer: &mut impl Write,
-    ) -> XarResult<usize> {
+    ) -> XarResult<u64> {
         let data = file.data.as_ref().ok_or(Error::FileNoData)?;
 
         let mut writer = match data.encoding.style.as_str() {
@@ -252,7 +289,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         &mut self,
         id: u64,
         writer: &mut impl Write,
-    ) -> XarResult<usize> {
+    ) -> XarResult<u64> {
         let file = self
             .toc
             .files()?
@@ -304,11 +341,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     /// The checksum consists of a digest format and a raw digest.
     pub fn checksum(&mut self) -> XarResult<(ChecksumType, Vec<u8>)> {
         let mut data = Vec::<u8>::with_capacity(self.toc.checksum.size as _);
-        self.write_heap_slice(
-            self.toc.checksum.offset,
-            self.toc.checksum.size as _,
-            &mut data,
-        )?;
+        self.write_heap_slice(self.toc.checksum.offset, self.toc.checksum.size, &mut data)?;
 
         Ok((self.toc.checksum.style, data))
     }
@@ -331,7 +364,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     pub fn rsa_signature(&mut self) -> XarResult<Option<(Vec<u8>, Vec<CapturedX509Certificate>)>> {
         if let Some(sig) = self.toc.find_signature(SignatureStyle::Rsa).cloned() {
             let mut data = Vec::<u8>::with_capacity(sig.size as _);
-            self.write_heap_slice(sig.offset, sig.size as _, &mut data)?;
+            self.write_heap_slice(sig.offset, sig.size, &mut data)?;
 
             let certs = sig.x509_certificates()?;
 
@@ -370,7 +403,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     pub fn cms_signature(&mut self) -> XarResult<Option<SignedData>> {
         if let Some(sig) = self.toc.find_signature(SignatureStyle::Cms).cloned() {
             let mut data = Vec::<u8>::with_capacity(sig.size as _);
-            self.write_heap_slice(sig.offset, sig.size as _, &mut data)?;
+            self.write_heap_slice(sig.offset, sig.size, &mut data)?;
 
             Ok(Some(SignedData::parse_ber(&data)?))
         } else {
@@ -395,3 +428,112 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         Ok(checked)
     }
 }
+
+impl<T: AsRef<[u8]> + Debug> XarReader<Cursor<T>> {
+    /// Obtain the heap file data for a given file record without copying it.
+    ///
+    /// This is available when the archive is held in memory. Wrapping a memory
+    /// map of the archive file in a [Cursor] provides access to the data of
+    /// large archives without reading them into memory.
+    pub fn file_data_heap_slice(&self, file: &File) -> XarResult<&[u8]> {
+        let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+        let start = self.heap_offset(data.offset, data.length)?;
+
+        let archive = self.reader.get_ref().as_ref();
+        usize::try_from(start)
+            .ok()
+            .zip(usize::try_from(start + data.length).ok())
+            .and_then(|(start, end)| archive.get(start..end))
+            .ok_or(Error::TableOfContentsCorrupted("file data out of range"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, scroll::IOwrite};
+
+    const TOC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xar>
+  <toc>
+    <creation-time>2024-01-01T00:00:00</creation-time>
+    <checksum style="sha1">
+      <offset>0</offset>
+      <size>20</size>
+    </checksum>
+    <file id="1">
+      <name>hello.txt</name>
+      <type>file</type>
+      <data>
+        <length>11</length>
+        <offset>20</offset>
+        <size>11</size>
+        <encoding style="application/octet-stream"/>
+        <extracted-checksum style="sha1">0</extracted-checksum>
+        <archived-checksum style="sha1">0</archived-checksum>
+      </data>
+    </file>
+    <file id="2">
+      <name>huge.bin</name>
+      <type>file</type>
+      <data>
+        <length>8589934592</length>
+        <offset>18446744073709551000</offset>
+        <size>8589934592</size>
+        <encoding style="application/octet-stream"/>
+        <extracted-checksum style="sha1">0</extracted-checksum>
+        <archived-checksum style="sha1">0</archived-checksum>
+      </data>
+    </file>
+  </toc>
+</xar>
+"#;
+
+    fn archive() -> XarResult<Vec<u8>> {
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zlib.write_all(TOC.as_bytes())?;
+        let toc = zlib.finish()?;
+
+        let header = XarHeader {
+            magic: 0x78617221,
+            size: 28,
+            version: 1,
+            toc_length_compressed: toc.len() as _,
+            toc_length_uncompressed: TOC.len() as _,
+            checksum_algorithm_id: XarChecksum::Sha1.into(),
+        };
+
+        let mut data = vec![];
+        data.iowrite_with(header, scroll::BE)?;
+        data.extend_from_slice(&toc);
+        data.extend_from_slice(&ChecksumType::Sha1.digest_data(&toc)?);
+        data.extend_from_slice(b"hello world");
+
+        Ok(data)
+    }
+
+    #[test]
+    fn heap_access() -> XarResult<()> {
+        let mut reader = XarReader::new(Cursor::new(archive()?))?;
+        assert!(reader.verify_table_of_contents_checksum()?);
+
+        let hello = reader.find_file("hello.txt")?.unwrap();
+        assert_eq!(reader.file_data_heap_slice(&hello)?, b"hello world");
+
+        let mut data = vec![];
+        reader
+            .file_data_decoded_reader(&hello)?
+            .read_to_end(&mut data)?;
+        assert_eq!(data, b"hello world");
+
+        // Offsets and lengths beyond 32 bits are retained. Out of range data is
+        // an error rather than a panic.
+        let huge = reader.find_file("huge.bin")?.unwrap();
+        assert_eq!(huge.data.as_ref().unwrap().length, 8589934592);
+        assert!(reader.file_data_heap_slice(&huge).is_err());
+        assert!(reader
+            .write_file_data_heap_from_file(&huge, &mut std::io::sink())
+            .is_err());
+
+        Ok(())
+    }
+}